@@ -0,0 +1,94 @@
+// Compares the binary BVH with the wide one, on many random spheres and on
+// the demo scene. Run with `cargo bench --bench bvh`.
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use raytracer::bvh::Bvh;
+use raytracer::demo::build_world;
+use raytracer::float::Float;
+use raytracer::hittable::{Hittable, Sphere};
+use raytracer::materials::DiffuseMaterial;
+use raytracer::ray::Ray;
+use raytracer::vec3::Vec3;
+use raytracer::wide_bvh::WideBvh;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const RAYS: usize = 200_000;
+
+// the fastest of a few runs of `f`
+fn time<F: FnMut()>(mut f: F) -> Duration {
+    (0..5)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn spheres(n: usize) -> Vec<Box<dyn Hittable + Send + Sync>> {
+    let mut rng = StdRng::seed_from_u64(1);
+    (0..n)
+        .map(|_| {
+            let sphere = Sphere {
+                center: Vec3(
+                    rng.gen::<Float>() * 100.0 - 50.0,
+                    rng.gen::<Float>() * 100.0 - 50.0,
+                    rng.gen::<Float>() * 100.0 - 50.0,
+                ),
+                radius: 0.1 + rng.gen::<Float>() * 0.4,
+                material: Box::new(DiffuseMaterial {
+                    albedo: Vec3(0.5, 0.5, 0.5),
+                }),
+            };
+            Box::new(sphere) as Box<dyn Hittable + Send + Sync>
+        })
+        .collect()
+}
+
+fn compare(name: &str, bvh: &Bvh, wide: &WideBvh, origin: Vec3) {
+    let mut rng = StdRng::seed_from_u64(2);
+    let rays: Vec<Ray> = (0..RAYS)
+        .map(|_| {
+            let dir = Vec3(
+                rng.gen::<Float>() - 0.5,
+                rng.gen::<Float>() - 0.5,
+                rng.gen::<Float>() - 0.5,
+            ) - 0.5 * origin.normalized();
+            Ray::new(origin, dir)
+        })
+        .collect();
+    let intersect = |objects: &dyn Hittable| {
+        for ray in &rays {
+            black_box(objects.hit(ray, 0.001, Float::MAX).map(|h| h.t));
+        }
+    };
+    let binary = time(|| intersect(bvh));
+    let wide_time = time(|| intersect(wide));
+    println!(
+        "{:<12} binary {:>9.2?}  wide {:>9.2?}  speedup {:.2}x",
+        name,
+        binary,
+        wide_time,
+        binary.as_secs_f64() / wide_time.as_secs_f64()
+    );
+    println!("{:<12} {:?}", "", wide.stats());
+}
+
+fn main() {
+    let n = 200_000;
+    compare(
+        "spheres",
+        &Bvh::new(spheres(n)),
+        &WideBvh::new(spheres(n)),
+        Vec3(0.0, 0.0, 80.0),
+    );
+    let scene = build_world(&mut StdRng::seed_from_u64(0));
+    compare(
+        "demo scene",
+        &scene.flatten().unwrap(),
+        &scene.flatten_wide().unwrap(),
+        Vec3(-2.0, 1.5, 6.0),
+    );
+}
@@ -0,0 +1,81 @@
+// Compares the scene graph's BVH of boxed objects with the compact scene of
+// enum dispatched primitives, on the demo scene the command line renders.
+// Run with `cargo bench --bench dispatch`.
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use raytracer::camera::{Camera, CameraBuilder};
+use raytracer::demo::build_world;
+use raytracer::film::Film;
+use raytracer::filter::MitchellFilter;
+use raytracer::float::Float;
+use raytracer::hittable::Hittable;
+use raytracer::integrator::render_tile;
+use raytracer::ray::Ray;
+use raytracer::scene::World;
+use raytracer::vec3::Vec3;
+use std::hint::black_box;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const RAYS: usize = 1_000_000;
+
+// the fastest of a few runs of `f`
+fn time<F: FnMut()>(mut f: F) -> Duration {
+    (0..5)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn report(name: &str, boxed: Duration, compact: Duration) {
+    println!(
+        "{:<14} boxed {:>9.2?}  compact {:>9.2?}  speedup {:.2}x",
+        name,
+        boxed,
+        compact,
+        boxed.as_secs_f64() / compact.as_secs_f64()
+    );
+}
+
+fn main() {
+    let boxed = build_world(&mut StdRng::seed_from_u64(0)).world().unwrap();
+    let compact = World::compact(
+        build_world(&mut StdRng::seed_from_u64(0))
+            .compact()
+            .unwrap(),
+    );
+    let camera = CameraBuilder::new(Vec3(-2.0, 1.5, 6.0), Vec3(0.0, 0.3, 0.0), 16.0 / 9.0)
+        .build(&boxed.objects)
+        .unwrap();
+
+    let mut rng = StdRng::seed_from_u64(1);
+    let rays: Vec<Ray> = (0..RAYS)
+        .filter_map(|_| camera.get_ray(rng.gen(), rng.gen(), &mut rng))
+        .collect();
+    let intersect = |world: &World| {
+        for ray in &rays {
+            black_box(world.objects.hit(ray, 0.001, Float::MAX).map(|h| h.t));
+        }
+    };
+    report(
+        "intersection",
+        time(|| intersect(&boxed)),
+        time(|| intersect(&compact)),
+    );
+
+    let filter = Arc::new(MitchellFilter {
+        radius: 2.0,
+        b: 1.0 / 3.0,
+        c: 1.0 / 3.0,
+    });
+    let film = Film::new(160, 90, filter);
+    let render = |world: &World| {
+        let tile = film.tile(0, 0, film.width, film.height);
+        black_box(render_tile(world, &camera, tile, 8, false, 1));
+    };
+    report("render", time(|| render(&boxed)), time(|| render(&compact)));
+}
@@ -0,0 +1,74 @@
+// Compares intersecting packed spheres and triangles with AVX against one
+// at a time. Run with `cargo bench --bench packed`.
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use raytracer::float::Float;
+use raytracer::hittable::Hittable;
+use raytracer::materials::DiffuseMaterial;
+use raytracer::packed::PackedPrimitives;
+use raytracer::ray::Ray;
+use raytracer::vec3::Vec3;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const RAYS: usize = 100_000;
+
+// the fastest of a few runs of `f`
+fn time<F: FnMut()>(mut f: F) -> Duration {
+    (0..5)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn random_vec(rng: &mut StdRng, scale: Float) -> Vec3 {
+    Vec3(
+        (rng.gen::<Float>() - 0.5) * scale,
+        (rng.gen::<Float>() - 0.5) * scale,
+        (rng.gen::<Float>() - 0.5) * scale,
+    )
+}
+
+fn main() {
+    let mut rng = StdRng::seed_from_u64(1);
+    let mut simd = PackedPrimitives::new();
+    let mut scalar = PackedPrimitives::new().without_simd();
+    for p in [&mut simd, &mut scalar].iter_mut() {
+        p.add_material(DiffuseMaterial {
+            albedo: Vec3(0.5, 0.5, 0.5),
+        });
+    }
+    for _ in 0..64 {
+        let (center, radius) = (random_vec(&mut rng, 10.0), 0.1 + 0.3 * rng.gen::<Float>());
+        simd.add_sphere(center, radius, 0);
+        scalar.add_sphere(center, radius, 0);
+    }
+    for _ in 0..128 {
+        let a = random_vec(&mut rng, 10.0);
+        let (b, c) = (a + random_vec(&mut rng, 2.0), a + random_vec(&mut rng, 2.0));
+        simd.add_triangle(a, b, c, 0);
+        scalar.add_triangle(a, b, c, 0);
+    }
+    let rays: Vec<Ray> = (0..RAYS)
+        .map(|_| {
+            let pos = random_vec(&mut rng, 30.0);
+            Ray::new(pos, random_vec(&mut rng, 10.0) - pos)
+        })
+        .collect();
+    let intersect = |packed: &PackedPrimitives| {
+        for ray in &rays {
+            black_box(packed.hit(ray, 0.001, Float::MAX).map(|h| h.t));
+        }
+    };
+    let (scalar, simd) = (time(|| intersect(&scalar)), time(|| intersect(&simd)));
+    println!(
+        "192 primitives  scalar {:>9.2?}  simd {:>9.2?}  speedup {:.2}x",
+        scalar,
+        simd,
+        scalar.as_secs_f64() / simd.as_secs_f64()
+    );
+}
@@ -0,0 +1,155 @@
+use crate::float::Float;
+use crate::ray::*;
+use crate::vec3::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(a: Vec3, b: Vec3) -> Self {
+        Aabb {
+            min: a.min(b),
+            max: a.max(b),
+        }
+    }
+
+    pub fn from_points(points: &[Vec3]) -> Self {
+        let mut result = Aabb::new(points[0], points[0]);
+        for p in &points[1..] {
+            result = result.union(&Aabb::new(*p, *p));
+        }
+        result
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    /// flat primitives have a zero-thickness box, which the slab test can
+    /// miss due to rounding, so give every axis at least `delta` of extent
+    pub fn padded(&self, delta: Float) -> Aabb {
+        let extent = self.max - self.min;
+        let pad_axis = |e: Float| if e < delta { 0.5 * delta } else { 0.0 };
+        let pad = Vec3(
+            pad_axis(extent.x()),
+            pad_axis(extent.y()),
+            pad_axis(extent.z()),
+        );
+        Aabb {
+            min: self.min - pad,
+            max: self.max + pad,
+        }
+    }
+
+    pub fn contains(&self, p: Vec3) -> bool {
+        (0..3).all(|axis| p[axis] >= self.min[axis] && p[axis] <= self.max[axis])
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn surface_area(&self) -> Float {
+        let d = self.max - self.min;
+        2.0 * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x())
+    }
+
+    pub fn corners(&self) -> [Vec3; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            Vec3(a.x(), a.y(), a.z()),
+            Vec3(b.x(), a.y(), a.z()),
+            Vec3(a.x(), b.y(), a.z()),
+            Vec3(b.x(), b.y(), a.z()),
+            Vec3(a.x(), a.y(), b.z()),
+            Vec3(b.x(), a.y(), b.z()),
+            Vec3(a.x(), b.y(), b.z()),
+            Vec3(b.x(), b.y(), b.z()),
+        ]
+    }
+
+    pub fn hit(&self, ray: &Ray, mut t_min: Float, mut t_max: Float) -> bool {
+        for axis in 0..3 {
+            // a ray parallel to the slab is inside it all along or never; the
+            // general case would give 0 * inf = NaN for a ray starting on a
+            // slab plane
+            if ray.dir[axis] == 0.0 {
+                if ray.pos[axis] < self.min[axis] || ray.pos[axis] > self.max[axis] {
+                    return false;
+                }
+                continue;
+            }
+            let inv_d = 1.0 / ray.dir[axis];
+            let mut t0 = (self.min[axis] - ray.pos[axis]) * inv_d;
+            let mut t1 = (self.max[axis] - ray.pos[axis]) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+            if t_max < t_min {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn new_sorts_corners() {
+        let b = Aabb::new(Vec3(1.0, -1.0, 2.0), Vec3(-1.0, 1.0, 0.0));
+        assert_eq!(b.min, Vec3(-1.0, -1.0, 0.0));
+        assert_eq!(b.max, Vec3(1.0, 1.0, 2.0));
+    }
+
+    #[test]
+    fn union_and_area() {
+        let a = Aabb::new(Vec3(0.0, 0.0, 0.0), Vec3(1.0, 1.0, 1.0));
+        let b = Aabb::new(Vec3(1.0, 1.0, 1.0), Vec3(2.0, 2.0, 2.0));
+        let u = a.union(&b);
+        assert_eq!(u.min, Vec3(0.0, 0.0, 0.0));
+        assert_eq!(u.max, Vec3(2.0, 2.0, 2.0));
+        assert_relative_eq!(a.surface_area(), 6.0);
+        assert_eq!(u.centroid(), Vec3(1.0, 1.0, 1.0));
+        assert!(u.contains(Vec3(1.5, 0.5, 2.0)));
+        assert!(!a.contains(Vec3(1.5, 0.5, 0.5)));
+    }
+
+    #[test]
+    fn padded_keeps_boxes_non_degenerate() {
+        let flat = Aabb::new(Vec3(0.0, 0.0, 0.0), Vec3(1.0, 0.0, 1.0)).padded(0.002);
+        assert_relative_eq!(flat.min.y(), -0.001);
+        assert_relative_eq!(flat.max.y(), 0.001);
+        assert_relative_eq!(flat.max.x(), 1.0);
+    }
+
+    #[test]
+    fn slab_test() {
+        let b = Aabb::new(Vec3(-1.0, -1.0, -1.0), Vec3(1.0, 1.0, 1.0));
+        let hits = Ray::new(Vec3(0.0, 0.0, -5.0), Vec3(0.0, 0.0, 1.0));
+        let misses = Ray::new(Vec3(0.0, 2.0, -5.0), Vec3(0.0, 0.0, 1.0));
+        assert!(b.hit(&hits, 0.0, Float::MAX));
+        assert!(!b.hit(&hits, 0.0, 3.0));
+        assert!(!b.hit(&misses, 0.0, Float::MAX));
+    }
+
+    #[test]
+    fn slab_test_from_a_slab_plane() {
+        let b = Aabb::new(Vec3(-1.0, -1.0, -1.0), Vec3(1.0, 1.0, 1.0));
+        let along_face = Ray::new(Vec3(1.0, 0.0, -5.0), Vec3(0.0, 0.0, 1.0));
+        let beside_face = Ray::new(Vec3(1.5, 0.0, -5.0), Vec3(0.0, 0.0, 1.0));
+        assert!(b.hit(&along_face, 0.0, Float::MAX));
+        assert!(!b.hit(&beside_face, 0.0, Float::MAX));
+    }
+}
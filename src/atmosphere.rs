@@ -0,0 +1,289 @@
+use crate::float::consts::PI;
+use crate::float::Float;
+use crate::ray::*;
+use crate::vec3::*;
+use rand::rngs::StdRng;
+use rand::Rng;
+
+/// Fog whose extinction falls off exponentially with height:
+/// sigma(y) = density * exp(-falloff * (y - base_height)), in scene units.
+/// The in-scattered light is approximated by a constant fog color.
+#[derive(Debug, Clone)]
+pub struct HeightFog {
+    pub density: Float,
+    pub falloff: Float,
+    pub base_height: Float,
+    pub color: Vec3,
+}
+
+impl HeightFog {
+    /// the integral of the extinction from t = 0 to `t_max`, in closed form
+    pub fn optical_depth(&self, ray: &Ray, t_max: Float) -> Float {
+        if self.density <= 0.0 {
+            return 0.0;
+        }
+        let ray_length = ray.dir.length();
+        let distance = t_max * ray_length;
+        let start = self.density * (-self.falloff * (ray.pos.y() - self.base_height)).exp();
+        let k = self.falloff * ray.dir.y() / ray_length;
+        if k == 0.0 || (k * distance).abs() < 1e-6 {
+            start * distance
+        } else {
+            start * (1.0 - (-k * distance).exp()) / k
+        }
+    }
+
+    pub fn integrate(&self, ray: &Ray, t_max: Float) -> (Vec3, Vec3) {
+        let transmittance = (-self.optical_depth(ray, t_max)).exp();
+        let t = Vec3(transmittance, transmittance, transmittance);
+        (t, (1.0 - transmittance) * self.color)
+    }
+}
+
+/// Single scattering of sunlight by air molecules (Rayleigh) and aerosols
+/// (Mie) in a spherical atmosphere with exponentially decreasing density.
+/// The ground of the scene (y = 0) lies on the planet's surface; scene
+/// units are converted with `meters_per_unit`. Shadowing of the sun by
+/// scene objects is not accounted for.
+#[derive(Debug, Clone)]
+pub struct SkyScattering {
+    /// normalized, pointing towards the sun
+    pub sun_direction: Vec3,
+    pub sun_intensity: Vec3,
+    pub meters_per_unit: Float,
+    pub planet_radius: Float,
+    pub atmosphere_height: Float,
+    /// scattering coefficients at sea level, per meter
+    pub rayleigh: Vec3,
+    pub rayleigh_scale_height: Float,
+    pub mie: Float,
+    pub mie_scale_height: Float,
+    pub mie_g: Float,
+    pub view_steps: usize,
+    pub light_steps: usize,
+}
+
+// Mie particles absorb a little of the light as well.
+const MIE_EXTINCTION_RATIO: Float = 1.1;
+
+// distances to the two intersections of a ray from `origin` along unit
+// direction `dir` with a sphere of `radius` around the planet's center
+fn sphere_intersections(origin: Vec3, dir: Vec3, radius: Float) -> Option<(Float, Float)> {
+    let b = origin.dot(dir);
+    let c = origin.dot(origin) - radius * radius;
+    let discriminant = b * b - c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    Some((-b - root, -b + root))
+}
+
+impl SkyScattering {
+    /// Earth's atmosphere with the usual sea level coefficients.
+    pub fn earth(sun_direction: Vec3, sun_intensity: Vec3, meters_per_unit: Float) -> Self {
+        SkyScattering {
+            sun_direction: sun_direction.normalized(),
+            sun_intensity,
+            meters_per_unit,
+            planet_radius: 6_360e3,
+            atmosphere_height: 60e3,
+            rayleigh: Vec3(5.8e-6, 13.5e-6, 33.1e-6),
+            rayleigh_scale_height: 7994.0,
+            mie: 21e-6,
+            mie_scale_height: 1200.0,
+            mie_g: 0.76,
+            view_steps: 16,
+            light_steps: 8,
+        }
+    }
+
+    fn extinction(&self, rayleigh_depth: Float, mie_depth: Float) -> Vec3 {
+        rayleigh_depth * self.rayleigh
+            + Vec3(1.0, 1.0, 1.0) * (MIE_EXTINCTION_RATIO * self.mie * mie_depth)
+    }
+
+    // Rayleigh and Mie optical depths (as density times meters) from `p`
+    // towards the sun, or None if the planet is in the way.
+    fn sun_depth(&self, p: Vec3) -> Option<(Float, Float)> {
+        if let Some((near, _)) = sphere_intersections(p, self.sun_direction, self.planet_radius) {
+            if near > 0.0 {
+                return None;
+            }
+        }
+        let top = self.planet_radius + self.atmosphere_height;
+        let (_, exit) = sphere_intersections(p, self.sun_direction, top)?;
+        let step = exit.max(0.0) / self.light_steps as Float;
+        let (mut rayleigh, mut mie) = (0.0, 0.0);
+        for i in 0..self.light_steps {
+            let q = p + (i as Float + 0.5) * step * self.sun_direction;
+            let height = q.length() - self.planet_radius;
+            rayleigh += (-height / self.rayleigh_scale_height).exp() * step;
+            mie += (-height / self.mie_scale_height).exp() * step;
+        }
+        Some((rayleigh, mie))
+    }
+
+    /// transmittance and in-scattered sunlight between t = 0 and `t_max`
+    pub fn integrate(&self, ray: &Ray, t_max: Float, rng: &mut StdRng) -> (Vec3, Vec3) {
+        let nothing = (Vec3(1.0, 1.0, 1.0), Vec3(0.0, 0.0, 0.0));
+        let ray_length = ray.dir.length();
+        let dir = ray.dir / ray_length;
+        let origin = self.meters_per_unit * ray.pos + Vec3(0.0, self.planet_radius, 0.0);
+        let top = self.planet_radius + self.atmosphere_height;
+        let (enter, exit) = match sphere_intersections(origin, dir, top) {
+            Some(hits) => hits,
+            None => return nothing,
+        };
+        let mut end = exit.min(t_max * ray_length * self.meters_per_unit);
+        if let Some((ground, _)) = sphere_intersections(origin, dir, self.planet_radius) {
+            if ground > 0.0 {
+                end = end.min(ground);
+            }
+        }
+        let start = enter.max(0.0);
+        if end <= start {
+            return nothing;
+        }
+
+        let step = (end - start) / self.view_steps as Float;
+        // one random offset for all steps turns banding into noise
+        let jitter = rng.gen::<Float>();
+        let (mut rayleigh_depth, mut mie_depth) = (0.0, 0.0);
+        let mut rayleigh_sum = Vec3(0.0, 0.0, 0.0);
+        let mut mie_sum = Vec3(0.0, 0.0, 0.0);
+        for i in 0..self.view_steps {
+            let p = origin + (start + (i as Float + jitter) * step) * dir;
+            let height = p.length() - self.planet_radius;
+            let rayleigh = (-height / self.rayleigh_scale_height).exp() * step;
+            let mie = (-height / self.mie_scale_height).exp() * step;
+            rayleigh_depth += rayleigh;
+            mie_depth += mie;
+            if let Some((sun_rayleigh, sun_mie)) = self.sun_depth(p) {
+                let attenuation =
+                    (-self.extinction(rayleigh_depth + sun_rayleigh, mie_depth + sun_mie)).exp();
+                rayleigh_sum += rayleigh * attenuation;
+                mie_sum += mie * attenuation;
+            }
+        }
+
+        let mu = dir.dot(self.sun_direction);
+        let g = self.mie_g;
+        let rayleigh_phase = 3.0 / (16.0 * PI) * (1.0 + mu * mu);
+        let mie_phase = 3.0 / (8.0 * PI) * ((1.0 - g * g) * (1.0 + mu * mu))
+            / ((2.0 + g * g) * (1.0 + g * g - 2.0 * g * mu).powf(1.5));
+        let inscatter = self.sun_intensity
+            * (rayleigh_sum * self.rayleigh * rayleigh_phase + mie_sum * self.mie * mie_phase);
+        let transmittance = (-self.extinction(rayleigh_depth, mie_depth)).exp();
+        (transmittance, inscatter)
+    }
+}
+
+/// The medium filling the whole scene. It acts on every ray segment,
+/// including rays that escape to the sky. Fog is taken to lie in front of
+/// the sky scattering, which is exact when the fog is close to the ground
+/// and the camera.
+#[derive(Debug, Clone, Default)]
+pub struct Atmosphere {
+    pub fog: Option<HeightFog>,
+    pub sky: Option<SkyScattering>,
+}
+
+impl Atmosphere {
+    /// transmittance and in-scattered light between t = 0 and `t_max`; the
+    /// light reaching the ray's origin is transmittance * L + in-scattered
+    pub fn integrate(&self, ray: &Ray, t_max: Float, rng: &mut StdRng) -> (Vec3, Vec3) {
+        let (fog_transmittance, fog_inscatter) = match &self.fog {
+            Some(fog) => fog.integrate(ray, t_max),
+            None => (Vec3(1.0, 1.0, 1.0), Vec3(0.0, 0.0, 0.0)),
+        };
+        let (sky_transmittance, sky_inscatter) = match &self.sky {
+            Some(sky) => sky.integrate(ray, t_max, rng),
+            None => (Vec3(1.0, 1.0, 1.0), Vec3(0.0, 0.0, 0.0)),
+        };
+        (
+            fog_transmittance * sky_transmittance,
+            fog_inscatter + fog_transmittance * sky_inscatter,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+    use rand::SeedableRng;
+
+    fn ray(pos: Vec3, dir: Vec3) -> Ray {
+        Ray::new(pos, dir)
+    }
+
+    fn fog() -> HeightFog {
+        HeightFog {
+            density: 0.2,
+            falloff: 0.5,
+            base_height: 0.0,
+            color: Vec3(0.5, 0.6, 0.7),
+        }
+    }
+
+    #[test]
+    fn horizontal_fog_is_constant_density() {
+        let r = ray(Vec3(0.0, 2.0, 0.0), Vec3(2.0, 0.0, 0.0));
+        let expected = 0.2 * (-(0.5 as Float) * 2.0).exp() * 10.0;
+        assert_relative_eq!(fog().optical_depth(&r, 5.0), expected, epsilon = 1e-12);
+        let (t, inscatter) = fog().integrate(&r, 5.0);
+        assert_relative_eq!(t.x(), (-expected).exp(), epsilon = 1e-12);
+        assert_relative_eq!(
+            inscatter.z(),
+            0.7 * (1.0 - (-expected).exp()),
+            epsilon = 1e-12
+        );
+    }
+
+    #[test]
+    fn vertical_fog_matches_integral() {
+        // integral of 0.2 exp(-0.5 y) from y = 0 to 4
+        let r = ray(Vec3(0.0, 0.0, 0.0), Vec3(0.0, 1.0, 0.0));
+        let expected = 0.2 / 0.5 * (1.0 - (-(2.0 as Float)).exp());
+        assert_relative_eq!(fog().optical_depth(&r, 4.0), expected, epsilon = 1e-12);
+        // escaping upwards the depth stays finite, downwards the fog is opaque
+        let up = fog().optical_depth(&r, Float::MAX);
+        assert_relative_eq!(up, 0.4, epsilon = 1e-12);
+        let down = ray(Vec3(0.0, 0.0, 0.0), Vec3(0.0, -1.0, 0.0));
+        assert_relative_eq!(fog().integrate(&down, Float::MAX).0.x(), 0.0);
+    }
+
+    #[test]
+    fn sky_is_blue_and_sunset_is_red() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let sky = SkyScattering::earth(Vec3(0.0, 1.0, 0.0), Vec3(20.0, 20.0, 20.0), 1.0);
+        let up = ray(Vec3(0.0, 1.0, 0.0), Vec3(0.3, 1.0, 0.0));
+        let (_, inscatter) = sky.integrate(&up, Float::MAX, &mut rng);
+        assert!(inscatter.b() > inscatter.g() && inscatter.g() > inscatter.r());
+        // sunlight crossing the atmosphere near the horizon loses its blue
+        let horizon = ray(Vec3(0.0, 1.0, 0.0), Vec3(1.0, 0.0, 0.0));
+        let (t, _) = sky.integrate(&horizon, Float::MAX, &mut rng);
+        assert!(t.r() > t.g() && t.g() > t.b());
+    }
+
+    #[test]
+    fn short_segments_are_nearly_clear() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let atmosphere = Atmosphere {
+            fog: None,
+            sky: Some(SkyScattering::earth(
+                Vec3(0.0, 1.0, 0.0),
+                Vec3(20.0, 20.0, 20.0),
+                1.0,
+            )),
+        };
+        let r = ray(Vec3(0.0, 1.0, 0.0), Vec3(1.0, 0.0, 0.0));
+        let (t, inscatter) = atmosphere.integrate(&r, 1.0, &mut rng);
+        assert_relative_eq!(t.b(), 1.0, epsilon = 1e-4);
+        assert!(inscatter.b() < 1e-3);
+        let (t, inscatter) = Atmosphere::default().integrate(&r, Float::MAX, &mut rng);
+        assert_eq!(t, Vec3(1.0, 1.0, 1.0));
+        assert_eq!(inscatter, Vec3(0.0, 0.0, 0.0));
+    }
+}
@@ -1,27 +1,14 @@
-use mtpng;
-use std::fs::File;
-use std::io::BufWriter;
+use raytracer::output::{save_png, PngOptions};
 use std::path::Path;
 use std::time::Instant;
 
-fn main() -> std::io::Result<()> {
+fn main() -> raytracer::Result<()> {
     let time_start = Instant::now();
 
     let width: usize = 1200;
     let height: usize = 800;
 
-    let mut header = mtpng::Header::new();
-    header.set_size(width as u32, height as u32).unwrap();
-    header
-        .set_color(mtpng::ColorType::TruecolorAlpha, 8)
-        .unwrap();
-    let options = mtpng::encoder::Options::new();
-    let path = Path::new(r"out_image.png");
-    let file_writer = BufWriter::new(File::create(path)?);
-    let mut encoder = mtpng::encoder::Encoder::new(file_writer, &options);
-
-    let mut data: Vec<u8> = Vec::with_capacity(width * height * 4);
-    data.resize(width * height * 4, 0);
+    let mut data: Vec<u8> = vec![0; width * height * 4];
 
     let time_a = Instant::now();
 
@@ -34,10 +21,8 @@ fn main() -> std::io::Result<()> {
         }
     }
     let time_b = Instant::now();
-    //file_data.write_image_data(&*data).unwrap(); // Save
-    encoder.write_header(&header).unwrap();
-    encoder.write_image_rows(&data).unwrap();
-    encoder.finish().unwrap();
+    let path = Path::new(r"out_image.png");
+    save_png(path, &data, width, height, &PngOptions::best())?;
     let time_c = Instant::now();
     println!(
         "{:?} {:?} {:?}",
@@ -1,20 +1,44 @@
+use raytracer::camera::CameraBuilder;
+use raytracer::film::Film;
+use raytracer::filter::MitchellFilter;
+use raytracer::hittable::Sphere;
+use raytracer::integrator::render_tile;
+use raytracer::materials::DiffuseMaterial;
+use raytracer::scene::{SceneGraph, SceneNode};
+use raytracer::vec3::Vec3;
+use std::sync::Arc;
 use std::thread;
 
 // This is the `main` thread
 fn main() {
-    // This is our data to process.
-    // We will calculate the sum of all digits via a threaded  map-reduce algorithm.
-    // Each whitespace separated chunk will be handled in a different thread.
-    //
-    // TODO: see what happens to the output if you insert spaces!
-    let data = "86967897737416471853297327050364959
-11861322575564723963297542624962850
-70856234701860851907960690014725639
-38397966707106094172783238747669219
-52380795257888236525459303330302837
-58495327135744041048897885734297812
-69920216438980873548808413720956532
-16278424637452589860345374828574668";
+    // This is our data to process: a small scene with a single sphere.
+    // We will render it with a threaded map-reduce algorithm, each band of
+    // rows handled in a different thread.
+    let mut scene = SceneGraph::new();
+    scene.root.add_child(SceneNode::with_geometry(
+        "sphere",
+        Sphere {
+            center: Vec3(0.0, 0.0, -2.0),
+            radius: 0.5,
+            material: Box::new(DiffuseMaterial {
+                albedo: Vec3(0.5, 0.5, 0.5),
+            }),
+        },
+    ));
+    // The world and the camera are shared between the threads, so they go
+    // behind an `Arc`.
+    let world = Arc::new(scene.world().unwrap());
+    let camera = Arc::new(
+        CameraBuilder::new(Vec3(0.0, 0.0, 0.0), Vec3(0.0, 0.0, -1.0), 2.0)
+            .build(&world.objects)
+            .unwrap(),
+    );
+    let filter = Arc::new(MitchellFilter {
+        radius: 2.0,
+        b: 1.0 / 3.0,
+        c: 1.0 / 3.0,
+    });
+    let mut film = Film::new(160, 80, filter);
 
     // Make a vector to hold the child-threads which we will spawn.
     let mut children = vec![];
@@ -22,75 +46,38 @@ fn main() {
     /*************************************************************************
      * "Map" phase
      *
-     * Divide our data into segments, and apply initial processing
+     * Divide the image into bands of rows, and render each one
      ************************************************************************/
 
-    // split our data into segments for individual calculation
-    // each chunk will be a reference (&str) into the actual data
-    let chunked_data = data.split_whitespace();
+    for (i, from) in (0..film.height).step_by(20).enumerate() {
+        println!("band {} starts at row {}", i, from);
 
-    // Iterate over the data segments.
-    // .enumerate() adds the current loop index to whatever is iterated
-    // the resulting tuple "(index, element)" is then immediately
-    // "destructured" into two variables, "i" and "data_segment" with a
-    // "destructuring assignment"
-    for (i, data_segment) in chunked_data.enumerate() {
-        println!("data segment {} is \"{}\"", i, data_segment);
+        // each thread gets its own tile of the film to render into, and its
+        // own handles to the world and the camera
+        let tile = film.tile(0, from, film.width, from + 20);
+        let world = world.clone();
+        let camera = camera.clone();
 
-        // Process each data segment in a separate thread
-        //
-        // spawn() returns a handle to the new thread,
-        // which we MUST keep to access the returned value
-        //
-        // 'move || -> u32' is syntax for a closure that:
-        // * takes no arguments ('||')
-        // * takes ownership of its captured variables ('move') and
-        // * returns an unsigned 32-bit integer ('-> u32')
-        //
-        // Rust is smart enough to infer the '-> u32' from
-        // the closure itself so we could have left that out.
-        //
-        // TODO: try removing the 'move' and see what happens
-        children.push(thread::spawn(move || -> u32 {
-            // Calculate the intermediate sum of this segment:
-            let result = data_segment
-                // iterate over the characters of our segment..
-                .chars()
-                // .. convert text-characters to their number value..
-                .map(|c| c.to_digit(10).expect("should be a digit"))
-                // .. and sum the resulting iterator of numbers
-                .sum();
-
-            // println! locks stdout, so no text-interleaving occurs
-            println!("processed segment {}, result={}", i, result);
-
-            // "return" not needed, because Rust is an "expression language", the
-            // last evaluated expression in each block is automatically its value.
-            result
+        // spawn() returns a handle to the new thread, which we MUST keep to
+        // get the rendered tile back
+        children.push(thread::spawn(move || {
+            let tile = render_tile(&world, &*camera, tile, 16, false, i as u64);
+            println!("rendered band {}", i);
+            tile
         }));
     }
 
     /*************************************************************************
      * "Reduce" phase
      *
-     * Collect our intermediate results, and combine them into a final result
+     * Collect the rendered tiles, and combine them into the final image
      ************************************************************************/
 
-    // collect each thread's intermediate results into a new Vec
-    let mut intermediate_sums = vec![];
     for child in children {
-        // collect each child thread's return-value
-        let intermediate_sum = child.join().unwrap();
-        intermediate_sums.push(intermediate_sum);
+        film.merge_tile(&child.join().unwrap());
     }
 
-    // combine all intermediate sums into a single final sum.
-    //
-    // we use the "turbofish" ::<> to provide sum() with a type hint.
-    //
-    // TODO: try without the turbofish, by instead explicitly
-    // specifying the type of final_result
-    let final_result = intermediate_sums.iter().sum::<u32>();
-
-    println!("Final sum result: {}", final_result);
+    let data = film.to_rgba8();
+    let brightness = data.iter().map(|&c| c as u64).sum::<u64>() / data.len() as u64;
+    println!("Average brightness: {}", brightness);
 }
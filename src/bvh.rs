@@ -0,0 +1,211 @@
+use crate::aabb::Aabb;
+use crate::float::Float;
+use crate::hittable::*;
+use crate::ray::*;
+
+enum BvhNode {
+    Leaf {
+        bbox: Aabb,
+        object: Box<dyn Hittable + Send + Sync>,
+    },
+    Branch {
+        bbox: Aabb,
+        left: Box<BvhNode>,
+        right: Box<BvhNode>,
+    },
+}
+
+impl BvhNode {
+    fn build(mut objects: Vec<(Aabb, Box<dyn Hittable + Send + Sync>)>) -> BvhNode {
+        if objects.len() == 1 {
+            let (bbox, object) = objects.pop().unwrap();
+            return BvhNode::Leaf { bbox, object };
+        }
+        // split at the median centroid along the axis where they spread most
+        let centroids = objects
+            .iter()
+            .map(|(b, _)| Aabb::new(b.centroid(), b.centroid()))
+            .fold(None, |acc: Option<Aabb>, b| {
+                Some(acc.map_or(b, |a| a.union(&b)))
+            })
+            .unwrap();
+        let extent = centroids.max - centroids.min;
+        let axis = if extent.x() > extent.y() && extent.x() > extent.z() {
+            0
+        } else if extent.y() > extent.z() {
+            1
+        } else {
+            2
+        };
+        objects.sort_by(|(a, _), (b, _)| {
+            a.centroid()[axis]
+                .partial_cmp(&b.centroid()[axis])
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        let right_objects = objects.split_off(objects.len() / 2);
+        let left = BvhNode::build(objects);
+        let right = BvhNode::build(right_objects);
+        BvhNode::Branch {
+            bbox: left.bbox().union(&right.bbox()),
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+
+    fn bbox(&self) -> Aabb {
+        match self {
+            BvhNode::Leaf { bbox, .. } => *bbox,
+            BvhNode::Branch { bbox, .. } => *bbox,
+        }
+    }
+
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        match self {
+            BvhNode::Leaf { bbox, object } => {
+                if !bbox.hit(ray, t_min, t_max) {
+                    return None;
+                }
+                object.hit(ray, t_min, t_max)
+            }
+            BvhNode::Branch { bbox, left, right } => {
+                if !bbox.hit(ray, t_min, t_max) {
+                    return None;
+                }
+                let left_hit = left.hit(ray, t_min, t_max);
+                let closest = left_hit.as_ref().map_or(t_max, |h| h.t);
+                right.hit(ray, t_min, closest).or(left_hit)
+            }
+        }
+    }
+}
+
+/// A binary bounding volume hierarchy over a set of objects. Objects without
+/// a bounding box, such as infinite planes, cannot be placed in the tree and
+/// are tested one by one instead.
+pub struct Bvh {
+    root: Option<BvhNode>,
+    unbounded: HittableList,
+}
+
+impl Bvh {
+    pub fn new(objects: Vec<Box<dyn Hittable + Send + Sync>>) -> Self {
+        let mut bounded = Vec::new();
+        let mut unbounded = HittableList::new();
+        for object in objects {
+            match object.bounding_box() {
+                Some(bbox) => bounded.push((bbox, object)),
+                None => unbounded.push_boxed(object),
+            }
+        }
+        Bvh {
+            root: if bounded.is_empty() {
+                None
+            } else {
+                Some(BvhNode::build(bounded))
+            },
+            unbounded,
+        }
+    }
+}
+
+impl Hittable for Bvh {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        let unbounded_hit = self.unbounded.hit(ray, t_min, t_max);
+        let closest = unbounded_hit.as_ref().map_or(t_max, |h| h.t);
+        let root_hit = self.root.as_ref().and_then(|r| r.hit(ray, t_min, closest));
+        root_hit.or(unbounded_hit)
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        if self.unbounded.is_empty() {
+            self.root.as_ref().map(|r| r.bbox())
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::Material;
+    use crate::shapes::Plane;
+    use crate::vec3::*;
+    use approx::assert_relative_eq;
+    use rand::rngs::StdRng;
+    use rand::Rng;
+    use rand::SeedableRng;
+
+    struct TestMaterial {}
+    impl Material for TestMaterial {}
+
+    fn random_spheres(n: usize) -> Vec<(Vec3, Float)> {
+        let mut rng = StdRng::seed_from_u64(1);
+        (0..n)
+            .map(|_| {
+                (
+                    Vec3(
+                        rng.gen::<Float>() * 10.0 - 5.0,
+                        rng.gen::<Float>() * 10.0 - 5.0,
+                        rng.gen::<Float>() * 10.0 - 5.0,
+                    ),
+                    0.1 + rng.gen::<Float>() * 0.5,
+                )
+            })
+            .collect()
+    }
+
+    fn sphere(center: Vec3, radius: Float) -> Sphere {
+        Sphere {
+            center,
+            radius,
+            material: Box::new(TestMaterial {}),
+        }
+    }
+
+    #[test]
+    fn bvh_matches_linear_list() {
+        let spheres = random_spheres(200);
+        let mut list = HittableList::new();
+        let mut objects: Vec<Box<dyn Hittable + Send + Sync>> = Vec::new();
+        for (c, r) in &spheres {
+            list.push(sphere(*c, *r));
+            objects.push(Box::new(sphere(*c, *r)));
+        }
+        let bvh = Bvh::new(objects);
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..500 {
+            let ray = Ray::new(
+                Vec3(0.0, 0.0, -20.0),
+                Vec3(rng.gen::<Float>() - 0.5, rng.gen::<Float>() - 0.5, 1.0),
+            );
+            let expected = list.hit(&ray, 0.001, Float::MAX).map(|h| h.t);
+            let actual = bvh.hit(&ray, 0.001, Float::MAX).map(|h| h.t);
+            assert_eq!(expected, actual);
+        }
+        assert_eq!(bvh.bounding_box(), list.bounding_box());
+    }
+
+    #[test]
+    fn unbounded_objects_are_kept() {
+        let objects: Vec<Box<dyn Hittable + Send + Sync>> = vec![
+            Box::new(sphere(Vec3(0.0, 1.0, 0.0), 0.5)),
+            Box::new(Plane {
+                point: Vec3(0.0, 0.0, 0.0),
+                normal: Vec3(0.0, 1.0, 0.0),
+                material: Box::new(TestMaterial {}),
+            }),
+        ];
+        let bvh = Bvh::new(objects);
+        assert!(bvh.bounding_box().is_none());
+        let down = |x: Float| Ray::new(Vec3(x, 5.0, 0.0), Vec3(0.0, -1.0, 0.0));
+        assert_relative_eq!(bvh.hit(&down(0.0), 0.001, Float::MAX).unwrap().t, 3.5);
+        assert_relative_eq!(bvh.hit(&down(2.0), 0.001, Float::MAX).unwrap().t, 5.0);
+    }
+
+    #[test]
+    fn empty_bvh_misses() {
+        let bvh = Bvh::new(Vec::new());
+        let ray = Ray::new(Vec3(0.0, 0.0, 0.0), Vec3(0.0, 0.0, 1.0));
+        assert!(bvh.hit(&ray, 0.001, Float::MAX).is_none());
+    }
+}
@@ -1,51 +1,263 @@
+use crate::error::{Error, Result};
+use crate::float::consts::PI;
+use crate::float::Float;
+use crate::hittable::*;
+use crate::materials::refract;
+use crate::motion::Keyframes;
 use crate::ray::*;
+use crate::spectrum::*;
 use crate::vec3::*;
-use rand::rngs::ThreadRng;
+use rand::rngs::StdRng;
 use rand::Rng;
 
+pub trait Camera {
+    /// The ray through image position (s, t), both in [0, 1] with t = 0 at
+    /// the bottom. None where the image is not covered by the projection,
+    /// such as outside the circle of a fisheye image.
+    fn get_ray(&self, s: Float, t: Float, rng: &mut StdRng) -> Option<Ray>;
+
+    /// How much of the light arriving along a ray from `get_ray` reaches the
+    /// film, for exposure and vignetting.
+    fn weight(&self, _ray: &Ray) -> Float {
+        1.0
+    }
+
+    /// A ray from `get_ray` with its `weight`, which is what renders ask
+    /// for. Cameras made of others, such as stereo pairs, override it to
+    /// weight each ray by the camera that made it.
+    fn weighted_ray(&self, s: Float, t: Float, rng: &mut StdRng) -> Option<(Ray, Float)> {
+        let ray = self.get_ray(s, t, rng)?;
+        let weight = self.weight(&ray);
+        Some((ray, weight))
+    }
+}
+
+/// The interval over which rays are spread in time, for motion blur. Every
+/// camera has one in its `shutter` field, closed at time 0 by default; rays
+/// are spread uniformly over the time it is open.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Shutter {
+    pub open: Float,
+    pub close: Float,
+}
+
+impl Shutter {
+    pub fn sample(&self, rng: &mut StdRng) -> Float {
+        self.open + rng.gen::<Float>() * (self.close - self.open)
+    }
+}
+
+/// The time the shutter is open, in seconds, and the film speed. With the
+/// f-number of the lens these scale the light reaching the film; at f/1,
+/// 1 s and ISO 100 (exposure value 0) radiance is recorded as it is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Exposure {
+    pub shutter_time: Float,
+    pub iso: Float,
+}
+
+impl Exposure {
+    pub fn scale(&self, f_number: Float) -> Float {
+        self.shutter_time * self.iso / (100.0 * f_number * f_number)
+    }
+}
+
+/// A grayscale image of the aperture, for bokeh shaped like it. `values`
+/// holds `width` x `height` weights row by row from the top, at least one
+/// of them positive.
+#[derive(Debug, Clone)]
+pub struct BokehImage {
+    width: usize,
+    height: usize,
+    // running sum of the weights, for picking pixels in proportion to them
+    cdf: Vec<Float>,
+}
+
+impl BokehImage {
+    pub fn new(width: usize, height: usize, values: &[Float]) -> Result<Self> {
+        if width == 0 || height == 0 || values.len() != width * height {
+            return Err(Error::Camera(format!(
+                "a {} x {} aperture image needs {} values, not {}",
+                width,
+                height,
+                width * height,
+                values.len()
+            )));
+        }
+        if !values.iter().any(|v| *v > 0.0) {
+            return Err(Error::Camera(
+                "an aperture image needs an open pixel".to_string(),
+            ));
+        }
+        let mut sum = 0.0;
+        let cdf = values
+            .iter()
+            .map(|v| {
+                sum += v.max(0.0);
+                sum
+            })
+            .collect();
+        Ok(BokehImage { width, height, cdf })
+    }
+
+    // a point in [-1, 1] x [-1, 1], distributed like the image
+    fn sample(&self, rng: &mut StdRng) -> (Float, Float) {
+        let total = self.cdf.last().copied().unwrap_or(0.0);
+        let target = rng.gen::<Float>() * total;
+        let index = self
+            .cdf
+            .partition_point(|c| *c <= target)
+            .min(self.cdf.len() - 1);
+        let x = (index % self.width) as Float + rng.gen::<Float>();
+        let y = (index / self.width) as Float + rng.gen::<Float>();
+        (
+            2.0 * x / self.width as Float - 1.0,
+            1.0 - 2.0 * y / self.height as Float,
+        )
+    }
+}
+
+/// The shape of a lens opening, which out-of-focus highlights take on.
+#[derive(Debug, Clone)]
+pub enum ApertureShape {
+    Circle,
+    /// a regular polygon formed by the diaphragm's blades, rotated by
+    /// `rotation` degrees; made with `polygon`, which checks the blades
+    Polygon {
+        blades: usize,
+        rotation: Float,
+    },
+    Image(BokehImage),
+}
+
+impl ApertureShape {
+    pub fn polygon(blades: usize, rotation: Float) -> Result<Self> {
+        let shape = ApertureShape::Polygon { blades, rotation };
+        shape.check()?;
+        Ok(shape)
+    }
+
+    // an error for a polygon the blades can't form
+    fn check(&self) -> Result<()> {
+        match self {
+            ApertureShape::Polygon { blades, .. } if *blades < 3 => Err(Error::Camera(format!(
+                "a diaphragm needs at least 3 blades, not {}",
+                blades
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    // a uniform point on the aperture, scaled to fit the unit circle (or
+    // square, for an image)
+    fn sample(&self, rng: &mut StdRng) -> (Float, Float) {
+        match self {
+            ApertureShape::Circle => loop {
+                let x = 2.0 * rng.gen::<Float>() - 1.0;
+                let y = 2.0 * rng.gen::<Float>() - 1.0;
+                if x * x + y * y < 1.0 {
+                    return (x, y);
+                }
+            },
+            ApertureShape::Polygon { blades, rotation } => {
+                // the polygon is a fan of equal triangles around the center
+                let step = 2.0 * PI / *blades as Float;
+                let i = rng.gen_range(0, *blades) as Float;
+                let a0 = rotation * PI / 180.0 + i * step;
+                let a1 = a0 + step;
+                let r = rng.gen::<Float>().sqrt();
+                let b = rng.gen::<Float>();
+                (
+                    r * ((1.0 - b) * a0.cos() + b * a1.cos()),
+                    r * ((1.0 - b) * a0.sin() + b * a1.sin()),
+                )
+            }
+            ApertureShape::Image(image) => image.sample(rng),
+        }
+    }
+}
+
+// right, up and backwards unit vectors of a camera at `origin` looking at
+// `look_at`
+fn frame(origin: Vec3, look_at: Vec3, up: Vec3) -> (Vec3, Vec3, Vec3) {
+    let w = (origin - look_at).normalized();
+    let u = up.cross(w).normalized();
+    let v = w.cross(u).normalized();
+    (u, v, w)
+}
+
+fn ray(pos: Vec3, dir: Vec3, time: Float) -> Option<Ray> {
+    Some(Ray {
+        pos,
+        dir,
+        time,
+        wavelength: None,
+    })
+}
+
+/// A perspective camera with a thin lens for depth of field.
 #[derive(Debug)]
-pub struct Camera {
+pub struct ThinLensCamera {
     pub origin: Vec3,
     pub horizontal: Vec3,
     pub vertical: Vec3,
     pub lower_left: Vec3,
-    pub lens_radius: f64,
+    pub lens_radius: Float,
     pub u: Vec3,
     pub v: Vec3,
+    pub w: Vec3,
+    pub shutter: Shutter,
+    pub aperture_shape: ApertureShape,
+    /// scale for the light reaching the film, from `Exposure`
+    pub exposure: Float,
+    /// darken the image towards its corners by the cos^4 law
+    pub vignetting: bool,
+    /// Lateral chromatic aberration: the difference in magnification
+    /// between the red and the blue end of the visible range. Each ray then
+    /// carries a single wavelength.
+    pub chromatic_aberration: Float,
 }
 
-impl Camera {
-    fn random_in_unit_disk(rng: &mut ThreadRng) -> Vec3 {
-        loop {
-            let p = 2.0 * Vec3(rng.gen::<f64>() - 0.5, rng.gen::<f64>() - 0.5, 0.0);
-            if p.squared_length() < 1.0 {
-                return p;
-            }
-        }
-    }
-
+impl ThinLensCamera {
+    /// `fov` is the horizontal field of view in degrees and `focus` the
+    /// focus distance as a fraction of the distance to `look_at`; see
+    /// `CameraBuilder` for other ways to set them up.
     pub fn new(
         origin: Vec3,
         look_at: Vec3,
         up: Vec3,
-        fov: f64,
-        aspect: f64,
-        aperture: f64,
-        focus: f64,
-    ) -> Camera {
-        let theta = fov * std::f64::consts::PI / 180.0;
+        fov: Float,
+        aspect: Float,
+        aperture: Float,
+        focus: Float,
+    ) -> ThinLensCamera {
+        let theta = fov * PI / 180.0;
         let half_width = (0.5 * theta).tan();
         let half_height = half_width / aspect;
-        // let theta = vfov * std::f64::consts::PI / 180.0;
-        // let half_height = (theta * 0.5).tan();
-        // let half_width = aspect * half_height;
-        let w = (origin - look_at).normalized();
-        let u = up.cross(w).normalized();
-        let v = w.cross(u).normalized();
-
         let focus_dist = focus * (look_at - origin).length();
+        Self::with_view(
+            origin,
+            look_at,
+            up,
+            (half_width, half_height),
+            aperture,
+            focus_dist,
+        )
+    }
 
-        Camera {
+    // `half_size` is the half width and height of the image at unit
+    // distance from the lens
+    fn with_view(
+        origin: Vec3,
+        look_at: Vec3,
+        up: Vec3,
+        half_size: (Float, Float),
+        aperture: Float,
+        focus_dist: Float,
+    ) -> ThinLensCamera {
+        let (half_width, half_height) = half_size;
+        let (u, v, w) = frame(origin, look_at, up);
+        ThinLensCamera {
             origin,
             horizontal: 2.0 * half_width * focus_dist * u,
             vertical: 2.0 * half_height * focus_dist * v,
@@ -56,14 +268,1201 @@ impl Camera {
             lens_radius: 0.5 * aperture,
             u,
             v,
+            w,
+            shutter: Shutter::default(),
+            aperture_shape: ApertureShape::Circle,
+            exposure: 1.0,
+            vignetting: false,
+            chromatic_aberration: 0.0,
         }
     }
-    pub fn get_ray(&self, s: f64, t: f64, rng: &mut ThreadRng) -> Ray {
-        let rd = self.lens_radius * Self::random_in_unit_disk(rng);
-        let offset = self.u * rd.x() + self.v * rd.y();
-        Ray {
+}
+
+impl Camera for ThinLensCamera {
+    fn get_ray(&self, s: Float, t: Float, rng: &mut StdRng) -> Option<Ray> {
+        let (s, t, wavelength) = if self.chromatic_aberration != 0.0 {
+            let range = LAMBDA_MAX - LAMBDA_MIN;
+            let lambda = LAMBDA_MIN + rng.gen::<Float>() * range;
+            // the image is magnified about its center, more so for red
+            let scale = 1.0 + self.chromatic_aberration * ((lambda - LAMBDA_MIN) / range - 0.5);
+            (
+                0.5 + (s - 0.5) / scale,
+                0.5 + (t - 0.5) / scale,
+                Some(lambda),
+            )
+        } else {
+            (s, t, None)
+        };
+        let (x, y) = self.aperture_shape.sample(rng);
+        let offset = self.lens_radius * (x * self.u + y * self.v);
+        Some(Ray {
             pos: self.origin + offset,
             dir: self.lower_left + s * self.horizontal + t * self.vertical - self.origin - offset,
+            time: self.shutter.sample(rng),
+            wavelength,
+        })
+    }
+
+    fn weight(&self, ray: &Ray) -> Float {
+        if self.vignetting {
+            let cos = -ray.dir.normalized().dot(self.w);
+            self.exposure * (cos * cos) * (cos * cos)
+        } else {
+            self.exposure
+        }
+    }
+}
+
+/// The angle of view of a perspective camera, in degrees, or as a lens'
+/// focal length and a sensor size in millimeters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldOfView {
+    Vertical(Float),
+    Horizontal(Float),
+    Diagonal(Float),
+    /// When the sensor's aspect ratio differs from the image's, the image
+    /// is cropped from the sensor, so it fills the sensor in one direction.
+    FocalLength {
+        focal_length: Float,
+        sensor_width: Float,
+        sensor_height: Float,
+    },
+}
+
+impl FieldOfView {
+    // half width and height of the image at unit distance
+    fn half_size(&self, aspect: Float) -> (Float, Float) {
+        let half_tan = |degrees: Float| (0.5 * degrees * PI / 180.0).tan();
+        match *self {
+            FieldOfView::Vertical(fov) => {
+                let half_height = half_tan(fov);
+                (half_height * aspect, half_height)
+            }
+            FieldOfView::Horizontal(fov) => {
+                let half_width = half_tan(fov);
+                (half_width, half_width / aspect)
+            }
+            FieldOfView::Diagonal(fov) => {
+                let half_height = half_tan(fov) / (1.0 + aspect * aspect).sqrt();
+                (half_height * aspect, half_height)
+            }
+            FieldOfView::FocalLength {
+                focal_length,
+                sensor_width,
+                sensor_height,
+            } => {
+                let half_width = 0.5 * sensor_width / focal_length;
+                let half_height = 0.5 * sensor_height / focal_length;
+                if aspect > sensor_width / sensor_height {
+                    (half_width, half_width / aspect)
+                } else {
+                    (half_height * aspect, half_height)
+                }
+            }
+        }
+    }
+}
+
+/// Where a perspective camera focuses.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Focus {
+    /// on the look-at point
+    LookAt,
+    /// at a distance along the view direction
+    Distance(Float),
+    /// Autofocus: on the first surface on the way from the camera to this
+    /// point, or on the point itself if nothing is in the way.
+    Target(Vec3),
+}
+
+/// Sets up a thin lens camera.
+#[derive(Debug, Clone)]
+pub struct CameraBuilder {
+    pub origin: Vec3,
+    pub look_at: Vec3,
+    pub up: Vec3,
+    pub aspect: Float,
+    pub fov: FieldOfView,
+    pub aperture: Float,
+    /// sets the aperture from the focal length instead, if given
+    pub f_number: Option<Float>,
+    pub aperture_shape: ApertureShape,
+    pub focus: Focus,
+    pub shutter: Shutter,
+    pub exposure: Option<Exposure>,
+    pub vignetting: bool,
+    pub chromatic_aberration: Float,
+}
+
+impl CameraBuilder {
+    pub fn new(origin: Vec3, look_at: Vec3, aspect: Float) -> Self {
+        CameraBuilder {
+            origin,
+            look_at,
+            up: Vec3(0.0, 1.0, 0.0),
+            aspect,
+            fov: FieldOfView::Vertical(40.0),
+            aperture: 0.0,
+            f_number: None,
+            aperture_shape: ApertureShape::Circle,
+            focus: Focus::LookAt,
+            shutter: Shutter::default(),
+            exposure: None,
+            vignetting: false,
+            chromatic_aberration: 0.0,
+        }
+    }
+
+    pub fn up(mut self, up: Vec3) -> Self {
+        self.up = up;
+        self
+    }
+
+    pub fn fov(mut self, fov: FieldOfView) -> Self {
+        self.fov = fov;
+        self
+    }
+
+    pub fn aperture(mut self, aperture: Float) -> Self {
+        self.aperture = aperture;
+        self
+    }
+
+    pub fn f_number(mut self, f_number: Float) -> Self {
+        self.f_number = Some(f_number);
+        self
+    }
+
+    pub fn aperture_shape(mut self, shape: ApertureShape) -> Self {
+        self.aperture_shape = shape;
+        self
+    }
+
+    pub fn exposure(mut self, shutter_time: Float, iso: Float) -> Self {
+        self.exposure = Some(Exposure { shutter_time, iso });
+        self
+    }
+
+    pub fn vignetting(mut self, vignetting: bool) -> Self {
+        self.vignetting = vignetting;
+        self
+    }
+
+    pub fn chromatic_aberration(mut self, amount: Float) -> Self {
+        self.chromatic_aberration = amount;
+        self
+    }
+
+    pub fn focus(mut self, focus: Focus) -> Self {
+        self.focus = focus;
+        self
+    }
+
+    pub fn shutter(mut self, open: Float, close: Float) -> Self {
+        self.shutter = Shutter { open, close };
+        self
+    }
+
+    /// the distance from the lens to the plane in focus; `world` is only
+    /// looked at for autofocus
+    pub fn focus_distance(&self, world: &dyn Hittable) -> Float {
+        let forward = (self.look_at - self.origin).normalized();
+        let target = match self.focus {
+            Focus::LookAt => self.look_at,
+            Focus::Distance(distance) => return distance,
+            Focus::Target(target) => {
+                let ray = Ray {
+                    pos: self.origin,
+                    dir: target - self.origin,
+                    time: self.shutter.open,
+                    wavelength: None,
+                };
+                // the target is at t = 1, so surfaces behind it don't count
+                match world.hit(&ray, 0.001, 1.0) {
+                    Some(hit) => hit.point,
+                    None => target,
+                }
+            }
+        };
+        (target - self.origin).dot(forward)
+    }
+
+    /// The focal length of the lens in scene units, taking these to be
+    /// meters. Angles of view are converted as the focal length giving the
+    /// same diagonal view on a 36 x 24 mm sensor.
+    pub fn focal_length(&self) -> Float {
+        let millimeters = match self.fov {
+            FieldOfView::FocalLength { focal_length, .. } => focal_length,
+            fov => {
+                let (half_width, half_height) = fov.half_size(self.aspect);
+                let half_diagonal = 0.5 * (36.0 as Float).hypot(24.0);
+                half_diagonal / half_width.hypot(half_height)
+            }
+        };
+        0.001 * millimeters
+    }
+
+    /// focal length over aperture diameter, or None for a pinhole
+    pub fn lens_f_number(&self) -> Option<Float> {
+        match self.f_number {
+            Some(f_number) => Some(f_number),
+            None if self.aperture > 0.0 => Some(self.focal_length() / self.aperture),
+            None => None,
+        }
+    }
+
+    /// The camera, or an error for settings it can't be built from: a
+    /// diaphragm of fewer than 3 blades, or an exposure for a pinhole, which
+    /// lets no light through.
+    pub fn build(&self, world: &dyn Hittable) -> Result<ThinLensCamera> {
+        self.aperture_shape.check()?;
+        let aperture = match self.f_number {
+            Some(f_number) => self.focal_length() / f_number,
+            None => self.aperture,
+        };
+        let mut camera = ThinLensCamera::with_view(
+            self.origin,
+            self.look_at,
+            self.up,
+            self.fov.half_size(self.aspect),
+            aperture,
+            self.focus_distance(world),
+        );
+        camera.shutter = self.shutter;
+        camera.aperture_shape = self.aperture_shape.clone();
+        if let Some(exposure) = self.exposure {
+            let f_number = self.lens_f_number().ok_or_else(|| {
+                Error::Camera("an exposure needs an aperture or an f-number".to_string())
+            })?;
+            camera.exposure = exposure.scale(f_number);
+        }
+        camera.vignetting = self.vignetting;
+        camera.chromatic_aberration = self.chromatic_aberration;
+        Ok(camera)
+    }
+}
+
+/// Keyframed camera settings, applied over a `CameraBuilder` for each
+/// frame of an animation. Settings without keys keep the builder's value.
+/// The camera is placed once per frame, so it doesn't blur as it moves.
+#[derive(Default)]
+pub struct CameraAnimation {
+    pub position: Option<Keyframes<Vec3>>,
+    pub look_at: Option<Keyframes<Vec3>>,
+    /// the angle of the builder's field of view in degrees, or its focal
+    /// length in mm
+    pub fov: Option<Keyframes<Float>>,
+    pub focus_distance: Option<Keyframes<Float>>,
+    pub aperture: Option<Keyframes<Float>>,
+}
+
+impl CameraAnimation {
+    pub fn at(&self, base: &CameraBuilder, time: Float) -> CameraBuilder {
+        let mut builder = base.clone();
+        if let Some(keys) = &self.position {
+            builder.origin = keys.at(time);
+        }
+        if let Some(keys) = &self.look_at {
+            builder.look_at = keys.at(time);
+        }
+        if let Some(keys) = &self.fov {
+            let value = keys.at(time);
+            builder.fov = match builder.fov {
+                FieldOfView::Vertical(_) => FieldOfView::Vertical(value),
+                FieldOfView::Horizontal(_) => FieldOfView::Horizontal(value),
+                FieldOfView::Diagonal(_) => FieldOfView::Diagonal(value),
+                FieldOfView::FocalLength {
+                    sensor_width,
+                    sensor_height,
+                    ..
+                } => FieldOfView::FocalLength {
+                    focal_length: value,
+                    sensor_width,
+                    sensor_height,
+                },
+            };
+        }
+        if let Some(keys) = &self.focus_distance {
+            builder.focus = Focus::Distance(keys.at(time));
+        }
+        if let Some(keys) = &self.aperture {
+            builder.aperture = keys.at(time);
+            builder.f_number = None;
+        }
+        builder
+    }
+}
+
+/// One surface of a lens prescription, in millimeters: the radius of
+/// curvature (positive when the center is towards the film, 0 for the
+/// aperture stop), the distance to the next surface towards the film, the
+/// index of refraction up to that surface (0 for air) and the diameter of
+/// the opening.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LensElement {
+    pub radius: Float,
+    pub thickness: Float,
+    pub ior: Float,
+    pub aperture: Float,
+}
+
+impl LensElement {
+    fn index(&self) -> Float {
+        if self.ior == 0.0 {
+            1.0
+        } else {
+            self.ior
+        }
+    }
+
+    // Where a ray meets the surface with its vertex at `z`, and the surface
+    // normal there facing the ray. None if it misses or the ray passes
+    // outside the opening.
+    fn intersect(&self, z: Float, pos: Vec3, dir: Vec3) -> Option<(Vec3, Vec3)> {
+        let (t, normal) = if self.radius == 0.0 {
+            ((z - pos.z()) / dir.z(), Vec3(0.0, 0.0, -dir.z().signum()))
+        } else {
+            let center = Vec3(0.0, 0.0, z - self.radius);
+            let oc = pos - center;
+            let a = dir.dot(dir);
+            let b = oc.dot(dir);
+            let c = oc.dot(oc) - self.radius * self.radius;
+            let discriminant = b * b - a * c;
+            if discriminant < 0.0 {
+                return None;
+            }
+            // the vertex side of the sphere is the nearer one for rays
+            // heading towards its center
+            let closer = (dir.z() < 0.0) != (self.radius < 0.0);
+            let t = if closer {
+                (-b - discriminant.sqrt()) / a
+            } else {
+                (-b + discriminant.sqrt()) / a
+            };
+            let n = (pos + t * dir - center).normalized();
+            (t, if n.dot(dir) > 0.0 { -n } else { n })
+        };
+        let p = pos + t * dir;
+        let r = 0.5 * self.aperture;
+        if t <= 0.0 || p.x() * p.x() + p.y() * p.y() > r * r {
+            return None;
+        }
+        Some((p, normal))
+    }
+}
+
+// radial bands of the film the exit pupil is bounded for, and how many
+// film positions and points on the rear element are tried for each
+const PUPIL_BANDS: usize = 32;
+const PUPIL_FILM_SAMPLES: usize = 8;
+const PUPIL_REAR_SAMPLES: usize = 64;
+
+/// A camera that traces rays through a real lens design, for its defocus,
+/// distortion and vignetting. The lens is described front to back by its
+/// prescription, with millimeters in the lens and meters in the scene; the
+/// film sits behind the last surface, at the distance that focuses the
+/// lens at `focus_distance` meters. Weights are scaled by the exit pupil at
+/// the center of the film, keeping the image about as bright as with a
+/// thin lens.
+#[derive(Debug)]
+pub struct RealisticCamera {
+    pub origin: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+    pub elements: Vec<LensElement>,
+    /// film size in millimeters
+    pub film_width: Float,
+    pub film_height: Float,
+    pub shutter: Shutter,
+    // For each radial band of the film, the bounds (x0, y0, x1, y1) on the
+    // rear element of the rays that get through the lens, seen from film
+    // points on the x axis; rays are only sampled inside these.
+    exit_pupil: Vec<Option<(Float, Float, Float, Float)>>,
+    center_area: Float,
+}
+
+impl RealisticCamera {
+    /// `film_diagonal` is in millimeters
+    pub fn new(
+        origin: Vec3,
+        look_at: Vec3,
+        up: Vec3,
+        elements: Vec<LensElement>,
+        film_diagonal: Float,
+        aspect: Float,
+        focus_distance: Float,
+    ) -> Self {
+        let (u, v, w) = frame(origin, look_at, up);
+        let film_height = film_diagonal / (1.0 + aspect * aspect).sqrt();
+        let mut camera = RealisticCamera {
+            origin,
+            u,
+            v,
+            w,
+            elements,
+            film_width: film_height * aspect,
+            film_height,
+            shutter: Shutter::default(),
+            exit_pupil: vec![],
+            center_area: 1.0,
+        };
+        camera.focus(1000.0 * focus_distance);
+        camera.exit_pupil = (0..PUPIL_BANDS)
+            .map(|i| camera.bound_exit_pupil(i))
+            .collect();
+        camera.center_area = match camera.exit_pupil[0] {
+            Some((x0, y0, x1, y1)) => (x1 - x0) * (y1 - y0),
+            None => 1.0,
+        };
+        camera
+    }
+
+    fn rear_z(&self) -> Float {
+        self.elements.last().map_or(0.0, |e| e.thickness)
+    }
+
+    fn front_z(&self) -> Float {
+        self.elements.iter().map(|e| e.thickness).sum()
+    }
+
+    // Lens space has the film at z = 0 and the lens towards +z, in
+    // millimeters. These trace a ray through all the surfaces, returning
+    // it as it leaves the lens, or None if it is blocked.
+    fn trace_from_film(&self, mut pos: Vec3, mut dir: Vec3) -> Option<(Vec3, Vec3)> {
+        let mut z = 0.0;
+        for i in (0..self.elements.len()).rev() {
+            let element = &self.elements[i];
+            z += element.thickness;
+            let (p, normal) = element.intersect(z, pos, dir)?;
+            pos = p;
+            if element.radius != 0.0 {
+                let outside = if i > 0 {
+                    self.elements[i - 1].index()
+                } else {
+                    1.0
+                };
+                dir = refract(&dir, &normal, element.index() / outside)?;
+            }
+        }
+        Some((pos, dir))
+    }
+
+    fn trace_from_scene(&self, mut pos: Vec3, mut dir: Vec3) -> Option<(Vec3, Vec3)> {
+        let mut z = self.front_z();
+        for (i, element) in self.elements.iter().enumerate() {
+            let (p, normal) = element.intersect(z, pos, dir)?;
+            pos = p;
+            if element.radius != 0.0 {
+                let outside = if i > 0 {
+                    self.elements[i - 1].index()
+                } else {
+                    1.0
+                };
+                dir = refract(&dir, &normal, outside / element.index())?;
+            }
+            z -= element.thickness;
+        }
+        Some((pos, dir))
+    }
+
+    // The principal plane and focal point of the lens, seen from the film
+    // if `rear` and from the scene otherwise, found with a ray parallel to
+    // the axis.
+    fn cardinal_points(&self, rear: bool) -> Option<(Float, Float)> {
+        let height = 0.001 * self.film_width.hypot(self.film_height);
+        let (o, d) = if rear {
+            let start = Vec3(height, 0.0, self.front_z() + 1.0);
+            self.trace_from_scene(start, Vec3(0.0, 0.0, -1.0))?
+        } else {
+            self.trace_from_film(Vec3(height, 0.0, 0.0), Vec3(0.0, 0.0, 1.0))?
+        };
+        let principal = o.z() + (height - o.x()) / d.x() * d.z();
+        let focal = o.z() - o.x() / d.x() * d.z();
+        Some((principal, focal))
+    }
+
+    /// the focal length in millimeters, from the thick lens approximation
+    pub fn focal_length(&self) -> Option<Float> {
+        let (principal, focal) = self.cardinal_points(true)?;
+        Some(principal - focal)
+    }
+
+    // Moves the film so that the lens focuses at `distance` mm from it,
+    // using the thick lens approximation.
+    fn focus(&mut self, distance: Float) {
+        let (front, rear) = match (self.cardinal_points(false), self.cardinal_points(true)) {
+            (Some((front, _)), Some((rear, focal))) => (front, (rear, rear - focal)),
+            _ => return,
+        };
+        let (rear, focal_length) = rear;
+        // with the lens moved by `delta`, the object is `distance - front -
+        // delta` from the front principal plane and the film `rear + delta`
+        // from the rear one; their sum is fixed, so the thin lens equation
+        // is a quadratic in the image distance
+        let sum = distance - front + rear;
+        let image = 0.5 * (sum - (sum * (sum - 4.0 * focal_length)).max(0.0).sqrt());
+        if let Some(last) = self.elements.last_mut() {
+            last.thickness += image - rear;
+        }
+    }
+
+    // bounds of the exit pupil for film points in radial band `band`
+    fn bound_exit_pupil(&self, band: usize) -> Option<(Float, Float, Float, Float)> {
+        let half_diagonal = 0.5 * self.film_width.hypot(self.film_height);
+        let rear_radius = 0.5 * self.elements.last()?.aperture;
+        // the rear element is tried over a square somewhat larger than it,
+        // as rays leaving the film at an angle can still make it through
+        let extent = 1.5 * rear_radius;
+        let step = 2.0 * extent / PUPIL_REAR_SAMPLES as Float;
+        let rear_z = self.rear_z();
+        let mut bounds: Option<(Float, Float, Float, Float)> = None;
+        for i in 0..PUPIL_FILM_SAMPLES {
+            let r = (band as Float + (i as Float + 0.5) / PUPIL_FILM_SAMPLES as Float)
+                / PUPIL_BANDS as Float
+                * half_diagonal;
+            let film = Vec3(r, 0.0, 0.0);
+            for j in 0..PUPIL_REAR_SAMPLES * PUPIL_REAR_SAMPLES {
+                let x = -extent + ((j % PUPIL_REAR_SAMPLES) as Float + 0.5) * step;
+                let y = -extent + ((j / PUPIL_REAR_SAMPLES) as Float + 0.5) * step;
+                let inside = bounds.is_some_and(|(x0, y0, x1, y1)| {
+                    (x0..=x1).contains(&x) && (y0..=y1).contains(&y)
+                });
+                if inside
+                    || self
+                        .trace_from_film(film, Vec3(x, y, rear_z) - film)
+                        .is_none()
+                {
+                    continue;
+                }
+                bounds = Some(match bounds {
+                    Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+                    None => (x, y, x, y),
+                });
+            }
+        }
+        // a sample spacing of margin for the rays between samples
+        bounds.map(|(x0, y0, x1, y1)| (x0 - step, y0 - step, x1 + step, y1 + step))
+    }
+
+    fn band(&self, film: Vec3) -> usize {
+        let half_diagonal = 0.5 * self.film_width.hypot(self.film_height);
+        let r = film.x().hypot(film.y());
+        ((r / half_diagonal * PUPIL_BANDS as Float) as usize).min(PUPIL_BANDS - 1)
+    }
+
+    fn to_world(&self, p: Vec3) -> Vec3 {
+        p.x() * self.u + p.y() * self.v - p.z() * self.w
+    }
+
+    fn to_lens(&self, p: Vec3) -> Vec3 {
+        Vec3(p.dot(self.u), p.dot(self.v), -p.dot(self.w))
+    }
+}
+
+impl Camera for RealisticCamera {
+    fn get_ray(&self, s: Float, t: Float, rng: &mut StdRng) -> Option<Ray> {
+        // the lens turns the image upside down
+        let film = Vec3(
+            (0.5 - s) * self.film_width,
+            (0.5 - t) * self.film_height,
+            0.0,
+        );
+        let (x0, y0, x1, y1) = self.exit_pupil[self.band(film)]?;
+        let x = x0 + rng.gen::<Float>() * (x1 - x0);
+        let y = y0 + rng.gen::<Float>() * (y1 - y0);
+        // the pupil was bounded for film points on the x axis
+        let r = film.x().hypot(film.y());
+        let (cos, sin) = if r > 0.0 {
+            (film.x() / r, film.y() / r)
+        } else {
+            (1.0, 0.0)
+        };
+        let rear = Vec3(cos * x - sin * y, sin * x + cos * y, self.rear_z());
+        let (pos, dir) = self.trace_from_film(film, rear - film)?;
+        ray(
+            self.origin + 0.001 * self.to_world(pos),
+            self.to_world(dir),
+            self.shutter.sample(rng),
+        )
+    }
+
+    // The light reaching the film falls off with the cos^4 of the angle it
+    // arrives at and with the size of the exit pupil. The ray is traced
+    // back through the lens to find where it lands.
+    fn weight(&self, ray: &Ray) -> Float {
+        // start a little in front of the lens, where the ray left it
+        let dir = self.to_lens(ray.dir).normalized();
+        let pos = self.to_lens(1000.0 * (ray.pos - self.origin)) + dir;
+        let (rear, dir) = match self.trace_from_scene(pos, -dir) {
+            Some(traced) => traced,
+            None => return 0.0,
+        };
+        let film = rear - rear.z() / dir.z() * dir;
+        let area = match self.exit_pupil[self.band(film)] {
+            Some((x0, y0, x1, y1)) => (x1 - x0) * (y1 - y0),
+            None => return 0.0,
+        };
+        let cos = dir.normalized().z();
+        (cos * cos) * (cos * cos) * area / self.center_area
+    }
+}
+
+/// Parallel rays from a `width` wide rectangle centered on `origin`.
+#[derive(Debug)]
+pub struct OrthographicCamera {
+    pub lower_left: Vec3,
+    pub horizontal: Vec3,
+    pub vertical: Vec3,
+    pub dir: Vec3,
+    pub shutter: Shutter,
+}
+
+impl OrthographicCamera {
+    pub fn new(origin: Vec3, look_at: Vec3, up: Vec3, width: Float, aspect: Float) -> Self {
+        let (u, v, w) = frame(origin, look_at, up);
+        let height = width / aspect;
+        OrthographicCamera {
+            lower_left: origin - 0.5 * width * u - 0.5 * height * v,
+            horizontal: width * u,
+            vertical: height * v,
+            dir: -w,
+            shutter: Shutter::default(),
         }
     }
 }
+
+impl Camera for OrthographicCamera {
+    fn get_ray(&self, s: Float, t: Float, rng: &mut StdRng) -> Option<Ray> {
+        ray(
+            self.lower_left + s * self.horizontal + t * self.vertical,
+            self.dir,
+            self.shutter.sample(rng),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FisheyeProjection {
+    /// distance from the image center proportional to the angle
+    Equidistant,
+    /// preserves shapes locally; r = 2 f tan(angle / 2)
+    Stereographic,
+}
+
+/// A fisheye whose image circle of `fov` degrees fills the height of the
+/// image. Pixels outside the circle stay black.
+#[derive(Debug)]
+pub struct FisheyeCamera {
+    pub origin: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+    pub fov: Float,
+    pub aspect: Float,
+    pub projection: FisheyeProjection,
+    pub shutter: Shutter,
+}
+
+impl FisheyeCamera {
+    pub fn new(
+        origin: Vec3,
+        look_at: Vec3,
+        up: Vec3,
+        fov: Float,
+        aspect: Float,
+        projection: FisheyeProjection,
+    ) -> Self {
+        let (u, v, w) = frame(origin, look_at, up);
+        FisheyeCamera {
+            origin,
+            u,
+            v,
+            w,
+            fov,
+            aspect,
+            projection,
+            shutter: Shutter::default(),
+        }
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn get_ray(&self, s: Float, t: Float, rng: &mut StdRng) -> Option<Ray> {
+        let x = (2.0 * s - 1.0) * self.aspect;
+        let y = 2.0 * t - 1.0;
+        let r = (x * x + y * y).sqrt();
+        if r > 1.0 {
+            return None;
+        }
+        let max_angle = 0.5 * self.fov * PI / 180.0;
+        let angle = match self.projection {
+            FisheyeProjection::Equidistant => r * max_angle,
+            FisheyeProjection::Stereographic => 2.0 * (r * (0.5 * max_angle).tan()).atan(),
+        };
+        let phi = y.atan2(x);
+        let dir = angle.sin() * (phi.cos() * self.u + phi.sin() * self.v) - angle.cos() * self.w;
+        ray(self.origin, dir, self.shutter.sample(rng))
+    }
+}
+
+/// A full 360 by 180 degree panorama in the equirectangular (latitude,
+/// longitude) layout, with the view direction in the image center.
+#[derive(Debug, Clone)]
+pub struct EquirectangularCamera {
+    pub origin: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+    pub shutter: Shutter,
+}
+
+impl EquirectangularCamera {
+    pub fn new(origin: Vec3, look_at: Vec3, up: Vec3) -> Self {
+        let (u, v, w) = frame(origin, look_at, up);
+        EquirectangularCamera {
+            origin,
+            u,
+            v,
+            w,
+            shutter: Shutter::default(),
+        }
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn get_ray(&self, s: Float, t: Float, rng: &mut StdRng) -> Option<Ray> {
+        let longitude = (s - 0.5) * 2.0 * PI;
+        let latitude = (t - 0.5) * PI;
+        let dir = latitude.cos() * (longitude.sin() * self.u - longitude.cos() * self.w)
+            + latitude.sin() * self.v;
+        ray(self.origin, dir, self.shutter.sample(rng))
+    }
+}
+
+/// The six 90 degree faces of a cube map in a 3 by 2 grid: right, left and
+/// up in the top row, down, front and back in the bottom row, where front
+/// is the view direction. The image should be 3:2 for square faces.
+#[derive(Debug)]
+pub struct CubeMapCamera {
+    pub origin: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+    pub shutter: Shutter,
+}
+
+impl CubeMapCamera {
+    pub fn new(origin: Vec3, look_at: Vec3, up: Vec3) -> Self {
+        let (u, v, w) = frame(origin, look_at, up);
+        CubeMapCamera {
+            origin,
+            u,
+            v,
+            w,
+            shutter: Shutter::default(),
+        }
+    }
+
+    // forward, right and up directions of face `index`
+    fn face(&self, index: usize) -> (Vec3, Vec3, Vec3) {
+        let (u, v, w) = (self.u, self.v, self.w);
+        match index {
+            0 => (u, w, v),
+            1 => (-u, -w, v),
+            2 => (v, u, w),
+            3 => (-v, u, -w),
+            4 => (-w, u, v),
+            _ => (w, -u, v),
+        }
+    }
+}
+
+impl Camera for CubeMapCamera {
+    fn get_ray(&self, s: Float, t: Float, rng: &mut StdRng) -> Option<Ray> {
+        let column = ((s * 3.0) as usize).min(2);
+        let row = (((1.0 - t) * 2.0) as usize).min(1);
+        let a = 2.0 * (s * 3.0 - column as Float) - 1.0;
+        let b = 2.0 * (t * 2.0 - (1 - row) as Float) - 1.0;
+        let (forward, right, up) = self.face(row * 3 + column);
+        ray(
+            self.origin,
+            forward + a * right + b * up,
+            self.shutter.sample(rng),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::float::tolerance;
+    use approx::assert_relative_eq;
+    use rand::SeedableRng;
+
+    fn assert_dir(camera: &dyn Camera, s: Float, t: Float, expected: Vec3) {
+        let mut rng = StdRng::seed_from_u64(1);
+        let r = camera.get_ray(s, t, &mut rng).unwrap();
+        let d = r.dir.normalized();
+        assert_relative_eq!(d.x(), expected.x(), epsilon = tolerance(1e-9));
+        assert_relative_eq!(d.y(), expected.y(), epsilon = tolerance(1e-9));
+        assert_relative_eq!(d.z(), expected.z(), epsilon = tolerance(1e-9));
+    }
+
+    struct TestMaterial {}
+    impl crate::materials::Material for TestMaterial {}
+
+    const ORIGIN: Vec3 = Vec3(0.0, 0.0, 0.0);
+    const FORWARD: Vec3 = Vec3(0.0, 0.0, -1.0);
+    const UP: Vec3 = Vec3(0.0, 1.0, 0.0);
+
+    #[test]
+    fn thin_lens_center_ray() {
+        let c = ThinLensCamera::new(ORIGIN, FORWARD, UP, 90.0, 2.0, 0.0, 1.0);
+        assert_dir(&c, 0.5, 0.5, FORWARD);
+        // the horizontal field of view is 90 degrees
+        assert_dir(&c, 1.0, 0.5, Vec3(1.0, 0.0, -1.0).normalized());
+    }
+
+    #[test]
+    fn builder_field_of_view() {
+        let world = HittableList::new();
+        let builder = CameraBuilder::new(ORIGIN, FORWARD, 2.0).fov(FieldOfView::Vertical(90.0));
+        let c = builder.build(&world).unwrap();
+        assert_dir(&c, 0.5, 1.0, Vec3(0.0, 1.0, -1.0).normalized());
+        assert_dir(&c, 1.0, 0.5, Vec3(2.0, 0.0, -1.0).normalized());
+        let c = builder
+            .clone()
+            .fov(FieldOfView::Horizontal(90.0))
+            .build(&world)
+            .unwrap();
+        assert_dir(&c, 1.0, 0.5, Vec3(1.0, 0.0, -1.0).normalized());
+        let c = builder
+            .clone()
+            .fov(FieldOfView::Diagonal(90.0))
+            .build(&world)
+            .unwrap();
+        let sqrt5 = (5.0 as Float).sqrt();
+        assert_dir(
+            &c,
+            1.0,
+            1.0,
+            Vec3(2.0 / sqrt5, 1.0 / sqrt5, -1.0).normalized(),
+        );
+        // a 36 x 24 mm sensor behind a 18 mm lens, cropped to 2:1
+        let c = builder
+            .fov(FieldOfView::FocalLength {
+                focal_length: 18.0,
+                sensor_width: 36.0,
+                sensor_height: 24.0,
+            })
+            .build(&world)
+            .unwrap();
+        assert_dir(&c, 1.0, 0.5, Vec3(1.0, 0.0, -1.0).normalized());
+        assert_dir(&c, 0.5, 1.0, Vec3(0.0, 0.5, -1.0).normalized());
+    }
+
+    #[test]
+    fn builder_focus() {
+        let mut world = HittableList::new();
+        world.push(crate::shapes::Plane {
+            point: Vec3(0.0, 0.0, -3.0),
+            normal: Vec3(0.0, 0.0, 1.0),
+            material: Box::new(TestMaterial {}),
+        });
+        let builder = CameraBuilder::new(ORIGIN, Vec3(0.0, 0.0, -5.0), 1.0);
+        assert_relative_eq!(builder.focus_distance(&world), 5.0);
+        let b = builder.clone().focus(Focus::Distance(2.5));
+        assert_relative_eq!(b.focus_distance(&world), 2.5);
+        // autofocus stops at the plane in front of the target, and measures
+        // along the view direction
+        let b = builder.focus(Focus::Target(Vec3(4.0, 0.0, -4.0)));
+        assert_relative_eq!(b.focus_distance(&world), 3.0, epsilon = 1e-12);
+        let c = b.build(&world).unwrap();
+        assert_relative_eq!(c.lower_left.z(), -3.0, epsilon = 1e-12);
+        // but not at the plane behind it
+        let b = b.focus(Focus::Target(Vec3(4.0, 0.0, -2.0)));
+        assert_relative_eq!(b.focus_distance(&world), 2.0, epsilon = 1e-12);
+    }
+
+    #[test]
+    fn animation_overrides_keyed_settings() {
+        let base = CameraBuilder::new(ORIGIN, FORWARD, 1.0)
+            .fov(FieldOfView::Horizontal(40.0))
+            .aperture(0.1);
+        let animation = CameraAnimation {
+            position: Some(Keyframes::linear(0.0, ORIGIN, 2.0, Vec3(2.0, 0.0, 0.0)).unwrap()),
+            fov: Some(Keyframes::linear(0.0, 40.0, 2.0, 80.0).unwrap()),
+            ..CameraAnimation::default()
+        };
+        let b = animation.at(&base, 1.0);
+        assert_eq!(b.origin, Vec3(1.0, 0.0, 0.0));
+        assert_eq!(b.fov, FieldOfView::Horizontal(60.0));
+        assert_eq!(b.look_at, FORWARD);
+        assert_eq!(b.aperture, 0.1);
+    }
+
+    #[test]
+    fn exposure_follows_f_number() {
+        let world = HittableList::new();
+        // a 50 mm lens
+        let builder = CameraBuilder::new(ORIGIN, FORWARD, 1.5).fov(FieldOfView::FocalLength {
+            focal_length: 50.0,
+            sensor_width: 36.0,
+            sensor_height: 24.0,
+        });
+        let c = builder
+            .clone()
+            .f_number(2.0)
+            .exposure(0.5, 200.0)
+            .build(&world)
+            .unwrap();
+        assert_relative_eq!(c.lens_radius, 0.0125, epsilon = 1e-12);
+        assert_relative_eq!(c.exposure, 0.25, epsilon = 1e-12);
+        // closing the aperture by one stop halves the light
+        let b = builder
+            .aperture(0.025 / (2.0 as Float).sqrt())
+            .exposure(0.5, 200.0);
+        assert_relative_eq!(
+            b.lens_f_number().unwrap(),
+            2.0 * (2.0 as Float).sqrt(),
+            epsilon = 1e-12
+        );
+        assert_relative_eq!(b.build(&world).unwrap().exposure, 0.125, epsilon = 1e-12);
+        // a 90 degree diagonal view is a 21.6 mm lens
+        let b = CameraBuilder::new(ORIGIN, FORWARD, 1.5).fov(FieldOfView::Diagonal(90.0));
+        assert_relative_eq!(b.focal_length(), 0.0216333, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn vignetting_falls_off_as_cos4() {
+        let world = HittableList::new();
+        let mut rng = StdRng::seed_from_u64(1);
+        let c = CameraBuilder::new(ORIGIN, FORWARD, 1.0)
+            .fov(FieldOfView::Horizontal(90.0))
+            .vignetting(true)
+            .build(&world)
+            .unwrap();
+        let center = c.get_ray(0.5, 0.5, &mut rng).unwrap();
+        assert_relative_eq!(c.weight(&center), 1.0, epsilon = 1e-12);
+        // 45 degrees off axis
+        let edge = c.get_ray(1.0, 0.5, &mut rng).unwrap();
+        assert_relative_eq!(c.weight(&edge), 0.25, epsilon = 1e-12);
+    }
+
+    #[test]
+    fn chromatic_aberration_magnifies_red() {
+        let world = HittableList::new();
+        let mut rng = StdRng::seed_from_u64(1);
+        let c = CameraBuilder::new(ORIGIN, FORWARD, 1.0)
+            .fov(FieldOfView::Horizontal(90.0))
+            .chromatic_aberration(0.1)
+            .build(&world)
+            .unwrap();
+        let mut red = 0;
+        for _ in 0..100 {
+            let r = c.get_ray(1.0, 0.5, &mut rng).unwrap();
+            let lambda = r.wavelength.unwrap();
+            let x = r.dir.x() / -r.dir.z();
+            // red is magnified, so a red ray at the image edge comes from
+            // closer to the axis
+            if lambda > 650.0 {
+                assert!(x < 0.99);
+                red += 1;
+            } else if lambda < 450.0 {
+                assert!(x > 1.01);
+            }
+        }
+        assert!(red > 0);
+        assert_dir(&c, 0.5, 0.5, FORWARD);
+    }
+
+    #[test]
+    fn aperture_shapes() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let hexagon = ApertureShape::polygon(6, 0.0).unwrap();
+        // the inner radius of a hexagon in the unit circle
+        let apothem = (PI / 6.0).cos();
+        for _ in 0..1000 {
+            let (x, y) = hexagon.sample(&mut rng);
+            for k in 0..6 {
+                // each edge's outward normal is halfway between two corners
+                let a = (k as Float + 0.5) * PI / 3.0;
+                assert!(x * a.cos() + y * a.sin() <= apothem + 1e-12);
+            }
+        }
+        // only the top right quarter of this image is open
+        let image = ApertureShape::Image(BokehImage::new(2, 2, &[0.0, 1.0, 0.0, 0.0]).unwrap());
+        for _ in 0..1000 {
+            let (x, y) = image.sample(&mut rng);
+            assert!((0.0..=1.0).contains(&x) && (0.0..=1.0).contains(&y));
+        }
+    }
+
+    #[test]
+    fn rejects_impossible_apertures() {
+        let world = HittableList::new();
+        assert!(ApertureShape::polygon(2, 0.0).is_err());
+        assert!(BokehImage::new(0, 0, &[]).is_err());
+        assert!(BokehImage::new(2, 1, &[1.0]).is_err());
+        assert!(BokehImage::new(1, 1, &[0.0]).is_err());
+        // the enum can still be written out by hand
+        let builder = CameraBuilder::new(ORIGIN, FORWARD, 1.0).aperture(0.1);
+        let no_blades = ApertureShape::Polygon {
+            blades: 0,
+            rotation: 0.0,
+        };
+        assert!(builder
+            .clone()
+            .aperture_shape(no_blades)
+            .build(&world)
+            .is_err());
+        // a pinhole can't be exposed, unless it's given an f-number
+        let pinhole = builder.aperture(0.0).exposure(0.5, 100.0);
+        assert!(pinhole.lens_f_number().is_none());
+        assert!(pinhole.build(&world).is_err());
+        assert!(pinhole.f_number(2.0).build(&world).is_ok());
+    }
+
+    // a 50 mm f/2 double Gauss lens (US patent 2,673,491)
+    fn double_gauss() -> Vec<LensElement> {
+        [
+            (29.475, 3.76, 1.67, 25.2),
+            (84.83, 0.12, 1.0, 25.2),
+            (19.275, 4.025, 1.67, 23.0),
+            (40.77, 3.275, 1.699, 23.0),
+            (12.75, 5.705, 1.0, 18.0),
+            (0.0, 4.5, 0.0, 17.1),
+            (-14.495, 1.18, 1.603, 17.0),
+            (40.77, 6.065, 1.658, 20.0),
+            (-20.385, 0.19, 1.0, 20.0),
+            (437.065, 3.22, 1.717, 20.0),
+            (-39.73, 0.0, 1.0, 20.0),
+        ]
+        .iter()
+        .map(|&(radius, thickness, ior, aperture)| LensElement {
+            radius,
+            thickness,
+            ior,
+            aperture,
+        })
+        .collect()
+    }
+
+    #[test]
+    fn realistic_lens_focuses() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let c = RealisticCamera::new(ORIGIN, FORWARD, UP, double_gauss(), 43.27, 1.5, 2.0);
+        let focal_length = c.focal_length().unwrap();
+        assert!((45.0..55.0).contains(&focal_length), "{}", focal_length);
+        // rays from the center of the film meet again 2 m away, up to the
+        // lens' spherical aberration; some of those sampled in the pupil's
+        // bounds are blocked
+        let rays: Vec<Ray> = (0..200)
+            .filter_map(|_| c.get_ray(0.5, 0.5, &mut rng))
+            .collect();
+        let spread = |distance: Float| {
+            let total: Float = rays
+                .iter()
+                .map(|r| {
+                    let p = r.point_at_t((-distance - r.pos.z()) / r.dir.z());
+                    p.x().hypot(p.y())
+                })
+                .sum();
+            total / rays.len() as Float
+        };
+        assert!(spread(2.0) < 1e-3);
+        assert!(spread(2.0) < 0.5 * spread(1.6) && spread(2.0) < 0.5 * spread(2.5));
+        for r in &rays {
+            let weight = c.weight(r);
+            assert!(weight > 0.8 && weight <= 1.0, "{}", weight);
+        }
+        // the image is the right way up and darker in the corners
+        let mut ray_at = |s, t| loop {
+            if let Some(r) = c.get_ray(s, t, &mut rng) {
+                return r;
+            }
+        };
+        assert!(ray_at(0.9, 0.5).dir.x() > 0.0);
+        let r = ray_at(0.85, 0.85);
+        assert!(r.dir.y() > 0.0);
+        assert!(c.weight(&r) < 0.9);
+    }
+
+    #[test]
+    fn realistic_exit_pupil_covers_lens() {
+        let c = RealisticCamera::new(ORIGIN, FORWARD, UP, double_gauss(), 43.27, 1.5, 10.0);
+        let mut rng = StdRng::seed_from_u64(1);
+        // every ray sampled from the center of the film's pupil bounds
+        // gets through, and the bounds are no larger than they need be
+        let (x0, y0, x1, y1) = c.exit_pupil[0].unwrap();
+        let rear_radius = 10.0;
+        assert!(x1 - x0 <= 2.2 * rear_radius && y1 - y0 <= 2.2 * rear_radius);
+        let through = (0..1000)
+            .filter(|_| c.get_ray(0.5, 0.5, &mut rng).is_some())
+            .count();
+        assert!(through > 500, "{}", through);
+        // the pupil narrows towards the corners
+        let (cx0, _, cx1, _) = c.exit_pupil[PUPIL_BANDS - 1].unwrap_or((0.0, 0.0, 0.0, 0.0));
+        assert!(cx1 - cx0 < x1 - x0);
+    }
+
+    #[test]
+    fn orthographic_rays_are_parallel() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let c = OrthographicCamera::new(ORIGIN, FORWARD, UP, 4.0, 2.0);
+        let r = c.get_ray(0.0, 1.0, &mut rng).unwrap();
+        assert_eq!(r.pos, Vec3(-2.0, 1.0, 0.0));
+        assert_eq!(r.dir, FORWARD);
+    }
+
+    #[test]
+    fn fisheye_projections() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for projection in &[
+            FisheyeProjection::Equidistant,
+            FisheyeProjection::Stereographic,
+        ] {
+            let c = FisheyeCamera::new(ORIGIN, FORWARD, UP, 180.0, 1.0, *projection);
+            assert_dir(&c, 0.5, 0.5, FORWARD);
+            // the edge of the circle looks sideways
+            assert_dir(&c, 1.0, 0.5, Vec3(1.0, 0.0, 0.0));
+            assert_dir(&c, 0.5, 1.0, UP);
+            assert!(c.get_ray(1.0, 1.0, &mut rng).is_none());
+        }
+        let c = FisheyeCamera::new(
+            ORIGIN,
+            FORWARD,
+            UP,
+            180.0,
+            1.0,
+            FisheyeProjection::Equidistant,
+        );
+        let half = (0.5 as Float).sqrt();
+        assert_dir(&c, 0.75, 0.5, Vec3(half, 0.0, -half));
+    }
+
+    #[test]
+    fn equirectangular_covers_sphere() {
+        let c = EquirectangularCamera::new(ORIGIN, FORWARD, UP);
+        assert_dir(&c, 0.5, 0.5, FORWARD);
+        assert_dir(&c, 0.75, 0.5, Vec3(1.0, 0.0, 0.0));
+        assert_dir(&c, 0.0, 0.5, Vec3(0.0, 0.0, 1.0));
+        assert_dir(&c, 0.3, 1.0, UP);
+    }
+
+    #[test]
+    fn cube_map_faces() {
+        let c = CubeMapCamera::new(ORIGIN, FORWARD, UP);
+        // face centers
+        assert_dir(&c, 1.0 / 6.0, 0.75, Vec3(1.0, 0.0, 0.0));
+        assert_dir(&c, 0.5, 0.75, Vec3(-1.0, 0.0, 0.0));
+        assert_dir(&c, 5.0 / 6.0, 0.75, UP);
+        assert_dir(&c, 1.0 / 6.0, 0.25, -UP);
+        assert_dir(&c, 0.5, 0.25, FORWARD);
+        assert_dir(&c, 5.0 / 6.0, 0.25, Vec3(0.0, 0.0, 1.0));
+        // neighbouring faces meet at 45 degrees
+        let edge = Vec3(0.0, -1.0, -1.0).normalized();
+        assert_dir(&c, 0.5, 0.0, edge);
+    }
+}
@@ -0,0 +1,595 @@
+use crate::aabb::Aabb;
+use crate::float::Float;
+use crate::hittable::*;
+use crate::materials::*;
+use crate::ray::*;
+use crate::shapes::{hit_infinite_plane, hit_quad, quad_bounds, Plane, Quad};
+use crate::spectrum::*;
+use crate::vec3::*;
+use rand::rngs::StdRng;
+use std::any::Any;
+use std::sync::Arc;
+
+// Calls `$call` on whichever material `$kind` holds, so built-in materials
+// are dispatched with a match rather than through a vtable.
+macro_rules! dispatch {
+    ($kind:expr, $m:ident => $call:expr) => {
+        match $kind {
+            MaterialKind::Diffuse($m) => $call,
+            MaterialKind::SpectralDiffuse($m) => $call,
+            MaterialKind::Light($m) => $call,
+            MaterialKind::Metal($m) => $call,
+            MaterialKind::Glass($m) => $call,
+            MaterialKind::Dynamic($m) => $call,
+        }
+    };
+}
+
+/// The built-in materials, plus any other material boxed in `Dynamic`.
+pub enum MaterialKind {
+    Diffuse(DiffuseMaterial),
+    SpectralDiffuse(SpectralDiffuseMaterial),
+    Light(DiffuseLight),
+    Metal(MetalMaterial),
+    Glass(GlassMaterial),
+    Dynamic(Box<dyn Material + Send + Sync>),
+}
+
+impl Material for MaterialKind {
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, rng: &mut StdRng) -> (Ray, Vec3) {
+        dispatch!(self, m => m.scatter(ray, hit_record, rng))
+    }
+    fn emitted(&self, ray: &Ray, hit_record: &HitRecord) -> Vec3 {
+        dispatch!(self, m => m.emitted(ray, hit_record))
+    }
+    fn scatter_spectral(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        wavelengths: &SampledWavelengths,
+        rng: &mut StdRng,
+    ) -> (Ray, SampledSpectrum) {
+        dispatch!(self, m => m.scatter_spectral(ray, hit_record, wavelengths, rng))
+    }
+    fn emitted_spectral(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        wavelengths: &SampledWavelengths,
+    ) -> SampledSpectrum {
+        dispatch!(self, m => m.emitted_spectral(ray, hit_record, wavelengths))
+    }
+}
+
+impl From<DiffuseMaterial> for MaterialKind {
+    fn from(m: DiffuseMaterial) -> Self {
+        MaterialKind::Diffuse(m)
+    }
+}
+
+impl From<SpectralDiffuseMaterial> for MaterialKind {
+    fn from(m: SpectralDiffuseMaterial) -> Self {
+        MaterialKind::SpectralDiffuse(m)
+    }
+}
+
+impl From<DiffuseLight> for MaterialKind {
+    fn from(m: DiffuseLight) -> Self {
+        MaterialKind::Light(m)
+    }
+}
+
+impl From<MetalMaterial> for MaterialKind {
+    fn from(m: MetalMaterial) -> Self {
+        MaterialKind::Metal(m)
+    }
+}
+
+impl From<GlassMaterial> for MaterialKind {
+    fn from(m: GlassMaterial) -> Self {
+        MaterialKind::Glass(m)
+    }
+}
+
+/// Unboxes the built-in materials, so that only other materials stay
+/// behind a vtable.
+impl From<Box<dyn Material + Send + Sync>> for MaterialKind {
+    fn from(m: Box<dyn Material + Send + Sync>) -> Self {
+        unbox(m)
+            .or_else(|m| unbox(m).map(MaterialKind::Diffuse))
+            .or_else(|m| unbox(m).map(MaterialKind::SpectralDiffuse))
+            .or_else(|m| unbox(m).map(MaterialKind::Light))
+            .or_else(|m| unbox(m).map(MaterialKind::Metal))
+            .or_else(|m| unbox(m).map(MaterialKind::Glass))
+            .unwrap_or_else(MaterialKind::Dynamic)
+    }
+}
+
+// the material in `m` if it is a `T`, or `m` back
+fn unbox<T: Material>(
+    m: Box<dyn Material + Send + Sync>,
+) -> Result<T, Box<dyn Material + Send + Sync>> {
+    if !(&*m as &dyn Any).is::<T>() {
+        return Err(m);
+    }
+    let m: Box<dyn Any> = m;
+    Ok(*m.downcast().unwrap())
+}
+
+// the shape in `object` if it is a `T` that nothing else shares, or
+// `object` back
+fn unshare<T: Hittable + Send + Sync>(
+    object: Arc<dyn Hittable + Send + Sync>,
+) -> Result<T, Arc<dyn Hittable + Send + Sync>> {
+    if !(&*object as &dyn Any).is::<T>() {
+        return Err(object);
+    }
+    let object: Arc<dyn Any + Send + Sync> = object;
+    let object: Arc<T> = object.downcast().unwrap();
+    Arc::try_unwrap(object).map_err(|object| object as Arc<dyn Hittable + Send + Sync>)
+}
+
+/// Materials are referred to by their index in the scene's material array.
+pub type MaterialId = u32;
+
+struct SphereData {
+    center: Vec3,
+    radius: Float,
+    material: MaterialId,
+}
+
+struct QuadData {
+    q: Vec3,
+    u: Vec3,
+    v: Vec3,
+    material: MaterialId,
+}
+
+struct PlaneData {
+    point: Vec3,
+    normal: Vec3,
+    material: MaterialId,
+}
+
+// A primitive by its kind and index into the array for that kind. Objects
+// are boxed hittables, for shapes that have no built-in kind.
+#[derive(Clone, Copy)]
+enum Primitive {
+    Sphere(u32),
+    Quad(u32),
+    Plane(u32),
+    Object(u32),
+}
+
+// A primitive in a leaf, with its index in the order it was added, which
+// becomes the hits' `object_id`.
+#[derive(Clone, Copy)]
+struct Leaf {
+    primitive: Primitive,
+    id: u32,
+}
+
+// A node of the flattened BVH. Leaves hold `count` primitives from `start`
+// on; interior nodes have `count` 0, their first child right after them and
+// their second child at `start`.
+struct Node {
+    bbox: Aabb,
+    start: u32,
+    count: u32,
+}
+
+const LEAF_SIZE: usize = 4;
+
+// Deep enough for any tree built by median splits.
+const STACK_SIZE: usize = 64;
+
+/// A scene of built-in primitives kept in flat arrays, one per kind, with
+/// their materials in another and a BVH laid out in depth first order. Hits
+/// are dispatched with matches on the primitive kind, and carry their
+/// `MaterialKind`, whose own dispatch is a match as well.
+pub struct CompactScene {
+    materials: Vec<MaterialKind>,
+    spheres: Vec<SphereData>,
+    quads: Vec<QuadData>,
+    planes: Vec<PlaneData>,
+    objects: Vec<Box<dyn Hittable + Send + Sync>>,
+    nodes: Vec<Node>,
+    leaves: Vec<Leaf>,
+    // primitives without a bounding box, tested one by one
+    unbounded: Vec<Leaf>,
+}
+
+/// Collects the materials and primitives of a `CompactScene`. Anything
+/// without a built-in kind can be added as a boxed object, which is called
+/// through its vtable.
+pub struct CompactSceneBuilder {
+    materials: Vec<MaterialKind>,
+    spheres: Vec<SphereData>,
+    quads: Vec<QuadData>,
+    planes: Vec<PlaneData>,
+    objects: Vec<Box<dyn Hittable + Send + Sync>>,
+    primitives: Vec<Primitive>,
+}
+
+impl CompactSceneBuilder {
+    pub fn new() -> Self {
+        CompactSceneBuilder {
+            materials: Vec::new(),
+            spheres: Vec::new(),
+            quads: Vec::new(),
+            planes: Vec::new(),
+            objects: Vec::new(),
+            primitives: Vec::new(),
+        }
+    }
+
+    pub fn add_material<M: Into<MaterialKind>>(&mut self, material: M) -> MaterialId {
+        self.materials.push(material.into());
+        (self.materials.len() - 1) as MaterialId
+    }
+
+    pub fn add_sphere(&mut self, center: Vec3, radius: Float, material: MaterialId) {
+        self.spheres.push(SphereData {
+            center,
+            radius,
+            material,
+        });
+        self.primitives
+            .push(Primitive::Sphere(self.spheres.len() as u32 - 1));
+    }
+
+    pub fn add_quad(&mut self, q: Vec3, u: Vec3, v: Vec3, material: MaterialId) {
+        self.quads.push(QuadData { q, u, v, material });
+        self.primitives
+            .push(Primitive::Quad(self.quads.len() as u32 - 1));
+    }
+
+    pub fn add_plane(&mut self, point: Vec3, normal: Vec3, material: MaterialId) {
+        self.planes.push(PlaneData {
+            point,
+            normal,
+            material,
+        });
+        self.primitives
+            .push(Primitive::Plane(self.planes.len() as u32 - 1));
+    }
+
+    /// Adds a sphere, quad or plane that nothing else shares as a built-in
+    /// primitive, and its material as a built-in one if it is. Gives back
+    /// any other object.
+    pub fn add_shape(
+        &mut self,
+        object: Arc<dyn Hittable + Send + Sync>,
+    ) -> Result<(), Arc<dyn Hittable + Send + Sync>> {
+        let object = match unshare::<Sphere>(object) {
+            Ok(s) => {
+                let material = self.add_material(s.material);
+                self.add_sphere(s.center, s.radius, material);
+                return Ok(());
+            }
+            Err(object) => object,
+        };
+        let object = match unshare::<Quad>(object) {
+            Ok(q) => {
+                let material = self.add_material(q.material);
+                self.add_quad(q.q, q.u, q.v, material);
+                return Ok(());
+            }
+            Err(object) => object,
+        };
+        let p = unshare::<Plane>(object)?;
+        let material = self.add_material(p.material);
+        self.add_plane(p.point, p.normal, material);
+        Ok(())
+    }
+
+    pub fn add_object<T: Hittable + 'static + Send + Sync>(&mut self, object: T) {
+        self.add_boxed(Box::new(object))
+    }
+
+    pub fn add_boxed(&mut self, object: Box<dyn Hittable + Send + Sync>) {
+        self.objects.push(object);
+        self.primitives
+            .push(Primitive::Object(self.objects.len() as u32 - 1));
+    }
+
+    /// builds the BVH over everything added
+    pub fn build(self) -> CompactScene {
+        let mut scene = CompactScene {
+            materials: self.materials,
+            spheres: self.spheres,
+            quads: self.quads,
+            planes: self.planes,
+            objects: self.objects,
+            nodes: Vec::new(),
+            leaves: Vec::new(),
+            unbounded: Vec::new(),
+        };
+        let mut bounded = Vec::new();
+        for (id, primitive) in self.primitives.into_iter().enumerate() {
+            let leaf = Leaf {
+                primitive,
+                id: id as u32,
+            };
+            match scene.bounds(primitive) {
+                Some(bbox) => bounded.push((bbox, leaf)),
+                None => scene.unbounded.push(leaf),
+            }
+        }
+        if !bounded.is_empty() {
+            build_node(&mut scene.nodes, &mut bounded, 0);
+        }
+        scene.leaves = bounded.into_iter().map(|(_, leaf)| leaf).collect();
+        scene
+    }
+}
+
+impl Default for CompactSceneBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CompactScene {
+    pub fn len(&self) -> usize {
+        self.leaves.len() + self.unbounded.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn bounds(&self, primitive: Primitive) -> Option<Aabb> {
+        match primitive {
+            Primitive::Sphere(i) => {
+                let s = &self.spheres[i as usize];
+                let r = Vec3(s.radius, s.radius, s.radius);
+                Some(Aabb::new(s.center - r, s.center + r))
+            }
+            Primitive::Quad(i) => {
+                let q = &self.quads[i as usize];
+                Some(quad_bounds(q.q, q.u, q.v))
+            }
+            Primitive::Plane(_) => None,
+            Primitive::Object(i) => self.objects[i as usize].bounding_box(),
+        }
+    }
+
+    fn hit_leaf(&self, leaf: Leaf, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        let material = |id: MaterialId| HitMaterial::Kind(&self.materials[id as usize]);
+        let hit = match leaf.primitive {
+            Primitive::Sphere(i) => {
+                let s = &self.spheres[i as usize];
+                hit_sphere(s.center, s.radius, material(s.material), ray, t_min, t_max)
+            }
+            Primitive::Quad(i) => {
+                let q = &self.quads[i as usize];
+                hit_quad(q.q, q.u, q.v, material(q.material), ray, t_min, t_max)
+            }
+            Primitive::Plane(i) => {
+                let p = &self.planes[i as usize];
+                hit_infinite_plane(p.point, p.normal, material(p.material), ray, t_min, t_max)
+            }
+            Primitive::Object(i) => self.objects[i as usize].hit(ray, t_min, t_max),
+        };
+        hit.map(|mut hit_record| {
+            hit_record.object_id = leaf.id as usize;
+            hit_record
+        })
+    }
+}
+
+// Adds the subtree over `leaves`, which start at `start` in the final leaf
+// order, and returns its index. Splits at the median centroid along the
+// axis where the centroids spread most, as `Bvh` does.
+fn build_node(nodes: &mut Vec<Node>, leaves: &mut [(Aabb, Leaf)], start: usize) -> usize {
+    let index = nodes.len();
+    let bbox = leaves[1..]
+        .iter()
+        .fold(leaves[0].0, |acc, (b, _)| acc.union(b));
+    nodes.push(Node {
+        bbox,
+        start: start as u32,
+        count: leaves.len() as u32,
+    });
+    if leaves.len() <= LEAF_SIZE {
+        return index;
+    }
+    let centroids = leaves[1..].iter().fold(
+        Aabb::new(leaves[0].0.centroid(), leaves[0].0.centroid()),
+        |acc, (b, _)| acc.union(&Aabb::new(b.centroid(), b.centroid())),
+    );
+    let extent = centroids.max - centroids.min;
+    let axis = if extent.x() > extent.y() && extent.x() > extent.z() {
+        0
+    } else if extent.y() > extent.z() {
+        1
+    } else {
+        2
+    };
+    leaves.sort_by(|(a, _), (b, _)| {
+        a.centroid()[axis]
+            .partial_cmp(&b.centroid()[axis])
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    let mid = leaves.len() / 2;
+    let (left, right) = leaves.split_at_mut(mid);
+    build_node(nodes, left, start);
+    let second = build_node(nodes, right, start + mid);
+    nodes[index].start = second as u32;
+    nodes[index].count = 0;
+    index
+}
+
+impl Hittable for CompactScene {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        let mut closest = None;
+        let mut t_closest = t_max;
+        for leaf in &self.unbounded {
+            if let Some(hit_record) = self.hit_leaf(*leaf, ray, t_min, t_closest) {
+                t_closest = hit_record.t;
+                closest = Some(hit_record);
+            }
+        }
+        if self.nodes.is_empty() {
+            return closest;
+        }
+        let mut stack = [0u32; STACK_SIZE];
+        let mut top = 1;
+        while top > 0 {
+            top -= 1;
+            let index = stack[top] as usize;
+            let node = &self.nodes[index];
+            if !node.bbox.hit(ray, t_min, t_closest) {
+                continue;
+            }
+            if node.count > 0 {
+                let start = node.start as usize;
+                for leaf in &self.leaves[start..start + node.count as usize] {
+                    if let Some(hit_record) = self.hit_leaf(*leaf, ray, t_min, t_closest) {
+                        t_closest = hit_record.t;
+                        closest = Some(hit_record);
+                    }
+                }
+            } else {
+                stack[top] = node.start;
+                stack[top + 1] = index as u32 + 1;
+                top += 2;
+            }
+        }
+        closest
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        if !self.unbounded.is_empty() {
+            return None;
+        }
+        self.nodes.first().map(|n| n.bbox)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::demo::build_world;
+    use crate::mat4::Mat4;
+    use crate::scene::{SceneGraph, SceneNode};
+    use crate::shapes::Disk;
+    use approx::assert_relative_eq;
+    use rand::Rng;
+    use rand::SeedableRng;
+
+    struct TestMaterial {}
+    impl Material for TestMaterial {
+        fn emitted(&self, _ray: &Ray, _hit_record: &HitRecord) -> Vec3 {
+            Vec3(1.0, 2.0, 3.0)
+        }
+    }
+
+    #[test]
+    fn matches_the_scene_graph() {
+        let bvh = build_world(&mut StdRng::seed_from_u64(1))
+            .flatten()
+            .unwrap();
+        let compact = build_world(&mut StdRng::seed_from_u64(1))
+            .compact()
+            .unwrap();
+        assert!(compact.len() > 10);
+        // every shape of the demo became a built-in primitive
+        assert!(compact.objects.is_empty());
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..1000 {
+            let ray = Ray::new(
+                Vec3(0.0, 2.0, 6.0),
+                Vec3(rng.gen::<Float>() - 0.5, -0.4 * rng.gen::<Float>(), -1.0),
+            );
+            let expected = bvh.hit(&ray, 0.001, Float::MAX).map(|h| (h.t, h.object_id));
+            let hit = compact.hit(&ray, 0.001, Float::MAX);
+            assert_eq!(expected, hit.as_ref().map(|h| (h.t, h.object_id)));
+            // with the metals unboxed
+            if let Some(hit) = hit {
+                assert!(matches!(
+                    hit.material,
+                    HitMaterial::Kind(MaterialKind::Metal(_))
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn boxed_objects_and_materials() {
+        let mut builder = CompactSceneBuilder::new();
+        let material: Box<dyn Material + Send + Sync> = Box::new(TestMaterial {});
+        let glowing = builder.add_material(material);
+        builder.add_quad(
+            Vec3(-1.0, -1.0, -4.0),
+            Vec3(2.0, 0.0, 0.0),
+            Vec3(0.0, 2.0, 0.0),
+            glowing,
+        );
+        builder.add_object(Disk {
+            center: Vec3(0.0, 0.0, -2.0),
+            normal: Vec3(0.0, 0.0, 1.0),
+            radius: 0.5,
+            material: Box::new(TestMaterial {}),
+        });
+        let scene = builder.build();
+        assert!(scene.bounding_box().is_some());
+        let ray = |x: Float| Ray::new(Vec3(x, 0.0, 0.0), Vec3(0.0, 0.0, -1.0));
+        let disk = scene.hit(&ray(0.0), 0.001, Float::MAX).unwrap();
+        assert_relative_eq!(disk.t, 2.0);
+        assert_eq!(disk.object_id, 1);
+        let quad = scene.hit(&ray(0.8), 0.001, Float::MAX).unwrap();
+        assert_relative_eq!(quad.t, 4.0);
+        assert_eq!(quad.object_id, 0);
+        assert_eq!(quad.material.emitted(&ray(0.8), &quad), Vec3(1.0, 2.0, 3.0));
+        assert!(scene.hit(&ray(2.0), 0.001, Float::MAX).is_none());
+    }
+
+    #[test]
+    fn boxes_moved_and_shared_shapes() {
+        let sphere = |x: Float| Sphere {
+            center: Vec3(x, 0.0, -2.0),
+            radius: 0.5,
+            material: Box::new(DiffuseMaterial {
+                albedo: Vec3(0.5, 0.5, 0.5),
+            }),
+        };
+        let mut scene = SceneGraph::new();
+        scene
+            .root
+            .add_child(SceneNode::with_geometry("plain", sphere(-2.0)));
+        let moved = scene
+            .root
+            .add_child(SceneNode::with_geometry("moved", sphere(0.0)));
+        moved.transform = Mat4::translation(Vec3(0.0, 0.0, -1.0));
+        let shared = scene
+            .root
+            .add_child(SceneNode::with_geometry("shared", sphere(2.0)));
+        let geometry = shared.geometry.clone();
+        let mut copy = SceneNode::new("copy");
+        copy.geometry = geometry;
+        scene.root.add_child(copy);
+        let compact = scene.compact().unwrap();
+        assert_eq!(compact.spheres.len(), 1);
+        assert_eq!(compact.objects.len(), 3);
+        let ray = |x: Float| Ray::new(Vec3(x, 0.0, 0.0), Vec3(0.0, 0.0, -1.0));
+        let plain = compact.hit(&ray(-2.0), 0.001, Float::MAX).unwrap();
+        assert!(matches!(
+            plain.material,
+            HitMaterial::Kind(MaterialKind::Diffuse(_))
+        ));
+        let moved = compact.hit(&ray(0.0), 0.001, Float::MAX).unwrap();
+        assert_relative_eq!(moved.t, 2.5, epsilon = crate::float::tolerance(1e-9));
+        assert_eq!(moved.object_id, 1);
+        assert!(matches!(moved.material, HitMaterial::Dyn(_)));
+    }
+
+    #[test]
+    fn empty_scene_misses() {
+        let scene = CompactSceneBuilder::new().build();
+        assert!(scene.is_empty());
+        let ray = Ray::new(Vec3(0.0, 0.0, 0.0), Vec3(0.0, 0.0, 1.0));
+        assert!(scene.hit(&ray, 0.001, Float::MAX).is_none());
+        assert!(scene.bounding_box().is_none());
+    }
+}
@@ -0,0 +1,100 @@
+use crate::float::Float;
+use crate::hittable::Sphere;
+use crate::materials::MetalMaterial;
+use crate::scene::{SceneGraph, SceneNode};
+use crate::shapes::Plane;
+use crate::vec3::*;
+use rand::rngs::StdRng;
+use rand::Rng;
+
+// A sphere of the demo scene.
+struct Ball {
+    center: Vec3,
+    radius: Float,
+    material: MetalMaterial,
+}
+
+fn metal(albedo: Vec3, fuzz: Float) -> MetalMaterial {
+    MetalMaterial {
+        albedo,
+        fuzz,
+        coating: None,
+    }
+}
+
+// A big metal sphere among small ones of random colors, which are kept
+// from overlapping. The ground is a metal plane through the origin.
+fn balls(rng: &mut StdRng) -> (Ball, Vec<Ball>) {
+    let big = Ball {
+        center: Vec3(1.0, 0.5, 0.0),
+        radius: 0.5,
+        material: metal(Vec3(0.8, 0.8, 0.8), 0.05),
+    };
+    let mut small = Vec::new();
+
+    let mut colliders = Vec::new();
+    colliders.push((big.center, big.radius));
+
+    for x in -11..5 {
+        for z in -11..5 {
+            let radius = 0.05 + rng.gen::<Float>() * rng.gen::<Float>() * 0.3;
+            let center = Vec3(
+                0.5 * (x as Float + 0.8 * rng.gen::<Float>()),
+                radius,
+                0.5 * (z as Float + 0.8 * rng.gen::<Float>()),
+            );
+
+            if center.squared_length() < 25.0 {
+                let mut collides = false;
+                for (c_center, c_radius) in &colliders {
+                    if (center - *c_center).length() < (radius + *c_radius) {
+                        collides = true;
+                        break;
+                    }
+                }
+                if !collides {
+                    let material = metal(
+                        Vec3(rng.gen::<Float>(), rng.gen::<Float>(), rng.gen::<Float>()),
+                        0.05 + rng.gen::<Float>() * 0.3,
+                    );
+                    small.push(Ball {
+                        center,
+                        radius,
+                        material,
+                    });
+                    colliders.push((center, radius));
+                }
+            }
+        }
+    }
+    (big, small)
+}
+
+/// The demo scene. Its `compact` form is the same scene with the shapes
+/// and materials unboxed.
+pub fn build_world(rng: &mut StdRng) -> SceneGraph {
+    let (big, small) = balls(rng);
+    let sphere = |ball: Ball| Sphere {
+        center: ball.center,
+        radius: ball.radius,
+        material: Box::new(ball.material),
+    };
+    let mut scene = SceneGraph::new();
+    scene
+        .root
+        .add_child(SceneNode::with_geometry("big_sphere", sphere(big)));
+    let small_spheres = scene.root.add_child(SceneNode::new("small_spheres"));
+    for ball in small {
+        let name = format!("sphere_{}", small_spheres.children.len());
+        small_spheres.add_child(SceneNode::with_geometry(&name, sphere(ball)));
+    }
+    scene.root.add_child(SceneNode::with_geometry(
+        "ground",
+        Plane {
+            point: Vec3(0.0, 0.0, 0.0),
+            normal: Vec3(0.0, 1.0, 0.0),
+            material: Box::new(metal(Vec3(0.5, 0.5, 0.5), 0.05)),
+        },
+    ));
+    scene
+}
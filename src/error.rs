@@ -0,0 +1,102 @@
+use crate::scenefile::ParseError;
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong while reading a scene, rendering it and
+/// writing out the result.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// the image could not be encoded, e.g. because it has no pixels
+    Encoding(String),
+    SceneParse {
+        path: String,
+        error: ParseError,
+    },
+    /// a render tile whose worker panicked, with the panic message
+    Worker {
+        tile: usize,
+        message: String,
+    },
+    /// a scene graph node, by path, whose world transform can't be inverted
+    SingularTransform {
+        node: String,
+    },
+    /// camera settings no image can be made with, e.g. a two-bladed aperture
+    Camera(String),
+    Usage(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Encoding(message) => write!(f, "encoding failed: {}", message),
+            Error::SceneParse { path, error } => write!(f, "{}: {}", path, error),
+            Error::Worker { tile, message } => write!(f, "tile {} failed: {}", tile, message),
+            Error::SingularTransform { node } => {
+                write!(
+                    f,
+                    "node \"{}\" has a transform that can't be inverted",
+                    node
+                )
+            }
+            Error::Camera(message) => write!(f, "bad camera: {}", message),
+            Error::Usage(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl Error {
+    /// The error for a worker that panicked with `payload`, which holds the
+    /// message if the panic had one.
+    pub fn from_panic(tile: usize, payload: Box<dyn std::any::Any + Send>) -> Self {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&str>() {
+                Ok(message) => message.to_string(),
+                Err(_) => "unknown panic".to_string(),
+            },
+        };
+        Error::Worker { tile, message }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_errors() {
+        let panicked = std::thread::spawn(|| panic!("no {} here", "rays")).join();
+        let error = Error::from_panic(3, panicked.unwrap_err());
+        assert_eq!(error.to_string(), "tile 3 failed: no rays here");
+        let error = Error::SceneParse {
+            path: "scene.txt".to_string(),
+            error: ParseError {
+                line: 2,
+                message: "unknown camera".to_string(),
+            },
+        };
+        assert_eq!(error.to_string(), "scene.txt: line 2: unknown camera");
+        let error: Error = io::Error::other("disk full").into();
+        assert!(std::error::Error::source(&error).is_some());
+    }
+}
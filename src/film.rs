@@ -0,0 +1,315 @@
+use crate::filter::Filter;
+use crate::float::Float;
+use crate::spectrum::*;
+use crate::vec3::*;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy)]
+struct FilmPixel {
+    rgb_sum: Vec3,
+    weight_sum: Float,
+}
+
+impl FilmPixel {
+    fn empty() -> Self {
+        FilmPixel {
+            rgb_sum: Vec3(0.0, 0.0, 0.0),
+            weight_sum: 0.0,
+        }
+    }
+}
+
+pub fn clamp_to_u8(val: Float) -> u8 {
+    let result = val.max(0.0).sqrt().min(1.0);
+    (result * 255.99) as u8
+}
+
+/// A region of the film that one worker renders into. Samples are only
+/// generated for pixels inside `sample_bounds`, but each sample is splatted
+/// into every pixel within the filter radius, so the tile keeps its own
+/// accumulation buffer over the larger `pixel_bounds`. Overlapping borders
+/// of neighbouring tiles are summed when the tiles are merged into the film,
+/// which is what keeps the tiles from showing seams.
+pub struct FilmTile {
+    pub sample_bounds: (usize, usize, usize, usize),
+    pub pixel_bounds: (usize, usize, usize, usize),
+    pub width: usize,
+    pub height: usize,
+    filter: Arc<dyn Filter + Send + Sync>,
+    pixels: Vec<FilmPixel>,
+}
+
+impl FilmTile {
+    /// adds a sample taken at continuous pixel coordinates (px, py), where
+    /// pixel (x, y) covers [x, x + 1) x [y, y + 1) and has its center at
+    /// (x + 0.5, y + 0.5).
+    pub fn add_sample(&mut self, px: Float, py: Float, rgb: Vec3) {
+        let radius = self.filter.radius();
+        let (x0, y0, x1, y1) = self.pixel_bounds;
+        let from_x = ((px - 0.5 - radius).ceil().max(x0 as Float)) as usize;
+        let from_y = ((py - 0.5 - radius).ceil().max(y0 as Float)) as usize;
+        let to_x = ((px - 0.5 + radius).floor() + 1.0).min(x1 as Float);
+        let to_y = ((py - 0.5 + radius).floor() + 1.0).min(y1 as Float);
+        if to_x < 0.0 || to_y < 0.0 {
+            return;
+        }
+        let tile_width = x1 - x0;
+        for y in from_y..to_y as usize {
+            for x in from_x..to_x as usize {
+                let weight = self
+                    .filter
+                    .evaluate(x as Float + 0.5 - px, y as Float + 0.5 - py);
+                let pixel = &mut self.pixels[(y - y0) * tile_width + (x - x0)];
+                pixel.rgb_sum += weight * rgb;
+                pixel.weight_sum += weight;
+            }
+        }
+    }
+
+    /// adds a path traced in spectral mode, converting it to the film's
+    /// color space first
+    pub fn add_spectral_sample(
+        &mut self,
+        px: Float,
+        py: Float,
+        spectrum: &SampledSpectrum,
+        wavelengths: &SampledWavelengths,
+        converter: &SpectrumConverter,
+    ) {
+        self.add_sample(px, py, converter.to_rgb(spectrum, wavelengths));
+    }
+}
+
+pub struct Film {
+    pub width: usize,
+    pub height: usize,
+    filter: Arc<dyn Filter + Send + Sync>,
+    pixels: Vec<FilmPixel>,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize, filter: Arc<dyn Filter + Send + Sync>) -> Self {
+        Film {
+            width,
+            height,
+            filter,
+            pixels: vec![FilmPixel::empty(); width * height],
+        }
+    }
+
+    /// creates a tile that takes samples for pixels in [x0, x1) x [y0, y1)
+    pub fn tile(&self, x0: usize, y0: usize, x1: usize, y1: usize) -> FilmTile {
+        let radius = self.filter.radius();
+        let pixel_x0 = (x0 as Float - radius).ceil().max(0.0) as usize;
+        let pixel_y0 = (y0 as Float - radius).ceil().max(0.0) as usize;
+        let pixel_x1 = ((x1 as Float - 1.0 + radius).floor() as usize + 1).min(self.width);
+        let pixel_y1 = ((y1 as Float - 1.0 + radius).floor() as usize + 1).min(self.height);
+        FilmTile {
+            sample_bounds: (x0, y0, x1, y1),
+            pixel_bounds: (pixel_x0, pixel_y0, pixel_x1, pixel_y1),
+            width: self.width,
+            height: self.height,
+            filter: self.filter.clone(),
+            pixels: vec![FilmPixel::empty(); (pixel_x1 - pixel_x0) * (pixel_y1 - pixel_y0)],
+        }
+    }
+
+    pub fn merge_tile(&mut self, tile: &FilmTile) {
+        let (x0, y0, x1, y1) = tile.pixel_bounds;
+        let tile_width = x1 - x0;
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let src = tile.pixels[(y - y0) * tile_width + (x - x0)];
+                let dst = &mut self.pixels[y * self.width + x];
+                dst.rgb_sum += src.rgb_sum;
+                dst.weight_sum += src.weight_sum;
+            }
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Vec3 {
+        let p = self.pixels[y * self.width + x];
+        if p.weight_sum > 0.0 {
+            p.rgb_sum / p.weight_sum
+        } else {
+            Vec3(0.0, 0.0, 0.0)
+        }
+    }
+
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.width * self.height * 4);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let rgb = self.pixel(x, y);
+                data.push(clamp_to_u8(rgb.r()));
+                data.push(clamp_to_u8(rgb.g()));
+                data.push(clamp_to_u8(rgb.b()));
+                data.push(255_u8);
+            }
+        }
+        data
+    }
+
+    /// The film as an uncompressed OpenEXR file with 32-bit float channels,
+    /// keeping the full range of the linear radiance.
+    pub fn to_exr(&self) -> Vec<u8> {
+        fn attribute(data: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+            data.extend_from_slice(name.as_bytes());
+            data.push(0);
+            data.extend_from_slice(kind.as_bytes());
+            data.push(0);
+            data.extend_from_slice(&(value.len() as i32).to_le_bytes());
+            data.extend_from_slice(value);
+        }
+        let mut data = vec![];
+        // magic number, then version 2 for a single part scanline file
+        data.extend_from_slice(&[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+        // channels are listed alphabetically, each FLOAT (2) and unsampled
+        let mut channels = vec![];
+        for name in &["B", "G", "R"] {
+            channels.extend_from_slice(name.as_bytes());
+            channels.push(0);
+            channels.extend_from_slice(&2_i32.to_le_bytes());
+            channels.extend_from_slice(&[0, 0, 0, 0]);
+            channels.extend_from_slice(&1_i32.to_le_bytes());
+            channels.extend_from_slice(&1_i32.to_le_bytes());
+        }
+        channels.push(0);
+        attribute(&mut data, "channels", "chlist", &channels);
+        attribute(&mut data, "compression", "compression", &[0]);
+        let mut window = vec![];
+        for v in &[0, 0, self.width as i32 - 1, self.height as i32 - 1] {
+            window.extend_from_slice(&v.to_le_bytes());
+        }
+        attribute(&mut data, "dataWindow", "box2i", &window);
+        attribute(&mut data, "displayWindow", "box2i", &window);
+        attribute(&mut data, "lineOrder", "lineOrder", &[0]);
+        attribute(
+            &mut data,
+            "pixelAspectRatio",
+            "float",
+            &1.0_f32.to_le_bytes(),
+        );
+        attribute(&mut data, "screenWindowCenter", "v2f", &[0; 8]);
+        attribute(
+            &mut data,
+            "screenWindowWidth",
+            "float",
+            &1.0_f32.to_le_bytes(),
+        );
+        data.push(0);
+        // a table of where each scanline starts, then the scanlines
+        let line_size = self.width * 3 * 4;
+        let first_line = data.len() + self.height * 8;
+        for y in 0..self.height {
+            let offset = first_line + y * (8 + line_size);
+            data.extend_from_slice(&(offset as u64).to_le_bytes());
+        }
+        for y in 0..self.height {
+            data.extend_from_slice(&(y as i32).to_le_bytes());
+            data.extend_from_slice(&(line_size as i32).to_le_bytes());
+            for channel in &[2, 1, 0] {
+                for x in 0..self.width {
+                    // a no-op with the `f32` feature
+                    #[allow(clippy::unnecessary_cast)]
+                    let value = self.pixel(x, y)[*channel] as f32;
+                    data.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+        }
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::*;
+    use crate::float::tolerance;
+    use approx::assert_relative_eq;
+
+    fn fill_tile(tile: &mut FilmTile, rgb: Vec3) {
+        let (x0, y0, x1, y1) = tile.sample_bounds;
+        for y in y0..y1 {
+            for x in x0..x1 {
+                for (dx, dy) in &[(0.25, 0.25), (0.75, 0.25), (0.25, 0.75), (0.75, 0.75)] {
+                    tile.add_sample(x as Float + dx, y as Float + dy, rgb);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn box_filter_matches_pixel_average() {
+        let mut film = Film::new(4, 4, Arc::new(BoxFilter { radius: 0.5 }));
+        let mut tile = film.tile(0, 0, 4, 4);
+        assert_eq!(tile.pixel_bounds, (0, 0, 4, 4));
+        tile.add_sample(1.2, 2.7, Vec3(1.0, 0.0, 0.0));
+        tile.add_sample(1.8, 2.1, Vec3(0.0, 1.0, 0.0));
+        film.merge_tile(&tile);
+        assert_eq!(film.pixel(1, 2), Vec3(0.5, 0.5, 0.0));
+        assert_eq!(film.pixel(0, 0), Vec3(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn tile_is_padded_by_filter_radius() {
+        let film = Film::new(10, 10, Arc::new(TentFilter { radius: 1.5 }));
+        let tile = film.tile(0, 4, 10, 6);
+        assert_eq!(tile.pixel_bounds, (0, 3, 10, 7));
+        let corner = film.tile(9, 9, 10, 10);
+        assert_eq!(corner.pixel_bounds, (8, 8, 10, 10));
+    }
+
+    #[test]
+    fn tiles_merge_without_seams() {
+        let filter = Arc::new(MitchellFilter {
+            radius: 2.0,
+            b: 1.0 / 3.0,
+            c: 1.0 / 3.0,
+        });
+        let mut film = Film::new(8, 8, filter);
+        for band in 0..4 {
+            let mut tile = film.tile(0, band * 2, 8, band * 2 + 2);
+            fill_tile(&mut tile, Vec3(0.25, 0.5, 0.75));
+            film.merge_tile(&tile);
+        }
+        for y in 0..8 {
+            for x in 0..8 {
+                let p = film.pixel(x, y);
+                assert_relative_eq!(p.r(), 0.25, epsilon = tolerance(1e-9));
+                assert_relative_eq!(p.g(), 0.5, epsilon = tolerance(1e-9));
+                assert_relative_eq!(p.b(), 0.75, epsilon = tolerance(1e-9));
+            }
+        }
+    }
+
+    #[test]
+    fn exr_layout() {
+        let mut film = Film::new(3, 2, Arc::new(BoxFilter { radius: 0.5 }));
+        let mut tile = film.tile(0, 0, 3, 2);
+        fill_tile(&mut tile, Vec3(0.25, 0.5, 2.0));
+        film.merge_tile(&tile);
+        let data = film.to_exr();
+        assert_eq!(&data[..4], &[0x76, 0x2f, 0x31, 0x01]);
+        // two scanlines of three pixels with three channels each, after
+        // their y and size
+        let line = 8 + 3 * 3 * 4;
+        let last = &data[data.len() - line..];
+        assert_eq!(&last[..4], &1_i32.to_le_bytes());
+        // blue comes first
+        assert_eq!(&last[8..12], &2.0_f32.to_le_bytes());
+        assert_eq!(&last[last.len() - 4..], &0.25_f32.to_le_bytes());
+        let table = data.len() - 2 * line - 16;
+        let mut first = [0; 8];
+        first.copy_from_slice(&data[table..table + 8]);
+        assert_eq!(u64::from_le_bytes(first) as usize, data.len() - 2 * line);
+    }
+
+    #[test]
+    fn clamp_to_u8_clamps() {
+        assert_eq!(clamp_to_u8(-1.0), 0);
+        assert_eq!(clamp_to_u8(0.0), 0);
+        assert_eq!(clamp_to_u8(1.0), 255);
+        assert_eq!(clamp_to_u8(4.0), 255);
+    }
+}
@@ -0,0 +1,192 @@
+use crate::float::Float;
+
+/// Pixel reconstruction filters. All filters here are separable, so
+/// `evaluate` is the product of a 1D profile in x and in y, and they are
+/// zero outside of [-radius, radius] on both axes.
+pub trait Filter {
+    fn radius(&self) -> Float;
+    fn evaluate(&self, x: Float, y: Float) -> Float;
+}
+
+pub struct BoxFilter {
+    pub radius: Float,
+}
+
+impl Filter for BoxFilter {
+    fn radius(&self) -> Float {
+        self.radius
+    }
+    fn evaluate(&self, x: Float, y: Float) -> Float {
+        if x.abs() <= self.radius && y.abs() <= self.radius {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+pub struct TentFilter {
+    pub radius: Float,
+}
+
+impl TentFilter {
+    fn tent_1d(&self, x: Float) -> Float {
+        (self.radius - x.abs()).max(0.0)
+    }
+}
+
+impl Filter for TentFilter {
+    fn radius(&self) -> Float {
+        self.radius
+    }
+    fn evaluate(&self, x: Float, y: Float) -> Float {
+        self.tent_1d(x) * self.tent_1d(y)
+    }
+}
+
+pub struct GaussianFilter {
+    pub radius: Float,
+    pub alpha: Float,
+}
+
+impl GaussianFilter {
+    // the gaussian is shifted down so it reaches exactly zero at the radius
+    fn gaussian_1d(&self, x: Float) -> Float {
+        let edge = (-self.alpha * self.radius * self.radius).exp();
+        ((-self.alpha * x * x).exp() - edge).max(0.0)
+    }
+}
+
+impl Filter for GaussianFilter {
+    fn radius(&self) -> Float {
+        self.radius
+    }
+    fn evaluate(&self, x: Float, y: Float) -> Float {
+        self.gaussian_1d(x) * self.gaussian_1d(y)
+    }
+}
+
+pub struct MitchellFilter {
+    pub radius: Float,
+    pub b: Float,
+    pub c: Float,
+}
+
+impl MitchellFilter {
+    // the cubic is defined on [-2, 2], so x is rescaled from [-radius, radius]
+    fn mitchell_1d(&self, x: Float) -> Float {
+        let x = (2.0 * x / self.radius).abs();
+        let (b, c) = (self.b, self.c);
+        let value = if x > 2.0 {
+            0.0
+        } else if x > 1.0 {
+            (-b - 6.0 * c) * x * x * x
+                + (6.0 * b + 30.0 * c) * x * x
+                + (-12.0 * b - 48.0 * c) * x
+                + (8.0 * b + 24.0 * c)
+        } else {
+            (12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                + (6.0 - 2.0 * b)
+        };
+        value / 6.0
+    }
+}
+
+impl Filter for MitchellFilter {
+    fn radius(&self) -> Float {
+        self.radius
+    }
+    fn evaluate(&self, x: Float, y: Float) -> Float {
+        self.mitchell_1d(x) * self.mitchell_1d(y)
+    }
+}
+
+pub struct LanczosFilter {
+    pub radius: Float,
+    pub tau: Float,
+}
+
+fn sinc(x: Float) -> Float {
+    let x = x.abs();
+    if x < 1e-5 {
+        return 1.0;
+    }
+    let px = crate::float::consts::PI * x;
+    px.sin() / px
+}
+
+impl LanczosFilter {
+    fn windowed_sinc_1d(&self, x: Float) -> Float {
+        let x = x.abs();
+        if x > self.radius {
+            return 0.0;
+        }
+        sinc(x) * sinc(x / self.tau)
+    }
+}
+
+impl Filter for LanczosFilter {
+    fn radius(&self) -> Float {
+        self.radius
+    }
+    fn evaluate(&self, x: Float, y: Float) -> Float {
+        self.windowed_sinc_1d(x) * self.windowed_sinc_1d(y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn filters_vanish_outside_radius() {
+        let filters: Vec<Box<dyn Filter>> = vec![
+            Box::new(BoxFilter { radius: 0.5 }),
+            Box::new(TentFilter { radius: 1.0 }),
+            Box::new(GaussianFilter {
+                radius: 1.5,
+                alpha: 2.0,
+            }),
+            Box::new(MitchellFilter {
+                radius: 2.0,
+                b: 1.0 / 3.0,
+                c: 1.0 / 3.0,
+            }),
+            Box::new(LanczosFilter {
+                radius: 3.0,
+                tau: 3.0,
+            }),
+        ];
+        for f in &filters {
+            let r = f.radius();
+            assert!(f.evaluate(0.0, 0.0) > 0.0);
+            assert_relative_eq!(f.evaluate(r + 0.01, 0.0), 0.0);
+            assert_relative_eq!(f.evaluate(0.0, -r - 0.01), 0.0);
+        }
+    }
+
+    #[test]
+    fn mitchell_is_normalized_at_center() {
+        let f = MitchellFilter {
+            radius: 2.0,
+            b: 0.0,
+            c: 0.0,
+        };
+        // with b = c = 0 the center weight is exactly 1
+        assert_relative_eq!(f.evaluate(0.0, 0.0), 1.0);
+        // and the cubic passes through zero at x = 1
+        assert_relative_eq!(f.evaluate(1.0, 0.0), 0.0, epsilon = 1e-12);
+    }
+
+    #[test]
+    fn lanczos_has_negative_lobes() {
+        let f = LanczosFilter {
+            radius: 3.0,
+            tau: 3.0,
+        };
+        assert_relative_eq!(f.evaluate(0.0, 0.0), 1.0);
+        assert!(f.evaluate(1.5, 0.0) < 0.0);
+    }
+}
@@ -0,0 +1,28 @@
+/// The floating point type of the render core: f64, or f32 with the `f32`
+/// feature, which halves memory traffic and doubles the SIMD lanes.
+#[cfg(not(feature = "f32"))]
+pub type Float = f64;
+#[cfg(feature = "f32")]
+pub type Float = f32;
+
+#[cfg(feature = "f32")]
+pub use std::f32::consts;
+#[cfg(not(feature = "f32"))]
+pub use std::f64::consts;
+
+/// A bound on the relative error of `n` rounded operations in a row.
+pub fn gamma(n: i32) -> Float {
+    let e = n as Float * Float::EPSILON * 0.5;
+    e / (1.0 - e)
+}
+
+// A test tolerance written for f64, scaled to the precision of f32 with the
+// `f32` feature.
+#[cfg(all(test, not(feature = "f32")))]
+pub(crate) fn tolerance(epsilon: f64) -> Float {
+    epsilon
+}
+#[cfg(all(test, feature = "f32"))]
+pub(crate) fn tolerance(epsilon: f64) -> Float {
+    (epsilon * (f32::EPSILON as f64 / f64::EPSILON)) as f32
+}
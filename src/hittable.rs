@@ -1,59 +1,293 @@
+use crate::aabb::Aabb;
+use crate::compact::MaterialKind;
+use crate::float::{gamma, Float};
+use crate::mat4::Mat4;
 use crate::materials::Material;
 use crate::ray::*;
+use crate::spectrum::*;
 use crate::vec3::*;
+use rand::rngs::StdRng;
+use std::any::Any;
 
+/// The material at a hit. The primitives of a compact scene refer to its
+/// `MaterialKind`s, which are dispatched with a match; everything else hands
+/// out a trait object. It has the methods of `Material`, which the
+/// integrator calls on it directly.
+#[derive(Clone, Copy)]
+pub enum HitMaterial<'a> {
+    Kind(&'a MaterialKind),
+    Dyn(&'a (dyn Material + Send + Sync)),
+}
+
+impl HitMaterial<'_> {
+    pub fn scatter(&self, ray: &Ray, hit_record: &HitRecord, rng: &mut StdRng) -> (Ray, Vec3) {
+        match self {
+            HitMaterial::Kind(m) => m.scatter(ray, hit_record, rng),
+            HitMaterial::Dyn(m) => m.scatter(ray, hit_record, rng),
+        }
+    }
+    pub fn emitted(&self, ray: &Ray, hit_record: &HitRecord) -> Vec3 {
+        match self {
+            HitMaterial::Kind(m) => m.emitted(ray, hit_record),
+            HitMaterial::Dyn(m) => m.emitted(ray, hit_record),
+        }
+    }
+    pub fn scatter_spectral(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        wavelengths: &SampledWavelengths,
+        rng: &mut StdRng,
+    ) -> (Ray, SampledSpectrum) {
+        match self {
+            HitMaterial::Kind(m) => m.scatter_spectral(ray, hit_record, wavelengths, rng),
+            HitMaterial::Dyn(m) => m.scatter_spectral(ray, hit_record, wavelengths, rng),
+        }
+    }
+    pub fn emitted_spectral(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        wavelengths: &SampledWavelengths,
+    ) -> SampledSpectrum {
+        match self {
+            HitMaterial::Kind(m) => m.emitted_spectral(ray, hit_record, wavelengths),
+            HitMaterial::Dyn(m) => m.emitted_spectral(ray, hit_record, wavelengths),
+        }
+    }
+}
+
+/// Where a ray hit a surface. Normals point out of the surface; the
+/// shading normal can differ from the geometric one where normals are
+/// interpolated or perturbed. `dpdu` and `dpdv` are the derivatives of the
+/// point with respect to the UVs, or some tangent frame where the surface
+/// has no natural one. `object_id` is the object's index in the scene, and
+/// `primitive_id` tells apart the parts of an object, such as a box's faces.
+/// `error` bounds the rounding error in `point` per axis; rays leave from
+/// `spawn_point` to clear it.
 pub struct HitRecord<'a> {
-    pub t: f64,
+    pub t: Float,
+    pub point: Vec3,
+    pub error: Vec3,
+    pub geometric_normal: Vec3,
     pub normal: Vec3,
-    pub material: Option<&'a (dyn Material + Send + Sync)>,
+    /// whether the ray arrived from outside, against the geometric normal
+    pub front_face: bool,
+    pub u: Float,
+    pub v: Float,
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    pub object_id: usize,
+    pub primitive_id: usize,
+    pub material: HitMaterial<'a>,
 }
 
-impl HitRecord<'_> {
-    fn new_miss() -> Self {
+impl<'a> HitRecord<'a> {
+    /// a hit at `t` along `ray` on a surface with the outward unit `normal`
+    pub fn new(ray: &Ray, t: Float, normal: Vec3, material: HitMaterial<'a>) -> Self {
+        let (dpdu, dpdv) = normal.orthonormal_basis();
+        let offset = t * ray.dir;
         HitRecord {
-            t: -1.0,
-            normal: Vec3(0.0, 0.0, 0.0),
-            material: None,
+            t,
+            point: ray.pos + offset,
+            // a few rounding errors in t, which dominate, and in the sum
+            error: gamma(7) * (ray.pos.abs() + offset.abs()),
+            geometric_normal: normal,
+            normal,
+            front_face: ray.dir.dot(normal) < 0.0,
+            u: 0.0,
+            v: 0.0,
+            dpdu,
+            dpdv,
+            object_id: 0,
+            primitive_id: 0,
+            material,
+        }
+    }
+
+    /// a point recomputed on the surface itself, which is more accurate than
+    /// the one along the ray
+    pub fn point_with_error(mut self, point: Vec3, error: Vec3) -> Self {
+        self.point = point;
+        self.error = error;
+        self
+    }
+
+    pub fn uv(mut self, u: Float, v: Float) -> Self {
+        self.u = u;
+        self.v = v;
+        self
+    }
+
+    pub fn tangents(mut self, dpdu: Vec3, dpdv: Vec3) -> Self {
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+        self
+    }
+
+    pub fn shading_normal(mut self, normal: Vec3) -> Self {
+        self.normal = normal;
+        self
+    }
+
+    pub fn primitive_id(mut self, id: usize) -> Self {
+        self.primitive_id = id;
+        self
+    }
+
+    /// the shading normal, turned to the side the ray came from
+    pub fn facing_normal(&self) -> Vec3 {
+        if self.front_face {
+            self.normal
+        } else {
+            -self.normal
         }
     }
+
+    /// The point a ray toward `dir` should leave from: the hit point pushed
+    /// along the geometric normal, to the side of `dir`, just past the error
+    /// bound, so that the ray can't hit the same surface again right away.
+    pub fn spawn_point(&self, dir: Vec3) -> Vec3 {
+        let n = self.geometric_normal;
+        let distance = n.abs().dot(self.error);
+        let offset = if dir.dot(n) < 0.0 {
+            -distance * n
+        } else {
+            distance * n
+        };
+        let p = self.point + offset;
+        // the sum can round back toward the surface, so step one more ulp
+        let away = |x: Float, o: Float| {
+            if o > 0.0 {
+                x.next_up()
+            } else if o < 0.0 {
+                x.next_down()
+            } else {
+                x
+            }
+        };
+        Vec3(
+            away(p.x(), offset.x()),
+            away(p.y(), offset.y()),
+            away(p.z(), offset.z()),
+        )
+    }
+
+    /// Moves a hit found with an object's local ray into the world; the
+    /// world ray has the same `t`.
+    pub fn transformed(mut self, transform: &Mat4, inverse: &Mat4) -> Self {
+        let (point, error) = transform.transform_point_with_error(self.point, self.error);
+        self.point = point;
+        self.error = error;
+        self.geometric_normal = inverse.transform_normal(self.geometric_normal).normalized();
+        self.normal = inverse.transform_normal(self.normal).normalized();
+        self.dpdu = transform.transform_vector(self.dpdu);
+        self.dpdv = transform.transform_vector(self.dpdv);
+        self
+    }
 }
 
-pub trait Hittable {
-    fn hit(&self, _ray: &Ray, _t_min: f64, _t_max: f64) -> HitRecord {
-        HitRecord::new_miss()
+/// `Any` lets a compact scene take built-in shapes out of a scene graph.
+pub trait Hittable: Any {
+    fn hit(&self, _ray: &Ray, _t_min: Float, _t_max: Float) -> Option<HitRecord<'_>> {
+        None
+    }
+    /// None for unbounded objects such as infinite planes
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
     }
 }
 
 pub struct Sphere {
     pub center: Vec3,
-    pub radius: f64,
+    pub radius: Float,
     pub material: Box<dyn Material + Send + Sync>,
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> HitRecord {
-        let oc = ray.pos - self.center;
-        let a = ray.dir.dot(ray.dir);
-        let b = 2.0 * oc.dot(ray.dir);
-        let c = oc.dot(oc) - self.radius * self.radius;
-        let discriminant = b * b - 4.0 * a * c;
-        if discriminant < 0.0 {
-            return HitRecord::new_miss();
-        }
-        let mut t = (-b - discriminant.sqrt()) / (2.0 * a);
-        if t < t_min || t > t_max {
-            t = (-b + discriminant.sqrt()) / (2.0 * a);
-        }
-        if t < t_min || t > t_max {
-            return HitRecord::new_miss();
-        }
-        let normal = (ray.point_at_t(t) - self.center).normalized();
-        HitRecord {
-            t,
-            normal,
-            material: Some(&*self.material),
-        }
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        hit_sphere(
+            self.center,
+            self.radius,
+            HitMaterial::Dyn(&*self.material),
+            ray,
+            t_min,
+            t_max,
+        )
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = Vec3(self.radius, self.radius, self.radius);
+        Some(Aabb::new(self.center - r, self.center + r))
+    }
+}
+
+/// The nearest hit on a sphere within [t_min, t_max]. Shared by `Sphere` and
+/// the spheres of a compact scene.
+pub fn hit_sphere<'a>(
+    center: Vec3,
+    radius: Float,
+    material: HitMaterial<'a>,
+    ray: &Ray,
+    t_min: Float,
+    t_max: Float,
+) -> Option<HitRecord<'a>> {
+    let oc = ray.pos - center;
+    let a = ray.dir.dot(ray.dir);
+    let b = 2.0 * oc.dot(ray.dir);
+    let c = oc.dot(oc) - radius * radius;
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let mut t = (-b - discriminant.sqrt()) / (2.0 * a);
+    if t < t_min || t > t_max {
+        t = (-b + discriminant.sqrt()) / (2.0 * a);
+    }
+    if t < t_min || t > t_max {
+        return None;
+    }
+    let normal = (ray.point_at_t(t) - center).normalized();
+    Some(sphere_hit(ray, t, center, normal, radius, material))
+}
+
+/// the hit on a sphere about `center` of `radius` where its unit normal is
+/// `normal`, with the UVs of `sphere_uv` and their tangents
+pub fn sphere_hit<'a>(
+    ray: &Ray,
+    t: Float,
+    center: Vec3,
+    normal: Vec3,
+    radius: Float,
+    material: HitMaterial<'a>,
+) -> HitRecord<'a> {
+    let (u, v) = sphere_uv(&normal);
+    // projected onto the sphere, which leaves only the error of the normal
+    let offset = radius * normal;
+    let hit = HitRecord::new(ray, t, normal, material)
+        .point_with_error(center + offset, gamma(6) * (center.abs() + offset.abs()))
+        .uv(u, v);
+    let (x, y, z) = (normal.x(), normal.y(), normal.z());
+    let ring = (x * x + z * z).sqrt();
+    // the poles have no tangents of their own
+    if ring < 1e-9 {
+        return hit;
     }
+    let pi = crate::float::consts::PI;
+    hit.tangents(
+        2.0 * pi * radius * Vec3(z, 0.0, -x),
+        pi * radius * Vec3(-y * x / ring, ring, -y * z / ring),
+    )
+}
+
+/// u goes around the y axis starting at -x, v goes from the bottom pole
+/// (v = 0) to the top pole (v = 1)
+pub fn sphere_uv(p: &Vec3) -> (Float, Float) {
+    let theta = (-p.y()).clamp(-1.0, 1.0).acos();
+    let phi = (-p.z()).atan2(p.x()) + crate::float::consts::PI;
+    (
+        phi / (2.0 * crate::float::consts::PI),
+        theta / crate::float::consts::PI,
+    )
 }
 
 pub struct HittableList {
@@ -61,18 +295,28 @@ pub struct HittableList {
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> HitRecord {
-        let mut closest = HitRecord::new_miss();
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        let mut closest = None;
+        let mut t_closest = t_max;
         for h in &self.list {
-            let hit_record = h.hit(ray, t_min, t_max);
-            if hit_record.t > 0.0 {
-                if closest.t < 0.0 || hit_record.t < closest.t {
-                    closest = hit_record
-                }
+            if let Some(hit_record) = h.hit(ray, t_min, t_closest) {
+                t_closest = hit_record.t;
+                closest = Some(hit_record);
             }
         }
         closest
     }
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut result: Option<Aabb> = None;
+        for h in &self.list {
+            let b = h.bounding_box()?;
+            result = Some(match result {
+                Some(r) => r.union(&b),
+                None => b,
+            });
+        }
+        result
+    }
 }
 
 impl HittableList {
@@ -83,12 +327,33 @@ impl HittableList {
     pub fn push<T: Hittable + 'static + Send + Sync>(&mut self, h: T) -> () {
         self.list.push(Box::new(h))
     }
+
+    pub fn push_boxed(&mut self, h: Box<dyn Hittable + Send + Sync>) {
+        self.list.push(h)
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+}
+
+impl Default for HittableList {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::float::consts::PI;
     use approx::assert_relative_eq;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
 
     struct TestMaterial {}
     impl Material for TestMaterial {}
@@ -100,19 +365,88 @@ mod tests {
             radius: 0.5,
             material: Box::new(TestMaterial {}),
         };
-        let r = Ray {
-            pos: Vec3(0.0, 0.0, 0.0),
-            dir: Vec3(0.0, 1.0, 0.0),
+        let r = Ray::new(Vec3(0.0, 0.0, 0.0), Vec3(0.0, 1.0, 0.0));
+        assert!(s.hit(&r, 0.0, Float::MAX).is_none()); // misses
+        let r2 = Ray::new(Vec3(0.0, 0.0, 0.0), Vec3(0.0, 0.0, -1.0));
+        assert_relative_eq!(s.hit(&r2, 0.0, Float::MAX).unwrap().t, 0.5); // hits the sphere
+        assert_relative_eq!(s.hit(&r2, 0.0, Float::MAX).unwrap().normal.length(), 1.0);
+        assert_relative_eq!(s.hit(&r2, 0.0, Float::MAX).unwrap().normal.z(), 1.0);
+    }
+    #[test]
+    #[cfg_attr(feature = "f32", ignore = "finite differences need f64")]
+    fn sphere_records_hit_frame() {
+        let s = Sphere {
+            center: Vec3(0.0, 0.0, 0.0),
+            radius: 2.0,
+            material: Box::new(TestMaterial {}),
+        };
+        let r = Ray::new(Vec3(0.0, 0.0, 5.0), Vec3(0.1, 0.2, -1.0));
+        let outside = s.hit(&r, 0.0, Float::MAX).unwrap();
+        assert!(outside.front_face);
+        // on the sphere, and close to where the ray got to
+        assert_relative_eq!(outside.point.length(), 2.0);
+        assert!((outside.point - r.point_at_t(outside.t)).length() < 1e-12);
+        assert_eq!(outside.facing_normal(), outside.normal);
+        let inside = s.hit(&r, outside.t + 1e-6, Float::MAX).unwrap();
+        assert!(!inside.front_face);
+        assert_eq!(inside.facing_normal(), -inside.normal);
+        // the tangents are the derivatives of the point along the UVs
+        let point = |u: Float, v: Float| {
+            let (theta, phi) = (v * PI, 2.0 * PI * u - PI);
+            2.0 * Vec3(
+                theta.sin() * phi.cos(),
+                -theta.cos(),
+                -theta.sin() * phi.sin(),
+            )
         };
-        assert_relative_eq!(s.hit(&r, 0.0, std::f64::MAX).t, -1.0); // misses
-        let r2 = Ray {
-            pos: Vec3(0.0, 0.0, 0.0),
-            dir: Vec3(0.0, 0.0, -1.0),
+        let h = 1e-6;
+        for hit in &[outside, inside] {
+            let (u, v) = (hit.u, hit.v);
+            assert_relative_eq!((point(u, v) - hit.point).length(), 0.0, epsilon = 1e-9);
+            let dpdu = (point(u + h, v) - point(u - h, v)) / (2.0 * h);
+            let dpdv = (point(u, v + h) - point(u, v - h)) / (2.0 * h);
+            assert_relative_eq!((dpdu - hit.dpdu).length(), 0.0, epsilon = 1e-6);
+            assert_relative_eq!((dpdv - hit.dpdv).length(), 0.0, epsilon = 1e-6);
+        }
+    }
+
+    #[test]
+    fn sphere_uv_and_bounds() {
+        let s = Sphere {
+            center: Vec3(1.0, 2.0, 3.0),
+            radius: 0.5,
+            material: Box::new(TestMaterial {}),
         };
-        assert_relative_eq!(s.hit(&r2, 0.0, std::f64::MAX).t, 0.5); // hits the sphere
-        assert_relative_eq!(s.hit(&r2, 0.0, std::f64::MAX).normal.length(), 1.0);
-        assert_relative_eq!(s.hit(&r2, 0.0, std::f64::MAX).normal.z(), 1.0);
+        let b = s.bounding_box().unwrap();
+        assert_eq!(b.min, Vec3(0.5, 1.5, 2.5));
+        assert_eq!(b.max, Vec3(1.5, 2.5, 3.5));
+        let (u, v) = sphere_uv(&Vec3(0.0, 1.0, 0.0));
+        assert_relative_eq!(v, 1.0);
+        assert!((0.0..=1.0).contains(&u));
+        let (u, v) = sphere_uv(&Vec3(-1.0, 0.0, 0.0));
+        assert_relative_eq!(u, 0.0);
+        assert_relative_eq!(v, 0.5);
     }
+
+    #[test]
+    fn list_bounds_cover_all_objects() {
+        let mut l = HittableList::new();
+        assert!(l.bounding_box().is_none());
+        l.push(Sphere {
+            center: Vec3(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material: Box::new(TestMaterial {}),
+        });
+        l.push(Sphere {
+            center: Vec3(3.0, 0.0, 0.0),
+            radius: 1.0,
+            material: Box::new(TestMaterial {}),
+        });
+        let b = l.bounding_box().unwrap();
+        assert_eq!(b.min, Vec3(-1.0, -1.0, -1.0));
+        assert_eq!(b.max, Vec3(4.0, 1.0, 1.0));
+    }
+
     #[test]
     fn list_can_add_stuff() {
         let mut l = HittableList::new();
@@ -127,41 +461,67 @@ mod tests {
             radius: 0.0,
             material: Box::new(TestMaterial {}),
         };
-        let r = Ray {
-            pos: Vec3(0.0, 0.0, 0.0),
-            dir: Vec3(0.0, 1.0, 0.0),
-        };
+        let r = Ray::new(Vec3(0.0, 0.0, 0.0), Vec3(0.0, 1.0, 0.0));
         l.push(s);
         l2.push(s2);
         l.push(l2);
-        assert_relative_eq!(l.hit(&r, 0.0, std::f64::MAX).t, -1.0);
+        assert!(l.hit(&r, 0.0, Float::MAX).is_none());
     }
 
     #[test]
     fn list_is_hittable() {
         let mut l = HittableList::new();
-        let r = Ray {
-            pos: Vec3(0.0, 0.0, 0.0),
-            dir: Vec3(0.0, 0.0, -1.0),
-        };
-        assert_relative_eq!(l.hit(&r, 0.0, std::f64::MAX).t, -1.0);
+        let r = Ray::new(Vec3(0.0, 0.0, 0.0), Vec3(0.0, 0.0, -1.0));
+        assert!(l.hit(&r, 0.0, Float::MAX).is_none());
         l.push(Sphere {
             center: Vec3(0.0, 0.0, -1.0),
             radius: 0.5,
             material: Box::new(TestMaterial {}),
         });
-        assert_relative_eq!(l.hit(&r, 0.0, std::f64::MAX).t, 0.5);
+        assert_relative_eq!(l.hit(&r, 0.0, Float::MAX).unwrap().t, 0.5);
         l.push(Sphere {
             center: Vec3(0.0, 0.0, -2.0),
             radius: 0.5,
             material: Box::new(TestMaterial {}),
         });
-        assert_relative_eq!(l.hit(&r, 0.0, std::f64::MAX).t, 0.5);
+        assert_relative_eq!(l.hit(&r, 0.0, Float::MAX).unwrap().t, 0.5);
         l.push(Sphere {
             center: Vec3(0.0, 0.0, -0.9),
             radius: 0.5,
             material: Box::new(TestMaterial {}),
         });
-        assert_relative_eq!(l.hit(&r, 0.0, std::f64::MAX).t, 0.4);
+        assert_relative_eq!(l.hit(&r, 0.0, Float::MAX).unwrap().t, 0.4);
+    }
+
+    fn random_dir(rng: &mut StdRng) -> Vec3 {
+        Vec3(
+            rng.gen::<Float>() - 0.5,
+            rng.gen::<Float>() - 0.5,
+            rng.gen::<Float>() - 0.5,
+        )
+        .normalized()
+    }
+
+    #[test]
+    fn spawned_rays_clear_the_surface() {
+        // far from the origin, where the rounding errors are large
+        let s = Sphere {
+            center: Vec3(1000.0, -2000.0, 500.0),
+            radius: 3.0,
+            material: Box::new(TestMaterial {}),
+        };
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..10000 {
+            let pos = s.center + 10.0 * random_dir(&mut rng);
+            let ray = Ray::new(pos, s.center + 2.9 * random_dir(&mut rng) - pos);
+            let hit = s.hit(&ray, 0.0, Float::MAX).unwrap();
+            let dir = random_dir(&mut rng);
+            let spawned = Ray::new(hit.spawn_point(dir), dir);
+            // leaving rays get away, entering ones only hit the far side
+            match s.hit(&spawned, 0.0, Float::MAX) {
+                Some(next) => assert!(dir.dot(hit.geometric_normal) < 0.0 && !next.front_face),
+                None => assert!(dir.dot(hit.geometric_normal) > 0.0),
+            }
+        }
     }
 }
@@ -0,0 +1,176 @@
+use crate::aabb::Aabb;
+use crate::float::Float;
+use crate::hittable::*;
+use crate::mat4::*;
+use crate::ray::*;
+use std::sync::Arc;
+
+/// Places a shared object in the world with an affine transform. The object
+/// sits behind an `Arc`, so any number of instances can reuse one copy of
+/// its geometry.
+pub struct Instance {
+    pub object: Arc<dyn Hittable + Send + Sync>,
+    transform: Mat4,
+    inverse: Mat4,
+}
+
+impl Instance {
+    /// None if the transform is singular, e.g. a scale by zero
+    pub fn new(object: Arc<dyn Hittable + Send + Sync>, transform: Mat4) -> Option<Self> {
+        let inverse = transform.inverse()?;
+        Some(Instance {
+            object,
+            transform,
+            inverse,
+        })
+    }
+
+    pub fn transform(&self) -> &Mat4 {
+        &self.transform
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        // the direction is not renormalized, so t is the same in both spaces
+        let local_ray = Ray {
+            pos: self.inverse.transform_point(ray.pos),
+            dir: self.inverse.transform_vector(ray.dir),
+            time: ray.time,
+            wavelength: ray.wavelength,
+        };
+        let hit_record = self.object.hit(&local_ray, t_min, t_max)?;
+        Some(hit_record.transformed(&self.transform, &self.inverse))
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        let local = self.object.bounding_box()?;
+        let corners = local.corners();
+        let world: Vec<_> = corners
+            .iter()
+            .map(|c| self.transform.transform_point(*c))
+            .collect();
+        Some(Aabb::from_points(&world))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::Material;
+    use crate::vec3::*;
+    use approx::assert_relative_eq;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    struct TestMaterial {}
+    impl Material for TestMaterial {}
+
+    fn unit_sphere() -> Arc<dyn Hittable + Send + Sync> {
+        Arc::new(Sphere {
+            center: Vec3(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material: Box::new(TestMaterial {}),
+        })
+    }
+
+    #[test]
+    fn translated_instance() {
+        let i = Instance::new(unit_sphere(), Mat4::translation(Vec3(0.0, 0.0, -5.0))).unwrap();
+        let r = Ray::new(Vec3(0.0, 0.0, 0.0), Vec3(0.0, 0.0, -1.0));
+        let h = i.hit(&r, 0.0, Float::MAX).unwrap();
+        assert_relative_eq!(h.t, 4.0);
+        assert_eq!(h.normal, Vec3(0.0, 0.0, 1.0));
+        let b = i.bounding_box().unwrap();
+        assert_eq!(b.min, Vec3(-1.0, -1.0, -6.0));
+    }
+
+    #[test]
+    fn non_uniform_scale_normals() {
+        // an ellipsoid twice as wide as it is tall
+        let i = Instance::new(unit_sphere(), Mat4::scaling(Vec3(2.0, 1.0, 1.0))).unwrap();
+        let r = Ray::new(Vec3(5.0, 0.0, 0.0), Vec3(-1.0, 0.0, 0.0));
+        assert_relative_eq!(i.hit(&r, 0.0, Float::MAX).unwrap().t, 3.0);
+        // at 45 degrees in local space, the world normal leans towards y
+        let p = Vec3(2.0 * (0.5 as Float).sqrt(), (0.5 as Float).sqrt(), 0.0);
+        let r = Ray::new(p + Vec3(0.0, 1.0, 0.0), Vec3(0.0, -1.0, 0.0));
+        let h = i.hit(&r, 0.0, Float::MAX).unwrap();
+        assert_relative_eq!(h.t, 1.0, epsilon = 1e-12);
+        assert_relative_eq!(h.normal.length(), 1.0);
+        assert!(h.normal.y() > h.normal.x());
+        let expected = Vec3(0.5, 1.0, 0.0).normalized();
+        assert_relative_eq!(h.normal.x(), expected.x(), epsilon = 1e-12);
+        assert_relative_eq!(h.normal.y(), expected.y(), epsilon = 1e-12);
+    }
+
+    #[test]
+    fn instances_share_geometry() {
+        let sphere = unit_sphere();
+        let mut world = HittableList::new();
+        for x in 0..3 {
+            world.push(
+                Instance::new(
+                    sphere.clone(),
+                    Mat4::translation(Vec3(3.0 * x as Float, 0.0, 0.0)),
+                )
+                .unwrap(),
+            );
+        }
+        assert_eq!(Arc::strong_count(&sphere), 4);
+        let r = Ray::new(Vec3(6.0, 5.0, 0.0), Vec3(0.0, -1.0, 0.0));
+        assert_relative_eq!(world.hit(&r, 0.0, Float::MAX).unwrap().t, 4.0);
+        let b = world.bounding_box().unwrap();
+        assert_eq!(b.max, Vec3(7.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn singular_transforms_are_rejected() {
+        let flat = Mat4::scaling(Vec3(1.0, 0.0, 1.0));
+        assert!(Instance::new(unit_sphere(), flat).is_none());
+    }
+
+    #[test]
+    fn rotated_bounds_cover_object() {
+        let cube: Arc<dyn Hittable + Send + Sync> = Arc::new(crate::shapes::Cuboid {
+            min: Vec3(-1.0, -1.0, -1.0),
+            max: Vec3(1.0, 1.0, 1.0),
+            material: Box::new(TestMaterial {}),
+        });
+        let i = Instance::new(cube, Mat4::rotation(Vec3(0.0, 1.0, 0.0), 45.0)).unwrap();
+        let b = i.bounding_box().unwrap();
+        assert_relative_eq!(b.max.x(), (2.0 as Float).sqrt(), epsilon = 1e-12);
+        assert_relative_eq!(b.max.y(), 1.0, epsilon = 1e-12);
+    }
+
+    fn random_dir(rng: &mut StdRng) -> Vec3 {
+        Vec3(
+            rng.gen::<Float>() - 0.5,
+            rng.gen::<Float>() - 0.5,
+            rng.gen::<Float>() - 0.5,
+        )
+        .normalized()
+    }
+
+    #[test]
+    fn spawned_rays_clear_the_surface() {
+        // a turned ellipsoid far from the origin, where the rounding
+        // errors are large
+        let center = Vec3(1000.0, -2000.0, 500.0);
+        let transform = Mat4::translation(center)
+            * Mat4::rotation(Vec3(1.0, 2.0, 3.0), 35.0)
+            * Mat4::scaling(Vec3(3.0, 1.0, 2.0));
+        let i = Instance::new(unit_sphere(), transform).unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..10000 {
+            let pos = center + 10.0 * random_dir(&mut rng);
+            let ray = Ray::new(pos, center + 0.9 * random_dir(&mut rng) - pos);
+            let hit = i.hit(&ray, 0.0, Float::MAX).unwrap();
+            let dir = random_dir(&mut rng);
+            let spawned = Ray::new(hit.spawn_point(dir), dir);
+            // leaving rays get away, entering ones only hit the far side
+            match i.hit(&spawned, 0.0, Float::MAX) {
+                Some(next) => assert!(dir.dot(hit.geometric_normal) < 0.0 && !next.front_face),
+                None => assert!(dir.dot(hit.geometric_normal) > 0.0),
+            }
+        }
+    }
+}
@@ -0,0 +1,383 @@
+use crate::camera::Camera;
+use crate::error::Error;
+use crate::film::*;
+use crate::filter::MitchellFilter;
+use crate::float::Float;
+use crate::hittable::{HitMaterial, HitRecord, Hittable};
+use crate::ray::*;
+use crate::scene::World;
+use crate::spectrum::*;
+use crate::vec3::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::VecDeque;
+use std::ops;
+use std::sync::Arc;
+use std::thread;
+
+// What a path carries: RGB, or the radiance at a set of sampled
+// wavelengths. `trace` follows paths the same way for both.
+trait PathRadiance {
+    type Value: Copy + ops::Add<Output = Self::Value> + ops::Mul<Output = Self::Value>;
+
+    fn is_black(value: &Self::Value) -> bool;
+    // an RGB reflectance or transmittance, and an RGB light
+    fn reflectance(&self, rgb: Vec3) -> Self::Value;
+    fn light(&self, rgb: Vec3) -> Self::Value;
+    fn emitted(&self, material: HitMaterial, ray: &Ray, hit_record: &HitRecord) -> Self::Value;
+    fn scatter(
+        &self,
+        material: HitMaterial,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        rng: &mut StdRng,
+    ) -> (Ray, Self::Value);
+}
+
+struct Rgb;
+
+impl PathRadiance for Rgb {
+    type Value = Vec3;
+
+    fn is_black(value: &Vec3) -> bool {
+        *value == Vec3(0.0, 0.0, 0.0)
+    }
+    fn reflectance(&self, rgb: Vec3) -> Vec3 {
+        rgb
+    }
+    fn light(&self, rgb: Vec3) -> Vec3 {
+        rgb
+    }
+    fn emitted(&self, material: HitMaterial, ray: &Ray, hit_record: &HitRecord) -> Vec3 {
+        material.emitted(ray, hit_record)
+    }
+    fn scatter(
+        &self,
+        material: HitMaterial,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        rng: &mut StdRng,
+    ) -> (Ray, Vec3) {
+        material.scatter(ray, hit_record, rng)
+    }
+}
+
+// Media, the atmosphere and the sky are RGB and get upsampled.
+struct Spectral<'a>(&'a SampledWavelengths);
+
+impl PathRadiance for Spectral<'_> {
+    type Value = SampledSpectrum;
+
+    fn is_black(value: &SampledSpectrum) -> bool {
+        value.is_black()
+    }
+    fn reflectance(&self, rgb: Vec3) -> SampledSpectrum {
+        Spectrum::Rgb(rgb).sample(self.0)
+    }
+    fn light(&self, rgb: Vec3) -> SampledSpectrum {
+        Spectrum::Illuminant(rgb).sample(self.0)
+    }
+    fn emitted(&self, material: HitMaterial, ray: &Ray, hit_record: &HitRecord) -> SampledSpectrum {
+        material.emitted_spectral(ray, hit_record, self.0)
+    }
+    fn scatter(
+        &self,
+        material: HitMaterial,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        rng: &mut StdRng,
+    ) -> (Ray, SampledSpectrum) {
+        material.scatter_spectral(ray, hit_record, self.0, rng)
+    }
+}
+
+// The light arriving along `ray`, following its path through `world` for up
+// to 50 bounces.
+fn trace<R: PathRadiance>(
+    radiance: &R,
+    world: &World,
+    ray: &Ray,
+    rng: &mut StdRng,
+    depth: u8,
+) -> R::Value {
+    if depth > 50 {
+        return radiance.reflectance(Vec3(0.0, 0.0, 0.0));
+    }
+    let hit_record = world.objects.hit(ray, 0.0, Float::MAX);
+    let t_surface = hit_record.as_ref().map_or(Float::MAX, |h| h.t);
+    let medium_sample = world.sample_media(ray, 0.0, t_surface, rng);
+    let (t_end, arriving) = if let Some((t, dir)) = medium_sample.scatter {
+        let scattered_ray = Ray {
+            pos: ray.point_at_t(t),
+            dir,
+            time: ray.time,
+            wavelength: ray.wavelength,
+        };
+        (t, trace(radiance, world, &scattered_ray, rng, depth + 1))
+    } else if let Some(hit_record) = hit_record {
+        let material = hit_record.material;
+        let emitted = radiance.emitted(material, ray, &hit_record);
+        let (scattered_ray, attenuation) = radiance.scatter(material, ray, &hit_record, rng);
+        if R::is_black(&attenuation) {
+            (hit_record.t, emitted)
+        } else {
+            let refl = trace(radiance, world, &scattered_ray, rng, depth + 1);
+            (hit_record.t, emitted + refl * attenuation)
+        }
+    } else {
+        // hit nothing. paint the sky:
+        let unit_dir = ray.dir.normalized();
+        let a = 0.5 * (unit_dir.y() + 1.0);
+        (
+            Float::MAX,
+            radiance.light((1.0 - a) * Vec3(1.0, 1.0, 1.0) + (a) * Vec3(0.5, 0.7, 1.0)),
+        )
+    };
+    let arriving = radiance.reflectance(medium_sample.weight) * arriving;
+
+    match &world.atmosphere {
+        Some(atmosphere) => {
+            let (transmittance, inscatter) = atmosphere.integrate(ray, t_end, rng);
+            radiance.reflectance(transmittance) * arriving + radiance.light(inscatter)
+        }
+        None => arriving,
+    }
+}
+
+/// The light arriving along `ray` in RGB.
+pub fn color(world: &World, ray: &Ray, rng: &mut StdRng, depth: u8) -> Vec3 {
+    trace(&Rgb, world, ray, rng, depth)
+}
+
+/// The spectral counterpart of `color`, following one path for a set of
+/// wavelengths.
+pub fn color_spectral(
+    world: &World,
+    ray: &Ray,
+    wavelengths: &SampledWavelengths,
+    rng: &mut StdRng,
+    depth: u8,
+) -> SampledSpectrum {
+    trace(&Spectral(wavelengths), world, ray, rng, depth)
+}
+
+/// Traces `rays_per_pixel` samples for every pixel of `tile`, with random
+/// numbers seeded from `seed`.
+pub fn render_tile(
+    world: &World,
+    camera: &dyn Camera,
+    mut tile: FilmTile,
+    rays_per_pixel: usize,
+    spectral: bool,
+    seed: u64,
+) -> FilmTile {
+    let mut t_rng = StdRng::seed_from_u64(seed);
+    let (x0, y0, x1, y1) = tile.sample_bounds;
+    let (width, height) = (tile.width as Float, tile.height as Float);
+    for y in y0..y1 {
+        for x in x0..x1 {
+            for _k in 0..rays_per_pixel {
+                let px = x as Float + t_rng.gen::<Float>();
+                let py = y as Float + t_rng.gen::<Float>();
+                let (ray, weight) =
+                    match camera.weighted_ray(px / width, 1.0 - py / height, &mut t_rng) {
+                        Some(sample) => sample,
+                        None => {
+                            tile.add_sample(px, py, Vec3(0.0, 0.0, 0.0));
+                            continue;
+                        }
+                    };
+                // a camera with chromatic aberration picks the wavelength
+                if spectral {
+                    let u = match ray.wavelength {
+                        Some(lambda) => (lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN),
+                        None => t_rng.gen::<Float>(),
+                    };
+                    let wavelengths = SampledWavelengths::sample(u);
+                    let mut s = color_spectral(world, &ray, &wavelengths, &mut t_rng, 1);
+                    if ray.wavelength.is_some() {
+                        s = s.hero_only();
+                    }
+                    tile.add_spectral_sample(px, py, &(s * weight), &wavelengths, converter());
+                } else {
+                    let mut rgb = color(world, &ray, &mut t_rng, 1);
+                    if let Some(lambda) = ray.wavelength {
+                        rgb *= converter().wavelength_to_rgb(lambda);
+                    }
+                    tile.add_sample(px, py, weight * rgb);
+                }
+            }
+        }
+    }
+    tile
+}
+
+/// splitmix64, to derive well spread seeds for frames and tiles
+pub fn mix_seed(seed: u64, index: u64) -> u64 {
+    let mut z = seed.wrapping_add(index.wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// How to split a frame into tiles and render them on worker threads.
+pub struct RenderSettings {
+    pub width: usize,
+    pub height: usize,
+    pub rays_per_pixel: usize,
+    /// trace wavelengths instead of RGB
+    pub spectral: bool,
+    pub n_work_chunks: usize,
+    pub n_max_threads: usize,
+    /// how often to render a tile again after its worker panicked
+    pub retries: usize,
+}
+
+/// A rendered frame. `failures` are the tiles that failed even after
+/// retrying and are left black, `retried` the failures that a retry fixed.
+pub struct Rendered {
+    pub film: Film,
+    pub failures: Vec<Error>,
+    pub retried: Vec<Error>,
+}
+
+/// Renders one frame with tiles seeded from `seed`, calling `progress` with
+/// the image so far as each tile finishes.
+pub fn render(
+    settings: &RenderSettings,
+    world: &Arc<World>,
+    camera: &Arc<dyn Camera + Send + Sync>,
+    seed: u64,
+    mut progress: impl FnMut(&Film),
+) -> Rendered {
+    let (width, height) = (settings.width, settings.height);
+    let n_rows_per_chunk = height.div_ceil(settings.n_work_chunks);
+    let filter = Arc::new(MitchellFilter {
+        radius: 2.0,
+        b: 1.0 / 3.0,
+        c: 1.0 / 3.0,
+    });
+    let mut film = Film::new(width, height, filter);
+    let mut failures = vec![];
+    let mut retried = vec![];
+    // (tile index, attempt, worker)
+    let mut threads = VecDeque::new();
+    let spawn = |film: &Film, index: usize, attempt: usize| {
+        let from = index * n_rows_per_chunk;
+        let tile = film.tile(0, from, width, (from + n_rows_per_chunk).min(height));
+        // a retry gets other random numbers, in case they caused the panic
+        let mut tile_seed = mix_seed(seed, index as u64);
+        if attempt > 0 {
+            tile_seed = mix_seed(tile_seed, attempt as u64);
+        }
+        let (world, camera) = (world.clone(), camera.clone());
+        let (rays_per_pixel, spectral) = (settings.rays_per_pixel, settings.spectral);
+        let worker = thread::spawn(move || {
+            render_tile(&world, &*camera, tile, rays_per_pixel, spectral, tile_seed)
+        });
+        (index, attempt, worker)
+    };
+
+    let n_tiles = height.div_ceil(n_rows_per_chunk);
+    let mut next = 0;
+    while next < n_tiles || !threads.is_empty() {
+        if next < n_tiles && threads.len() < settings.n_max_threads {
+            threads.push_back(spawn(&film, next, 0));
+            next += 1;
+            continue;
+        }
+        let (index, attempt, worker) = threads.pop_front().unwrap();
+        match worker.join() {
+            Ok(tile) => {
+                film.merge_tile(&tile);
+                progress(&film);
+            }
+            Err(payload) => {
+                let error = Error::from_panic(index, payload);
+                if attempt < settings.retries {
+                    threads.push_back(spawn(&film, index, attempt + 1));
+                    retried.push(error);
+                } else {
+                    failures.push(error);
+                }
+            }
+        }
+    }
+    Rendered {
+        film,
+        failures,
+        retried,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::CameraBuilder;
+    use crate::scene::SceneGraph;
+
+    // looks straight up into the sky, and panics in the top half of the
+    // image
+    struct SkyCamera;
+
+    impl Camera for SkyCamera {
+        fn get_ray(&self, _s: Float, t: Float, _rng: &mut StdRng) -> Option<Ray> {
+            assert!(t < 0.5, "top half");
+            Some(Ray::new(Vec3(0.0, 0.0, 0.0), Vec3(0.0, 1.0, 0.0)))
+        }
+    }
+
+    #[test]
+    fn tiles_are_reproducible() {
+        let world = SceneGraph::new().world().unwrap();
+        let camera = CameraBuilder::new(Vec3(0.0, 0.0, 0.0), Vec3(0.0, 0.0, -1.0), 1.0)
+            .build(&world.objects)
+            .unwrap();
+        let image = |seed| {
+            let filter = Arc::new(MitchellFilter {
+                radius: 2.0,
+                b: 1.0 / 3.0,
+                c: 1.0 / 3.0,
+            });
+            let mut film = Film::new(4, 4, filter);
+            let tile = film.tile(0, 0, 4, 4);
+            film.merge_tile(&render_tile(&world, &camera, tile, 2, false, seed));
+            film.to_rgba8()
+        };
+        assert_eq!(image(7), image(7));
+        assert_ne!(mix_seed(7, 0), mix_seed(7, 1));
+    }
+
+    #[test]
+    fn failed_tiles_are_reported() {
+        let settings = RenderSettings {
+            width: 4,
+            height: 8,
+            rays_per_pixel: 1,
+            spectral: false,
+            n_work_chunks: 4,
+            n_max_threads: 2,
+            retries: 1,
+        };
+        let world = Arc::new(SceneGraph::new().world().unwrap());
+        let camera: Arc<dyn Camera + Send + Sync> = Arc::new(SkyCamera);
+        let mut progress = 0;
+        let rendered = render(&settings, &world, &camera, 0, |_| progress += 1);
+        // the two tiles at the top fail twice, the other two render
+        assert_eq!(progress, 2);
+        assert_eq!(rendered.retried.len(), 2);
+        let mut failed: Vec<_> = rendered
+            .failures
+            .iter()
+            .map(|e| match e {
+                Error::Worker { tile, message } => {
+                    assert_eq!(message, "top half");
+                    *tile
+                }
+                _ => panic!("{}", e),
+            })
+            .collect();
+        failed.sort();
+        assert_eq!(failed, vec![0, 1]);
+    }
+}
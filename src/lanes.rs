@@ -0,0 +1,309 @@
+// SIMD vectors of `Float`s for the AVX kernels: four f64 lanes, or eight
+// f32 lanes with the `f32` feature. The functions are thin wrappers over the
+// intrinsics, so that each kernel is written once for both.
+
+#[cfg(not(feature = "f32"))]
+pub const LANES: usize = 4;
+#[cfg(feature = "f32")]
+pub const LANES: usize = 8;
+
+#[cfg(target_arch = "x86_64")]
+pub fn simd_available() -> bool {
+    is_x86_feature_detected!("avx")
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+pub fn simd_available() -> bool {
+    false
+}
+
+#[cfg(target_arch = "x86_64")]
+pub use self::avx::*;
+
+#[cfg(all(target_arch = "x86_64", not(feature = "f32")))]
+mod avx {
+    use super::LANES;
+    use std::arch::x86_64::*;
+
+    pub type V = __m256d;
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn splat(x: f64) -> V {
+        _mm256_set1_pd(x)
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn load(column: &[f64], start: usize) -> V {
+        _mm256_loadu_pd(column[start..start + LANES].as_ptr())
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn store(a: V, out: &mut [f64; LANES]) {
+        _mm256_storeu_pd(out.as_mut_ptr(), a)
+    }
+
+    // the 8 bit integers as floats
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn widen(q: [u8; LANES]) -> V {
+        _mm256_cvtepi32_pd(_mm_cvtepu8_epi32(_mm_cvtsi32_si128(i32::from_le_bytes(q))))
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn add(a: V, b: V) -> V {
+        _mm256_add_pd(a, b)
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn sub(a: V, b: V) -> V {
+        _mm256_sub_pd(a, b)
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn mul(a: V, b: V) -> V {
+        _mm256_mul_pd(a, b)
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn div(a: V, b: V) -> V {
+        _mm256_div_pd(a, b)
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn sqrt(a: V) -> V {
+        _mm256_sqrt_pd(a)
+    }
+
+    // the second operand where either is NaN
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn min(a: V, b: V) -> V {
+        _mm256_min_pd(a, b)
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn max(a: V, b: V) -> V {
+        _mm256_max_pd(a, b)
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn and(a: V, b: V) -> V {
+        _mm256_and_pd(a, b)
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn or(a: V, b: V) -> V {
+        _mm256_or_pd(a, b)
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn neg(a: V) -> V {
+        _mm256_xor_pd(a, _mm256_set1_pd(-0.0))
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn abs(a: V) -> V {
+        _mm256_andnot_pd(_mm256_set1_pd(-0.0), a)
+    }
+
+    // `b` where `mask` is set, `a` elsewhere
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn select(a: V, b: V, mask: V) -> V {
+        _mm256_blendv_pd(a, b, mask)
+    }
+
+    // !(a < b), true for NaNs
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn not_lt(a: V, b: V) -> V {
+        _mm256_cmp_pd::<_CMP_NLT_UQ>(a, b)
+    }
+
+    // !(a > b), true for NaNs
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn not_gt(a: V, b: V) -> V {
+        _mm256_cmp_pd::<_CMP_NGT_UQ>(a, b)
+    }
+
+    // a >= b, false for NaNs
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn ge(a: V, b: V) -> V {
+        _mm256_cmp_pd::<_CMP_GE_OQ>(a, b)
+    }
+
+    // a <= b, false for NaNs
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn le(a: V, b: V) -> V {
+        _mm256_cmp_pd::<_CMP_LE_OQ>(a, b)
+    }
+
+    // a bit for each lane whose mask is set
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn bits(mask: V) -> u32 {
+        _mm256_movemask_pd(mask) as u32
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", feature = "f32"))]
+mod avx {
+    use super::LANES;
+    use std::arch::x86_64::*;
+
+    pub type V = __m256;
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn splat(x: f32) -> V {
+        _mm256_set1_ps(x)
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn load(column: &[f32], start: usize) -> V {
+        _mm256_loadu_ps(column[start..start + LANES].as_ptr())
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn store(a: V, out: &mut [f32; LANES]) {
+        _mm256_storeu_ps(out.as_mut_ptr(), a)
+    }
+
+    // the 8 bit integers as floats
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn widen(q: [u8; LANES]) -> V {
+        let half = |i: usize| {
+            let bytes = [q[i], q[i + 1], q[i + 2], q[i + 3]];
+            _mm_cvtepu8_epi32(_mm_cvtsi32_si128(i32::from_le_bytes(bytes)))
+        };
+        _mm256_cvtepi32_ps(_mm256_set_m128i(half(4), half(0)))
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn add(a: V, b: V) -> V {
+        _mm256_add_ps(a, b)
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn sub(a: V, b: V) -> V {
+        _mm256_sub_ps(a, b)
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn mul(a: V, b: V) -> V {
+        _mm256_mul_ps(a, b)
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn div(a: V, b: V) -> V {
+        _mm256_div_ps(a, b)
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn sqrt(a: V) -> V {
+        _mm256_sqrt_ps(a)
+    }
+
+    // the second operand where either is NaN
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn min(a: V, b: V) -> V {
+        _mm256_min_ps(a, b)
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn max(a: V, b: V) -> V {
+        _mm256_max_ps(a, b)
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn and(a: V, b: V) -> V {
+        _mm256_and_ps(a, b)
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn or(a: V, b: V) -> V {
+        _mm256_or_ps(a, b)
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn neg(a: V) -> V {
+        _mm256_xor_ps(a, _mm256_set1_ps(-0.0))
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn abs(a: V) -> V {
+        _mm256_andnot_ps(_mm256_set1_ps(-0.0), a)
+    }
+
+    // `b` where `mask` is set, `a` elsewhere
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn select(a: V, b: V, mask: V) -> V {
+        _mm256_blendv_ps(a, b, mask)
+    }
+
+    // !(a < b), true for NaNs
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn not_lt(a: V, b: V) -> V {
+        _mm256_cmp_ps::<_CMP_NLT_UQ>(a, b)
+    }
+
+    // !(a > b), true for NaNs
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn not_gt(a: V, b: V) -> V {
+        _mm256_cmp_ps::<_CMP_NGT_UQ>(a, b)
+    }
+
+    // a >= b, false for NaNs
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn ge(a: V, b: V) -> V {
+        _mm256_cmp_ps::<_CMP_GE_OQ>(a, b)
+    }
+
+    // a <= b, false for NaNs
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn le(a: V, b: V) -> V {
+        _mm256_cmp_ps::<_CMP_LE_OQ>(a, b)
+    }
+
+    // a bit for each lane whose mask is set
+    #[inline]
+    #[target_feature(enable = "avx")]
+    pub unsafe fn bits(mask: V) -> u32 {
+        _mm256_movemask_ps(mask) as u32
+    }
+}
@@ -0,0 +1,130 @@
+//! A path tracer. A scene is a [`scene::SceneGraph`] of shapes and
+//! materials that flattens into a [`scene::World`]; a camera turns pixel
+//! positions into rays, and the integrator traces them into a
+//! [`film::Film`], which is written out as PNG or OpenEXR.
+//!
+//! The math works in [`float::Float`], which is `f64`, or `f32` with the
+//! `f32` feature.
+//!
+//! The types most renders need are re-exported here; the modules hold the
+//! shapes, materials, cameras and the rest to build scenes from.
+//!
+//! ```
+//! use raytracer::hittable::Sphere;
+//! use raytracer::materials::DiffuseMaterial;
+//! use raytracer::{render, Camera, CameraBuilder, RenderSettings, SceneGraph, SceneNode, Vec3};
+//! use std::sync::Arc;
+//!
+//! let mut scene = SceneGraph::new();
+//! scene.root.add_child(SceneNode::with_geometry(
+//!     "ball",
+//!     Sphere {
+//!         center: Vec3(0.0, 0.0, -2.0),
+//!         radius: 0.5,
+//!         material: Box::new(DiffuseMaterial {
+//!             albedo: Vec3(0.8, 0.3, 0.3),
+//!         }),
+//!     },
+//! ));
+//! let world = Arc::new(scene.world().unwrap());
+//! let camera: Arc<dyn Camera + Send + Sync> = Arc::new(
+//!     CameraBuilder::new(Vec3(0.0, 0.0, 0.0), Vec3(0.0, 0.0, -1.0), 2.0)
+//!         .build(&world.objects)
+//!         .unwrap(),
+//! );
+//! let settings = RenderSettings {
+//!     width: 16,
+//!     height: 8,
+//!     rays_per_pixel: 4,
+//!     spectral: false,
+//!     n_work_chunks: 2,
+//!     n_max_threads: 2,
+//!     retries: 0,
+//! };
+//! let rendered = render(&settings, &world, &camera, 1, |_| {});
+//! assert!(rendered.failures.is_empty());
+//! assert_eq!(rendered.film.to_rgba8().len(), 16 * 8 * 4);
+//! ```
+
+// the original vector and material code is written in this style
+#![allow(clippy::needless_return, clippy::toplevel_ref_arg, clippy::unused_unit)]
+
+/// Axis-aligned bounding boxes.
+pub mod aabb;
+/// Physically based sky and aerial perspective.
+pub mod atmosphere;
+/// Bounding volume hierarchies over hittable objects.
+pub mod bvh;
+/// Cameras: thin lens, realistic lens systems, orthographic, fisheye and
+/// panoramic projections, and their animation.
+pub mod camera;
+/// Compact scenes of built-in primitives in flat arrays, dispatched with
+/// matches instead of trait objects.
+pub mod compact;
+/// The demo scene the command line renders, shared with the benchmarks.
+pub mod demo;
+/// The crate's error type.
+pub mod error;
+/// The image being rendered, its tiles and its conversion to 8 bit and
+/// OpenEXR images.
+pub mod film;
+/// Pixel reconstruction filters.
+pub mod filter;
+/// The floating point type of the render core.
+pub mod float;
+/// The `Hittable` trait for anything a ray can hit.
+pub mod hittable;
+/// Transformed and shared instances of objects.
+pub mod instance;
+/// Path tracing, in RGB and spectrally, and rendering frames on threads.
+pub mod integrator;
+mod lanes;
+/// 4x4 transformation matrices.
+pub mod mat4;
+/// Surface materials.
+pub mod materials;
+/// Participating media such as fog and smoke.
+pub mod medium;
+/// Keyframes, interpolation and moving objects.
+pub mod motion;
+/// Procedural noise and textures built from it.
+pub mod noise;
+/// Writing images: PNG encoding, atomic writes and progress snapshots.
+pub mod output;
+/// Spheres and triangles in structure of arrays form, intersected several
+/// at a time with SIMD.
+pub mod packed;
+mod poly;
+/// Quaternions for rotations that interpolate smoothly.
+pub mod quat;
+/// Rays.
+pub mod ray;
+/// The scene graph and the world it flattens into.
+pub mod scene;
+/// The scene file format.
+pub mod scenefile;
+/// Geometric primitives.
+pub mod shapes;
+/// Spectra, sampled wavelengths and color conversion.
+pub mod spectrum;
+/// Stereo cameras and rigs, and anaglyphs of their images.
+pub mod stereo;
+/// Implicit and parametric surfaces.
+pub mod surfaces;
+/// Thin film interference coatings.
+pub mod thinfilm;
+/// 3D vectors.
+pub mod vec3;
+/// Wide BVHs with quantized child bounds, tested with SIMD.
+pub mod wide_bvh;
+
+pub use camera::{Camera, CameraBuilder, FieldOfView, Focus};
+pub use error::{Error, Result};
+pub use film::Film;
+pub use float::Float;
+pub use hittable::{HitMaterial, HitRecord, Hittable, HittableList};
+pub use integrator::{render, RenderSettings, Rendered};
+pub use materials::Material;
+pub use ray::Ray;
+pub use scene::{SceneGraph, SceneNode, World};
+pub use vec3::Vec3;
@@ -1,294 +1,240 @@
-use mtpng;
-use rand::rngs::ThreadRng;
-use rand::Rng;
-use std::fs::File;
-use std::io::BufWriter;
-use std::path::Path;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use raytracer::camera::*;
+use raytracer::demo::build_world;
+use raytracer::error::*;
+use raytracer::film::Film;
+use raytracer::float::Float;
+use raytracer::integrator::*;
+use raytracer::output::*;
+use raytracer::scenefile;
+use raytracer::vec3::*;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::thread;
-
-mod camera;
-mod hittable;
-mod materials;
-mod ray;
-mod vec3;
-use camera::*;
-use hittable::*;
-use materials::*;
-use ray::*;
-use vec3::*;
-
-fn color(world: &HittableList, ray: &Ray, rng: &mut ThreadRng, depth: u8) -> Vec3 {
-    if depth > 50 {
-        return Vec3(0.0, 0.0, 0.0);
-    }
-    let hit_record = world.hit(&ray, 0.001, std::f64::MAX);
-    if hit_record.t > 0.0 {
-        let (scattered_ray, attenuation) =
-            hit_record.material.unwrap().scatter(ray, &hit_record, rng);
-        let refl = color(world, &scattered_ray, rng, depth + 1);
-        return refl * attenuation;
-    }
-
-    // hit nothing. paint the sky:
-    let unit_dir = ray.dir.normalized();
-    let a = 0.5 * (unit_dir.y() + 1.0);
-    (1.0 - a) * Vec3(1.0, 1.0, 1.0) + (a) * Vec3(0.5, 0.7, 1.0)
-}
-
-fn clamp_to_u8(val: f64) -> u8 {
-    let mut result = val.sqrt();
-    if result < 0.0 {
-        result = 0.0;
-    }
-    if result > 1.0 {
-        result = 1.0;
-    }
-    (result * 255.99) as u8
+use std::time::Duration;
+
+// Command line: `raytracer [scene file] [--frames FIRST-LAST] [--fps N]
+// [--seed N] [--output PATTERN] [--snapshot-every SECONDS] [--retries N]
+// [--spectral]`.
+// A frame range renders each frame to the output pattern with its run of
+// `#`s replaced by the frame number, as PNG or, for a `.exr` pattern,
+// OpenEXR. Frames already on disk are skipped. A single image is also saved
+// while it renders, every so many seconds and whenever the process gets
+// SIGUSR1. Tiles whose worker panics are rendered again up to `--retries`
+// times. `--spectral` traces wavelengths instead of RGB. A scene file
+// asking for an anaglyph gets one next to each image, as
+// `<name>_anaglyph.png`.
+struct Options {
+    scene: Option<String>,
+    frames: Option<(u64, u64)>,
+    fps: Float,
+    seed: u64,
+    output: Option<String>,
+    snapshot_every: Option<Duration>,
+    retries: usize,
+    spectral: bool,
 }
 
-fn build_world(rng: &mut ThreadRng) -> HittableList {
-    let mut world = HittableList::new();
-    // world.push(Sphere {
-    //     center: Vec3(-1.0, 0.5, 0.0),
-    //     radius: 0.5,
-
-    //     material: Box::new(DiffuseMaterial {
-    //         albedo: Vec3(0.5, 0.7, 0.5),
-    //     }),
-    // });
-    // world.push(Sphere {
-    //     center: Vec3(0.0, 0.5, 0.0),
-    //     radius: 0.5,
-    //     material: Box::new(MetalMaterial {
-    //         albedo: Vec3(0.8, 0.8, 0.8),
-    //         fuzz: 0.1,
-    //     }),
-    // });
-    // world.push(Sphere {
-    //     center: Vec3(1.0, 0.5, 0.0),
-    //     radius: 0.5,
-    //     material: Box::new(GlassMaterial {
-    //         albedo: Vec3(0.9, 0.9, 0.9),
-    //         ref_idx: 1.5,
-    //     }),
-    // });
-    // world.push(Sphere {
-    //     center: Vec3(0.0, -500.0, 0.0),
-    //     radius: 500.0,
-    //     material: Box::new(DiffuseMaterial {
-    //         albedo: Vec3(0.3, 0.35, 0.4),
-    //     }),
-    // });
-
-    // for x in -11..5 {
-    //     for z in -11..5 {
-    //         let center = Vec3(
-    //             0.5 * (x as f64 + 0.8 * rng.gen::<f64>()),
-    //             0.1,
-    //             0.5 * (z as f64 + 0.8 * rng.gen::<f64>()),
-    //         );
-    //         if center.squared_length() < 25.0
-    //             && (center - Vec3(-1.0, 0.0, 0.0)).squared_length() > 0.25
-    //             && (center - Vec3(0.0, 0.0, 0.0)).squared_length() > 0.25
-    //             && (center - Vec3(1.0, 0.0, 0.0)).squared_length() > 0.25
-    //         {
-    //             let material: Box<dyn Material + Send + Sync> = if rng.gen::<f64>() > 0.5 {
-    //                 Box::new(MetalMaterial {
-    //                     albedo: Vec3(rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()),
-    //                     fuzz: rng.gen::<f64>(),
-    //                 })
-    //             } else {
-    //                 Box::new(DiffuseMaterial {
-    //                     albedo: Vec3(rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()),
-    //                 })
-    //             };
-    //             world.push(Sphere {
-    //                 center,
-    //                 radius: 0.1,
-    //                 material,
-    //             })
-    //         }
-    //     }
-    // }
-
-    world.push(Sphere {
-        center: Vec3(1.0, 0.5, 0.0),
-        radius: 0.5,
-        material: Box::new(MetalMaterial {
-            albedo: Vec3(0.8, 0.8, 0.8),
-            fuzz: 0.05,
-        }),
-    });
-
-    let mut colliders = Vec::new();
-    colliders.push((Vec3(1.0, 0.5, 0.0), 0.5));
-
-    for x in -11..5 {
-        for z in -11..5 {
-            let radius = 0.05 + rng.gen::<f64>() * rng.gen::<f64>() * 0.3;
-            let center = Vec3(
-                0.5 * (x as f64 + 0.8 * rng.gen::<f64>()),
-                radius,
-                0.5 * (z as f64 + 0.8 * rng.gen::<f64>()),
-            );
-
-            if center.squared_length() < 25.0 {
-                let mut collides = false;
-                for (c_center, c_radius) in &colliders {
-                    if (center - *c_center).length() < (radius + *c_radius) {
-                        collides = true;
-                        break;
-                    }
-                }
-                if !collides {
-                    let material: Box<dyn Material + Send + Sync> = Box::new(MetalMaterial {
-                        albedo: Vec3(rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()),
-                        fuzz: 0.05 + rng.gen::<f64>() * 0.3,
-                    });
-                    world.push(Sphere {
-                        center,
-                        radius: radius,
-                        material,
-                    });
-                    colliders.push((center, radius));
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options> {
+    let mut options = Options {
+        scene: None,
+        frames: None,
+        fps: 24.0,
+        seed: 0,
+        output: None,
+        snapshot_every: None,
+        retries: 1,
+        spectral: false,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| -> Result<String> {
+            args.next()
+                .ok_or_else(|| Error::Usage(format!("{} needs a value", name)))
+        };
+        let bad = |name: &str| Error::Usage(format!("bad value for {}", name));
+        match arg.as_str() {
+            "--frames" => {
+                let range = value("--frames")?;
+                let frames = range
+                    .split_once('-')
+                    .and_then(|(first, last)| Some((first.parse().ok()?, last.parse().ok()?)));
+                let (first, last) = frames.ok_or_else(|| bad("--frames"))?;
+                if first > last {
+                    return Err(Error::Usage(format!(
+                        "--frames {}-{} ends before it starts",
+                        first, last
+                    )));
                 }
+                options.frames = Some((first, last));
             }
+            "--fps" => options.fps = value("--fps")?.parse().map_err(|_| bad("--fps"))?,
+            "--seed" => options.seed = value("--seed")?.parse().map_err(|_| bad("--seed"))?,
+            "--output" => options.output = Some(value("--output")?),
+            "--snapshot-every" => {
+                let seconds = value("--snapshot-every")?
+                    .parse()
+                    .map_err(|_| bad("--snapshot-every"))?;
+                options.snapshot_every = Some(Duration::from_secs_f64(seconds));
+            }
+            "--retries" => {
+                options.retries = value("--retries")?.parse().map_err(|_| bad("--retries"))?
+            }
+            "--spectral" => options.spectral = true,
+            _ => options.scene = Some(arg),
         }
     }
-    world.push(Sphere {
-        center: Vec3(0.0, -2000.0, 0.0),
-        radius: 2000.0,
-        material: Box::new(MetalMaterial {
-            albedo: Vec3(0.5, 0.5, 0.5),
-            fuzz: 0.05,
-        }),
-    });
-    world
+    if options.frames.is_some() && options.output.as_ref().is_some_and(|o| !o.contains('#')) {
+        return Err(Error::Usage(
+            "--output needs a # for the frame number".to_string(),
+        ));
+    }
+    Ok(options)
 }
 
-fn spawn_worker(
-    world: &Arc<HittableList>,
-    camera: &Arc<Camera>,
-    from_i: usize,
-    to_i: usize,
-    width: usize,
-    height: usize,
-    rays_per_pixel: usize,
-    n_pixels_per_chunk: usize,
-) -> thread::JoinHandle<(usize, usize, Vec<u8>)> {
-    let camera = camera.clone();
-    let world = world.clone();
-    thread::spawn(move || {
-        let mut data: Vec<u8> = Vec::with_capacity(n_pixels_per_chunk * 4);
-        data.resize(n_pixels_per_chunk * 4, 0);
-
-        let mut t_rng = rand::thread_rng();
-        for i in from_i..to_i {
-            let mut rgb = Vec3(0.0, 0.0, 0.0);
-            for _k in 0..rays_per_pixel {
-                let u = ((i % width) as f64 + t_rng.gen::<f64>()) / width as f64;
-                let v = 1.0 - ((i / width) as f64 + t_rng.gen::<f64>()) / height as f64;
-                let ray = camera.get_ray(u, v, &mut t_rng);
-                rgb += color(&world, &ray, &mut t_rng, 1);
-            }
-            rgb /= rays_per_pixel as f64;
-            data[4 * (i - from_i)] = clamp_to_u8(rgb.r()) as u8;
-            data[4 * (i - from_i) + 1] = clamp_to_u8(rgb.g()) as u8;
-            data[4 * (i - from_i) + 2] = clamp_to_u8(rgb.b()) as u8;
-            data[4 * (i - from_i) + 3] = 255_u8;
-        }
-        (from_i, to_i, data)
-    })
+// the output pattern with its `#`s replaced by the zero-padded frame number
+fn frame_path(pattern: &str, frame: u64) -> String {
+    let start = pattern.find('#').unwrap_or(pattern.len());
+    let digits = pattern[start..].chars().take_while(|c| *c == '#').count();
+    format!(
+        "{}{:0width$}{}",
+        &pattern[..start],
+        frame,
+        &pattern[start + digits..],
+        width = digits
+    )
 }
 
-fn write_data(
-    aggregated_data: &mut Vec<u8>,
-    width: usize,
-    height: usize,
-    from: usize,
-    to: usize,
-    data: &Vec<u8>,
-) {
-    for i in from..to {
-        aggregated_data[4 * i] = data[4 * (i - from)];
-        aggregated_data[4 * i + 1] = data[4 * (i - from) + 1];
-        aggregated_data[4 * i + 2] = data[4 * (i - from) + 2];
-        aggregated_data[4 * i + 3] = data[4 * (i - from) + 3];
-    }
-    save_to_file("out_image.png", &aggregated_data, width, height).unwrap();
+// where the anaglyph of the image saved to `path` goes: next to it, as a
+// PNG with `_anaglyph` added to the name
+fn anaglyph_path(path: &Path) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push("_anaglyph.png");
+    path.with_file_name(name)
 }
 
 fn main() {
-    let width: usize = 3840;
-    let height: usize = 2160;
-    // let width: usize = 600;
-    // let height: usize = 400;
-    let rays_per_pixel: usize = 2000;
-    let n_work_chunks: usize = 100;
-    let n_pixels_per_chunk = width * height / n_work_chunks;
-
-    let n_max_threads: usize = 16;
-    let mut threads = vec![];
-    let mut rng = rand::thread_rng();
-
-    let camera = Arc::new(Camera::new(
-        Vec3(8.0, 1.0, 4.0),
-        Vec3(0.0, 0.3, 0.0),
-        Vec3(0.0, 1.0, 0.0),
-        25.0,
-        width as f64 / height as f64,
-        0.05,
-        0.9,
-    ));
-
-    // println!("camera: {:?}", camera);
-    let world = Arc::new(build_world(&mut rng));
-
-    let mut aggregated_data = Vec::with_capacity(width * height * 4);
-    aggregated_data.resize(width * height * 4, 0);
-
-    for t_i in 0..n_work_chunks {
-        let from = t_i * n_pixels_per_chunk;
-        let to = from + n_pixels_per_chunk;
-        threads.push(spawn_worker(
-            &world,
-            &camera,
-            from,
-            to,
-            width,
-            height,
-            rays_per_pixel,
-            n_pixels_per_chunk,
-        ));
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
 
-        if threads.len() >= n_max_threads {
-            let t = threads.remove(0);
-            let (from, to, data) = t.join().unwrap();
-            write_data(&mut aggregated_data, width, height, from, to, &data);
+fn run() -> Result<()> {
+    let options = parse_options(std::env::args().skip(1))?;
+    let settings = RenderSettings {
+        width: 3840,
+        height: 2160,
+        // width: 600,
+        // height: 400,
+        rays_per_pixel: 2000,
+        spectral: options.spectral,
+        n_work_chunks: 100,
+        n_max_threads: 16,
+        retries: options.retries,
+    };
+    listen_for_snapshot_signal();
+    // the scene is laid out the same for every frame
+    let mut rng = StdRng::seed_from_u64(options.seed);
+
+    let aspect = settings.width as Float / settings.height as Float;
+    // a scene file given on the command line can choose the camera
+    let scene_file = match &options.scene {
+        Some(path) => Some(scenefile::load(path)?),
+        None => None,
+    };
+
+    let scene = build_world(&mut rng);
+    let world = Arc::new(scene.world()?);
+
+    let anaglyph = scene_file.as_ref().and_then(|f| f.anaglyph());
+    // the camera for the frame at `time`, with its shutter open for
+    // `exposure` seconds
+    let camera_at = |time: Float, exposure: Float| -> Result<Arc<dyn Camera + Send + Sync>> {
+        let from_file = match &scene_file {
+            Some(f) => f.camera(aspect, &world.objects, time, exposure)?,
+            None => None,
+        };
+        Ok(match from_file {
+            Some(camera) => Arc::from(camera),
+            None => Arc::new(
+                CameraBuilder::new(Vec3(8.0, 1.0, 4.0), Vec3(0.0, 0.3, 0.0), aspect)
+                    .fov(FieldOfView::Horizontal(25.0))
+                    .aperture(0.05)
+                    // autofocus on the big sphere
+                    .focus(Focus::Target(Vec3(1.0, 0.5, 0.0)))
+                    .shutter(time, time + exposure)
+                    .build(&world.objects)?,
+            ),
+        })
+    };
+    let save_anaglyph = |film: &Film, path: &Path| -> Result<()> {
+        match anaglyph {
+            Some(layout) => {
+                let (data, eye_width, eye_height) = layout.anaglyph_rgba8(film);
+                let png = PngOptions::best();
+                save_png(&anaglyph_path(path), &data, eye_width, eye_height, &png)
+            }
+            None => Ok(()),
         }
+    };
+
+    let (first, last) = match options.frames {
+        Some(frames) => frames,
+        None => {
+            let path = Path::new(options.output.as_deref().unwrap_or("out_image.png"));
+            let mut snapshots = Snapshots::new(options.snapshot_every);
+            let rendered = render(
+                &settings,
+                &world,
+                &camera_at(0.0, 1.0)?,
+                options.seed,
+                |film| {
+                    if snapshots.due() {
+                        if let Err(e) = save_film(path, film, &PngOptions::snapshot()) {
+                            eprintln!("could not save a snapshot: {}", e);
+                        }
+                    }
+                },
+            );
+            let (film, failure) = report(rendered);
+            // keep what did render, even with some tiles missing
+            save_film(path, &film, &PngOptions::best())?;
+            save_anaglyph(&film, path)?;
+            return failure.map_or(Ok(()), Err);
+        }
+    };
+    let pattern = options.output.as_deref().unwrap_or("out_####.png");
+    let mut failed = None;
+    for frame in first..=last {
+        let path = frame_path(pattern, frame);
+        if Path::new(&path).exists() {
+            println!("skipping {}, it already exists", path);
+            continue;
+        }
+        // a 180 degree shutter
+        let camera = camera_at(frame as Float / options.fps, 0.5 / options.fps)?;
+        let seed = mix_seed(options.seed, frame);
+        let (film, failure) = report(render(&settings, &world, &camera, seed, |_| {}));
+        // an incomplete frame is left for the next run to render again
+        if let Some(error) = failure {
+            eprintln!("frame {} is incomplete, not saving it", frame);
+            failed.get_or_insert(error);
+            continue;
+        }
+        save_film(Path::new(&path), &film, &PngOptions::best())?;
+        save_anaglyph(&film, Path::new(&path))?;
+        println!("wrote {}", path);
     }
-    for t in threads {
-        let (from, to, data) = t.join().unwrap();
-        write_data(&mut aggregated_data, width, height, from, to, &data);
-    }
-
-    save_to_file("out_image.png", &aggregated_data, width, height).unwrap();
+    failed.map_or(Ok(()), Err)
 }
 
-fn save_to_file(fname: &str, data: &Vec<u8>, width: usize, height: usize) -> std::io::Result<()> {
-    let mut header = mtpng::Header::new();
-    header.set_size(width as u32, height as u32).unwrap();
-    header
-        .set_color(mtpng::ColorType::TruecolorAlpha, 8)
-        .unwrap();
-    let options = mtpng::encoder::Options::new();
-    let path = Path::new(fname);
-    let file_writer = BufWriter::new(File::create(path)?);
-    let mut encoder = mtpng::encoder::Encoder::new(file_writer, &options);
-
-    encoder.write_header(&header).unwrap();
-    encoder.write_image_rows(&data).unwrap();
-    encoder.finish().unwrap();
-    Ok(())
+// prints the tiles that failed, returning the film and the first failure
+fn report(rendered: Rendered) -> (Film, Option<Error>) {
+    for error in &rendered.retried {
+        eprintln!("{}, retried", error);
+    }
+    for error in &rendered.failures {
+        eprintln!("{}", error);
+    }
+    (rendered.film, rendered.failures.into_iter().next())
 }
@@ -0,0 +1,306 @@
+use crate::float::{gamma, Float};
+use crate::vec3::*;
+use std::ops;
+
+/// A 4x4 affine transform, stored row-major and applied to column vectors,
+/// so `a * b` applies `b` first.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Mat4(pub [[Float; 4]; 4]);
+
+impl Mat4 {
+    pub fn identity() -> Mat4 {
+        Mat4([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn translation(offset: Vec3) -> Mat4 {
+        Mat4([
+            [1.0, 0.0, 0.0, offset.x()],
+            [0.0, 1.0, 0.0, offset.y()],
+            [0.0, 0.0, 1.0, offset.z()],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn scaling(scale: Vec3) -> Mat4 {
+        Mat4([
+            [scale.x(), 0.0, 0.0, 0.0],
+            [0.0, scale.y(), 0.0, 0.0],
+            [0.0, 0.0, scale.z(), 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// counter-clockwise rotation by `degrees` around `axis`, looking down
+    /// the axis towards the origin
+    pub fn rotation(axis: Vec3, degrees: Float) -> Mat4 {
+        let a = axis.normalized();
+        let theta = degrees * crate::float::consts::PI / 180.0;
+        let (s, c) = theta.sin_cos();
+        let t = 1.0 - c;
+        Mat4([
+            [
+                t * a.x() * a.x() + c,
+                t * a.x() * a.y() - s * a.z(),
+                t * a.x() * a.z() + s * a.y(),
+                0.0,
+            ],
+            [
+                t * a.x() * a.y() + s * a.z(),
+                t * a.y() * a.y() + c,
+                t * a.y() * a.z() - s * a.x(),
+                0.0,
+            ],
+            [
+                t * a.x() * a.z() - s * a.y(),
+                t * a.y() * a.z() + s * a.x(),
+                t * a.z() * a.z() + c,
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        let m = &self.0;
+        let x = m[0][0] * p.x() + m[0][1] * p.y() + m[0][2] * p.z() + m[0][3];
+        let y = m[1][0] * p.x() + m[1][1] * p.y() + m[1][2] * p.z() + m[1][3];
+        let z = m[2][0] * p.x() + m[2][1] * p.y() + m[2][2] * p.z() + m[2][3];
+        let w = m[3][0] * p.x() + m[3][1] * p.y() + m[3][2] * p.z() + m[3][3];
+        if w == 1.0 {
+            Vec3(x, y, z)
+        } else {
+            Vec3(x, y, z) / w
+        }
+    }
+
+    /// An affine transform of a point known up to `error` per axis, with a
+    /// bound on the error of the result.
+    pub fn transform_point_with_error(&self, p: Vec3, error: Vec3) -> (Vec3, Vec3) {
+        let m = &self.0;
+        let row = |i: usize| {
+            let rounding = (m[i][0] * p.x()).abs()
+                + (m[i][1] * p.y()).abs()
+                + (m[i][2] * p.z()).abs()
+                + m[i][3].abs();
+            let carried =
+                m[i][0].abs() * error.x() + m[i][1].abs() * error.y() + m[i][2].abs() * error.z();
+            gamma(3) * rounding + (1.0 + gamma(3)) * carried
+        };
+        (self.transform_point(p), Vec3(row(0), row(1), row(2)))
+    }
+
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.0;
+        Vec3(
+            m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+            m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+            m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z(),
+        )
+    }
+
+    /// Normals have to be transformed by the inverse transpose. Call this on
+    /// the inverse matrix; it applies the transpose without building it.
+    pub fn transform_normal(&self, n: Vec3) -> Vec3 {
+        let m = &self.0;
+        Vec3(
+            m[0][0] * n.x() + m[1][0] * n.y() + m[2][0] * n.z(),
+            m[0][1] * n.x() + m[1][1] * n.y() + m[2][1] * n.z(),
+            m[0][2] * n.x() + m[1][2] * n.y() + m[2][2] * n.z(),
+        )
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut result = [[0.0; 4]; 4];
+        for (i, row) in result.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.0[j][i];
+            }
+        }
+        Mat4(result)
+    }
+
+    /// The inverse of an affine transform in closed form, from the adjugate
+    /// of its 3x3 part, which is much cheaper than `inverse`. None if the
+    /// transform is singular.
+    pub fn affine_inverse(&self) -> Option<Mat4> {
+        let m = &self.0;
+        let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| {
+            m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+        };
+        let adjugate = [
+            [
+                cofactor(1, 2, 1, 2),
+                -cofactor(0, 2, 1, 2),
+                cofactor(0, 1, 1, 2),
+            ],
+            [
+                -cofactor(1, 2, 0, 2),
+                cofactor(0, 2, 0, 2),
+                -cofactor(0, 1, 0, 2),
+            ],
+            [
+                cofactor(1, 2, 0, 1),
+                -cofactor(0, 2, 0, 1),
+                cofactor(0, 1, 0, 1),
+            ],
+        ];
+        let det = m[0][0] * adjugate[0][0] + m[0][1] * adjugate[1][0] + m[0][2] * adjugate[2][0];
+        if det.abs() < Float::MIN_POSITIVE {
+            return None;
+        }
+        let mut inv = Mat4::identity().0;
+        for i in 0..3 {
+            for j in 0..3 {
+                inv[i][j] = adjugate[i][j] / det;
+            }
+            inv[i][3] = -(0..3).map(|j| inv[i][j] * m[j][3]).sum::<Float>();
+        }
+        Some(Mat4(inv))
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting. None if the matrix
+    /// is singular or has entries that aren't finite.
+    pub fn inverse(&self) -> Option<Mat4> {
+        if self.0.iter().flatten().any(|v| !v.is_finite()) {
+            return None;
+        }
+        let mut a = self.0;
+        let mut inv = Mat4::identity().0;
+        for col in 0..4 {
+            let mut pivot = col;
+            for row in col + 1..4 {
+                if a[row][col].abs().total_cmp(&a[pivot][col].abs()).is_gt() {
+                    pivot = row;
+                }
+            }
+            if a[pivot][col].abs() < Float::MIN_POSITIVE {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+            let scale = 1.0 / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= scale;
+                inv[col][j] *= scale;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let factor = a[row][col];
+                    for j in 0..4 {
+                        a[row][j] -= factor * a[col][j];
+                        inv[row][j] -= factor * inv[col][j];
+                    }
+                }
+            }
+        }
+        Some(Mat4(inv))
+    }
+}
+
+impl ops::Mul<Mat4> for Mat4 {
+    type Output = Mat4;
+    fn mul(self, rhs: Mat4) -> Mat4 {
+        let mut result = [[0.0; 4]; 4];
+        for (i, row) in result.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.0[i][k] * rhs.0[k][j]).sum();
+            }
+        }
+        Mat4(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::float::tolerance;
+    use approx::assert_relative_eq;
+
+    fn assert_vec_eq(a: Vec3, b: Vec3) {
+        assert_relative_eq!(a.x(), b.x(), epsilon = tolerance(1e-12));
+        assert_relative_eq!(a.y(), b.y(), epsilon = tolerance(1e-12));
+        assert_relative_eq!(a.z(), b.z(), epsilon = tolerance(1e-12));
+    }
+
+    #[test]
+    fn translation_moves_points_not_vectors() {
+        let m = Mat4::translation(Vec3(1.0, 2.0, 3.0));
+        assert_eq!(m.transform_point(Vec3(1.0, 1.0, 1.0)), Vec3(2.0, 3.0, 4.0));
+        assert_eq!(m.transform_vector(Vec3(1.0, 1.0, 1.0)), Vec3(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn rotation_is_counter_clockwise() {
+        let m = Mat4::rotation(Vec3(0.0, 0.0, 1.0), 90.0);
+        assert_vec_eq(m.transform_vector(Vec3(1.0, 0.0, 0.0)), Vec3(0.0, 1.0, 0.0));
+        let m = Mat4::rotation(Vec3(0.0, 1.0, 0.0), 90.0);
+        assert_vec_eq(m.transform_vector(Vec3(0.0, 0.0, 1.0)), Vec3(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn multiplication_applies_right_first() {
+        let t = Mat4::translation(Vec3(1.0, 0.0, 0.0));
+        let s = Mat4::scaling(Vec3(2.0, 2.0, 2.0));
+        assert_eq!(
+            (t * s).transform_point(Vec3(1.0, 0.0, 0.0)),
+            Vec3(3.0, 0.0, 0.0)
+        );
+        assert_eq!(
+            (s * t).transform_point(Vec3(1.0, 0.0, 0.0)),
+            Vec3(4.0, 0.0, 0.0)
+        );
+        assert_eq!(t * Mat4::identity(), t);
+    }
+
+    #[test]
+    fn inverse_undoes_transform() {
+        let m = Mat4::translation(Vec3(1.0, -2.0, 3.0))
+            * Mat4::rotation(Vec3(1.0, 1.0, 0.0), 30.0)
+            * Mat4::scaling(Vec3(2.0, 0.5, 3.0));
+        let inv = m.inverse().unwrap();
+        let p = Vec3(0.3, 0.7, -1.1);
+        assert_vec_eq(inv.transform_point(m.transform_point(p)), p);
+        let product = m * inv;
+        for i in 0..4 {
+            for j in 0..4 {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert_relative_eq!(product.0[i][j], expected, epsilon = tolerance(1e-12));
+            }
+        }
+        assert!(Mat4::scaling(Vec3(1.0, 0.0, 1.0)).inverse().is_none());
+        let nan = Mat4::translation(Vec3(Float::NAN, 0.0, 0.0));
+        assert!(nan.inverse().is_none());
+    }
+
+    #[test]
+    fn affine_inverse_matches_inverse() {
+        let m = Mat4::translation(Vec3(1.0, -2.0, 3.0))
+            * Mat4::rotation(Vec3(0.0, 1.0, 2.0), 70.0)
+            * Mat4::scaling(Vec3(2.0, 0.5, -3.0));
+        let (affine, general) = (m.affine_inverse().unwrap(), m.inverse().unwrap());
+        for i in 0..4 {
+            for j in 0..4 {
+                assert_relative_eq!(affine.0[i][j], general.0[i][j], epsilon = tolerance(1e-12));
+            }
+        }
+        assert!(Mat4::scaling(Vec3(1.0, 0.0, 1.0))
+            .affine_inverse()
+            .is_none());
+    }
+
+    #[test]
+    fn normals_use_inverse_transpose() {
+        // squashing a 45 degree slope makes its normal steeper, not flatter
+        let m = Mat4::scaling(Vec3(1.0, 0.5, 1.0));
+        let inv = m.inverse().unwrap();
+        let n = inv.transform_normal(Vec3(1.0, 1.0, 0.0));
+        let tangent = m.transform_vector(Vec3(1.0, -1.0, 0.0));
+        assert_relative_eq!(n.dot(tangent), 0.0);
+        assert_eq!(inv.transform_normal(n), inv.transpose().transform_vector(n));
+    }
+}
@@ -1,30 +1,62 @@
+use crate::float::Float;
 use crate::hittable::*;
 use crate::ray::*;
+use crate::spectrum::*;
+use crate::thinfilm::ThinFilm;
 use crate::vec3::*;
 
-use rand::rngs::ThreadRng;
+use rand::rngs::StdRng;
 use rand::Rng;
+use std::any::Any;
 
-fn random_in_unit_sphere(rng: &mut ThreadRng) -> Vec3 {
+fn random_in_unit_sphere(rng: &mut StdRng) -> Vec3 {
     let mut v = Vec3(1.0, 1.0, 1.0);
     while v.squared_length() > 1.0 {
         v = Vec3(
-            2.0 * rng.gen::<f64>() - 1.0,
-            2.0 * rng.gen::<f64>() - 1.0,
-            2.0 * rng.gen::<f64>() - 1.0,
+            2.0 * rng.gen::<Float>() - 1.0,
+            2.0 * rng.gen::<Float>() - 1.0,
+            2.0 * rng.gen::<Float>() - 1.0,
         );
     }
     v
 }
 
-pub trait Material {
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, rng: &mut ThreadRng) -> (Ray, Vec3) {
+/// `Any` lets a compact scene unbox the built-in materials.
+pub trait Material: Any {
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, rng: &mut StdRng) -> (Ray, Vec3) {
+        let dir = hit_record.normal + random_in_unit_sphere(rng);
         let new_ray = Ray {
-            pos: ray.point_at_t(hit_record.t),
-            dir: hit_record.normal + random_in_unit_sphere(rng),
+            pos: hit_record.spawn_point(dir),
+            dir,
+            time: ray.time,
+            wavelength: ray.wavelength,
         };
         return (new_ray, Vec3(0.5, 0.5, 0.5));
     }
+    /// light given off by the surface itself towards the ray's origin
+    fn emitted(&self, _ray: &Ray, _hit_record: &HitRecord) -> Vec3 {
+        Vec3(0.0, 0.0, 0.0)
+    }
+    /// The spectral versions default to upsampling the RGB results, so that
+    /// every material works in the spectral renderer.
+    fn scatter_spectral(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        wavelengths: &SampledWavelengths,
+        rng: &mut StdRng,
+    ) -> (Ray, SampledSpectrum) {
+        let (new_ray, attenuation) = self.scatter(ray, hit_record, rng);
+        (new_ray, Spectrum::Rgb(attenuation).sample(wavelengths))
+    }
+    fn emitted_spectral(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        wavelengths: &SampledWavelengths,
+    ) -> SampledSpectrum {
+        Spectrum::Illuminant(self.emitted(ray, hit_record)).sample(wavelengths)
+    }
 }
 
 pub struct DiffuseMaterial {
@@ -32,75 +64,439 @@ pub struct DiffuseMaterial {
 }
 
 impl Material for DiffuseMaterial {
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, rng: &mut ThreadRng) -> (Ray, Vec3) {
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, rng: &mut StdRng) -> (Ray, Vec3) {
+        let dir = hit_record.normal + random_in_unit_sphere(rng);
         let new_ray = Ray {
-            pos: ray.point_at_t(hit_record.t),
-            dir: hit_record.normal + random_in_unit_sphere(rng),
+            pos: hit_record.spawn_point(dir),
+            dir,
+            time: ray.time,
+            wavelength: ray.wavelength,
         };
         return (new_ray, self.albedo);
     }
 }
 
+/// A diffuse surface with a reflectance spectrum, e.g. measured data. The
+/// RGB renderer uses the spectrum's color.
+pub struct SpectralDiffuseMaterial {
+    pub reflectance: Spectrum,
+    rgb: Vec3,
+}
+
+impl SpectralDiffuseMaterial {
+    pub fn new(reflectance: Spectrum) -> Self {
+        let rgb = converter().spectrum_to_rgb(&reflectance);
+        SpectralDiffuseMaterial { reflectance, rgb }
+    }
+}
+
+impl Material for SpectralDiffuseMaterial {
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, rng: &mut StdRng) -> (Ray, Vec3) {
+        let dir = hit_record.normal + random_in_unit_sphere(rng);
+        let new_ray = Ray {
+            pos: hit_record.spawn_point(dir),
+            dir,
+            time: ray.time,
+            wavelength: ray.wavelength,
+        };
+        (new_ray, self.rgb)
+    }
+    fn scatter_spectral(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        wavelengths: &SampledWavelengths,
+        rng: &mut StdRng,
+    ) -> (Ray, SampledSpectrum) {
+        let (new_ray, _) = self.scatter(ray, hit_record, rng);
+        (new_ray, self.reflectance.sample(wavelengths))
+    }
+}
+
+/// An emitter that absorbs all light falling on it. `radiance` can be an
+/// RGB color or spectral data such as a fluorescent lamp's lines.
+pub struct DiffuseLight {
+    pub radiance: Spectrum,
+    rgb: Vec3,
+}
+
+impl DiffuseLight {
+    /// an RGB radiance is upsampled as a light, with the illuminant basis
+    pub fn new(radiance: Spectrum) -> Self {
+        let radiance = match radiance {
+            Spectrum::Rgb(rgb) => Spectrum::Illuminant(rgb),
+            radiance => radiance,
+        };
+        let rgb = converter().spectrum_to_rgb(&radiance);
+        DiffuseLight { radiance, rgb }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, _rng: &mut StdRng) -> (Ray, Vec3) {
+        let new_ray = Ray {
+            pos: hit_record.spawn_point(hit_record.normal),
+            dir: hit_record.normal,
+            time: ray.time,
+            wavelength: ray.wavelength,
+        };
+        (new_ray, Vec3(0.0, 0.0, 0.0))
+    }
+    fn emitted(&self, _ray: &Ray, _hit_record: &HitRecord) -> Vec3 {
+        self.rgb
+    }
+    fn emitted_spectral(
+        &self,
+        _ray: &Ray,
+        _hit_record: &HitRecord,
+        wavelengths: &SampledWavelengths,
+    ) -> SampledSpectrum {
+        self.radiance.sample(wavelengths)
+    }
+}
+
 pub struct MetalMaterial {
     pub albedo: Vec3,
-    pub fuzz: f64,
+    pub fuzz: Float,
+    pub coating: Option<ThinFilm>,
+}
+
+// A complex index of refraction (n, k) for a metal that reflects
+// `reflectance` head on, using Gulbrandsen's "Artist Friendly Metallic
+// Fresnel" (2014) with the edge tint set to the same reflectance.
+fn metal_ior(reflectance: Float) -> (Float, Float) {
+    let r = reflectance.clamp(0.0, 0.999);
+    let sqrt_r = r.sqrt();
+    let n_min = (1.0 - r) / (1.0 + r);
+    let n_max = (1.0 + sqrt_r) / (1.0 - sqrt_r);
+    let n = r * n_min + (1.0 - r) * n_max;
+    let k2 = ((n + 1.0) * (n + 1.0) * r - (n - 1.0) * (n - 1.0)) / (1.0 - r);
+    (n, k2.max(0.0).sqrt())
+}
+
+impl MetalMaterial {
+    fn reflected_ray(&self, ray: &Ray, hit_record: &HitRecord, rng: &mut StdRng) -> Ray {
+        let dir = reflect(&ray.dir.normalized(), &hit_record.normal)
+            + self.fuzz * random_in_unit_sphere(rng);
+        Ray {
+            pos: hit_record.spawn_point(dir),
+            dir,
+            time: ray.time,
+            wavelength: ray.wavelength,
+        }
+    }
+
+    // the coating's reflectance at `lambda` nm, over a metal of this color
+    fn coated_reflectance(
+        &self,
+        film: &ThinFilm,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        lambda: Float,
+    ) -> Float {
+        let p = hit_record.point;
+        let thickness = film.thickness.thickness(hit_record.u, hit_record.v, p);
+        let cosine = ray.dir.dot(hit_record.normal) / ray.dir.length();
+        let substrate = metal_ior(Spectrum::Rgb(self.albedo).evaluate(lambda));
+        film.reflectance(thickness, lambda, cosine, 1.0, substrate)
+    }
 }
 
 impl Material for MetalMaterial {
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, rng: &mut ThreadRng) -> (Ray, Vec3) {
-        let new_ray = Ray {
-            pos: ray.point_at_t(hit_record.t),
-            dir: reflect(&ray.dir.normalized(), &hit_record.normal)
-                + self.fuzz * random_in_unit_sphere(rng),
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, rng: &mut StdRng) -> (Ray, Vec3) {
+        let new_ray = self.reflected_ray(ray, hit_record, rng);
+        let film = match &self.coating {
+            Some(film) => film,
+            None => return (new_ray, self.albedo),
         };
-        return (new_ray, self.albedo);
+        if let Some(lambda) = ray.wavelength {
+            let r = self.coated_reflectance(film, ray, hit_record, lambda);
+            return (new_ray, Vec3(r, r, r));
+        }
+        let p = hit_record.point;
+        let thickness = film.thickness.thickness(hit_record.u, hit_record.v, p);
+        let cosine = ray.dir.dot(hit_record.normal) / ray.dir.length();
+        let albedo = Spectrum::Rgb(self.albedo);
+        let attenuation = film.reflectance_rgb(thickness, cosine, 1.0, |lambda| {
+            metal_ior(albedo.evaluate(lambda))
+        });
+        (new_ray, attenuation)
+    }
+    fn scatter_spectral(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        wavelengths: &SampledWavelengths,
+        rng: &mut StdRng,
+    ) -> (Ray, SampledSpectrum) {
+        let new_ray = self.reflected_ray(ray, hit_record, rng);
+        let attenuation = match &self.coating {
+            Some(film) => {
+                let mut values = [0.0; N_WAVELENGTHS];
+                for (v, lambda) in values.iter_mut().zip(wavelengths.lambda.iter()) {
+                    *v = self.coated_reflectance(film, ray, hit_record, *lambda);
+                }
+                SampledSpectrum(values)
+            }
+            None => Spectrum::Rgb(self.albedo).sample(wavelengths),
+        };
+        (new_ray, attenuation)
     }
 }
 
+/// How the index of refraction of a dielectric changes with wavelength.
+/// Wavelengths in the formulas are in micrometers.
+pub enum Dispersion {
+    None,
+    /// n = a + b / lambda^2
+    Cauchy {
+        a: Float,
+        b: Float,
+    },
+    /// n^2 = 1 + sum of b_i lambda^2 / (lambda^2 - c_i)
+    Sellmeier {
+        b: [Float; 3],
+        c: [Float; 3],
+    },
+    /// a Cauchy fit with the glass' `ref_idx` at the Fraunhofer d line and
+    /// the given Abbe number
+    Abbe(Float),
+}
+
+// Fraunhofer lines used to define the Abbe number, in micrometers
+const LAMBDA_D: Float = 0.5876;
+const LAMBDA_F: Float = 0.4861;
+const LAMBDA_C: Float = 0.6563;
+
 pub struct GlassMaterial {
     pub albedo: Vec3,
-    pub ref_idx: f64,
+    pub ref_idx: Float,
+    pub dispersion: Dispersion,
+    /// a film on the outside of the glass; a soap bubble is a coated glass
+    /// with a `ref_idx` of 1
+    pub coating: Option<ThinFilm>,
 }
 
-impl Material for GlassMaterial {
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, rng: &mut ThreadRng) -> (Ray, Vec3) {
-        let outward_normal: Vec3;
-        let ni_over_nt: f64;
-        let cosine: f64;
-
-        if ray.dir.dot(hit_record.normal) > 0.0 {
-            outward_normal = -hit_record.normal;
-            ni_over_nt = self.ref_idx;
-            cosine = self.ref_idx * ray.dir.dot(hit_record.normal) / ray.dir.length();
+// Which way a ray crosses the surface of a glass.
+struct Crossing {
+    outward_normal: Vec3,
+    ni_over_nt: Float,
+    // the cosine Schlick's approximation is evaluated with
+    schlick_cosine: Float,
+    // cosine of the angle of incidence and the index on that side
+    cos_incident: Float,
+    n_incident: Float,
+    n_transmitted: Float,
+}
+
+impl GlassMaterial {
+    /// the index of refraction at `lambda` nanometers
+    pub fn ior(&self, lambda: Float) -> Float {
+        let l2 = (lambda * 1e-3) * (lambda * 1e-3);
+        match &self.dispersion {
+            Dispersion::None => self.ref_idx,
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let sum: Float = b.iter().zip(c.iter()).map(|(b, c)| b * l2 / (l2 - c)).sum();
+                (1.0 + sum).sqrt()
+            }
+            Dispersion::Abbe(abbe) => {
+                let b = (self.ref_idx - 1.0)
+                    / (abbe * (1.0 / (LAMBDA_F * LAMBDA_F) - 1.0 / (LAMBDA_C * LAMBDA_C)));
+                let a = self.ref_idx - b / (LAMBDA_D * LAMBDA_D);
+                a + b / l2
+            }
+        }
+    }
+
+    fn crossing(ray: &Ray, hit_record: &HitRecord, ref_idx: Float) -> Crossing {
+        let cos = (ray.dir.dot(hit_record.normal) / ray.dir.length()).abs();
+        if hit_record.front_face {
+            Crossing {
+                outward_normal: hit_record.normal,
+                ni_over_nt: 1.0 / ref_idx,
+                schlick_cosine: cos,
+                cos_incident: cos,
+                n_incident: 1.0,
+                n_transmitted: ref_idx,
+            }
         } else {
-            outward_normal = hit_record.normal;
-            ni_over_nt = 1.0 / self.ref_idx;
-            cosine = -ray.dir.dot(hit_record.normal) / ray.dir.length();
+            Crossing {
+                outward_normal: -hit_record.normal,
+                ni_over_nt: ref_idx,
+                schlick_cosine: ref_idx * cos,
+                cos_incident: cos,
+                n_incident: ref_idx,
+                n_transmitted: 1.0,
+            }
         }
+    }
 
-        if rng.gen::<f64>() > schlick(cosine, self.ref_idx) {
+    // The coating's reflectance at `lambda` nm. The film is taken to look
+    // the same from inside the glass as from outside.
+    fn coated_reflectance(
+        film: &ThinFilm,
+        thickness: Float,
+        crossing: &Crossing,
+        lambda: Float,
+    ) -> Float {
+        film.reflectance(
+            thickness,
+            lambda,
+            crossing.cos_incident,
+            crossing.n_incident,
+            (crossing.n_transmitted, 0.0),
+        )
+    }
+
+    // Refracts or reflects, picking one with probability `p_reflect`.
+    // Returns the direction and whether it was a reflection.
+    fn choose(
+        ray: &Ray,
+        hit_record: &HitRecord,
+        crossing: &Crossing,
+        p_reflect: Float,
+        rng: &mut StdRng,
+    ) -> (Vec3, bool) {
+        if rng.gen::<Float>() > p_reflect {
             // borrowed this code from https://github.com/perliedman/raytracing-in-one-weekend/blob/master/src/material.rs
-            match refract(&ray.dir, &outward_normal, ni_over_nt) {
-                Some(refraction) => {
-                    return (
-                        Ray {
-                            pos: ray.point_at_t(hit_record.t),
-                            dir: refraction,
-                        },
-                        self.albedo,
-                    );
-                }
-                None => {}
+            if let Some(refraction) =
+                refract(&ray.dir, &crossing.outward_normal, crossing.ni_over_nt)
+            {
+                return (refraction, false);
             }
         }
+        (reflect(&ray.dir.normalized(), &hit_record.normal), true)
+    }
 
-        (
-            Ray {
-                pos: ray.point_at_t(hit_record.t),
-                dir: reflect(&ray.dir.normalized(), &hit_record.normal),
-            },
-            self.albedo,
-        )
+    fn new_ray(ray: &Ray, hit_record: &HitRecord, dir: Vec3, wavelength: Option<Float>) -> Ray {
+        Ray {
+            pos: hit_record.spawn_point(dir),
+            dir,
+            time: ray.time,
+            wavelength,
+        }
+    }
+}
+
+// The weight of the chosen branch when reflecting with probability `p`
+// although the true reflectance is `r`.
+fn branch_weight(r: Float, p: Float, reflected: bool, total_internal: bool) -> Float {
+    if total_internal {
+        1.0
+    } else if reflected {
+        r / p
+    } else {
+        (1.0 - r) / (1.0 - p)
+    }
+}
+
+// reflection probability for a coated crossing, kept away from 0 and 1 so
+// both branches stay possible
+fn reflect_probability(average: Float) -> Float {
+    average.clamp(0.01, 0.99)
+}
+
+impl Material for GlassMaterial {
+    // A dispersive glass picks one wavelength for the path at the first
+    // hit and weights the path with that wavelength's color.
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, rng: &mut StdRng) -> (Ray, Vec3) {
+        let (wavelength, weight) = match (&self.dispersion, ray.wavelength) {
+            (Dispersion::None, _) => (ray.wavelength, Vec3(1.0, 1.0, 1.0)),
+            (_, Some(lambda)) => (Some(lambda), Vec3(1.0, 1.0, 1.0)),
+            (_, None) => {
+                let lambda = LAMBDA_MIN + rng.gen::<Float>() * (LAMBDA_MAX - LAMBDA_MIN);
+                (Some(lambda), converter().wavelength_to_rgb(lambda))
+            }
+        };
+        let ref_idx = wavelength.map_or(self.ref_idx, |lambda| self.ior(lambda));
+        let crossing = Self::crossing(ray, hit_record, ref_idx);
+        let film = match &self.coating {
+            Some(film) => film,
+            None => {
+                let p = schlick(crossing.schlick_cosine, ref_idx);
+                let (dir, _) = Self::choose(ray, hit_record, &crossing, p, rng);
+                let new_ray = Self::new_ray(ray, hit_record, dir, wavelength);
+                return (new_ray, self.albedo * weight);
+            }
+        };
+        let p = hit_record.point;
+        let thickness = film.thickness.thickness(hit_record.u, hit_record.v, p);
+        let reflectance = match wavelength {
+            Some(lambda) => {
+                let r = Self::coated_reflectance(film, thickness, &crossing, lambda);
+                Vec3(r, r, r)
+            }
+            None => film.reflectance_rgb(
+                thickness,
+                crossing.cos_incident,
+                crossing.n_incident,
+                |_| (crossing.n_transmitted, 0.0),
+            ),
+        };
+        let p_reflect =
+            reflect_probability((reflectance.r() + reflectance.g() + reflectance.b()) / 3.0);
+        let (dir, reflected) = Self::choose(ray, hit_record, &crossing, p_reflect, rng);
+        let total_internal =
+            refract(&ray.dir, &crossing.outward_normal, crossing.ni_over_nt).is_none();
+        let coat = Vec3(
+            branch_weight(reflectance.r(), p_reflect, reflected, total_internal),
+            branch_weight(reflectance.g(), p_reflect, reflected, total_internal),
+            branch_weight(reflectance.b(), p_reflect, reflected, total_internal),
+        );
+        let new_ray = Self::new_ray(ray, hit_record, dir, wavelength);
+        (new_ray, self.albedo * weight * coat)
+    }
+    // A dispersive glass only follows the hero wavelength, dropping the
+    // others; the hero is weighted up to keep the estimate unbiased.
+    fn scatter_spectral(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        wavelengths: &SampledWavelengths,
+        rng: &mut StdRng,
+    ) -> (Ray, SampledSpectrum) {
+        let mut attenuation = Spectrum::Rgb(self.albedo).sample(wavelengths);
+        let (ref_idx, wavelength) = match self.dispersion {
+            Dispersion::None => (self.ref_idx, ray.wavelength),
+            _ => {
+                let hero = wavelengths.lambda[0];
+                let weight = if ray.wavelength.is_some() {
+                    1.0
+                } else {
+                    N_WAVELENGTHS as Float
+                };
+                attenuation = SampledSpectrum::constant(0.0);
+                attenuation.0[0] = Spectrum::Rgb(self.albedo).evaluate(hero) * weight;
+                (self.ior(hero), Some(hero))
+            }
+        };
+        let crossing = Self::crossing(ray, hit_record, ref_idx);
+        let film = match &self.coating {
+            Some(film) => film,
+            None => {
+                let p = schlick(crossing.schlick_cosine, ref_idx);
+                let (dir, _) = Self::choose(ray, hit_record, &crossing, p, rng);
+                let new_ray = Self::new_ray(ray, hit_record, dir, wavelength);
+                return (new_ray, attenuation);
+            }
+        };
+        let p = hit_record.point;
+        let thickness = film.thickness.thickness(hit_record.u, hit_record.v, p);
+        let mut reflectance = [0.0; N_WAVELENGTHS];
+        for (r, lambda) in reflectance.iter_mut().zip(wavelengths.lambda.iter()) {
+            *r = Self::coated_reflectance(film, thickness, &crossing, *lambda);
+        }
+        let p_reflect =
+            reflect_probability(reflectance.iter().sum::<Float>() / N_WAVELENGTHS as Float);
+        let (dir, reflected) = Self::choose(ray, hit_record, &crossing, p_reflect, rng);
+        let total_internal =
+            refract(&ray.dir, &crossing.outward_normal, crossing.ni_over_nt).is_none();
+        for (a, r) in attenuation.0.iter_mut().zip(reflectance.iter()) {
+            *a *= branch_weight(*r, p_reflect, reflected, total_internal);
+        }
+        let new_ray = Self::new_ray(ray, hit_record, dir, wavelength);
+        (new_ray, attenuation)
     }
 }
 
@@ -109,8 +505,10 @@ fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
     *v - 2.0 * v.dot(*n) * *n
 }
 
+/// `v` bent through a surface with normal `n`, where `ni_over_nt` is the
+/// ratio of the indices of refraction; None if it is reflected entirely.
 // borrowed this code from https://github.com/perliedman/raytracing-in-one-weekend/blob/master/src/material.rs
-fn refract(v: &Vec3, n: &Vec3, ni_over_nt: f64) -> Option<Vec3> {
+pub fn refract(v: &Vec3, n: &Vec3, ni_over_nt: Float) -> Option<Vec3> {
     let uv = v.normalized();
     let dt = uv.dot(*n);
     let discriminant = 1.0 - ni_over_nt * ni_over_nt * (1.0 - dt * dt);
@@ -122,8 +520,100 @@ fn refract(v: &Vec3, n: &Vec3, ni_over_nt: f64) -> Option<Vec3> {
 }
 
 // borrowed this code from https://github.com/perliedman/raytracing-in-one-weekend/blob/master/src/material.rs
-fn schlick(cosine: f64, ref_idx: f64) -> f64 {
+fn schlick(cosine: Float, ref_idx: Float) -> Float {
     let r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
     let r0sq = r0 * r0;
     r0sq + (1.0 - r0sq) * (1.0 - cosine).powf(5.0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::float::tolerance;
+    use approx::assert_relative_eq;
+    use rand::SeedableRng;
+
+    fn glass(dispersion: Dispersion) -> GlassMaterial {
+        GlassMaterial {
+            albedo: Vec3(1.0, 1.0, 1.0),
+            ref_idx: 1.5168,
+            dispersion,
+            coating: None,
+        }
+    }
+
+    #[test]
+    fn abbe_number_sets_dispersion() {
+        let g = glass(Dispersion::Abbe(64.17));
+        assert_relative_eq!(g.ior(587.6), 1.5168, epsilon = tolerance(1e-12));
+        let spread = g.ior(486.1) - g.ior(656.3);
+        assert_relative_eq!(spread, 0.5168 / 64.17, epsilon = tolerance(1e-12));
+        assert_relative_eq!(glass(Dispersion::None).ior(400.0), 1.5168);
+    }
+
+    #[test]
+    #[allow(clippy::excessive_precision)]
+    fn sellmeier_bk7() {
+        let g = glass(Dispersion::Sellmeier {
+            b: [1.03961212, 0.231792344, 1.01046945],
+            c: [0.00600069867, 0.0200179144, 103.560653],
+        });
+        assert_relative_eq!(g.ior(587.6), 1.5168, epsilon = 1e-4);
+        assert!(g.ior(400.0) > g.ior(700.0));
+        let c = glass(Dispersion::Cauchy { a: 1.5, b: 0.01 });
+        assert_relative_eq!(c.ior(500.0), 1.54, epsilon = 1e-12);
+    }
+
+    #[test]
+    fn dispersive_glass_picks_a_wavelength() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let g = glass(Dispersion::Abbe(30.0));
+        let ray = Ray::new(Vec3(0.0, 0.0, 1.0), Vec3(0.3, 0.0, -1.0));
+        let hit = HitRecord::new(&ray, 1.0, Vec3(0.0, 0.0, 1.0), HitMaterial::Dyn(&g));
+        let (scattered, _) = g.scatter(&ray, &hit, &mut rng);
+        let lambda = scattered.wavelength.unwrap();
+        // later hits keep the wavelength without weighting again
+        let (again, weight) = g.scatter(&scattered, &hit, &mut rng);
+        assert_eq!(again.wavelength, Some(lambda));
+        assert_eq!(weight, Vec3(1.0, 1.0, 1.0));
+
+        let wavelengths = SampledWavelengths::sample(0.5);
+        let (hero_ray, attenuation) = g.scatter_spectral(&ray, &hit, &wavelengths, &mut rng);
+        assert_eq!(hero_ray.wavelength, Some(wavelengths.lambda[0]));
+        assert!(attenuation.0[0] > 1.0);
+        assert_eq!(&attenuation.0[1..], &[0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn coated_metal_is_iridescent() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut metal = MetalMaterial {
+            albedo: Vec3(0.6, 0.6, 0.6),
+            fuzz: 0.0,
+            coating: None,
+        };
+        let ray = Ray::new(Vec3(0.0, 0.0, 1.0), Vec3(0.0, 0.0, -1.0));
+        let plain = MetalMaterial {
+            albedo: Vec3(0.6, 0.6, 0.6),
+            fuzz: 0.0,
+            coating: None,
+        };
+        let hit = HitRecord::new(&ray, 1.0, Vec3(0.0, 0.0, 1.0), HitMaterial::Dyn(&plain));
+        assert_eq!(metal.scatter(&ray, &hit, &mut rng).1, Vec3(0.6, 0.6, 0.6));
+        // Gulbrandsen's fit reproduces the reflectance head on
+        let (n, k) = metal_ior(0.6);
+        let r = ((n - 1.0) * (n - 1.0) + k * k) / ((n + 1.0) * (n + 1.0) + k * k);
+        assert_relative_eq!(r, 0.6, epsilon = 1e-9);
+
+        metal.coating = Some(ThinFilm {
+            ior: 2.0,
+            thickness: Box::new(250.0),
+        });
+        let (_, c) = metal.scatter(&ray, &hit, &mut rng);
+        assert!(
+            (c.r() - c.g()).abs() + (c.g() - c.b()).abs() > 0.02,
+            "{:?}",
+            c
+        );
+    }
+}
@@ -0,0 +1,470 @@
+use crate::aabb::Aabb;
+use crate::float::Float;
+use crate::hittable::*;
+use crate::noise::Perlin;
+use crate::ray::*;
+use crate::vec3::*;
+use rand::rngs::StdRng;
+use rand::Rng;
+
+/// Picks a new direction for light scattered inside a medium. `dir` is the
+/// normalized direction the light was travelling in. Phase functions are
+/// sampled exactly, so the scattering weight is carried by the medium.
+pub trait PhaseFunction {
+    fn sample(&self, dir: Vec3, rng: &mut StdRng) -> Vec3;
+}
+
+pub struct Isotropic {}
+
+impl PhaseFunction for Isotropic {
+    fn sample(&self, _dir: Vec3, rng: &mut StdRng) -> Vec3 {
+        let z = 1.0 - 2.0 * rng.gen::<Float>();
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * crate::float::consts::PI * rng.gen::<Float>();
+        Vec3(r * phi.cos(), r * phi.sin(), z)
+    }
+}
+
+/// Henyey-Greenstein phase function. Positive `g` scatters forward,
+/// negative `g` backward; `g` is the mean cosine of the scattering angle.
+pub struct HenyeyGreenstein {
+    pub g: Float,
+}
+
+impl PhaseFunction for HenyeyGreenstein {
+    fn sample(&self, dir: Vec3, rng: &mut StdRng) -> Vec3 {
+        let g = self.g;
+        let u = rng.gen::<Float>();
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u
+        } else {
+            let sq = (1.0 - g * g) / (1.0 + g - 2.0 * g * u);
+            (1.0 + g * g - sq * sq) / (2.0 * g)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * crate::float::consts::PI * rng.gen::<Float>();
+        let (tangent, bitangent) = dir.orthonormal_basis();
+        sin_theta * phi.cos() * tangent + sin_theta * phi.sin() * bitangent + cos_theta * dir
+    }
+}
+
+/// The outcome of tracking a ray segment through a medium: either the light
+/// scatters at `t` into `dir`, or it passes through. Either way its
+/// contribution is multiplied by `weight`.
+pub struct MediumSample {
+    pub weight: Vec3,
+    pub scatter: Option<(Float, Vec3)>,
+}
+
+impl MediumSample {
+    pub fn pass_through() -> Self {
+        MediumSample {
+            weight: Vec3(1.0, 1.0, 1.0),
+            scatter: None,
+        }
+    }
+}
+
+pub trait Medium {
+    /// the parts of [t_min, t_max] along the ray that are inside the medium,
+    /// nearest first
+    fn intervals(&self, ray: &Ray, t_min: Float, t_max: Float) -> Vec<(Float, Float)>;
+    /// samples a free-flight distance through the segment [t0, t1]
+    fn sample(&self, ray: &Ray, t0: Float, t1: Float, rng: &mut StdRng) -> MediumSample;
+}
+
+// Where the ray is inside a closed boundary, which need not be convex. The
+// crossings are walked from the far side of the ray's origin, so the first
+// entry can lie behind the origin when the ray starts inside the boundary.
+fn boundary_intervals(
+    boundary: &(dyn Hittable + Send + Sync),
+    ray: &Ray,
+    t_min: Float,
+    t_max: Float,
+) -> Vec<(Float, Float)> {
+    let mut intervals = Vec::new();
+    let mut entry = None;
+    let mut crossing = boundary.hit(ray, Float::NEG_INFINITY, Float::INFINITY);
+    while let Some(hit) = crossing {
+        let t = (hit.point - ray.pos).dot(ray.dir) / ray.dir.squared_length();
+        if hit.front_face {
+            entry = Some(t);
+        } else if let Some(t_entry) = entry.take() {
+            let (t0, t1) = (t_entry.max(t_min), t.min(t_max));
+            if t0 < t1 {
+                intervals.push((t0, t1));
+            }
+        }
+        if t >= t_max {
+            break;
+        }
+        // the next crossing, looking on from just past this one
+        let past = Ray {
+            pos: hit.spawn_point(ray.dir),
+            dir: ray.dir,
+            time: ray.time,
+            wavelength: ray.wavelength,
+        };
+        crossing = boundary.hit(&past, 0.0, Float::INFINITY);
+    }
+    intervals
+}
+
+fn average(v: Vec3) -> Float {
+    (v.x() + v.y() + v.z()) / 3.0
+}
+
+/// A medium of constant density filling a closed `boundary`. Absorption and
+/// scattering coefficients are per unit distance and may differ per color
+/// channel; distances are sampled with the extinction of a randomly chosen
+/// channel and the result is weighted with the pdf over all channels.
+pub struct HomogeneousMedium {
+    pub boundary: Box<dyn Hittable + Send + Sync>,
+    pub sigma_a: Vec3,
+    pub sigma_s: Vec3,
+    pub phase: Box<dyn PhaseFunction + Send + Sync>,
+}
+
+impl Medium for HomogeneousMedium {
+    fn intervals(&self, ray: &Ray, t_min: Float, t_max: Float) -> Vec<(Float, Float)> {
+        boundary_intervals(&*self.boundary, ray, t_min, t_max)
+    }
+
+    fn sample(&self, ray: &Ray, t0: Float, t1: Float, rng: &mut StdRng) -> MediumSample {
+        let sigma_t = self.sigma_a + self.sigma_s;
+        let ray_length = ray.dir.length();
+        let segment = (t1 - t0) * ray_length;
+        let channel_sigma = sigma_t[rng.gen_range(0, 3)];
+        let distance = if channel_sigma > 0.0 {
+            -(1.0 - rng.gen::<Float>()).ln() / channel_sigma
+        } else {
+            Float::INFINITY
+        };
+        let scattered = distance < segment;
+        let travelled = distance.min(segment);
+        let transmittance = (-travelled * sigma_t).exp();
+        if scattered {
+            let pdf = average(sigma_t * transmittance);
+            let dir = self.phase.sample(ray.dir / ray_length, rng);
+            MediumSample {
+                weight: transmittance * self.sigma_s / pdf,
+                scatter: Some((t0 + distance / ray_length, dir)),
+            }
+        } else {
+            MediumSample {
+                weight: transmittance / average(transmittance),
+                scatter: None,
+            }
+        }
+    }
+}
+
+/// A scalar density at every point of space, used to scale the
+/// coefficients of a heterogeneous medium.
+pub trait DensityField {
+    fn density(&self, p: Vec3) -> Float;
+    /// an upper bound of the density, used as the majorant for tracking
+    fn max_density(&self) -> Float;
+}
+
+/// Densities on a regular grid spanning `bounds`, stored x fastest, then y,
+/// then z, and interpolated trilinearly. Zero outside of the bounds.
+pub struct DensityGrid {
+    pub bounds: Aabb,
+    pub resolution: (usize, usize, usize),
+    pub values: Vec<Float>,
+}
+
+impl DensityGrid {
+    fn value(&self, x: usize, y: usize, z: usize) -> Float {
+        let (nx, ny, _) = self.resolution;
+        self.values[(z * ny + y) * nx + x]
+    }
+}
+
+impl DensityField for DensityGrid {
+    fn density(&self, p: Vec3) -> Float {
+        if !self.bounds.contains(p) {
+            return 0.0;
+        }
+        let (nx, ny, nz) = self.resolution;
+        let extent = self.bounds.max - self.bounds.min;
+        let rel = (p - self.bounds.min) / extent;
+        // sample positions sit at the centers of the grid cells
+        let coord = |r: Float, n: usize| {
+            let c = (r * n as Float - 0.5).max(0.0).min((n - 1) as Float);
+            let i = (c.floor() as usize).min(n.saturating_sub(2));
+            (i, (c - i as Float).min(1.0))
+        };
+        let (x, fx) = coord(rel.x(), nx);
+        let (y, fy) = coord(rel.y(), ny);
+        let (z, fz) = coord(rel.z(), nz);
+        let (x1, y1, z1) = (
+            (x + 1).min(nx - 1),
+            (y + 1).min(ny - 1),
+            (z + 1).min(nz - 1),
+        );
+        let lerp = |a: Float, b: Float, t: Float| a + (b - a) * t;
+        let c00 = lerp(self.value(x, y, z), self.value(x1, y, z), fx);
+        let c10 = lerp(self.value(x, y1, z), self.value(x1, y1, z), fx);
+        let c01 = lerp(self.value(x, y, z1), self.value(x1, y, z1), fx);
+        let c11 = lerp(self.value(x, y1, z1), self.value(x1, y1, z1), fx);
+        lerp(lerp(c00, c10, fy), lerp(c01, c11, fy), fz)
+    }
+    fn max_density(&self) -> Float {
+        self.values.iter().fold(0.0, |m, v| m.max(*v))
+    }
+}
+
+/// Procedural density from Perlin turbulence, for smoke and clouds.
+pub struct NoiseDensity {
+    pub noise: Perlin,
+    pub frequency: Float,
+    pub octaves: usize,
+    pub scale: Float,
+}
+
+impl DensityField for NoiseDensity {
+    fn density(&self, p: Vec3) -> Float {
+        self.scale * self.noise.turbulence(self.frequency * p, self.octaves)
+    }
+    fn max_density(&self) -> Float {
+        // each octave contributes at most its weight
+        2.0 * self.scale
+    }
+}
+
+/// A medium whose density varies through space, tracked with delta
+/// tracking. Extinction is taken to be the same for all color channels (the
+/// average of sigma_a + sigma_s), while the scattering albedo
+/// sigma_s / (sigma_a + sigma_s) stays colored.
+pub struct HeterogeneousMedium {
+    pub boundary: Box<dyn Hittable + Send + Sync>,
+    pub density: Box<dyn DensityField + Send + Sync>,
+    pub sigma_a: Vec3,
+    pub sigma_s: Vec3,
+    pub phase: Box<dyn PhaseFunction + Send + Sync>,
+}
+
+impl Medium for HeterogeneousMedium {
+    fn intervals(&self, ray: &Ray, t_min: Float, t_max: Float) -> Vec<(Float, Float)> {
+        boundary_intervals(&*self.boundary, ray, t_min, t_max)
+    }
+
+    fn sample(&self, ray: &Ray, t0: Float, t1: Float, rng: &mut StdRng) -> MediumSample {
+        let sigma_t = average(self.sigma_a + self.sigma_s);
+        // per channel, so no channel scatters more light than reaches it
+        let albedo_of = |s: Float, a: Float| if s + a > 0.0 { s / (s + a) } else { 0.0 };
+        let albedo = Vec3(
+            albedo_of(self.sigma_s.x(), self.sigma_a.x()),
+            albedo_of(self.sigma_s.y(), self.sigma_a.y()),
+            albedo_of(self.sigma_s.z(), self.sigma_a.z()),
+        );
+        let majorant = sigma_t * self.density.max_density();
+        if majorant <= 0.0 {
+            return MediumSample::pass_through();
+        }
+        let ray_length = ray.dir.length();
+        let segment = (t1 - t0) * ray_length;
+        let mut distance = 0.0;
+        loop {
+            distance -= (1.0 - rng.gen::<Float>()).ln() / majorant;
+            if distance >= segment {
+                return MediumSample::pass_through();
+            }
+            let t = t0 + distance / ray_length;
+            let density = self.density.density(ray.point_at_t(t));
+            if density * sigma_t > rng.gen::<Float>() * majorant {
+                // a real collision; it scatters rather than absorbs with the
+                // albedo, which is applied as a weight
+                let dir = self.phase.sample(ray.dir / ray_length, rng);
+                return MediumSample {
+                    weight: albedo,
+                    scatter: Some((t, dir)),
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::Material;
+    use approx::assert_relative_eq;
+    use rand::SeedableRng;
+
+    struct TestMaterial {}
+    impl Material for TestMaterial {}
+
+    fn unit_sphere() -> Box<dyn Hittable + Send + Sync> {
+        Box::new(Sphere {
+            center: Vec3(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material: Box::new(TestMaterial {}),
+        })
+    }
+
+    fn through_center() -> Ray {
+        Ray::new(Vec3(0.0, 0.0, -5.0), Vec3(0.0, 0.0, 1.0))
+    }
+
+    #[test]
+    fn henyey_greenstein_mean_cosine_is_g() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let dir = Vec3(0.0, 1.0, 0.0);
+        for g in &[-0.5, 0.0, 0.7] {
+            let phase = HenyeyGreenstein { g: *g };
+            let n = 20000;
+            let mean: Float = (0..n)
+                .map(|_| phase.sample(dir, &mut rng).dot(dir))
+                .sum::<Float>()
+                / n as Float;
+            assert!((mean - g).abs() < 0.03, "g = {}, mean = {}", g, mean);
+        }
+        let iso = Isotropic {};
+        assert_relative_eq!(iso.sample(dir, &mut rng).length(), 1.0, epsilon = 1e-12);
+    }
+
+    #[test]
+    fn interval_covers_inside_of_boundary() {
+        let medium = HomogeneousMedium {
+            boundary: unit_sphere(),
+            sigma_a: Vec3(0.0, 0.0, 0.0),
+            sigma_s: Vec3(1.0, 1.0, 1.0),
+            phase: Box::new(Isotropic {}),
+        };
+        let intervals = medium.intervals(&through_center(), 0.001, Float::MAX);
+        assert_eq!(intervals.len(), 1);
+        assert_relative_eq!(intervals[0].0, 4.0);
+        assert_relative_eq!(intervals[0].1, 6.0);
+        // stops at a surface in front of the exit
+        let intervals = medium.intervals(&through_center(), 0.001, 5.5);
+        assert_relative_eq!(intervals[0].1, 5.5);
+        // starting inside the medium
+        let inside = Ray::new(Vec3(0.0, 0.0, 0.0), Vec3(1.0, 0.0, 0.0));
+        let intervals = medium.intervals(&inside, 0.001, Float::MAX);
+        assert_relative_eq!(intervals[0].0, 0.001);
+        assert_relative_eq!(intervals[0].1, 1.0);
+        let misses = Ray::new(Vec3(0.0, 2.0, -5.0), Vec3(0.0, 0.0, 1.0));
+        assert!(medium.intervals(&misses, 0.001, Float::MAX).is_empty());
+    }
+
+    #[test]
+    fn intervals_of_a_non_convex_boundary() {
+        // two separate balls along the ray
+        let mut boundary = HittableList::new();
+        for z in &[-2.0, 2.0] {
+            boundary.push(Sphere {
+                center: Vec3(0.0, 0.0, *z),
+                radius: 1.0,
+                material: Box::new(TestMaterial {}),
+            });
+        }
+        let medium = HomogeneousMedium {
+            boundary: Box::new(boundary),
+            sigma_a: Vec3(0.0, 0.0, 0.0),
+            sigma_s: Vec3(1.0, 1.0, 1.0),
+            phase: Box::new(Isotropic {}),
+        };
+        let intervals = medium.intervals(&through_center(), 0.001, Float::MAX);
+        assert_eq!(intervals.len(), 2);
+        assert_relative_eq!(intervals[0].0, 2.0, epsilon = 1e-9);
+        assert_relative_eq!(intervals[0].1, 4.0, epsilon = 1e-9);
+        assert_relative_eq!(intervals[1].0, 6.0, epsilon = 1e-9);
+        assert_relative_eq!(intervals[1].1, 8.0, epsilon = 1e-9);
+        // ending between the balls leaves out the second one
+        assert_eq!(medium.intervals(&through_center(), 0.001, 5.0).len(), 1);
+    }
+
+    #[test]
+    fn homogeneous_transmittance_is_unbiased() {
+        // a purely absorbing grey medium: the mean weight of the rays that
+        // pass through is the transmittance exp(-sigma * length)
+        let medium = HomogeneousMedium {
+            boundary: unit_sphere(),
+            sigma_a: Vec3(0.5, 0.5, 0.5),
+            sigma_s: Vec3(0.0, 0.0, 0.0),
+            phase: Box::new(Isotropic {}),
+        };
+        let mut rng = StdRng::seed_from_u64(1);
+        let n = 20000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            let s = medium.sample(&through_center(), 4.0, 6.0, &mut rng);
+            if s.scatter.is_none() {
+                sum += s.weight.x();
+            }
+        }
+        let expected = (-(0.5 as Float) * 2.0).exp();
+        assert!((sum / n as Float - expected).abs() < 0.02);
+    }
+
+    #[test]
+    fn delta_tracking_matches_homogeneous_case() {
+        // a constant density grid must give the same scattering
+        // probability as the analytic medium
+        let medium = HeterogeneousMedium {
+            boundary: unit_sphere(),
+            density: Box::new(DensityGrid {
+                bounds: Aabb::new(Vec3(-1.0, -1.0, -1.0), Vec3(1.0, 1.0, 1.0)),
+                resolution: (2, 2, 2),
+                values: vec![0.5; 8],
+            }),
+            sigma_a: Vec3(0.0, 0.0, 0.0),
+            sigma_s: Vec3(2.0, 2.0, 2.0),
+            phase: Box::new(Isotropic {}),
+        };
+        let mut rng = StdRng::seed_from_u64(1);
+        let n = 20000;
+        let passed = (0..n)
+            .filter(|_| {
+                medium
+                    .sample(&through_center(), 4.0, 6.0, &mut rng)
+                    .scatter
+                    .is_none()
+            })
+            .count();
+        let expected = (-(2.0 as Float)).exp();
+        assert!((passed as Float / n as Float - expected).abs() < 0.02);
+    }
+
+    #[test]
+    fn colored_scattering_does_not_add_energy() {
+        let medium = HeterogeneousMedium {
+            boundary: unit_sphere(),
+            density: Box::new(DensityGrid {
+                bounds: Aabb::new(Vec3(-1.0, -1.0, -1.0), Vec3(1.0, 1.0, 1.0)),
+                resolution: (1, 1, 1),
+                values: vec![1.0],
+            }),
+            sigma_a: Vec3(0.0, 1.0, 1.0),
+            sigma_s: Vec3(3.0, 0.5, 0.0),
+            phase: Box::new(Isotropic {}),
+        };
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..100 {
+            let s = medium.sample(&through_center(), 4.0, 6.0, &mut rng);
+            if s.scatter.is_some() {
+                assert_relative_eq!(s.weight.x(), 1.0);
+                assert_relative_eq!(s.weight.y(), 1.0 / 3.0);
+                assert_relative_eq!(s.weight.z(), 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn density_grid_interpolates() {
+        let grid = DensityGrid {
+            bounds: Aabb::new(Vec3(0.0, 0.0, 0.0), Vec3(2.0, 1.0, 1.0)),
+            resolution: (2, 1, 1),
+            values: vec![0.0, 1.0],
+        };
+        // cell centers are at x = 0.5 and x = 1.5
+        assert_relative_eq!(grid.density(Vec3(0.5, 0.5, 0.5)), 0.0);
+        assert_relative_eq!(grid.density(Vec3(1.0, 0.5, 0.5)), 0.5);
+        assert_relative_eq!(grid.density(Vec3(1.9, 0.5, 0.5)), 1.0);
+        assert_relative_eq!(grid.density(Vec3(3.0, 0.5, 0.5)), 0.0);
+        assert_relative_eq!(grid.max_density(), 1.0);
+    }
+}
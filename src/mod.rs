@@ -1,5 +0,0 @@
-pub mod ray;
-pub mod vec3;
-pub mod hittable;
-pub mod camera;
-pub mod material;
\ No newline at end of file
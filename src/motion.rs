@@ -0,0 +1,554 @@
+use crate::aabb::Aabb;
+use crate::float::Float;
+use crate::hittable::*;
+use crate::mat4::*;
+use crate::materials::Material;
+use crate::quat::Quat;
+use crate::ray::*;
+use crate::vec3::*;
+use std::sync::Arc;
+
+pub trait Lerp {
+    // Whether `lerp` moves in a straight line, so anything that depends
+    // linearly on the value (like a transformed point) stays inside the
+    // hull of the values it blends.
+    const LINEAR: bool = true;
+
+    fn lerp(&self, other: &Self, t: Float) -> Self;
+}
+
+impl Lerp for Float {
+    fn lerp(&self, other: &Float, t: Float) -> Float {
+        self + (other - self) * t
+    }
+}
+
+impl Lerp for Vec3 {
+    fn lerp(&self, other: &Vec3, t: Float) -> Vec3 {
+        *self + (*other - *self) * t
+    }
+}
+
+/// Matrices are blended entry by entry. That is exact for translation and
+/// scale; rotations of more than a few degrees between two keys should be
+/// split up into more keys.
+impl Lerp for Mat4 {
+    fn lerp(&self, other: &Mat4, t: Float) -> Mat4 {
+        let mut result = self.0;
+        for (row, other_row) in result.iter_mut().zip(other.0.iter()) {
+            for (value, other_value) in row.iter_mut().zip(other_row.iter()) {
+                *value = value.lerp(other_value, t);
+            }
+        }
+        Mat4(result)
+    }
+}
+
+/// How keyframes are blended between keys.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    Linear,
+    /// A smooth curve through the keys, with the tangent at each key set by
+    /// its neighbours (Catmull-Rom, parameterized by the key times).
+    CatmullRom,
+    /// Cubic Bezier segments shaped by an in and an out handle at each key.
+    Bezier,
+}
+
+/// Values at a set of times, interpolated in between and held constant
+/// before the first and after the last key.
+pub struct Keyframes<T> {
+    keys: Vec<(Float, T)>,
+    interpolation: Interpolation,
+    // the (in, out) Bezier handles of each key
+    handles: Vec<(T, T)>,
+}
+
+// Curves are bounded by sampling them this finely between keys; they may
+// stray slightly outside between samples.
+const BOUND_SAMPLES: usize = 16;
+
+// Sorts keys by time, keeping only the last one given for each time; keys
+// that share a time would leave nothing to interpolate between them.
+fn sort_keys<K>(keys: &mut Vec<K>, time: impl Fn(&K) -> Float) {
+    keys.reverse();
+    // stable, so each time's last key comes first
+    keys.sort_by(|a, b| time(a).total_cmp(&time(b)));
+    keys.dedup_by(|a, b| time(a) == time(b));
+}
+
+impl<T: Lerp + Copy> Keyframes<T> {
+    /// None without keys, or with a time that isn't finite. Of keys given
+    /// for the same time, the last one wins.
+    pub fn new(mut keys: Vec<(Float, T)>) -> Option<Self> {
+        if keys.is_empty() || keys.iter().any(|k| !k.0.is_finite()) {
+            return None;
+        }
+        sort_keys(&mut keys, |k| k.0);
+        Some(Keyframes {
+            keys,
+            interpolation: Interpolation::Linear,
+            handles: vec![],
+        })
+    }
+
+    pub fn constant(value: T) -> Self {
+        Keyframes {
+            keys: vec![(0.0, value)],
+            interpolation: Interpolation::Linear,
+            handles: vec![],
+        }
+    }
+
+    pub fn linear(time0: Float, value0: T, time1: Float, value1: T) -> Option<Self> {
+        Keyframes::new(vec![(time0, value0), (time1, value1)])
+    }
+
+    /// keys as (time, value, in handle, out handle), checked like `new`'s
+    pub fn bezier(mut keys: Vec<(Float, T, T, T)>) -> Option<Self> {
+        if keys.is_empty() || keys.iter().any(|k| !k.0.is_finite()) {
+            return None;
+        }
+        sort_keys(&mut keys, |k| k.0);
+        Some(Keyframes {
+            keys: keys.iter().map(|k| (k.0, k.1)).collect(),
+            interpolation: Interpolation::Bezier,
+            handles: keys.iter().map(|k| (k.2, k.3)).collect(),
+        })
+    }
+
+    /// Switches to another interpolation. Keyframes switched to Bezier get
+    /// their handles on the keys themselves, which eases in and out of
+    /// each; `bezier` takes handles of their own.
+    pub fn with_interpolation(mut self, interpolation: Interpolation) -> Self {
+        self.handles = match interpolation {
+            Interpolation::Bezier => self.keys.iter().map(|k| (k.1, k.1)).collect(),
+            _ => vec![],
+        };
+        self.interpolation = interpolation;
+        self
+    }
+
+    /// The key whose value holds at `time`: the first or last key outside
+    /// the keys' range, or a key at exactly `time`. None between keys.
+    pub fn key_at(&self, time: Float) -> Option<usize> {
+        let last = self.keys.len() - 1;
+        if time <= self.keys[0].0 {
+            Some(0)
+        } else if time >= self.keys[last].0 {
+            Some(last)
+        } else {
+            self.keys.iter().position(|(t, _)| *t == time)
+        }
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.keys.iter().map(|(_, value)| value)
+    }
+
+    pub fn at(&self, time: Float) -> T {
+        let next = self.keys.iter().position(|(t, _)| *t > time);
+        match next {
+            Some(0) => self.keys[0].1,
+            Some(i) => self.segment(i - 1, time),
+            None => self.keys[self.keys.len() - 1].1,
+        }
+    }
+
+    // the value at `time` between key `i` and the next one
+    fn segment(&self, i: usize, time: Float) -> T {
+        let (t1, p1) = self.keys[i];
+        let (t2, p2) = self.keys[i + 1];
+        let u = (time - t1) / (t2 - t1);
+        match self.interpolation {
+            Interpolation::Linear => p1.lerp(&p2, u),
+            Interpolation::Bezier => {
+                // de Casteljau's construction
+                let (c1, c2) = (self.handles[i].1, self.handles[i + 1].0);
+                let (a, b, c) = (p1.lerp(&c1, u), c1.lerp(&c2, u), c2.lerp(&p2, u));
+                let (d, e) = (a.lerp(&b, u), b.lerp(&c, u));
+                d.lerp(&e, u)
+            }
+            Interpolation::CatmullRom => {
+                // the keys beyond the ends are mirrored, which keeps the
+                // curve from overshooting there
+                let (t0, p0) = match i {
+                    0 => (2.0 * t1 - t2, p1.lerp(&p2, -1.0)),
+                    _ => self.keys[i - 1],
+                };
+                let (t3, p3) = match self.keys.get(i + 2) {
+                    Some(key) => *key,
+                    None => (2.0 * t2 - t1, p1.lerp(&p2, 2.0)),
+                };
+                // Barry and Goldman's pyramid of linear blends
+                let blend = |a: &T, b: &T, ta: Float, tb: Float| a.lerp(b, (time - ta) / (tb - ta));
+                let a1 = blend(&p0, &p1, t0, t1);
+                let a2 = blend(&p1, &p2, t1, t2);
+                let a3 = blend(&p2, &p3, t2, t3);
+                let b1 = blend(&a1, &a2, t0, t2);
+                let b2 = blend(&a2, &a3, t1, t3);
+                blend(&b1, &b2, t1, t2)
+            }
+        }
+    }
+
+    /// Values whose hull (for a `LINEAR` type) covers everything the
+    /// keyframes pass through: the keys of straight segments, the control
+    /// points of Bezier ones, and samples along anything else.
+    pub fn bounding_values(&self) -> Vec<T> {
+        match self.interpolation {
+            Interpolation::Linear if T::LINEAR => self.keys.iter().map(|k| k.1).collect(),
+            Interpolation::Bezier if T::LINEAR => {
+                let handles = self.handles.iter().flat_map(|h| vec![h.0, h.1]);
+                self.keys.iter().map(|k| k.1).chain(handles).collect()
+            }
+            _ => {
+                let mut values = vec![self.keys[0].1];
+                for (i, pair) in self.keys.windows(2).enumerate() {
+                    for j in 1..=BOUND_SAMPLES {
+                        let u = j as Float / BOUND_SAMPLES as Float;
+                        values.push(self.segment(i, pair[0].0 + u * (pair[1].0 - pair[0].0)));
+                    }
+                }
+                values
+            }
+        }
+    }
+}
+
+/// A transform split into parts that interpolate well: scale, then rotate,
+/// then translate.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Transform {
+            translation: Vec3(0.0, 0.0, 0.0),
+            rotation: Quat::identity(),
+            scale: Vec3(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Lerp for Transform {
+    const LINEAR: bool = false;
+
+    fn lerp(&self, other: &Transform, t: Float) -> Transform {
+        Transform {
+            translation: self.translation.lerp(&other.translation, t),
+            rotation: self.rotation.slerp(&other.rotation, t),
+            scale: self.scale.lerp(&other.scale, t),
+        }
+    }
+}
+
+impl From<Transform> for Mat4 {
+    fn from(t: Transform) -> Mat4 {
+        Mat4::translation(t.translation) * t.rotation.to_mat4() * Mat4::scaling(t.scale)
+    }
+}
+
+/// A keyframed value that stands for a transform. Moving instances need the
+/// inverse for every ray, so it has to be cheap to find.
+pub trait KeyTransform: Lerp + Copy {
+    fn matrix(&self) -> Mat4;
+    /// None if the transform is singular
+    fn inverse_matrix(&self) -> Option<Mat4>;
+}
+
+impl KeyTransform for Mat4 {
+    fn matrix(&self) -> Mat4 {
+        *self
+    }
+    fn inverse_matrix(&self) -> Option<Mat4> {
+        self.affine_inverse()
+    }
+}
+
+impl KeyTransform for Transform {
+    fn matrix(&self) -> Mat4 {
+        (*self).into()
+    }
+    // each part undone on its own, in reverse order
+    fn inverse_matrix(&self) -> Option<Mat4> {
+        let s = self.scale;
+        if s.x() == 0.0 || s.y() == 0.0 || s.z() == 0.0 {
+            return None;
+        }
+        Some(
+            Mat4::scaling(Vec3(1.0 / s.x(), 1.0 / s.y(), 1.0 / s.z()))
+                * self.rotation.conjugate().to_mat4()
+                * Mat4::translation(-self.translation),
+        )
+    }
+}
+
+/// A sphere whose center follows a keyframed path.
+pub struct MovingSphere {
+    pub path: Keyframes<Vec3>,
+    pub radius: Float,
+    pub material: Box<dyn Material + Send + Sync>,
+}
+
+impl Hittable for MovingSphere {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        let center = self.path.at(ray.time);
+        hit_sphere(
+            center,
+            self.radius,
+            HitMaterial::Dyn(&*self.material),
+            ray,
+            t_min,
+            t_max,
+        )
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = Vec3(self.radius, self.radius, self.radius);
+        let values = self.path.bounding_values();
+        let boxes = values.iter().map(|c| Aabb::new(*c - r, *c + r));
+        boxes.reduce(|a, b| a.union(&b))
+    }
+}
+
+/// An instance whose transform is keyframed over time, either as matrices
+/// or as `Transform`s for rotations that slerp. The matrices of the keys
+/// are kept, so only rays between keys invert a transform.
+pub struct MovingInstance<T = Mat4> {
+    pub object: Arc<dyn Hittable + Send + Sync>,
+    transforms: Keyframes<T>,
+    // each key's transform and its inverse
+    key_matrices: Vec<(Mat4, Mat4)>,
+}
+
+impl<T: KeyTransform> MovingInstance<T> {
+    /// None if the transform of any key is singular
+    pub fn new(object: Arc<dyn Hittable + Send + Sync>, transforms: Keyframes<T>) -> Option<Self> {
+        let key_matrices = transforms
+            .values()
+            .map(|t| Some((t.matrix(), t.inverse_matrix()?)))
+            .collect::<Option<_>>()?;
+        Some(MovingInstance {
+            object,
+            transforms,
+            key_matrices,
+        })
+    }
+
+    pub fn transforms(&self) -> &Keyframes<T> {
+        &self.transforms
+    }
+}
+
+impl<T: KeyTransform + 'static> Hittable for MovingInstance<T> {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        let (transform, inverse) = match self.transforms.key_at(ray.time) {
+            Some(key) => self.key_matrices[key],
+            None => {
+                let t = self.transforms.at(ray.time);
+                (t.matrix(), t.inverse_matrix()?)
+            }
+        };
+        let local_ray = Ray {
+            pos: inverse.transform_point(ray.pos),
+            dir: inverse.transform_vector(ray.dir),
+            time: ray.time,
+            wavelength: ray.wavelength,
+        };
+        let hit_record = self.object.hit(&local_ray, t_min, t_max)?;
+        Some(hit_record.transformed(&transform, &inverse))
+    }
+    // The corners of the object's box move linearly between two keys when
+    // the matrices are blended linearly, so the union over the keys covers
+    // the whole motion; other motion is sampled.
+    fn bounding_box(&self) -> Option<Aabb> {
+        let local = self.object.bounding_box()?;
+        let corners = local.corners();
+        let world: Vec<_> = self
+            .transforms
+            .bounding_values()
+            .into_iter()
+            .flat_map(|t| {
+                let m = t.matrix();
+                corners.iter().map(move |c| m.transform_point(*c))
+            })
+            .collect();
+        Some(Aabb::from_points(&world))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::float::tolerance;
+    use approx::assert_relative_eq;
+
+    struct TestMaterial {}
+    impl Material for TestMaterial {}
+
+    fn ray_at(time: Float) -> Ray {
+        Ray {
+            pos: Vec3(0.0, 10.0, 0.0),
+            dir: Vec3(0.0, -1.0, 0.0),
+            time,
+            wavelength: None,
+        }
+    }
+
+    #[test]
+    fn keyframes_interpolate_and_clamp() {
+        let k = Keyframes::new(vec![(1.0, 10.0), (0.0, 0.0), (2.0, 0.0)]).unwrap();
+        assert_relative_eq!(k.at(-1.0), 0.0);
+        assert_relative_eq!(k.at(0.5), 5.0);
+        assert_relative_eq!(k.at(1.0), 10.0);
+        assert_relative_eq!(k.at(1.25), 7.5);
+        assert_relative_eq!(k.at(3.0), 0.0);
+        assert_relative_eq!(Keyframes::constant(4.0).at(100.0), 4.0);
+        assert!(Keyframes::<Float>::new(vec![]).is_none());
+        assert!(Keyframes::linear(0.0, 1.0, Float::NAN, 2.0).is_none());
+    }
+
+    #[test]
+    #[cfg_attr(feature = "f32", ignore = "finite differences need f64")]
+    fn catmull_rom_is_smooth_through_keys() {
+        let k = Keyframes::new(vec![(0.0, 0.0), (1.0, 1.0), (2.0, 0.0), (4.0, 2.0)])
+            .unwrap()
+            .with_interpolation(Interpolation::CatmullRom);
+        assert_relative_eq!(k.at(1.0), 1.0);
+        assert_relative_eq!(k.at(2.0), 0.0, epsilon = tolerance(1e-12));
+        // it rounds off the peak that linear interpolation would have
+        assert!(k.at(1.1) > 0.9);
+        let slope = |t: Float| (k.at(t + 1e-6) - k.at(t - 1e-6)) / 2e-6;
+        assert_relative_eq!(slope(1.0), 0.0, epsilon = 1e-6);
+        // straight keys stay on a straight line
+        let line = Keyframes::new(vec![(0.0, 0.0), (1.0, 2.0), (3.0, 6.0)])
+            .unwrap()
+            .with_interpolation(Interpolation::CatmullRom);
+        assert_relative_eq!(line.at(0.5), 1.0, epsilon = tolerance(1e-12));
+        assert_relative_eq!(line.at(2.5), 5.0, epsilon = tolerance(1e-12));
+        // a key given twice keeps its last value, rather than dividing by
+        // the zero time between the two
+        let twice = Keyframes::new(vec![(0.0, 0.0), (1.0, 5.0), (2.0, 0.0), (1.0, 1.0)])
+            .unwrap()
+            .with_interpolation(Interpolation::CatmullRom);
+        assert_eq!(twice.values().count(), 3);
+        assert_relative_eq!(twice.at(1.0), 1.0);
+        assert!(twice.at(0.5).is_finite());
+    }
+
+    #[test]
+    fn bezier_follows_handles() {
+        let k = Keyframes::bezier(vec![(0.0, 0.0, 0.0, 1.0), (1.0, 0.0, 1.0, 0.0)]).unwrap();
+        assert_relative_eq!(k.at(0.0), 0.0);
+        assert_relative_eq!(k.at(0.5), 0.75);
+        assert_relative_eq!(k.at(1.0), 0.0);
+        assert_eq!(k.bounding_values().len(), 6);
+        // without handles of their own, keys ease in and out
+        let eased = Keyframes::linear(0.0, 0.0, 1.0, 1.0)
+            .unwrap()
+            .with_interpolation(Interpolation::Bezier);
+        assert_relative_eq!(eased.at(0.5), 0.5);
+        assert!(eased.at(0.1) < 0.1);
+    }
+
+    #[test]
+    fn moving_sphere_follows_path() {
+        let s = MovingSphere {
+            path: Keyframes::linear(0.0, Vec3(0.0, 0.0, 0.0), 1.0, Vec3(4.0, 0.0, 0.0)).unwrap(),
+            radius: 1.0,
+            material: Box::new(TestMaterial {}),
+        };
+        assert_relative_eq!(s.hit(&ray_at(0.0), 0.0, Float::MAX).unwrap().t, 9.0);
+        // at t = 0.5 the sphere has moved away from x = 0
+        assert!(s.hit(&ray_at(0.5), 0.0, Float::MAX).is_none());
+        assert_relative_eq!(s.hit(&ray_at(0.25), 0.0, Float::MAX).unwrap().t, 10.0);
+        let b = s.bounding_box().unwrap();
+        assert_eq!(b.min, Vec3(-1.0, -1.0, -1.0));
+        assert_eq!(b.max, Vec3(5.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn moving_instance_bounds_cover_motion() {
+        let sphere: Arc<dyn Hittable + Send + Sync> = Arc::new(Sphere {
+            center: Vec3(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material: Box::new(TestMaterial {}),
+        });
+        let i = MovingInstance::new(
+            sphere,
+            Keyframes::linear(
+                0.0,
+                Mat4::identity(),
+                1.0,
+                Mat4::translation(Vec3(0.0, 0.0, 4.0)) * Mat4::scaling(Vec3(2.0, 2.0, 2.0)),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_relative_eq!(i.hit(&ray_at(0.0), 0.0, Float::MAX).unwrap().t, 9.0);
+        assert!(i.hit(&ray_at(1.0), 0.0, Float::MAX).is_none());
+        // half way, between the keys, the sphere is at z = 2 with radius 1.5
+        let r = Ray {
+            pos: Vec3(0.0, 10.0, 2.0),
+            ..ray_at(0.5)
+        };
+        assert_relative_eq!(
+            i.hit(&r, 0.0, Float::MAX).unwrap().t,
+            8.5,
+            epsilon = tolerance(1e-9)
+        );
+        let b = i.bounding_box().unwrap();
+        assert_eq!(b.min, Vec3(-2.0, -2.0, -1.0));
+        assert_eq!(b.max, Vec3(2.0, 2.0, 6.0));
+    }
+
+    #[test]
+    fn moving_instance_rejects_singular_keys() {
+        let sphere: Arc<dyn Hittable + Send + Sync> = Arc::new(Sphere {
+            center: Vec3(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material: Box::new(TestMaterial {}),
+        });
+        let flat = Mat4::scaling(Vec3(1.0, 0.0, 1.0));
+        let keys = Keyframes::linear(0.0, Mat4::identity(), 1.0, flat).unwrap();
+        assert!(MovingInstance::new(sphere, keys).is_none());
+    }
+
+    #[test]
+    fn moving_instance_slerps_transforms() {
+        let bar: Arc<dyn Hittable + Send + Sync> = Arc::new(crate::shapes::Cuboid {
+            min: Vec3(-1.0, -0.1, -0.1),
+            max: Vec3(1.0, 0.1, 0.1),
+            material: Box::new(TestMaterial {}),
+        });
+        let start = Transform {
+            translation: Vec3(0.0, 5.0, 0.0),
+            ..Transform::identity()
+        };
+        let end = Transform {
+            rotation: Quat::from_axis_angle(Vec3(0.0, 1.0, 0.0), 180.0),
+            ..start
+        };
+        let i = MovingInstance::new(bar, Keyframes::linear(0.0, start, 1.0, end).unwrap()).unwrap();
+        // a quarter turn in, the bar lies along z
+        let r = Ray {
+            pos: Vec3(0.0, 10.0, 0.9),
+            ..ray_at(0.5)
+        };
+        assert_relative_eq!(i.hit(&r, 0.0, Float::MAX).unwrap().t, 4.9, epsilon = 1e-9);
+        assert_relative_eq!(
+            i.hit(&ray_at(0.0), 0.0, Float::MAX).unwrap().t,
+            4.9,
+            epsilon = 1e-9
+        );
+        let before = Ray { time: 0.0, ..r };
+        assert!(i.hit(&before, 0.0, Float::MAX).is_none());
+        // blending the matrices entry by entry would have shrunk it there
+        let b = i.bounding_box().unwrap();
+        assert!(b.max.z() > 0.99 && b.min.z() < -0.99);
+    }
+}
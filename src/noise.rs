@@ -0,0 +1,120 @@
+use crate::float::Float;
+use crate::vec3::*;
+use rand::rngs::StdRng;
+use rand::Rng;
+
+const POINT_COUNT: usize = 256;
+
+/// Gradient (Perlin) noise with random unit gradient vectors.
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new(rng: &mut StdRng) -> Self {
+        let gradients = (0..POINT_COUNT)
+            .map(|_| {
+                Vec3(
+                    rng.gen::<Float>() * 2.0 - 1.0,
+                    rng.gen::<Float>() * 2.0 - 1.0,
+                    rng.gen::<Float>() * 2.0 - 1.0,
+                )
+                .normalized()
+            })
+            .collect();
+        Perlin {
+            gradients,
+            perm_x: Self::permutation(rng),
+            perm_y: Self::permutation(rng),
+            perm_z: Self::permutation(rng),
+        }
+    }
+
+    fn permutation(rng: &mut StdRng) -> Vec<usize> {
+        let mut p: Vec<usize> = (0..POINT_COUNT).collect();
+        for i in (1..POINT_COUNT).rev() {
+            let target = rng.gen_range(0, i + 1);
+            p.swap(i, target);
+        }
+        p
+    }
+
+    /// smooth noise in [-1, 1]; the unit gradients keep it within sqrt(3) / 2
+    pub fn noise(&self, p: Vec3) -> Float {
+        let (fx, fy, fz) = (p.x().floor(), p.y().floor(), p.z().floor());
+        let (u, v, w) = (p.x() - fx, p.y() - fy, p.z() - fz);
+        let (i, j, k) = (fx as i64, fy as i64, fz as i64);
+        // hermite smoothing of the interpolation weights
+        let (uu, vv, ww) = (
+            u * u * (3.0 - 2.0 * u),
+            v * v * (3.0 - 2.0 * v),
+            w * w * (3.0 - 2.0 * w),
+        );
+        let mut sum = 0.0;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let index = self.perm_x[((i + di) & 255) as usize]
+                        ^ self.perm_y[((j + dj) & 255) as usize]
+                        ^ self.perm_z[((k + dk) & 255) as usize];
+                    let weight = Vec3(u - di as Float, v - dj as Float, w - dk as Float);
+                    let (fi, fj, fk) = (di as Float, dj as Float, dk as Float);
+                    sum += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                        * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                        * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                        * self.gradients[index].dot(weight);
+                }
+            }
+        }
+        sum
+    }
+
+    /// sum of `octaves` layers of |noise|, each at twice the frequency and
+    /// half the weight of the previous one; in [0, 2)
+    pub fn turbulence(&self, p: Vec3, octaves: usize) -> Float {
+        let mut sum = 0.0;
+        let mut p = p;
+        let mut weight = 1.0;
+        for _ in 0..octaves {
+            sum += weight * self.noise(p).abs();
+            weight *= 0.5;
+            p *= 2.0;
+        }
+        sum
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn noise_is_bounded_and_continuous() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let perlin = Perlin::new(&mut rng);
+        for _ in 0..1000 {
+            let p = Vec3(
+                rng.gen::<Float>() * 100.0,
+                rng.gen::<Float>() * 100.0,
+                rng.gen::<Float>() * 100.0,
+            );
+            let n = perlin.noise(p);
+            assert!(n.abs() <= 1.0);
+            let nearby = perlin.noise(p + Vec3(1e-6, 0.0, 0.0));
+            assert!((n - nearby).abs() < 1e-4);
+            let t = perlin.turbulence(p, 5);
+            assert!((0.0..2.0).contains(&t));
+        }
+    }
+
+    #[test]
+    fn noise_vanishes_on_lattice_points() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let perlin = Perlin::new(&mut rng);
+        assert!(perlin.noise(Vec3(3.0, -7.0, 12.0)).abs() < 1e-12);
+    }
+}
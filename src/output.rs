@@ -0,0 +1,241 @@
+use crate::error::*;
+use crate::film::Film;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// How a PNG gets encoded. mtpng compresses `chunk_size` byte pieces of the
+/// image in parallel on its thread pool; streaming writes each piece out as
+/// its own IDAT chunk as soon as it is done instead of holding on to it.
+#[derive(Clone, Copy)]
+pub struct PngOptions {
+    pub chunk_size: usize,
+    pub streaming: bool,
+    pub compression: mtpng::CompressionLevel,
+}
+
+impl PngOptions {
+    /// for the finished image
+    pub fn best() -> Self {
+        PngOptions {
+            chunk_size: 256 * 1024,
+            streaming: false,
+            compression: mtpng::CompressionLevel::High,
+        }
+    }
+
+    /// for progress snapshots, which get replaced soon anyway
+    pub fn snapshot() -> Self {
+        PngOptions {
+            chunk_size: 128 * 1024,
+            streaming: true,
+            compression: mtpng::CompressionLevel::Fast,
+        }
+    }
+}
+
+// mtpng reports bad input and failed writes alike as IO errors
+fn encoding_error(e: io::Error) -> Error {
+    match e.kind() {
+        io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => Error::Encoding(e.to_string()),
+        _ => Error::Io(e),
+    }
+}
+
+/// Encodes RGBA8 `data` as a PNG into `writer`.
+pub fn encode_png<W: Write>(
+    writer: W,
+    data: &[u8],
+    width: usize,
+    height: usize,
+    png: &PngOptions,
+) -> Result<W> {
+    let encode = || {
+        let mut header = mtpng::Header::new();
+        header.set_size(width as u32, height as u32)?;
+        header.set_color(mtpng::ColorType::TruecolorAlpha, 8)?;
+        let mut options = mtpng::encoder::Options::new();
+        options.set_chunk_size(png.chunk_size)?;
+        options.set_streaming(png.streaming)?;
+        options.set_compression_level(png.compression)?;
+        let mut encoder = mtpng::encoder::Encoder::new(writer, &options);
+        encoder.write_header(&header)?;
+        encoder.write_image_rows(data)?;
+        encoder.finish()
+    };
+    encode().map_err(encoding_error)
+}
+
+/// Writes `path` through a temporary file next to it that is renamed into
+/// place once complete, so that readers see the old file or the new one but
+/// never half of one.
+pub fn write_atomically<F>(path: &Path, write: F) -> Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<()>,
+{
+    let temp = temp_path(path);
+    let result = File::create(&temp).map_err(Error::from).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        write(&mut writer)?;
+        Ok(writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?)
+    });
+    match result {
+        Ok(()) => Ok(fs::rename(&temp, path)?),
+        Err(e) => {
+            let _ = fs::remove_file(&temp);
+            Err(e)
+        }
+    }
+}
+
+// `dir/.name.<pid>.tmp` for `dir/name`, so that processes writing the
+// same file don't write into each other's temporary file
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".{}.tmp", std::process::id()));
+    path.with_file_name(name)
+}
+
+pub fn save_png(
+    path: &Path,
+    data: &[u8],
+    width: usize,
+    height: usize,
+    png: &PngOptions,
+) -> Result<()> {
+    write_atomically(path, |writer| {
+        encode_png(writer, data, width, height, png).map(|_| ())
+    })
+}
+
+/// Saves `film` as OpenEXR if `path` ends in `.exr`, as PNG otherwise.
+pub fn save_film(path: &Path, film: &Film, png: &PngOptions) -> Result<()> {
+    if path.extension().is_some_and(|e| e == "exr") {
+        write_atomically(path, |w| Ok(w.write_all(&film.to_exr())?))
+    } else {
+        save_png(path, &film.to_rgba8(), film.width, film.height, png)
+    }
+}
+
+static SNAPSHOT_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Asks for a snapshot of the render in progress, as SIGUSR1 does once
+/// `listen_for_snapshot_signal` has been called.
+pub fn request_snapshot() {
+    SNAPSHOT_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+pub fn listen_for_snapshot_signal() {
+    // only touches an atomic, which is safe to do from a signal handler
+    extern "C" fn on_signal(_: libc::c_int) {
+        request_snapshot();
+    }
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        // don't fail reads and writes that the signal interrupts
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGUSR1, &action, std::ptr::null_mut());
+    }
+}
+
+#[cfg(not(unix))]
+pub fn listen_for_snapshot_signal() {}
+
+/// Decides when to write a snapshot of a render in progress: every
+/// `interval`, if there is one, and whenever one has been requested.
+pub struct Snapshots {
+    pub interval: Option<Duration>,
+    last: Instant,
+}
+
+impl Snapshots {
+    pub fn new(interval: Option<Duration>) -> Self {
+        Snapshots {
+            interval,
+            last: Instant::now(),
+        }
+    }
+
+    pub fn due(&mut self) -> bool {
+        let requested = SNAPSHOT_REQUESTED.swap(false, Ordering::SeqCst);
+        let elapsed = self.interval.is_some_and(|i| self.last.elapsed() >= i);
+        if requested || elapsed {
+            self.last = Instant::now();
+        }
+        requested || elapsed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("raytracer-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn encodes_png() {
+        let (width, height) = (300, 200);
+        let data: Vec<u8> = (0..width * height * 4).map(|i| (i % 251) as u8).collect();
+        for png in &[PngOptions::best(), PngOptions::snapshot()] {
+            let bytes = encode_png(vec![], &data, width, height, png).unwrap();
+            assert_eq!(&bytes[..8], b"\x89PNG\r\n\x1a\n");
+            assert_eq!(&bytes[12..16], b"IHDR");
+            assert_eq!(&bytes[bytes.len() - 8..bytes.len() - 4], b"IEND");
+        }
+        let empty = encode_png(vec![], &[], 0, 0, &PngOptions::best());
+        assert!(matches!(empty, Err(Error::Encoding(_))));
+    }
+
+    #[test]
+    fn writes_atomically() {
+        let dir = scratch_dir("atomic");
+        let path = dir.join("image.png");
+        fs::write(&path, b"old").unwrap();
+        // a failed write leaves the old file alone
+        let failed = write_atomically(&path, |w| {
+            w.write_all(b"partial")?;
+            Err(io::Error::other("interrupted").into())
+        });
+        assert!(failed.is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"old");
+        write_atomically(&path, |w| Ok(w.write_all(b"new")?)).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        // and no temporary files are left behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        // another process saving the same file uses another one
+        let temp = temp_path(&path).to_string_lossy().into_owned();
+        assert!(temp.ends_with(&format!(".image.png.{}.tmp", std::process::id())));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn snapshots_when_due() {
+        let mut never = Snapshots::new(None);
+        assert!(!never.due());
+        request_snapshot();
+        assert!(never.due());
+        assert!(!never.due());
+        // as does SIGUSR1
+        #[cfg(unix)]
+        {
+            listen_for_snapshot_signal();
+            unsafe { libc::raise(libc::SIGUSR1) };
+            assert!(never.due());
+        }
+        let mut always = Snapshots::new(Some(Duration::from_secs(0)));
+        assert!(always.due());
+    }
+}
@@ -0,0 +1,462 @@
+use crate::aabb::Aabb;
+use crate::compact::{MaterialId, MaterialKind};
+use crate::float::Float;
+use crate::hittable::*;
+use crate::lanes::{simd_available, LANES};
+use crate::ray::*;
+use crate::shapes::{hit_triangle, triangle_bounds};
+use crate::vec3::*;
+
+// Primitives are intersected LANES at a time: four in double precision and
+// eight in single.
+
+// Sets lane `i` of a structure of arrays column, growing it by a whole
+// group of lanes when `i` starts a new one, so that every group can be
+// loaded at once. The padding lanes are never reported as hits.
+fn set_lane(column: &mut Vec<Float>, i: usize, value: Float) {
+    if i.is_multiple_of(LANES) {
+        column.resize(i + LANES, 0.0);
+    }
+    column[i] = value;
+}
+
+#[derive(Default)]
+struct Spheres {
+    x: Vec<Float>,
+    y: Vec<Float>,
+    z: Vec<Float>,
+    radius: Vec<Float>,
+    material: Vec<MaterialId>,
+}
+
+impl Spheres {
+    fn len(&self) -> usize {
+        self.material.len()
+    }
+
+    fn center(&self, i: usize) -> Vec3 {
+        Vec3(self.x[i], self.y[i], self.z[i])
+    }
+}
+
+// Triangles by a corner and the two edges from it, as the intersection
+// test uses them.
+#[derive(Default)]
+struct Triangles {
+    a: [Vec<Float>; 3],
+    e1: [Vec<Float>; 3],
+    e2: [Vec<Float>; 3],
+    material: Vec<MaterialId>,
+}
+
+impl Triangles {
+    fn len(&self) -> usize {
+        self.material.len()
+    }
+
+    fn get(&self, i: usize) -> (Vec3, Vec3, Vec3) {
+        let v = |c: &[Vec<Float>; 3]| Vec3(c[0][i], c[1][i], c[2][i]);
+        (v(&self.a), v(&self.e1), v(&self.e2))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Sphere,
+    Triangle,
+}
+
+/// Spheres and triangles in structure of arrays form, intersected a group
+/// of lanes at a time with AVX where the CPU has it, and one at a time
+/// otherwise. Both paths do the same floating point operations in the same
+/// order as `hit_sphere` and `Triangle`, so they find exactly the same hits.
+/// There is no hierarchy inside, so this is meant for clusters of up to a
+/// few hundred primitives, such as the triangles of a small mesh, which can
+/// go into a BVH like any other object.
+pub struct PackedPrimitives {
+    materials: Vec<MaterialKind>,
+    spheres: Spheres,
+    triangles: Triangles,
+    bbox: Option<Aabb>,
+    simd: bool,
+}
+
+impl PackedPrimitives {
+    pub fn new() -> Self {
+        PackedPrimitives {
+            materials: Vec::new(),
+            spheres: Spheres::default(),
+            triangles: Triangles::default(),
+            bbox: None,
+            simd: simd_available(),
+        }
+    }
+
+    /// intersects one primitive at a time even where SIMD is available
+    pub fn without_simd(mut self) -> Self {
+        self.simd = false;
+        self
+    }
+
+    pub fn add_material<M: Into<MaterialKind>>(&mut self, material: M) -> MaterialId {
+        self.materials.push(material.into());
+        (self.materials.len() - 1) as MaterialId
+    }
+
+    pub fn add_sphere(&mut self, center: Vec3, radius: Float, material: MaterialId) {
+        let i = self.spheres.len();
+        set_lane(&mut self.spheres.x, i, center.x());
+        set_lane(&mut self.spheres.y, i, center.y());
+        set_lane(&mut self.spheres.z, i, center.z());
+        set_lane(&mut self.spheres.radius, i, radius);
+        self.spheres.material.push(material);
+        let r = Vec3(radius, radius, radius);
+        self.grow(Aabb::new(center - r, center + r));
+    }
+
+    pub fn add_triangle(&mut self, a: Vec3, b: Vec3, c: Vec3, material: MaterialId) {
+        let i = self.triangles.len();
+        let (e1, e2) = (b - a, c - a);
+        for axis in 0..3 {
+            set_lane(&mut self.triangles.a[axis], i, a[axis]);
+            set_lane(&mut self.triangles.e1[axis], i, e1[axis]);
+            set_lane(&mut self.triangles.e2[axis], i, e2[axis]);
+        }
+        self.triangles.material.push(material);
+        self.grow(triangle_bounds(a, e1, e2));
+    }
+
+    fn grow(&mut self, bbox: Aabb) {
+        self.bbox = Some(self.bbox.map_or(bbox, |b| b.union(&bbox)));
+    }
+
+    pub fn len(&self) -> usize {
+        self.spheres.len() + self.triangles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn hit_one(
+        &self,
+        kind: Kind,
+        i: usize,
+        ray: &Ray,
+        t_min: Float,
+        t_max: Float,
+    ) -> Option<HitRecord<'_>> {
+        let hit = match kind {
+            Kind::Sphere => {
+                let material =
+                    HitMaterial::Kind(&self.materials[self.spheres.material[i] as usize]);
+                hit_sphere(
+                    self.spheres.center(i),
+                    self.spheres.radius[i],
+                    material,
+                    ray,
+                    t_min,
+                    t_max,
+                )
+            }
+            Kind::Triangle => {
+                let material =
+                    HitMaterial::Kind(&self.materials[self.triangles.material[i] as usize]);
+                let (a, e1, e2) = self.triangles.get(i);
+                hit_triangle(a, e1, e2, material, ray, t_min, t_max)
+            }
+        };
+        hit.map(|hit_record| hit_record.primitive_id(i))
+    }
+
+    // The nearest primitive and its t, testing one primitive at a time. A
+    // later primitive at the same t wins, as in a `HittableList`.
+    fn nearest_scalar(
+        &self,
+        ray: &Ray,
+        t_min: Float,
+        t_max: Float,
+    ) -> Option<(Kind, usize, Float)> {
+        let mut nearest = None;
+        let mut t_closest = t_max;
+        let all = (0..self.spheres.len())
+            .map(|i| (Kind::Sphere, i))
+            .chain((0..self.triangles.len()).map(|i| (Kind::Triangle, i)));
+        for (kind, i) in all {
+            if let Some(hit_record) = self.hit_one(kind, i, ray, t_min, t_closest) {
+                t_closest = hit_record.t;
+                nearest = Some((kind, i, t_closest));
+            }
+        }
+        nearest
+    }
+
+    // The same as `nearest_scalar`, a group of lanes at a time. Each group
+    // is tested against the nearest t so far and its hits are then taken in
+    // lane order, so ties go the same way.
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "avx")]
+    unsafe fn nearest_avx(
+        &self,
+        ray: &Ray,
+        t_min: Float,
+        t_max: Float,
+    ) -> Option<(Kind, usize, Float)> {
+        let mut nearest = None;
+        let mut t_closest = t_max;
+        let mut ts = [0.0; LANES];
+        let groups = [
+            (Kind::Sphere, self.spheres.len()),
+            (Kind::Triangle, self.triangles.len()),
+        ];
+        for &(kind, len) in &groups {
+            for start in (0..len).step_by(LANES) {
+                let mask = match kind {
+                    Kind::Sphere => {
+                        avx::spheres(&self.spheres, start, ray, t_min, t_closest, &mut ts)
+                    }
+                    Kind::Triangle => {
+                        avx::triangles(&self.triangles, start, ray, t_min, t_closest, &mut ts)
+                    }
+                };
+                for (lane, &t) in ts.iter().enumerate().take(len - start) {
+                    if mask & (1 << lane) != 0 && t <= t_closest {
+                        t_closest = t;
+                        nearest = Some((kind, start + lane, t));
+                    }
+                }
+            }
+        }
+        nearest
+    }
+}
+
+impl Default for PackedPrimitives {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hittable for PackedPrimitives {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        #[cfg(target_arch = "x86_64")]
+        let nearest = if self.simd {
+            // only set when the CPU has AVX
+            unsafe { self.nearest_avx(ray, t_min, t_max) }
+        } else {
+            self.nearest_scalar(ray, t_min, t_max)
+        };
+        #[cfg(not(target_arch = "x86_64"))]
+        let nearest = self.nearest_scalar(ray, t_min, t_max);
+        // the record is filled in by the scalar test, which finds the same t
+        let (kind, i, t) = nearest?;
+        self.hit_one(kind, i, ray, t_min, t)
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bbox
+    }
+}
+
+// The AVX kernels. They write each lane's t to `ts` and return a bit mask
+// of the lanes that hit within [t_min, t_max]. Comparisons are unordered
+// where the scalar tests reject with `<` or `>`, so NaNs go the same way.
+#[cfg(target_arch = "x86_64")]
+mod avx {
+    use super::{Spheres, Triangles};
+    use crate::float::Float;
+    use crate::lanes::*;
+    use crate::ray::Ray;
+
+    struct Rays {
+        pos: [V; 3],
+        dir: [V; 3],
+    }
+
+    #[target_feature(enable = "avx")]
+    unsafe fn rays(ray: &Ray) -> Rays {
+        Rays {
+            pos: [splat(ray.pos.x()), splat(ray.pos.y()), splat(ray.pos.z())],
+            dir: [splat(ray.dir.x()), splat(ray.dir.y()), splat(ray.dir.z())],
+        }
+    }
+
+    #[target_feature(enable = "avx")]
+    unsafe fn load3(columns: &[Vec<Float>; 3], start: usize) -> [V; 3] {
+        [
+            load(&columns[0], start),
+            load(&columns[1], start),
+            load(&columns[2], start),
+        ]
+    }
+
+    #[target_feature(enable = "avx")]
+    unsafe fn sub3(a: [V; 3], b: [V; 3]) -> [V; 3] {
+        [sub(a[0], b[0]), sub(a[1], b[1]), sub(a[2], b[2])]
+    }
+
+    #[target_feature(enable = "avx")]
+    unsafe fn dot(a: [V; 3], b: [V; 3]) -> V {
+        add(add(mul(a[0], b[0]), mul(a[1], b[1])), mul(a[2], b[2]))
+    }
+
+    // as `Vec3::cross`, including the negated middle term
+    #[target_feature(enable = "avx")]
+    unsafe fn cross(a: [V; 3], b: [V; 3]) -> [V; 3] {
+        [
+            sub(mul(a[1], b[2]), mul(a[2], b[1])),
+            neg(sub(mul(a[0], b[2]), mul(a[2], b[0]))),
+            sub(mul(a[0], b[1]), mul(a[1], b[0])),
+        ]
+    }
+
+    // the lanes where neither `t < t_min` nor `t > t_max`
+    #[target_feature(enable = "avx")]
+    unsafe fn in_range(t: V, t_min: V, t_max: V) -> V {
+        and(not_lt(t, t_min), not_gt(t, t_max))
+    }
+
+    /// as `hit_sphere`
+    #[target_feature(enable = "avx")]
+    pub unsafe fn spheres(
+        spheres: &Spheres,
+        start: usize,
+        ray: &Ray,
+        t_min: Float,
+        t_max: Float,
+        ts: &mut [Float; LANES],
+    ) -> u32 {
+        let r = rays(ray);
+        let center = [
+            load(&spheres.x, start),
+            load(&spheres.y, start),
+            load(&spheres.z, start),
+        ];
+        let radius = load(&spheres.radius, start);
+        let oc = sub3(r.pos, center);
+        let a = ray.dir.dot(ray.dir);
+        let b = mul(splat(2.0), dot(oc, r.dir));
+        let c = sub(dot(oc, oc), mul(radius, radius));
+        let discriminant = sub(mul(b, b), mul(splat(4.0 * a), c));
+        let real = not_lt(discriminant, splat(0.0));
+        let root = sqrt(discriminant);
+        let two_a = splat(2.0 * a);
+        let near = div(sub(neg(b), root), two_a);
+        let far = div(add(neg(b), root), two_a);
+        let (t_min, t_max) = (splat(t_min), splat(t_max));
+        let near_in = in_range(near, t_min, t_max);
+        let far_in = in_range(far, t_min, t_max);
+        store(select(far, near, near_in), ts);
+        bits(and(real, or(near_in, far_in)))
+    }
+
+    /// as `hit_triangle`
+    #[target_feature(enable = "avx")]
+    pub unsafe fn triangles(
+        triangles: &Triangles,
+        start: usize,
+        ray: &Ray,
+        t_min: Float,
+        t_max: Float,
+        ts: &mut [Float; LANES],
+    ) -> u32 {
+        let r = rays(ray);
+        let a = load3(&triangles.a, start);
+        let e1 = load3(&triangles.e1, start);
+        let e2 = load3(&triangles.e2, start);
+        let (zero, one) = (splat(0.0), splat(1.0));
+        let pvec = cross(r.dir, e2);
+        let det = dot(e1, pvec);
+        let mut hit = not_lt(abs(det), splat(1e-12));
+        let inv_det = div(one, det);
+        let tvec = sub3(r.pos, a);
+        let u = mul(dot(tvec, pvec), inv_det);
+        // `u` is checked with `contains`, which rejects NaN
+        hit = and(hit, and(ge(u, zero), le(u, one)));
+        let qvec = cross(tvec, e1);
+        let v = mul(dot(r.dir, qvec), inv_det);
+        hit = and(hit, and(not_lt(v, zero), not_gt(add(u, v), one)));
+        let t = mul(dot(e2, qvec), inv_det);
+        hit = and(hit, in_range(t, splat(t_min), splat(t_max)));
+        store(t, ts);
+        bits(hit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::Material;
+    use crate::shapes::Triangle;
+    use rand::rngs::StdRng;
+    use rand::Rng;
+    use rand::SeedableRng;
+
+    struct TestMaterial {}
+    impl Material for TestMaterial {}
+
+    fn random_vec(rng: &mut StdRng, scale: Float) -> Vec3 {
+        Vec3(
+            (rng.gen::<Float>() - 0.5) * scale,
+            (rng.gen::<Float>() - 0.5) * scale,
+            (rng.gen::<Float>() - 0.5) * scale,
+        )
+    }
+
+    #[test]
+    fn simd_matches_scalar() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut packed = PackedPrimitives::new();
+        let mut scalar = PackedPrimitives::new().without_simd();
+        let mut list = HittableList::new();
+        for p in [&mut packed, &mut scalar].iter_mut() {
+            let material: Box<dyn Material + Send + Sync> = Box::new(TestMaterial {});
+            p.add_material(material);
+        }
+        // odd counts, so the last groups are partly padding
+        for _ in 0..37 {
+            let (center, radius) = (random_vec(&mut rng, 10.0), 0.2 + rng.gen::<Float>());
+            packed.add_sphere(center, radius, 0);
+            scalar.add_sphere(center, radius, 0);
+            list.push(Sphere {
+                center,
+                radius,
+                material: Box::new(TestMaterial {}),
+            });
+        }
+        for _ in 0..53 {
+            let a = random_vec(&mut rng, 10.0);
+            let (b, c) = (a + random_vec(&mut rng, 3.0), a + random_vec(&mut rng, 3.0));
+            packed.add_triangle(a, b, c, 0);
+            scalar.add_triangle(a, b, c, 0);
+            list.push(Triangle {
+                a,
+                b,
+                c,
+                material: Box::new(TestMaterial {}),
+            });
+        }
+        assert_eq!(packed.bounding_box(), list.bounding_box());
+        let mut hits = 0;
+        for _ in 0..2000 {
+            // from anywhere around the primitives, towards their midst
+            let pos = random_vec(&mut rng, 20.0);
+            let ray = Ray::new(pos, random_vec(&mut rng, 6.0) - pos);
+            let t_min = if rng.gen::<bool>() { 0.001 } else { 2.0 };
+            let key = |h: HitRecord| (h.t, h.point, h.normal, h.u, h.v, h.dpdu, h.dpdv);
+            let expected = list.hit(&ray, t_min, Float::MAX).map(key);
+            assert_eq!(packed.hit(&ray, t_min, Float::MAX).map(key), expected);
+            assert_eq!(scalar.hit(&ray, t_min, Float::MAX).map(key), expected);
+            hits += expected.is_some() as usize;
+        }
+        assert!(hits > 500);
+    }
+
+    #[test]
+    fn empty_store_misses() {
+        let packed = PackedPrimitives::new();
+        let ray = Ray::new(Vec3(0.0, 0.0, 0.0), Vec3(0.0, 0.0, 1.0));
+        assert!(packed.is_empty());
+        assert!(packed.hit(&ray, 0.001, Float::MAX).is_none());
+        assert!(packed.bounding_box().is_none());
+    }
+}
@@ -0,0 +1,173 @@
+// Real root finding for the low degree polynomials that come up in ray
+// intersection. Coefficients are stored lowest degree first, so
+// `[c0, c1, c2]` is the polynomial c0 + c1 x + c2 x^2.
+
+use crate::float::Float;
+
+pub fn eval(coeffs: &[Float], x: Float) -> Float {
+    coeffs.iter().rev().fold(0.0, |acc, c| acc * x + c)
+}
+
+fn derivative(coeffs: &[Float]) -> Vec<Float> {
+    coeffs
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(i, c)| i as Float * c)
+        .collect()
+}
+
+// Roots of c + b x + a x^2 in ascending order, using the numerically stable
+// form that avoids cancellation between -b and the square root.
+pub fn solve_quadratic(c: Float, b: Float, a: Float) -> Option<(Float, Float)> {
+    if a == 0.0 {
+        if b == 0.0 {
+            return None;
+        }
+        let x = -c / b;
+        return Some((x, x));
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    if q == 0.0 {
+        // b and c are both zero
+        return Some((0.0, 0.0));
+    }
+    let (x0, x1) = (q / a, c / q);
+    Some(if x0 < x1 { (x0, x1) } else { (x1, x0) })
+}
+
+fn bisect(coeffs: &[Float], mut lo: Float, mut hi: Float) -> Float {
+    let mut f_lo = eval(coeffs, lo);
+    for _ in 0..100 {
+        let mid = 0.5 * (lo + hi);
+        if mid <= lo || mid >= hi {
+            break;
+        }
+        let f_mid = eval(coeffs, mid);
+        if f_mid == 0.0 {
+            return mid;
+        }
+        if (f_mid < 0.0) == (f_lo < 0.0) {
+            lo = mid;
+            f_lo = f_mid;
+        } else {
+            hi = mid;
+        }
+    }
+    0.5 * (lo + hi)
+}
+
+fn scale(coeffs: &[Float]) -> Float {
+    coeffs.iter().fold(0.0 as Float, |m, c| m.max(c.abs()))
+}
+
+// How close to zero a value of the polynomial has to be to count as a root,
+// a few thousand rounding errors of its largest term.
+pub fn root_tolerance(coeffs: &[Float]) -> Float {
+    4096.0 * Float::EPSILON * scale(coeffs)
+}
+
+// All real roots in [lo, hi], in ascending order. The roots of the
+// derivative split the interval into pieces on which the polynomial is
+// monotonic, so each piece holds at most one root, which is then found by
+// bisection. This does not suffer from the cancellation problems of the
+// closed-form cubic and quartic solutions.
+pub fn real_roots_in(coeffs: &[Float], lo: Float, hi: Float) -> Vec<Float> {
+    let tolerance = root_tolerance(coeffs);
+    let mut degree = coeffs.len();
+    while degree > 0 && coeffs[degree - 1].abs() <= 64.0 * Float::EPSILON * scale(coeffs) {
+        degree -= 1;
+    }
+    let coeffs = &coeffs[..degree];
+    if degree < 2 || lo > hi {
+        return Vec::new();
+    }
+    if degree == 2 {
+        let x = -coeffs[0] / coeffs[1];
+        return if x >= lo && x <= hi {
+            vec![x]
+        } else {
+            Vec::new()
+        };
+    }
+
+    let mut bounds = vec![lo];
+    bounds.extend(real_roots_in(&derivative(coeffs), lo, hi));
+    bounds.push(hi);
+
+    // values this close to zero at the ends of a piece are taken to be
+    // (double) roots, otherwise tangent hits would slip through
+    let mut roots: Vec<Float> = Vec::new();
+    let mut push_root = |r: Float| {
+        if roots.last().is_none_or(|last| r > *last) {
+            roots.push(r);
+        }
+    };
+    for pair in bounds.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        let (fa, fb) = (eval(coeffs, a), eval(coeffs, b));
+        if fa.abs() <= tolerance {
+            push_root(a);
+        } else if fb.abs() > tolerance && (fa < 0.0) != (fb < 0.0) {
+            push_root(bisect(coeffs, a, b));
+        }
+    }
+    if eval(coeffs, hi).abs() <= tolerance {
+        push_root(hi);
+    }
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::float::tolerance;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn eval_uses_ascending_coefficients() {
+        // 1 + 2x + 3x^2
+        assert_relative_eq!(eval(&[1.0, 2.0, 3.0], 2.0), 17.0);
+    }
+
+    #[test]
+    fn quadratic_roots() {
+        // (x - 1)(x - 3) = 3 - 4x + x^2
+        let (x0, x1) = solve_quadratic(3.0, -4.0, 1.0).unwrap();
+        assert_relative_eq!(x0, 1.0);
+        assert_relative_eq!(x1, 3.0);
+        assert!(solve_quadratic(1.0, 0.0, 1.0).is_none());
+        // tiny root next to a huge one keeps its precision
+        let (x0, _) = solve_quadratic(1.0, -1e9, 1.0).unwrap();
+        assert_relative_eq!(x0, 1e-9, max_relative = 1e-12);
+    }
+
+    #[test]
+    fn quartic_roots() {
+        // (x - 1)(x - 2)(x - 3)(x - 4)
+        let coeffs = [24.0, -50.0, 35.0, -10.0, 1.0];
+        let roots = real_roots_in(&coeffs, -10.0, 10.0);
+        assert_eq!(roots.len(), 4);
+        for (r, expected) in roots.iter().zip(&[1.0, 2.0, 3.0, 4.0]) {
+            assert_relative_eq!(*r, *expected, epsilon = tolerance(1e-9));
+        }
+        let roots = real_roots_in(&coeffs, 2.5, 10.0);
+        assert_eq!(roots.len(), 2);
+        assert_relative_eq!(roots[0], 3.0, epsilon = tolerance(1e-9));
+        // x^4 + 1 has no real roots
+        assert!(real_roots_in(&[1.0, 0.0, 0.0, 0.0, 1.0], -10.0, 10.0).is_empty());
+    }
+
+    #[test]
+    fn double_roots_are_found_once() {
+        // (x - 1)^2 (x + 2) = 2 - 3x + x^3
+        let roots = real_roots_in(&[2.0, -3.0, 0.0, 1.0], -5.0, 5.0);
+        assert_eq!(roots.len(), 2);
+        assert_relative_eq!(roots[0], -2.0, epsilon = 1e-9);
+        assert_relative_eq!(roots[1], 1.0, epsilon = 1e-9);
+    }
+}
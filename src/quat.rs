@@ -0,0 +1,162 @@
+use crate::float::Float;
+use crate::mat4::*;
+use crate::motion::Lerp;
+use crate::vec3::*;
+
+/// A unit quaternion w + xi + yj + zk describing a rotation, for rotations
+/// that interpolate at a steady angular speed.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Quat {
+    pub w: Float,
+    pub x: Float,
+    pub y: Float,
+    pub z: Float,
+}
+
+impl Quat {
+    pub fn identity() -> Quat {
+        Quat {
+            w: 1.0,
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        }
+    }
+
+    /// the same rotation as `Mat4::rotation(axis, degrees)`
+    pub fn from_axis_angle(axis: Vec3, degrees: Float) -> Quat {
+        let a = axis.normalized();
+        let half = 0.5 * degrees * crate::float::consts::PI / 180.0;
+        let (s, c) = half.sin_cos();
+        Quat {
+            w: c,
+            x: s * a.x(),
+            y: s * a.y(),
+            z: s * a.z(),
+        }
+    }
+
+    pub fn dot(&self, other: &Quat) -> Float {
+        self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn normalized(&self) -> Quat {
+        let length = self.dot(self).sqrt();
+        Quat {
+            w: self.w / length,
+            x: self.x / length,
+            y: self.y / length,
+            z: self.z / length,
+        }
+    }
+
+    /// the opposite rotation
+    pub fn conjugate(&self) -> Quat {
+        Quat {
+            w: self.w,
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+
+    /// Spherical linear interpolation, taking the shorter way around.
+    pub fn slerp(&self, other: &Quat, t: Float) -> Quat {
+        let mut cos = self.dot(other);
+        let mut other = *other;
+        // q and -q are the same rotation
+        if cos < 0.0 {
+            cos = -cos;
+            other = Quat {
+                w: -other.w,
+                x: -other.x,
+                y: -other.y,
+                z: -other.z,
+            };
+        }
+        // nearly parallel quaternions are blended linearly, which avoids
+        // dividing by a vanishing sine
+        let (a, b) = if cos > 0.9995 {
+            (1.0 - t, t)
+        } else {
+            let theta = cos.acos();
+            let sin = theta.sin();
+            (((1.0 - t) * theta).sin() / sin, (t * theta).sin() / sin)
+        };
+        Quat {
+            w: a * self.w + b * other.w,
+            x: a * self.x + b * other.x,
+            y: a * self.y + b * other.y,
+            z: a * self.z + b * other.z,
+        }
+        .normalized()
+    }
+
+    pub fn to_mat4(self) -> Mat4 {
+        let Quat { w, x, y, z } = self;
+        Mat4([
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - w * z),
+                2.0 * (x * z + w * y),
+                0.0,
+            ],
+            [
+                2.0 * (x * y + w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - w * x),
+                0.0,
+            ],
+            [
+                2.0 * (x * z - w * y),
+                2.0 * (y * z + w * x),
+                1.0 - 2.0 * (x * x + y * y),
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+}
+
+impl Lerp for Quat {
+    const LINEAR: bool = false;
+
+    fn lerp(&self, other: &Quat, t: Float) -> Quat {
+        self.slerp(other, t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::float::tolerance;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn matches_matrix_rotation() {
+        let axis = Vec3(1.0, 2.0, -0.5);
+        let q = Quat::from_axis_angle(axis, 70.0).to_mat4();
+        let m = Mat4::rotation(axis, 70.0);
+        for (a, b) in q.0.iter().flatten().zip(m.0.iter().flatten()) {
+            assert_relative_eq!(a, b, epsilon = tolerance(1e-12));
+        }
+    }
+
+    #[test]
+    fn slerp_turns_at_constant_speed() {
+        let up = Vec3(0.0, 1.0, 0.0);
+        let a = Quat::from_axis_angle(up, 0.0);
+        let b = Quat::from_axis_angle(up, 120.0);
+        let p = a
+            .slerp(&b, 0.25)
+            .to_mat4()
+            .transform_vector(Vec3(1.0, 0.0, 0.0));
+        assert_relative_eq!(p.x(), (30.0 as Float).to_radians().cos(), epsilon = 1e-12);
+        assert_relative_eq!(p.length(), 1.0, epsilon = 1e-12);
+        // 350 degrees is the same as -10, so halfway to it is -5
+        let c = Quat::from_axis_angle(up, 350.0);
+        let half = a.slerp(&c, 0.5);
+        let expected = Quat::from_axis_angle(up, -5.0);
+        assert_relative_eq!(half.dot(&expected).abs(), 1.0, epsilon = 1e-12);
+    }
+}
@@ -1,12 +1,28 @@
+use crate::float::Float;
 use crate::vec3::*;
 
 pub struct Ray {
     pub pos: Vec3,
     pub dir: Vec3,
+    /// the instant within the camera shutter interval the ray belongs to
+    pub time: Float,
+    /// Set once a path has been split up by wavelength (in nm), such as by
+    /// dispersion. Only this wavelength is carried from then on.
+    pub wavelength: Option<Float>,
 }
 
 impl Ray {
-    pub fn point_at_t(&self, t: f64) -> Vec3 {
+    /// a ray at time zero that carries every wavelength
+    pub fn new(pos: Vec3, dir: Vec3) -> Self {
+        Ray {
+            pos,
+            dir,
+            time: 0.0,
+            wavelength: None,
+        }
+    }
+
+    pub fn point_at_t(&self, t: Float) -> Vec3 {
         self.pos + self.dir * t
     }
 }
@@ -16,10 +32,7 @@ mod tests {
     use super::*;
     #[test]
     fn ray_struct() {
-        let r = Ray {
-            pos: Vec3(0.0, 0.0, 0.0),
-            dir: Vec3(1.0, 0.0, 0.0),
-        };
+        let r = Ray::new(Vec3(0.0, 0.0, 0.0), Vec3(1.0, 0.0, 0.0));
         assert_eq!(r.pos.x(), 0.0);
         assert_eq!(r.dir.x(), 1.0);
         assert_eq!(r.dir.0, 1.0);
@@ -27,10 +40,7 @@ mod tests {
     }
     #[test]
     fn point_at_t() {
-        let r = Ray {
-            pos: Vec3(0.0, 0.0, 0.0),
-            dir: Vec3(1.0, 0.0, 0.0),
-        };
+        let r = Ray::new(Vec3(0.0, 0.0, 0.0), Vec3(1.0, 0.0, 0.0));
         assert_eq!(r.point_at_t(4.0), Vec3(4.0, 0.0, 0.0));
     }
 }
@@ -0,0 +1,496 @@
+use crate::aabb::Aabb;
+use crate::atmosphere::Atmosphere;
+use crate::bvh::Bvh;
+use crate::compact::{CompactScene, CompactSceneBuilder};
+use crate::error::{Error, Result};
+use crate::float::Float;
+use crate::hittable::*;
+use crate::instance::Instance;
+use crate::mat4::*;
+use crate::materials::Material;
+use crate::medium::*;
+use crate::ray::*;
+use crate::wide_bvh::WideBvh;
+use rand::rngs::StdRng;
+use std::sync::Arc;
+
+// Replaces the material reported by an object's hits, so one piece of
+// geometry can be bound to different materials in the scene graph.
+struct MaterialBinding {
+    object: Arc<dyn Hittable + Send + Sync>,
+    material: Arc<dyn Material + Send + Sync>,
+}
+
+impl Hittable for MaterialBinding {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        let mut hit_record = self.object.hit(ray, t_min, t_max)?;
+        hit_record.material = HitMaterial::Dyn(&*self.material);
+        Some(hit_record)
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.object.bounding_box()
+    }
+}
+
+/// A named node of the scene graph. The transform is relative to the parent
+/// node. A material set on a node overrides the materials of its geometry
+/// and of all descendants that do not set their own.
+pub struct SceneNode {
+    pub name: String,
+    pub transform: Mat4,
+    pub geometry: Option<Arc<dyn Hittable + Send + Sync>>,
+    pub material: Option<Arc<dyn Material + Send + Sync>>,
+    pub visible: bool,
+    pub children: Vec<SceneNode>,
+}
+
+impl SceneNode {
+    pub fn new(name: &str) -> Self {
+        SceneNode {
+            name: name.to_string(),
+            transform: Mat4::identity(),
+            geometry: None,
+            material: None,
+            visible: true,
+            children: Vec::new(),
+        }
+    }
+
+    pub fn with_geometry<T: Hittable + 'static + Send + Sync>(name: &str, geometry: T) -> Self {
+        let mut node = SceneNode::new(name);
+        node.geometry = Some(Arc::new(geometry));
+        node
+    }
+
+    /// adds a child and returns it, so that it can be filled in further
+    pub fn add_child(&mut self, child: SceneNode) -> &mut SceneNode {
+        self.children.push(child);
+        self.children.last_mut().unwrap()
+    }
+
+    pub fn child(&self, name: &str) -> Option<&SceneNode> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    pub fn child_mut(&mut self, name: &str) -> Option<&mut SceneNode> {
+        self.children.iter_mut().find(|c| c.name == name)
+    }
+
+    // `path` is the node's own path in the graph, for error messages
+    fn place_into(
+        &self,
+        path: &str,
+        parent_transform: &Mat4,
+        parent_material: Option<&Arc<dyn Material + Send + Sync>>,
+        placed: &mut Vec<Placed>,
+    ) {
+        if !self.visible {
+            return;
+        }
+        let transform = *parent_transform * self.transform;
+        let material = self.material.as_ref().or(parent_material);
+        if let Some(geometry) = &self.geometry {
+            placed.push(Placed {
+                path: path.to_string(),
+                geometry: geometry.clone(),
+                transform,
+                material: material.cloned(),
+            });
+        }
+        for child in &self.children {
+            let child_path = if path.is_empty() {
+                child.name.clone()
+            } else {
+                format!("{}/{}", path, child.name)
+            };
+            child.place_into(&child_path, &transform, material, placed);
+        }
+    }
+}
+
+// A visible node's geometry with the transform and material it inherits.
+struct Placed {
+    path: String,
+    geometry: Arc<dyn Hittable + Send + Sync>,
+    transform: Mat4,
+    material: Option<Arc<dyn Material + Send + Sync>>,
+}
+
+impl Placed {
+    // the geometry bound to the material and moved into world space, as
+    // the object with index `id` in the flattened scene
+    fn object(self, id: usize) -> Result<Box<dyn Hittable + Send + Sync>> {
+        let mut object = self.geometry;
+        if let Some(material) = self.material {
+            object = Arc::new(MaterialBinding { object, material });
+        }
+        if self.transform != Mat4::identity() {
+            let instance = Instance::new(object, self.transform)
+                .ok_or(Error::SingularTransform { node: self.path })?;
+            object = Arc::new(instance);
+        }
+        Ok(Box::new(SceneObject { object, id }))
+    }
+}
+
+// Lets geometry that is shared through an Arc go into the BVH directly,
+// and tags its hits with the object's index in the flattened scene.
+struct SceneObject {
+    object: Arc<dyn Hittable + Send + Sync>,
+    id: usize,
+}
+
+impl Hittable for SceneObject {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        let mut hit_record = self.object.hit(ray, t_min, t_max)?;
+        hit_record.object_id = self.id;
+        Some(hit_record)
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.object.bounding_box()
+    }
+}
+
+/// A hierarchy of named nodes. Nodes are addressed by the '/' separated
+/// names of the nodes leading to them from (but not including) the root,
+/// such as "table/leg_1". The empty path is the root itself.
+pub struct SceneGraph {
+    pub root: SceneNode,
+    pub media: Vec<Arc<dyn Medium + Send + Sync>>,
+    pub atmosphere: Option<Atmosphere>,
+}
+
+impl SceneGraph {
+    pub fn new() -> Self {
+        SceneGraph {
+            root: SceneNode::new("root"),
+            media: Vec::new(),
+            atmosphere: None,
+        }
+    }
+
+    pub fn find(&self, path: &str) -> Option<&SceneNode> {
+        let mut node = &self.root;
+        for name in path.split('/').filter(|n| !n.is_empty()) {
+            node = node.child(name)?;
+        }
+        Some(node)
+    }
+
+    pub fn find_mut(&mut self, path: &str) -> Option<&mut SceneNode> {
+        let mut node = &mut self.root;
+        for name in path.split('/').filter(|n| !n.is_empty()) {
+            node = node.child_mut(name)?;
+        }
+        Some(node)
+    }
+
+    /// returns false if there is no node at `path`
+    pub fn set_visible(&mut self, path: &str, visible: bool) -> bool {
+        match self.find_mut(path) {
+            Some(node) => {
+                node.visible = visible;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// puts `node` in place of the node at `path` and returns the old one
+    pub fn replace(&mut self, path: &str, node: SceneNode) -> Option<SceneNode> {
+        let old = self.find_mut(path)?;
+        Some(std::mem::replace(old, node))
+    }
+
+    pub fn remove(&mut self, path: &str) -> Option<SceneNode> {
+        let path = path.trim_end_matches('/');
+        let (parent_path, name) = match path.rfind('/') {
+            Some(i) => (&path[..i], &path[i + 1..]),
+            None => ("", path),
+        };
+        let parent = self.find_mut(parent_path)?;
+        let index = parent.children.iter().position(|c| c.name == name)?;
+        Some(parent.children.remove(index))
+    }
+
+    /// Bakes the visible nodes into world space and builds a BVH over them.
+    /// Done once per render, after all edits. Fails on the first node whose
+    /// transform can't be inverted.
+    pub fn flatten(&self) -> Result<Bvh> {
+        Ok(Bvh::new(self.objects()?))
+    }
+
+    /// As `flatten`, into a wide BVH, which pays off for scenes of many
+    /// thousands of objects but is slower than `Bvh` for small ones.
+    pub fn flatten_wide(&self) -> Result<WideBvh> {
+        Ok(WideBvh::new(self.objects()?))
+    }
+
+    fn objects(&self) -> Result<Vec<Box<dyn Hittable + Send + Sync>>> {
+        self.placed()
+            .into_iter()
+            .enumerate()
+            .map(|(id, placed)| placed.object(id))
+            .collect()
+    }
+
+    fn placed(&self) -> Vec<Placed> {
+        let mut placed = Vec::new();
+        self.root
+            .place_into("", &Mat4::identity(), None, &mut placed);
+        placed
+    }
+
+    /// As `flatten`, into a compact scene. Spheres, quads and planes that
+    /// are neither moved, bound to another material nor shared between
+    /// nodes become built-in primitives; everything else is boxed. Takes the
+    /// graph apart to do so, and drops its media and atmosphere.
+    pub fn compact(self) -> Result<CompactScene> {
+        let placed = self.placed();
+        // leaves the placed geometry as the only owner of what isn't shared
+        drop(self);
+        let mut builder = CompactSceneBuilder::new();
+        for (id, mut placed) in placed.into_iter().enumerate() {
+            if placed.material.is_none() && placed.transform == Mat4::identity() {
+                match builder.add_shape(placed.geometry) {
+                    Ok(()) => continue,
+                    Err(geometry) => placed.geometry = geometry,
+                }
+            }
+            builder.add_boxed(placed.object(id)?);
+        }
+        Ok(builder.build())
+    }
+
+    pub fn world(&self) -> Result<World> {
+        Ok(World {
+            objects: Surfaces::Bvh(self.flatten()?),
+            media: self.media.clone(),
+            atmosphere: self.atmosphere.clone(),
+        })
+    }
+}
+
+impl Default for SceneGraph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The surfaces of a world: one of the BVHs a scene graph flattens into,
+/// or a compact scene of built-in primitives.
+pub enum Surfaces {
+    Bvh(Bvh),
+    Wide(WideBvh),
+    Compact(CompactScene),
+}
+
+impl Hittable for Surfaces {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        match self {
+            Surfaces::Bvh(bvh) => bvh.hit(ray, t_min, t_max),
+            Surfaces::Wide(bvh) => bvh.hit(ray, t_min, t_max),
+            Surfaces::Compact(scene) => scene.hit(ray, t_min, t_max),
+        }
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        match self {
+            Surfaces::Bvh(bvh) => bvh.bounding_box(),
+            Surfaces::Wide(bvh) => bvh.bounding_box(),
+            Surfaces::Compact(scene) => scene.bounding_box(),
+        }
+    }
+}
+
+/// Everything a render needs: the surfaces and the participating media,
+/// which are tracked separately since rays pass through them. The
+/// atmosphere, if any, fills all of space.
+pub struct World {
+    pub objects: Surfaces,
+    pub media: Vec<Arc<dyn Medium + Send + Sync>>,
+    pub atmosphere: Option<Atmosphere>,
+}
+
+impl World {
+    /// a world of just the surfaces of a compact scene
+    pub fn compact(scene: CompactScene) -> Self {
+        World {
+            objects: Surfaces::Compact(scene),
+            media: Vec::new(),
+            atmosphere: None,
+        }
+    }
+
+    /// Tracks the ray through the media in front of `t_max`, nearest first,
+    /// and stops at the first scattering event. Where media overlap, the
+    /// overlap is tracked once per medium, which is exact for
+    /// non-overlapping media only.
+    pub fn sample_media(
+        &self,
+        ray: &Ray,
+        t_min: Float,
+        t_max: Float,
+        rng: &mut StdRng,
+    ) -> MediumSample {
+        let mut intervals: Vec<_> = self
+            .media
+            .iter()
+            .flat_map(|m| {
+                m.intervals(ray, t_min, t_max)
+                    .into_iter()
+                    .map(move |i| (i, m))
+            })
+            .collect();
+        intervals.sort_by(|a, b| (a.0).0.total_cmp(&(b.0).0));
+        let mut result = MediumSample::pass_through();
+        for ((t0, t1), medium) in intervals {
+            let sample = medium.sample(ray, t0, t1, rng);
+            result.weight *= sample.weight;
+            if sample.scatter.is_some() {
+                result.scatter = sample.scatter;
+                break;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::*;
+    use approx::assert_relative_eq;
+
+    struct TestMaterial {}
+    impl Material for TestMaterial {}
+
+    fn unit_sphere() -> Sphere {
+        Sphere {
+            center: Vec3(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material: Box::new(TestMaterial {}),
+        }
+    }
+
+    fn down_at(x: Float) -> Ray {
+        Ray::new(Vec3(x, 10.0, 0.0), Vec3(0.0, -1.0, 0.0))
+    }
+
+    fn table() -> SceneGraph {
+        let mut scene = SceneGraph::new();
+        let mut table = SceneNode::new("table");
+        table.transform = Mat4::translation(Vec3(5.0, 0.0, 0.0));
+        let leg = table.add_child(SceneNode::with_geometry("leg_1", unit_sphere()));
+        leg.transform = Mat4::translation(Vec3(1.0, 0.0, 0.0));
+        table.add_child(SceneNode::with_geometry("leg_2", unit_sphere()));
+        scene.root.add_child(table);
+        scene
+    }
+
+    #[test]
+    fn find_by_path() {
+        let scene = table();
+        assert_eq!(scene.find("").unwrap().name, "root");
+        assert_eq!(scene.find("table").unwrap().children.len(), 2);
+        assert_eq!(scene.find("table/leg_2").unwrap().name, "leg_2");
+        assert!(scene.find("table/leg_3").is_none());
+        assert!(scene.find("chair").is_none());
+    }
+
+    #[test]
+    fn flatten_composes_transforms() {
+        let world = table().flatten().unwrap();
+        // leg_1 sits at x = 6, leg_2 at x = 5
+        assert_relative_eq!(world.hit(&down_at(6.0), 0.001, Float::MAX).unwrap().t, 9.0);
+        assert_relative_eq!(world.hit(&down_at(5.0), 0.001, Float::MAX).unwrap().t, 9.0);
+        assert!(world.hit(&down_at(0.0), 0.001, Float::MAX).is_none());
+        // hits tell the legs apart, and are in world space
+        let leg_1 = world.hit(&down_at(6.0), 0.001, Float::MAX).unwrap();
+        let leg_2 = world.hit(&down_at(5.0), 0.001, Float::MAX).unwrap();
+        assert_eq!((leg_1.object_id, leg_2.object_id), (0, 1));
+        assert_relative_eq!(leg_1.point.x(), 6.0);
+        assert_relative_eq!(leg_1.point.y(), 1.0);
+        let b = world.bounding_box().unwrap();
+        assert_relative_eq!(b.min.x(), 4.0);
+        assert_relative_eq!(b.max.x(), 7.0);
+    }
+
+    #[test]
+    fn flatten_wide_finds_the_same_hits() {
+        let scene = table();
+        let (bvh, wide) = (scene.flatten().unwrap(), scene.flatten_wide().unwrap());
+        for x in &[0.0, 4.5, 5.0, 5.9, 6.2, 7.5] {
+            let expected = bvh.hit(&down_at(*x), 0.001, Float::MAX);
+            let actual = wide.hit(&down_at(*x), 0.001, Float::MAX);
+            assert_eq!(
+                actual.map(|h| (h.t, h.object_id)),
+                expected.map(|h| (h.t, h.object_id))
+            );
+        }
+    }
+
+    #[test]
+    fn hidden_nodes_are_skipped() {
+        let mut scene = table();
+        assert!(scene.set_visible("table/leg_1", false));
+        assert!(!scene.set_visible("table/leg_9", false));
+        let world = scene.flatten().unwrap();
+        assert!(world.hit(&down_at(6.2), 0.001, Float::MAX).is_none());
+        scene.set_visible("table", false);
+        let world = scene.flatten().unwrap();
+        assert!(world.hit(&down_at(5.0), 0.001, Float::MAX).is_none());
+    }
+
+    #[test]
+    fn replace_and_remove_nodes() {
+        let mut scene = table();
+        let mut big = SceneNode::with_geometry("leg_2", unit_sphere());
+        big.transform = Mat4::scaling(Vec3(2.0, 2.0, 2.0));
+        let old = scene.replace("table/leg_2", big).unwrap();
+        assert_eq!(old.name, "leg_2");
+        let world = scene.flatten().unwrap();
+        assert_relative_eq!(world.hit(&down_at(5.0), 0.001, Float::MAX).unwrap().t, 8.0);
+
+        let removed = scene.remove("table/leg_1").unwrap();
+        assert_eq!(removed.name, "leg_1");
+        assert!(scene.find("table/leg_1").is_none());
+        assert!(scene.remove("table/leg_1").is_none());
+    }
+
+    #[test]
+    fn remove_with_trailing_slash() {
+        let mut scene = table();
+        let removed = scene.remove("table/leg_1/").unwrap();
+        assert_eq!(removed.name, "leg_1");
+        assert_eq!(scene.find("table").unwrap().children.len(), 1);
+        assert_eq!(scene.remove("table/").unwrap().name, "table");
+        assert!(scene.root.children.is_empty());
+    }
+
+    #[test]
+    fn singular_transforms_name_the_node() {
+        let mut scene = table();
+        scene.find_mut("table/leg_2").unwrap().transform = Mat4::scaling(Vec3(0.0, 1.0, 1.0));
+        match scene.flatten() {
+            Err(Error::SingularTransform { node }) => assert_eq!(node, "table/leg_2"),
+            _ => panic!("expected a singular transform error"),
+        }
+    }
+
+    #[test]
+    fn material_binding_is_inherited() {
+        struct OtherMaterial {}
+        impl Material for OtherMaterial {}
+
+        let mut scene = table();
+        let material: Arc<dyn Material + Send + Sync> = Arc::new(OtherMaterial {});
+        scene.find_mut("table").unwrap().material = Some(material.clone());
+        let world = scene.flatten().unwrap();
+        let hit = world.hit(&down_at(5.0), 0.001, Float::MAX).unwrap();
+        let bound = match hit.material {
+            HitMaterial::Dyn(m) => m as *const _ as *const u8,
+            HitMaterial::Kind(_) => panic!("a scene graph binds trait objects"),
+        };
+        assert_eq!(bound, &*material as *const _ as *const u8);
+    }
+}
@@ -0,0 +1,1039 @@
+use crate::camera::*;
+use crate::error::Error;
+use crate::float::Float;
+use crate::hittable::Hittable;
+use crate::motion::*;
+use crate::stereo::*;
+use crate::vec3::*;
+use std::fmt;
+use std::path::Path;
+
+/// A problem in a scene file, with the (1-based) line it was found on.
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// The settings read from a scene file. Scene files are plain text with one
+/// statement per line; `#` starts a comment. A `camera <type>` line starts
+/// the camera description and the lines after it set its parameters:
+///
+/// ```text
+/// camera fisheye
+/// position 0 1 0
+/// look_at 0 1 -1
+/// fov 180
+/// projection stereographic
+/// ```
+///
+/// Camera types are thin_lens, realistic, orthographic, fisheye,
+/// equirectangular and cube_map. A thin lens camera takes its angle of view
+/// from one of `fov` (horizontal, in degrees), `vfov`, `hfov`, `dfov` or
+/// `focal_length` with `sensor` (in mm), and focuses by `focus` (a fraction
+/// of the look-at distance), `focus_distance` or autofocus on a
+/// `focus_target` point. Its lens is set by `aperture` (a diameter) or
+/// `f_stop`, `blades <count> <rotation>` for a polygonal opening,
+/// `exposure <seconds> <iso>`, `vignetting` and `chromatic_aberration`. A
+/// realistic camera is given its lens one `element <radius> <thickness>
+/// <ior> <aperture>` line per surface, front to back as in a lens
+/// prescription, or `lens <path>` to read them from a prescription file
+/// (see `parse_lens`), and a `film_diagonal` in mm; it focuses like a thin
+/// lens camera.
+///
+/// `stereo parallel` or `stereo toe_in` turns a thin lens camera into a
+/// stereo pair, and `stereo ods` an equirectangular one into an
+/// omni-directional stereo panorama. The eyes are `interocular` apart, a
+/// pair converges at `convergence` (the look-at distance by default), and
+/// `layout over_under` or `layout side_by_side` arranges them in the
+/// image. `anaglyph` asks for a red/cyan composite as well.
+///
+/// A thin lens camera can be animated with `key <time> <parameter>
+/// <values>` lines, where the parameter is position, look_at, fov (the
+/// angle of whichever field of view is set, or the focal length),
+/// focus_distance or aperture. Keys are blended linearly, smoothly with
+/// `interpolation catmull_rom`, or along Bezier curves with `interpolation
+/// bezier`, for which each key's values are followed by those of its in
+/// and out handles. Shutter times are relative to the time of the frame,
+/// and default to the exposure the renderer asks for.
+pub struct SceneFile {
+    camera: Option<CameraSettings>,
+}
+
+impl SceneFile {
+    /// the layout of the stereo image, if an anaglyph of it was asked for
+    pub fn anaglyph(&self) -> Option<StereoLayout> {
+        match &self.camera {
+            Some(c) if c.anaglyph && c.stereo.is_some() => Some(c.layout),
+            _ => None,
+        }
+    }
+
+    /// the camera at `time`, if the file describes one, with its shutter
+    /// open for `exposure` seconds unless the file sets a shutter; `world` is
+    /// used for autofocus
+    pub fn camera(
+        &self,
+        aspect: Float,
+        world: &dyn Hittable,
+        time: Float,
+        exposure: Float,
+    ) -> Result<Option<Box<dyn Camera + Send + Sync>>, Error> {
+        self.camera
+            .as_ref()
+            .map(|c| c.build(aspect, world, time, exposure))
+            .transpose()
+    }
+}
+
+struct CameraSettings {
+    kind: String,
+    position: Vec3,
+    look_at: Vec3,
+    up: Vec3,
+    fov: Float,
+    field_of_view: Option<FieldOfView>,
+    focal_length: Option<Float>,
+    sensor: (Float, Float),
+    aperture: Float,
+    f_number: Option<Float>,
+    blades: Option<(usize, Float)>,
+    exposure: Option<Exposure>,
+    vignetting: bool,
+    chromatic_aberration: Float,
+    // `focus` as a fraction of the look-at distance, for the plain `fov`
+    focus: Focus,
+    focus_distance: Option<Focus>,
+    elements: Vec<LensElement>,
+    // a lens file to read the elements from, and the line naming it
+    lens: Option<(usize, String)>,
+    film_diagonal: Float,
+    width: Float,
+    projection: FisheyeProjection,
+    // relative to the frame's time, if the file sets it
+    shutter: Option<Shutter>,
+    stereo: Option<Stereo>,
+    interocular: Float,
+    convergence: Option<Float>,
+    layout: StereoLayout,
+    anaglyph: bool,
+    keys: CameraKeys,
+    interpolation: Interpolation,
+}
+
+// What a `stereo` line makes of the camera: a rig of two thin lens
+// cameras, or an omni-directional stereo panorama.
+#[derive(Clone, Copy)]
+enum Stereo {
+    Rig(StereoMode),
+    Ods,
+}
+
+impl Stereo {
+    fn name(self) -> &'static str {
+        match self {
+            Stereo::Rig(StereoMode::Parallel) => "parallel",
+            Stereo::Rig(StereoMode::ToeIn) => "toe_in",
+            Stereo::Ods => "ods",
+        }
+    }
+}
+
+// a keyed value at a time, with its in and out handles for Bezier keys
+type Key<T> = (Float, T, Option<(T, T)>);
+
+#[derive(Default)]
+struct CameraKeys {
+    position: Vec<Key<Vec3>>,
+    look_at: Vec<Key<Vec3>>,
+    fov: Vec<Key<Float>>,
+    focus_distance: Vec<Key<Float>>,
+    aperture: Vec<Key<Float>>,
+}
+
+impl CameraKeys {
+    fn is_empty(&self) -> bool {
+        self.position.is_empty()
+            && self.look_at.is_empty()
+            && self.fov.is_empty()
+            && self.focus_distance.is_empty()
+            && self.aperture.is_empty()
+    }
+
+    // whether any key has, or any key lacks, Bezier handles
+    fn handles(&self) -> (bool, bool) {
+        fn check<T>(keys: &[Key<T>], has: &mut bool, lacks: &mut bool) {
+            for key in keys {
+                *has |= key.2.is_some();
+                *lacks |= key.2.is_none();
+            }
+        }
+        let (mut has, mut lacks) = (false, false);
+        check(&self.position, &mut has, &mut lacks);
+        check(&self.look_at, &mut has, &mut lacks);
+        check(&self.fov, &mut has, &mut lacks);
+        check(&self.focus_distance, &mut has, &mut lacks);
+        check(&self.aperture, &mut has, &mut lacks);
+        (has, lacks)
+    }
+
+    // Bezier keys are checked to all have handles when parsed.
+    fn animation(&self, interpolation: Interpolation) -> CameraAnimation {
+        // None for a setting without keys
+        fn keyframes<T: Lerp + Copy>(
+            keys: &[Key<T>],
+            interpolation: Interpolation,
+        ) -> Option<Keyframes<T>> {
+            if interpolation == Interpolation::Bezier {
+                let keys = keys.iter().map(|&(time, value, handles)| {
+                    let (handle_in, handle_out) = handles.unwrap_or((value, value));
+                    (time, value, handle_in, handle_out)
+                });
+                Keyframes::bezier(keys.collect())
+            } else {
+                let keys = keys.iter().map(|&(time, value, _)| (time, value));
+                Some(Keyframes::new(keys.collect())?.with_interpolation(interpolation))
+            }
+        }
+        CameraAnimation {
+            position: keyframes(&self.position, interpolation),
+            look_at: keyframes(&self.look_at, interpolation),
+            fov: keyframes(&self.fov, interpolation),
+            focus_distance: keyframes(&self.focus_distance, interpolation),
+            aperture: keyframes(&self.aperture, interpolation),
+        }
+    }
+}
+
+impl CameraSettings {
+    fn new(kind: &str) -> Self {
+        CameraSettings {
+            kind: kind.to_string(),
+            position: Vec3(0.0, 0.0, 0.0),
+            look_at: Vec3(0.0, 0.0, -1.0),
+            up: Vec3(0.0, 1.0, 0.0),
+            fov: 90.0,
+            field_of_view: None,
+            focal_length: None,
+            sensor: (36.0, 24.0),
+            aperture: 0.0,
+            f_number: None,
+            blades: None,
+            exposure: None,
+            vignetting: false,
+            chromatic_aberration: 0.0,
+            focus: Focus::LookAt,
+            focus_distance: None,
+            elements: vec![],
+            lens: None,
+            film_diagonal: (36.0 as Float).hypot(24.0),
+            width: 2.0,
+            projection: FisheyeProjection::Equidistant,
+            shutter: None,
+            stereo: None,
+            interocular: 0.064,
+            convergence: None,
+            layout: StereoLayout::OverUnder,
+            anaglyph: false,
+            keys: CameraKeys::default(),
+            interpolation: Interpolation::Linear,
+        }
+    }
+
+    fn build(
+        &self,
+        aspect: Float,
+        world: &dyn Hittable,
+        time: Float,
+        exposure: Float,
+    ) -> Result<Box<dyn Camera + Send + Sync>, Error> {
+        let shutter = self.shutter_at(time, exposure);
+        let mode = match self.stereo {
+            Some(Stereo::Rig(mode)) => mode,
+            Some(Stereo::Ods) => {
+                let mut panorama = EquirectangularCamera::new(self.position, self.look_at, self.up);
+                panorama.shutter = shutter;
+                return Ok(Box::new(StereoCamera::ods(
+                    panorama,
+                    self.interocular,
+                    self.layout,
+                )));
+            }
+            None => return self.camera(aspect, world, time, shutter),
+        };
+        let rig = StereoRig {
+            camera: self.thin_lens(self.layout.eye_aspect(aspect), time, shutter),
+            interocular: self.interocular,
+            convergence: self
+                .convergence
+                .unwrap_or_else(|| (self.look_at - self.position).length()),
+            mode,
+        };
+        Ok(Box::new(rig.build(world, self.layout)?))
+    }
+
+    fn camera(
+        &self,
+        aspect: Float,
+        world: &dyn Hittable,
+        time: Float,
+        shutter: Shutter,
+    ) -> Result<Box<dyn Camera + Send + Sync>, Error> {
+        Ok(match self.kind.as_str() {
+            "orthographic" => {
+                let mut camera = OrthographicCamera::new(
+                    self.position,
+                    self.look_at,
+                    self.up,
+                    self.width,
+                    aspect,
+                );
+                camera.shutter = shutter;
+                Box::new(camera)
+            }
+            "fisheye" => {
+                let mut camera = FisheyeCamera::new(
+                    self.position,
+                    self.look_at,
+                    self.up,
+                    self.fov,
+                    aspect,
+                    self.projection,
+                );
+                camera.shutter = shutter;
+                Box::new(camera)
+            }
+            "equirectangular" => {
+                let mut camera = EquirectangularCamera::new(self.position, self.look_at, self.up);
+                camera.shutter = shutter;
+                Box::new(camera)
+            }
+            "cube_map" => {
+                let mut camera = CubeMapCamera::new(self.position, self.look_at, self.up);
+                camera.shutter = shutter;
+                Box::new(camera)
+            }
+            "realistic" => {
+                let focus_distance = CameraBuilder::new(self.position, self.look_at, aspect)
+                    .focus(self.focus())
+                    .focus_distance(world);
+                let mut camera = RealisticCamera::new(
+                    self.position,
+                    self.look_at,
+                    self.up,
+                    self.elements.clone(),
+                    self.film_diagonal,
+                    aspect,
+                    focus_distance,
+                );
+                camera.shutter = shutter;
+                Box::new(camera)
+            }
+            _ => Box::new(self.thin_lens(aspect, time, shutter).build(world)?),
+        })
+    }
+
+    // the shutter interval of the frame at `time`, open for `exposure`
+    // seconds unless the file says otherwise
+    fn shutter_at(&self, time: Float, exposure: Float) -> Shutter {
+        let shutter = self.shutter.unwrap_or(Shutter {
+            open: 0.0,
+            close: exposure,
+        });
+        Shutter {
+            open: time + shutter.open,
+            close: time + shutter.close,
+        }
+    }
+
+    fn thin_lens(&self, aspect: Float, time: Float, shutter: Shutter) -> CameraBuilder {
+        let fov = match (self.focal_length, self.field_of_view) {
+            (Some(focal_length), _) => FieldOfView::FocalLength {
+                focal_length,
+                sensor_width: self.sensor.0,
+                sensor_height: self.sensor.1,
+            },
+            (None, Some(fov)) => fov,
+            (None, None) => FieldOfView::Horizontal(self.fov),
+        };
+        let mut builder = CameraBuilder::new(self.position, self.look_at, aspect)
+            .up(self.up)
+            .fov(fov)
+            .aperture(self.aperture)
+            .focus(self.focus());
+        builder.shutter = shutter;
+        builder.f_number = self.f_number;
+        if let Some((blades, rotation)) = self.blades {
+            // checked to be at least 3 when parsed
+            builder.aperture_shape = ApertureShape::Polygon { blades, rotation };
+        }
+        builder.exposure = self.exposure;
+        builder.vignetting = self.vignetting;
+        builder.chromatic_aberration = self.chromatic_aberration;
+        self.keys.animation(self.interpolation).at(&builder, time)
+    }
+
+    fn focus(&self) -> Focus {
+        match (self.focus_distance, self.focus) {
+            (Some(focus), _) => focus,
+            (None, Focus::Distance(fraction)) => {
+                Focus::Distance(fraction * (self.look_at - self.position).length())
+            }
+            (None, focus) => focus,
+        }
+    }
+}
+
+const CAMERA_TYPES: [&str; 6] = [
+    "thin_lens",
+    "realistic",
+    "orthographic",
+    "fisheye",
+    "equirectangular",
+    "cube_map",
+];
+
+fn numbers(line: usize, args: &[&str], count: usize) -> Result<Vec<Float>, ParseError> {
+    if args.len() != count {
+        return Err(ParseError {
+            line,
+            message: format!("expected {} numbers, found {}", count, args.len()),
+        });
+    }
+    args.iter()
+        .map(|a| match a.parse::<Float>() {
+            Ok(value) if value.is_finite() => Ok(value),
+            _ => Err(ParseError {
+                line,
+                message: format!("'{}' is not a number", a),
+            }),
+        })
+        .collect()
+}
+
+fn vector(line: usize, args: &[&str]) -> Result<Vec3, ParseError> {
+    let v = numbers(line, args, 3)?;
+    Ok(Vec3(v[0], v[1], v[2]))
+}
+
+fn number(line: usize, args: &[&str]) -> Result<Float, ParseError> {
+    Ok(numbers(line, args, 1)?[0])
+}
+
+// A key for `keys` at `time`: `width` numbers for the value, or three
+// times as many for the value and its in and out Bezier handles.
+fn push_key<T>(
+    keys: &mut Vec<Key<T>>,
+    line: usize,
+    time: Float,
+    args: &[&str],
+    width: usize,
+    value: fn(usize, &[&str]) -> Result<T, ParseError>,
+) -> Result<(), ParseError> {
+    if keys.iter().any(|key| key.0 == time) {
+        return Err(ParseError {
+            line,
+            message: format!("there is already a key at time {}", time),
+        });
+    }
+    let key = if args.len() == 3 * width {
+        let handle_in = value(line, &args[width..2 * width])?;
+        let handle_out = value(line, &args[2 * width..])?;
+        (
+            time,
+            value(line, &args[..width])?,
+            Some((handle_in, handle_out)),
+        )
+    } else {
+        (time, value(line, args)?, None)
+    };
+    keys.push(key);
+    Ok(())
+}
+
+fn lens_element(line: usize, args: &[&str]) -> Result<LensElement, ParseError> {
+    let v = numbers(line, args, 4)?;
+    Ok(LensElement {
+        radius: v[0],
+        thickness: v[1],
+        ior: v[2],
+        aperture: v[3],
+    })
+}
+
+/// Reads a lens prescription: one surface per line, front to back, as
+/// `radius thickness ior aperture` in millimeters, with `#` comments.
+pub fn parse_lens(text: &str) -> Result<Vec<LensElement>, ParseError> {
+    let mut elements = vec![];
+    for (index, raw) in text.lines().enumerate() {
+        let content = raw.split('#').next().unwrap_or("");
+        let args: Vec<&str> = content.split_whitespace().collect();
+        if !args.is_empty() {
+            elements.push(lens_element(index + 1, &args)?);
+        }
+    }
+    Ok(elements)
+}
+
+/// Reads the scene file at `path`, with the lens file it names, if any,
+/// found relative to it.
+pub fn load(path: &str) -> Result<SceneFile, Error> {
+    let text = std::fs::read_to_string(path)?;
+    let error_in = |path: &str, error| Error::SceneParse {
+        path: path.to_string(),
+        error,
+    };
+    let mut scene = parse(&text).map_err(|error| error_in(path, error))?;
+    let settings = match scene.camera.as_mut() {
+        Some(settings) => settings,
+        None => return Ok(scene),
+    };
+    if let Some((line, lens)) = settings.lens.take() {
+        let lens_path = Path::new(path).with_file_name(&lens);
+        let text = std::fs::read_to_string(&lens_path).map_err(|e| {
+            let message = format!("can't read lens '{}': {}", lens, e);
+            error_in(path, ParseError { line, message })
+        })?;
+        let lens_path = lens_path.to_string_lossy();
+        settings.elements = parse_lens(&text).map_err(|error| error_in(&lens_path, error))?;
+        if settings.elements.is_empty() {
+            let message = format!("lens '{}' has no elements", lens);
+            return Err(error_in(path, ParseError { line, message }));
+        }
+    }
+    Ok(scene)
+}
+
+/// Reads a scene file from text. A `lens` line is only checked here; `load`
+/// reads the file it names.
+pub fn parse(text: &str) -> Result<SceneFile, ParseError> {
+    let mut camera: Option<CameraSettings> = None;
+    let mut camera_line = 0;
+    for (index, raw) in text.lines().enumerate() {
+        let line = index + 1;
+        let content = raw.split('#').next().unwrap_or("");
+        let words: Vec<&str> = content.split_whitespace().collect();
+        let (keyword, args) = match words.split_first() {
+            Some((keyword, args)) => (*keyword, args),
+            None => continue,
+        };
+        if keyword == "camera" {
+            match args {
+                [kind] if CAMERA_TYPES.contains(kind) => {
+                    camera = Some(CameraSettings::new(kind));
+                    camera_line = line;
+                }
+                _ => {
+                    return Err(ParseError {
+                        line,
+                        message: format!("camera type must be one of {}", CAMERA_TYPES.join(", ")),
+                    })
+                }
+            }
+            continue;
+        }
+        let settings = match camera.as_mut() {
+            Some(settings) => settings,
+            None => {
+                return Err(ParseError {
+                    line,
+                    message: format!("unknown statement '{}'", keyword),
+                })
+            }
+        };
+        match keyword {
+            "position" => settings.position = vector(line, args)?,
+            "look_at" => settings.look_at = vector(line, args)?,
+            "up" => settings.up = vector(line, args)?,
+            "fov" => settings.fov = number(line, args)?,
+            "vfov" => settings.field_of_view = Some(FieldOfView::Vertical(number(line, args)?)),
+            "hfov" => settings.field_of_view = Some(FieldOfView::Horizontal(number(line, args)?)),
+            "dfov" => settings.field_of_view = Some(FieldOfView::Diagonal(number(line, args)?)),
+            "focal_length" => settings.focal_length = Some(number(line, args)?),
+            "sensor" => {
+                let v = numbers(line, args, 2)?;
+                settings.sensor = (v[0], v[1]);
+            }
+            "aperture" => settings.aperture = number(line, args)?,
+            "f_stop" => settings.f_number = Some(number(line, args)?),
+            "blades" => {
+                let v = numbers(line, args, 2)?;
+                if v[0] < 3.0 || v[0].fract() != 0.0 {
+                    return Err(ParseError {
+                        line,
+                        message: "a diaphragm needs a whole number of at least 3 blades"
+                            .to_string(),
+                    });
+                }
+                settings.blades = Some((v[0] as usize, v[1]));
+            }
+            "exposure" => {
+                let v = numbers(line, args, 2)?;
+                settings.exposure = Some(Exposure {
+                    shutter_time: v[0],
+                    iso: v[1],
+                });
+            }
+            "vignetting" => {
+                numbers(line, args, 0)?;
+                settings.vignetting = true;
+            }
+            "chromatic_aberration" => settings.chromatic_aberration = number(line, args)?,
+            "focus" => settings.focus = Focus::Distance(number(line, args)?),
+            "focus_distance" => {
+                settings.focus_distance = Some(Focus::Distance(number(line, args)?))
+            }
+            "focus_target" => settings.focus_distance = Some(Focus::Target(vector(line, args)?)),
+            "element" => settings.elements.push(lens_element(line, args)?),
+            "lens" => match args {
+                [path] => settings.lens = Some((line, path.to_string())),
+                _ => {
+                    return Err(ParseError {
+                        line,
+                        message: "expected the path of a lens file".to_string(),
+                    })
+                }
+            },
+            "film_diagonal" => settings.film_diagonal = number(line, args)?,
+            "width" => settings.width = number(line, args)?,
+            "stereo" => match args {
+                ["parallel"] => settings.stereo = Some(Stereo::Rig(StereoMode::Parallel)),
+                ["toe_in"] => settings.stereo = Some(Stereo::Rig(StereoMode::ToeIn)),
+                ["ods"] => settings.stereo = Some(Stereo::Ods),
+                _ => {
+                    return Err(ParseError {
+                        line,
+                        message: "stereo must be parallel, toe_in or ods".to_string(),
+                    })
+                }
+            },
+            "key" => {
+                let (time, parameter, values) = match args {
+                    [time, parameter, values @ ..] => (number(line, &[time])?, *parameter, values),
+                    _ => {
+                        return Err(ParseError {
+                            line,
+                            message: "expected a time and a parameter".to_string(),
+                        })
+                    }
+                };
+                let keys = &mut settings.keys;
+                match parameter {
+                    "position" => push_key(&mut keys.position, line, time, values, 3, vector)?,
+                    "look_at" => push_key(&mut keys.look_at, line, time, values, 3, vector)?,
+                    "fov" => push_key(&mut keys.fov, line, time, values, 1, number)?,
+                    "focus_distance" => {
+                        push_key(&mut keys.focus_distance, line, time, values, 1, number)?
+                    }
+                    "aperture" => push_key(&mut keys.aperture, line, time, values, 1, number)?,
+                    _ => {
+                        return Err(ParseError {
+                            line,
+                            message: format!("'{}' can't be animated", parameter),
+                        })
+                    }
+                }
+            }
+            "interpolation" => {
+                settings.interpolation = match args {
+                    ["linear"] => Interpolation::Linear,
+                    ["catmull_rom"] => Interpolation::CatmullRom,
+                    ["bezier"] => Interpolation::Bezier,
+                    _ => {
+                        return Err(ParseError {
+                            line,
+                            message: "interpolation must be linear, catmull_rom or bezier"
+                                .to_string(),
+                        })
+                    }
+                }
+            }
+            "interocular" => settings.interocular = number(line, args)?,
+            "convergence" => settings.convergence = Some(number(line, args)?),
+            "layout" => {
+                settings.layout = match args {
+                    ["over_under"] => StereoLayout::OverUnder,
+                    ["side_by_side"] => StereoLayout::SideBySide,
+                    _ => {
+                        return Err(ParseError {
+                            line,
+                            message: "layout must be over_under or side_by_side".to_string(),
+                        })
+                    }
+                }
+            }
+            "anaglyph" => {
+                numbers(line, args, 0)?;
+                settings.anaglyph = true;
+            }
+            "shutter" => {
+                let v = numbers(line, args, 2)?;
+                settings.shutter = Some(Shutter {
+                    open: v[0],
+                    close: v[1],
+                });
+            }
+            "projection" => {
+                settings.projection = match args {
+                    ["equidistant"] => FisheyeProjection::Equidistant,
+                    ["stereographic"] => FisheyeProjection::Stereographic,
+                    _ => {
+                        return Err(ParseError {
+                            line,
+                            message: "projection must be equidistant or stereographic".to_string(),
+                        })
+                    }
+                }
+            }
+            _ => {
+                return Err(ParseError {
+                    line,
+                    message: format!("unknown camera parameter '{}'", keyword),
+                })
+            }
+        }
+    }
+    if let Some(settings) = &camera {
+        if settings.lens.is_some() && !settings.elements.is_empty() {
+            return Err(ParseError {
+                line: camera_line,
+                message: "give a lens file or element lines, not both".to_string(),
+            });
+        }
+        if settings.kind == "realistic" && settings.elements.is_empty() && settings.lens.is_none() {
+            return Err(ParseError {
+                line: camera_line,
+                message: "a realistic camera needs lens elements".to_string(),
+            });
+        }
+        let pinhole = settings.aperture <= 0.0
+            && settings.f_number.is_none()
+            && settings.keys.aperture.is_empty();
+        if settings.kind == "thin_lens" && settings.exposure.is_some() && pinhole {
+            return Err(ParseError {
+                line: camera_line,
+                message: "an exposure needs an aperture or an f_stop".to_string(),
+            });
+        }
+        let (has_handles, lacks_handles) = settings.keys.handles();
+        let bezier = settings.interpolation == Interpolation::Bezier;
+        if bezier && lacks_handles {
+            return Err(ParseError {
+                line: camera_line,
+                message: "bezier keys need in and out handles".to_string(),
+            });
+        }
+        if !bezier && has_handles {
+            return Err(ParseError {
+                line: camera_line,
+                message: "only bezier keys take handles".to_string(),
+            });
+        }
+        if settings.kind != "thin_lens" && !settings.keys.is_empty() {
+            return Err(ParseError {
+                line: camera_line,
+                message: "only thin lens cameras can be animated".to_string(),
+            });
+        }
+        if let Some(stereo) = settings.stereo {
+            let kind = match stereo {
+                Stereo::Rig(_) => "thin_lens",
+                Stereo::Ods => "equirectangular",
+            };
+            if settings.kind != kind {
+                return Err(ParseError {
+                    line: camera_line,
+                    message: format!("stereo {} needs a camera of type {}", stereo.name(), kind),
+                });
+            }
+        }
+    }
+    Ok(SceneFile { camera })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::float::tolerance;
+    use crate::hittable::HittableList;
+    use approx::assert_relative_eq;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn parses_camera() {
+        let text = "
+            # a panorama
+            camera equirectangular
+            position 0 1 0   # eye height
+            look_at 0 1 -1
+            shutter 0 0.5
+        ";
+        let scene = parse(text).unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+        let camera = scene
+            .camera(2.0, &HittableList::new(), 0.0, 0.0)
+            .unwrap()
+            .unwrap();
+        let r = camera.get_ray(0.5, 0.5, &mut rng).unwrap();
+        assert_eq!(r.pos, Vec3(0.0, 1.0, 0.0));
+        assert_relative_eq!(r.dir.z(), -1.0, epsilon = 1e-12);
+        assert!(r.time <= 0.5);
+    }
+
+    #[test]
+    fn every_camera_type_builds() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for kind in &CAMERA_TYPES {
+            let text = format!(
+                "camera {}\nprojection stereographic\nelement 50 3 1.5 20\nelement -50 0 1 20\n",
+                kind
+            );
+            let camera = parse(&text)
+                .unwrap()
+                .camera(1.5, &HittableList::new(), 0.0, 0.0)
+                .unwrap()
+                .unwrap();
+            // a realistic camera may block a few rays
+            assert!((0..10).any(|_| camera.get_ray(0.5, 0.5, &mut rng).is_some()));
+        }
+        assert!(parse("").unwrap().camera.is_none());
+    }
+
+    #[test]
+    fn thin_lens_settings() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let world = HittableList::new();
+        let camera = |text: &str| {
+            parse(text)
+                .unwrap()
+                .camera(2.0, &world, 0.0, 0.0)
+                .unwrap()
+                .unwrap()
+        };
+        // a vertical field of view of 90 degrees
+        let c = camera("camera thin_lens\nvfov 90\nfocus_distance 3");
+        let r = c.get_ray(0.5, 1.0, &mut rng).unwrap();
+        assert_relative_eq!(r.dir.y(), 3.0, epsilon = 1e-12);
+        assert_relative_eq!(r.dir.z(), -3.0, epsilon = 1e-12);
+        // the old horizontal fov with focus as a fraction of the look-at
+        // distance, given before the look-at point
+        let c = camera("camera thin_lens\nfov 90\nfocus 0.5\nlook_at 0 0 -4");
+        let r = c.get_ray(1.0, 0.5, &mut rng).unwrap();
+        assert_relative_eq!(r.dir.x(), 2.0, epsilon = 1e-12);
+        assert_relative_eq!(r.dir.z(), -2.0, epsilon = 1e-12);
+        let c = camera("camera thin_lens\nfocal_length 18\nsensor 36 24");
+        let r = c.get_ray(1.0, 0.5, &mut rng).unwrap();
+        assert_relative_eq!(r.dir.x(), -r.dir.z(), epsilon = 1e-12);
+        // exposure at f/1.8, 1/50 s and ISO 400 with a 50 mm lens; rays
+        // through the edge of the lens are slightly vignetted
+        let c = camera(
+            "camera thin_lens\nfocal_length 50\nf_stop 1.8\nblades 7 15\nexposure 0.02 400\nvignetting",
+        );
+        let r = c.get_ray(0.5, 0.5, &mut rng).unwrap();
+        assert_relative_eq!(c.weight(&r), 0.08 / (1.8 * 1.8), max_relative = 1e-3);
+    }
+
+    #[test]
+    fn stereo_settings() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let world = HittableList::new();
+        let text =
+            "camera thin_lens\nstereo toe_in\nlayout side_by_side\ninterocular 0.5\nanaglyph";
+        let scene = parse(text).unwrap();
+        assert_eq!(scene.anaglyph(), Some(StereoLayout::SideBySide));
+        let camera = scene.camera(2.0, &world, 0.0, 0.0).unwrap().unwrap();
+        let left = camera.get_ray(0.25, 0.5, &mut rng).unwrap();
+        let right = camera.get_ray(0.75, 0.5, &mut rng).unwrap();
+        assert_relative_eq!(left.pos.x(), -0.25);
+        assert_relative_eq!(right.pos.x(), 0.25);
+        // both look at the point a unit ahead
+        assert_relative_eq!(left.dir.x(), -left.dir.z() * 0.25, epsilon = 1e-12);
+        let scene = parse("camera equirectangular\nstereo ods").unwrap();
+        assert!(scene.camera(1.0, &world, 0.0, 0.0).unwrap().is_some());
+        assert_eq!(scene.anaglyph(), None);
+        assert_eq!(
+            parse("camera fisheye\nstereo ods").err().unwrap().message,
+            "stereo ods needs a camera of type equirectangular"
+        );
+    }
+
+    #[test]
+    fn animated_camera() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let world = HittableList::new();
+        let text = "
+            camera thin_lens
+            key 0 position 0 0 0
+            key 2 position 4 0 0
+            key 0 look_at 0 0 -1
+            key 2 look_at 4 0 -1
+            key 0 fov 60
+            key 1 fov 90
+        ";
+        let scene = parse(text).unwrap();
+        let camera = scene.camera(1.0, &world, 1.0, 0.0).unwrap().unwrap();
+        let r = camera.get_ray(1.0, 0.5, &mut rng).unwrap();
+        assert_relative_eq!(r.pos.x(), 2.0);
+        assert_relative_eq!(r.dir.x(), -r.dir.z(), epsilon = 1e-12);
+        // before the first key and after the last, the camera holds still
+        let r = scene
+            .camera(1.0, &world, 5.0, 0.0)
+            .unwrap()
+            .unwrap()
+            .get_ray(0.5, 0.5, &mut rng)
+            .unwrap();
+        assert_relative_eq!(r.pos.x(), 4.0);
+        let err = parse("camera fisheye\nkey 0 fov 10").err().unwrap();
+        assert_eq!(err.message, "only thin lens cameras can be animated");
+        assert_eq!(
+            parse("camera thin_lens\nkey 0 up 0 1 0")
+                .err()
+                .unwrap()
+                .line,
+            2
+        );
+        let err = |text: &str| parse(text).err().unwrap();
+        assert_eq!(err("camera thin_lens\nkey nan fov 10").line, 2);
+        assert_eq!(err("camera thin_lens\nkey inf fov 10").line, 2);
+        assert_eq!(
+            err("camera thin_lens\nkey 1 fov 10\nkey 1 fov 20").message,
+            "there is already a key at time 1"
+        );
+    }
+
+    #[test]
+    fn bezier_keys_take_handles() {
+        let world = HittableList::new();
+        let text = "
+            camera thin_lens
+            interpolation bezier
+            # value, in handle, out handle
+            key 0 fov 60  60  60
+            key 1 fov 90  90  90
+        ";
+        let scene = parse(text).unwrap();
+        let settings = scene.camera.as_ref().unwrap();
+        let fov = |time| {
+            let builder = settings.thin_lens(1.0, time, Shutter::default());
+            match builder.fov {
+                FieldOfView::Horizontal(angle) => angle,
+                fov => panic!("unexpected field of view {:?}", fov),
+            }
+        };
+        assert_relative_eq!(fov(0.5), 75.0, epsilon = tolerance(1e-9));
+        // handles on the keys themselves ease in and out of them
+        assert!(fov(0.1) > 60.0 && fov(0.1) < 63.0);
+        assert!(scene.camera(1.0, &world, 0.5, 0.0).unwrap().is_some());
+        let err = |text: &str| parse(text).err().unwrap().message;
+        assert_eq!(
+            err("camera thin_lens\ninterpolation bezier\nkey 0 fov 60"),
+            "bezier keys need in and out handles"
+        );
+        assert_eq!(
+            err("camera thin_lens\nkey 0 fov 60 60 60"),
+            "only bezier keys take handles"
+        );
+        assert_eq!(
+            err("camera thin_lens\nkey 0 position 0 0 0 1"),
+            "expected 3 numbers, found 4"
+        );
+    }
+
+    #[test]
+    fn shutter_defaults_to_frame_exposure() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let world = HittableList::new();
+        let mut times = |text: &str| {
+            let camera = parse(text)
+                .unwrap()
+                .camera(1.0, &world, 2.0, 0.25)
+                .unwrap()
+                .unwrap();
+            (0..100)
+                .map(|_| camera.get_ray(0.5, 0.5, &mut rng).unwrap().time)
+                .fold((Float::MAX, Float::MIN), |(lo, hi), t| {
+                    (lo.min(t), hi.max(t))
+                })
+        };
+        let (open, close) = times("camera fisheye");
+        assert!(open >= 2.0 && close <= 2.25 && close - open > 0.2);
+        // a shutter in the file wins, relative to the frame's time
+        let (open, close) = times("camera thin_lens\nshutter 0 0");
+        assert_eq!((open, close), (2.0, 2.0));
+    }
+
+    #[test]
+    fn parses_lens_prescription() {
+        let text = "
+            # radius thickness ior aperture
+            29.475  3.76   1.67  25.2
+            0       4.5    0     17.1   # stop
+        ";
+        let elements = parse_lens(text).unwrap();
+        assert_eq!(elements.len(), 2);
+        assert_eq!(elements[0].ior, 1.67);
+        assert_eq!(elements[1].radius, 0.0);
+        assert_eq!(parse_lens("1 2 3").err().unwrap().line, 1);
+    }
+
+    #[test]
+    fn loads_lens_file() {
+        let dir = std::env::temp_dir().join(format!("raytracer-lens-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, text: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, text).unwrap();
+            path.to_string_lossy().into_owned()
+        };
+        write("lens.txt", "50 3 1.5 20\n-50 0 1 20\n");
+        let scene = load(&write("scene.txt", "camera realistic\nlens lens.txt")).unwrap();
+        let world = HittableList::new();
+        assert!(scene.camera(1.5, &world, 0.0, 0.0).unwrap().is_some());
+        // failures in either file name the file and line
+        let missing = load(&write("missing.txt", "camera realistic\nlens none.txt"));
+        match missing.err().unwrap() {
+            Error::SceneParse { path, error } => {
+                assert!(path.ends_with("missing.txt"));
+                assert_eq!(error.line, 2);
+            }
+            e => panic!("unexpected error {}", e),
+        }
+        write("bad.txt", "\n50 3 1.5\n");
+        let bad = load(&write("uses_bad.txt", "camera realistic\nlens bad.txt"));
+        match bad.err().unwrap() {
+            Error::SceneParse { path, error } => {
+                assert!(path.ends_with("bad.txt"));
+                assert_eq!(error.line, 2);
+            }
+            e => panic!("unexpected error {}", e),
+        }
+        assert!(parse("camera realistic\nlens a.txt\nelement 50 3 1.5 20").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reports_errors_with_line() {
+        let err = |text: &str| parse(text).err().unwrap();
+        assert_eq!(err("camera pinhole").line, 1);
+        assert_eq!(err("\nfov 30").message, "unknown statement 'fov'");
+        assert_eq!(err("camera fisheye\nfov wide").line, 2);
+        assert_eq!(
+            err("camera fisheye\nposition 1 2").message,
+            "expected 3 numbers, found 2"
+        );
+        assert_eq!(err("camera fisheye\nzoom 2").line, 2);
+        assert_eq!(err("camera thin_lens\nblades 2.5 0").line, 2);
+        assert_eq!(err("\ncamera realistic\nfocus 1").line, 2);
+        assert_eq!(
+            err("camera thin_lens\nexposure 0.02 400").message,
+            "an exposure needs an aperture or an f_stop"
+        );
+    }
+}
@@ -0,0 +1,617 @@
+use crate::aabb::Aabb;
+use crate::float::{gamma, Float};
+use crate::hittable::*;
+use crate::materials::Material;
+use crate::ray::*;
+use crate::vec3::*;
+
+// Flat shapes are padded by this much so their bounding boxes have volume.
+const FLAT_PADDING: Float = 1e-4;
+
+// Intersects the ray with the plane through `point` with unit `normal`.
+fn hit_plane(point: Vec3, normal: Vec3, ray: &Ray, t_min: Float, t_max: Float) -> Option<Float> {
+    let denom = normal.dot(ray.dir);
+    if denom.abs() < 1e-12 {
+        return None;
+    }
+    let t = (point - ray.pos).dot(normal) / denom;
+    if t < t_min || t > t_max {
+        return None;
+    }
+    Some(t)
+}
+
+/// An infinite plane. UVs are the planar coordinates of the hit point along
+/// a tangent frame of the normal, so they are unbounded.
+pub struct Plane {
+    pub point: Vec3,
+    pub normal: Vec3,
+    pub material: Box<dyn Material + Send + Sync>,
+}
+
+impl Hittable for Plane {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        hit_infinite_plane(
+            self.point,
+            self.normal,
+            HitMaterial::Dyn(&*self.material),
+            ray,
+            t_min,
+            t_max,
+        )
+    }
+}
+
+// The hit on an infinite plane, shared by `Plane` and compact scenes.
+pub(crate) fn hit_infinite_plane<'a>(
+    point: Vec3,
+    normal: Vec3,
+    material: HitMaterial<'a>,
+    ray: &Ray,
+    t_min: Float,
+    t_max: Float,
+) -> Option<HitRecord<'a>> {
+    let normal = normal.normalized();
+    let t = hit_plane(point, normal, ray, t_min, t_max)?;
+    let (tangent, bitangent) = normal.orthonormal_basis();
+    let offset = ray.point_at_t(t) - point;
+    Some(
+        HitRecord::new(ray, t, normal, material)
+            .uv(offset.dot(tangent), offset.dot(bitangent))
+            .tangents(tangent, bitangent),
+    )
+}
+
+/// A parallelogram spanned by the edges `u` and `v` from the corner `q`.
+/// The normal is u x v, and the UVs run from 0 to 1 along the two edges.
+pub struct Quad {
+    pub q: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub material: Box<dyn Material + Send + Sync>,
+}
+
+impl Hittable for Quad {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        hit_quad(
+            self.q,
+            self.u,
+            self.v,
+            HitMaterial::Dyn(&*self.material),
+            ray,
+            t_min,
+            t_max,
+        )
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(quad_bounds(self.q, self.u, self.v))
+    }
+}
+
+// The hit on a parallelogram, shared by `Quad` and compact scenes.
+pub(crate) fn hit_quad<'a>(
+    q: Vec3,
+    u: Vec3,
+    v: Vec3,
+    material: HitMaterial<'a>,
+    ray: &Ray,
+    t_min: Float,
+    t_max: Float,
+) -> Option<HitRecord<'a>> {
+    let n = u.cross(v);
+    let normal = n.normalized();
+    let t = hit_plane(q, normal, ray, t_min, t_max)?;
+    let w = n / n.dot(n);
+    let offset = ray.point_at_t(t) - q;
+    let alpha = w.dot(offset.cross(v));
+    let beta = w.dot(u.cross(offset));
+    if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+        return None;
+    }
+    Some(
+        HitRecord::new(ray, t, normal, material)
+            .uv(alpha, beta)
+            .tangents(u, v),
+    )
+}
+
+pub(crate) fn quad_bounds(q: Vec3, u: Vec3, v: Vec3) -> Aabb {
+    Aabb::from_points(&[q, q + u, q + v, q + u + v]).padded(FLAT_PADDING)
+}
+
+// Shared by disks and annuli: hits the plane and keeps points whose
+// distance from the center is within [inner, outer]. v runs from 0 at the
+// inner radius to 1 at the outer one.
+#[allow(clippy::too_many_arguments)]
+fn hit_ring<'a>(
+    center: Vec3,
+    normal: Vec3,
+    inner: Float,
+    outer: Float,
+    material: HitMaterial<'a>,
+    ray: &Ray,
+    t_min: Float,
+    t_max: Float,
+) -> Option<HitRecord<'a>> {
+    let normal = normal.normalized();
+    let t = hit_plane(center, normal, ray, t_min, t_max)?;
+    let offset = ray.point_at_t(t) - center;
+    let dist = offset.length();
+    if dist > outer || dist < inner {
+        return None;
+    }
+    let (tangent, bitangent) = normal.orthonormal_basis();
+    let phi = offset.dot(bitangent).atan2(offset.dot(tangent));
+    let phi = if phi < 0.0 {
+        phi + 2.0 * crate::float::consts::PI
+    } else {
+        phi
+    };
+    let hit = HitRecord::new(ray, t, normal, material).uv(
+        phi / (2.0 * crate::float::consts::PI),
+        (dist - inner) / (outer - inner),
+    );
+    // the center of a disk has no tangents of its own
+    if dist == 0.0 {
+        return Some(hit);
+    }
+    Some(hit.tangents(
+        2.0 * crate::float::consts::PI * normal.cross(offset),
+        (outer - inner) / dist * offset,
+    ))
+}
+
+// extent of a disk along each axis is radius * sin(angle to normal)
+fn ring_bounds(center: Vec3, normal: Vec3, radius: Float) -> Aabb {
+    let n = normal.normalized();
+    let extent = |c: Float| radius * (1.0 - c * c).max(0.0).sqrt();
+    let e = Vec3(extent(n.x()), extent(n.y()), extent(n.z()));
+    Aabb::new(center - e, center + e).padded(FLAT_PADDING)
+}
+
+/// A flat disk. u is the angle around the normal and v the distance from
+/// the center, both scaled to [0, 1].
+pub struct Disk {
+    pub center: Vec3,
+    pub normal: Vec3,
+    pub radius: Float,
+    pub material: Box<dyn Material + Send + Sync>,
+}
+
+impl Hittable for Disk {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        hit_ring(
+            self.center,
+            self.normal,
+            0.0,
+            self.radius,
+            HitMaterial::Dyn(&*self.material),
+            ray,
+            t_min,
+            t_max,
+        )
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(ring_bounds(self.center, self.normal, self.radius))
+    }
+}
+
+/// A disk with a hole in the middle. Same UVs as the disk, except that v
+/// starts at the inner radius.
+pub struct Annulus {
+    pub center: Vec3,
+    pub normal: Vec3,
+    pub inner_radius: Float,
+    pub outer_radius: Float,
+    pub material: Box<dyn Material + Send + Sync>,
+}
+
+impl Hittable for Annulus {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        hit_ring(
+            self.center,
+            self.normal,
+            self.inner_radius,
+            self.outer_radius,
+            HitMaterial::Dyn(&*self.material),
+            ray,
+            t_min,
+            t_max,
+        )
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(ring_bounds(self.center, self.normal, self.outer_radius))
+    }
+}
+
+/// A triangle with corners `a`, `b` and `c`, intersected with the
+/// Moller-Trumbore test. The normal is (b - a) x (c - a) and the UVs are the
+/// barycentric coordinates of b and c.
+pub struct Triangle {
+    pub a: Vec3,
+    pub b: Vec3,
+    pub c: Vec3,
+    pub material: Box<dyn Material + Send + Sync>,
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        let (e1, e2) = (self.b - self.a, self.c - self.a);
+        hit_triangle(
+            self.a,
+            e1,
+            e2,
+            HitMaterial::Dyn(&*self.material),
+            ray,
+            t_min,
+            t_max,
+        )
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::from_points(&[self.a, self.b, self.c]).padded(FLAT_PADDING))
+    }
+}
+
+// The hit on the triangle at `a` with edges `e1` and `e2`, shared by
+// `Triangle` and packed triangles.
+pub(crate) fn hit_triangle<'a>(
+    a: Vec3,
+    e1: Vec3,
+    e2: Vec3,
+    material: HitMaterial<'a>,
+    ray: &Ray,
+    t_min: Float,
+    t_max: Float,
+) -> Option<HitRecord<'a>> {
+    let pvec = ray.dir.cross(e2);
+    let det = e1.dot(pvec);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    let tvec = ray.pos - a;
+    let u = tvec.dot(pvec) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let qvec = tvec.cross(e1);
+    let v = ray.dir.dot(qvec) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = e2.dot(qvec) * inv_det;
+    if t < t_min || t > t_max {
+        return None;
+    }
+    // from the barycentrics, which are more accurate than t
+    let (pu, pv) = (u * e1, v * e2);
+    Some(
+        HitRecord::new(ray, t, e1.cross(e2).normalized(), material)
+            .point_with_error(a + pu + pv, gamma(7) * (a.abs() + pu.abs() + pv.abs()))
+            .uv(u, v)
+            .tangents(e1, e2),
+    )
+}
+
+pub(crate) fn triangle_bounds(a: Vec3, e1: Vec3, e2: Vec3) -> Aabb {
+    Aabb::from_points(&[a, a + e1, a + e2]).padded(FLAT_PADDING)
+}
+
+/// An axis-aligned box, intersected with a slab test. The normal points out
+/// of the face that was hit and the UVs span that face from 0 to 1.
+pub struct Cuboid {
+    pub min: Vec3,
+    pub max: Vec3,
+    pub material: Box<dyn Material + Send + Sync>,
+}
+
+impl Hittable for Cuboid {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        let mut t_enter = Float::NEG_INFINITY;
+        let mut t_exit = Float::INFINITY;
+        let mut enter_axis = 0;
+        let mut exit_axis = 0;
+        for axis in 0..3 {
+            // parallel to the slab, as in `Aabb::hit`
+            if ray.dir[axis] == 0.0 {
+                if ray.pos[axis] < self.min[axis] || ray.pos[axis] > self.max[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let inv_d = 1.0 / ray.dir[axis];
+            let t0 = (self.min[axis] - ray.pos[axis]) * inv_d;
+            let t1 = (self.max[axis] - ray.pos[axis]) * inv_d;
+            let (near, far) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+            if near > t_enter {
+                t_enter = near;
+                enter_axis = axis;
+            }
+            if far < t_exit {
+                t_exit = far;
+                exit_axis = axis;
+            }
+        }
+        if t_enter > t_exit {
+            return None;
+        }
+        let (t, axis) = if t_enter >= t_min && t_enter <= t_max {
+            (t_enter, enter_axis)
+        } else if t_exit >= t_min && t_exit <= t_max {
+            (t_exit, exit_axis)
+        } else {
+            return None;
+        };
+
+        let p = ray.point_at_t(t);
+        let center = 0.5 * (self.min + self.max);
+        let sign = if p[axis] > center[axis] { 1.0 } else { -1.0 };
+        let mut normal = Vec3(0.0, 0.0, 0.0);
+        match axis {
+            0 => normal.0 = sign,
+            1 => normal.1 = sign,
+            _ => normal.2 = sign,
+        }
+        let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+        let extent = self.max - self.min;
+        let along = |axis: usize| match axis {
+            0 => Vec3(extent.0, 0.0, 0.0),
+            1 => Vec3(0.0, extent.1, 0.0),
+            _ => Vec3(0.0, 0.0, extent.2),
+        };
+        // faces are numbered -x, +x, -y, +y, -z, +z
+        let face = 2 * axis + (sign > 0.0) as usize;
+        Some(
+            HitRecord::new(ray, t, normal, HitMaterial::Dyn(&*self.material))
+                .uv(
+                    (p[a] - self.min[a]) / extent[a],
+                    (p[b] - self.min[b]) / extent[b],
+                )
+                .tangents(along(a), along(b))
+                .primitive_id(face),
+        )
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.min, self.max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    struct TestMaterial {}
+    impl Material for TestMaterial {}
+
+    fn ray(pos: Vec3, dir: Vec3) -> Ray {
+        Ray::new(pos, dir)
+    }
+
+    #[test]
+    fn triangle_is_hittable() {
+        let t = Triangle {
+            a: Vec3(0.0, 0.0, -1.0),
+            b: Vec3(2.0, 0.0, -1.0),
+            c: Vec3(0.0, 2.0, -1.0),
+            material: Box::new(TestMaterial {}),
+        };
+        let h = t
+            .hit(
+                &ray(Vec3(0.5, 1.0, 0.0), Vec3(0.0, 0.0, -1.0)),
+                0.0,
+                Float::MAX,
+            )
+            .unwrap();
+        assert_relative_eq!(h.t, 1.0);
+        assert_eq!(h.normal, Vec3(0.0, 0.0, 1.0));
+        assert!(h.front_face);
+        assert_relative_eq!(h.u, 0.25);
+        assert_relative_eq!(h.v, 0.5);
+        assert_eq!(h.point, t.a + h.u * h.dpdu + h.v * h.dpdv);
+        // past the hypotenuse
+        let outside = ray(Vec3(1.5, 1.0, 0.0), Vec3(0.0, 0.0, -1.0));
+        assert!(t.hit(&outside, 0.0, Float::MAX).is_none());
+        let parallel = ray(Vec3(0.5, 1.0, -1.0), Vec3(1.0, 0.0, 0.0));
+        assert!(t.hit(&parallel, 0.0, Float::MAX).is_none());
+        let b = t.bounding_box().unwrap();
+        assert_eq!((b.min.x(), b.max.y()), (0.0, 2.0));
+    }
+
+    #[test]
+    fn plane_is_hittable() {
+        let p = Plane {
+            point: Vec3(0.0, -1.0, 0.0),
+            normal: Vec3(0.0, 2.0, 0.0),
+            material: Box::new(TestMaterial {}),
+        };
+        let down = ray(Vec3(0.0, 1.0, 0.0), Vec3(0.0, -1.0, 0.0));
+        let h = p.hit(&down, 0.0, Float::MAX).unwrap();
+        assert_relative_eq!(h.t, 2.0);
+        assert_eq!(h.normal, Vec3(0.0, 1.0, 0.0));
+        let parallel = ray(Vec3(0.0, 1.0, 0.0), Vec3(1.0, 0.0, 0.0));
+        assert!(p.hit(&parallel, 0.0, Float::MAX).is_none());
+        assert!(p.bounding_box().is_none());
+    }
+
+    #[test]
+    fn quad_uv_and_bounds() {
+        let q = Quad {
+            q: Vec3(0.0, 0.0, 0.0),
+            u: Vec3(2.0, 0.0, 0.0),
+            v: Vec3(0.0, 4.0, 0.0),
+            material: Box::new(TestMaterial {}),
+        };
+        let h = q
+            .hit(
+                &ray(Vec3(0.5, 3.0, 5.0), Vec3(0.0, 0.0, -1.0)),
+                0.0,
+                Float::MAX,
+            )
+            .unwrap();
+        assert_relative_eq!(h.t, 5.0);
+        assert_eq!(h.normal, Vec3(0.0, 0.0, 1.0));
+        assert_relative_eq!(h.u, 0.25);
+        assert_relative_eq!(h.v, 0.75);
+        let outside = ray(Vec3(2.5, 3.0, 5.0), Vec3(0.0, 0.0, -1.0));
+        assert!(q.hit(&outside, 0.0, Float::MAX).is_none());
+        let b = q.bounding_box().unwrap();
+        assert_relative_eq!(b.max.x(), 2.0);
+        assert_relative_eq!(b.max.y(), 4.0);
+        assert!(b.max.z() > b.min.z());
+    }
+
+    #[test]
+    fn disk_uv_and_bounds() {
+        let d = Disk {
+            center: Vec3(0.0, 0.0, 0.0),
+            normal: Vec3(0.0, 1.0, 0.0),
+            radius: 2.0,
+            material: Box::new(TestMaterial {}),
+        };
+        let h = d
+            .hit(
+                &ray(Vec3(1.0, 1.0, 0.0), Vec3(0.0, -1.0, 0.0)),
+                0.0,
+                Float::MAX,
+            )
+            .unwrap();
+        assert_relative_eq!(h.t, 1.0);
+        assert_relative_eq!(h.v, 0.5);
+        assert!((0.0..=1.0).contains(&h.u));
+        // u grows around the normal and v outwards
+        let turn = 2.0 * crate::float::consts::PI;
+        assert_relative_eq!((h.dpdu - Vec3(0.0, 0.0, -turn)).length(), 0.0);
+        assert_eq!(h.dpdv, Vec3(2.0, 0.0, 0.0));
+        let outside = ray(Vec3(2.5, 1.0, 0.0), Vec3(0.0, -1.0, 0.0));
+        assert!(d.hit(&outside, 0.0, Float::MAX).is_none());
+        let b = d.bounding_box().unwrap();
+        assert_relative_eq!(b.max.x(), 2.0);
+        assert_relative_eq!(b.max.z(), 2.0);
+        assert!(b.max.y() < 0.001);
+    }
+
+    #[test]
+    fn annulus_has_a_hole() {
+        let a = Annulus {
+            center: Vec3(0.0, 0.0, 0.0),
+            normal: Vec3(0.0, 0.0, 1.0),
+            inner_radius: 1.0,
+            outer_radius: 2.0,
+            material: Box::new(TestMaterial {}),
+        };
+        let through_hole = ray(Vec3(0.5, 0.0, 1.0), Vec3(0.0, 0.0, -1.0));
+        assert!(a.hit(&through_hole, 0.0, Float::MAX).is_none());
+        let h = a
+            .hit(
+                &ray(Vec3(0.0, 1.5, 1.0), Vec3(0.0, 0.0, -1.0)),
+                0.0,
+                Float::MAX,
+            )
+            .unwrap();
+        assert_relative_eq!(h.t, 1.0);
+        assert_relative_eq!(h.v, 0.5);
+        assert_relative_eq!(a.bounding_box().unwrap().max.x(), 2.0);
+    }
+
+    #[test]
+    fn cuboid_faces() {
+        let c = Cuboid {
+            min: Vec3(-1.0, -1.0, -1.0),
+            max: Vec3(1.0, 1.0, 1.0),
+            material: Box::new(TestMaterial {}),
+        };
+        let h = c
+            .hit(
+                &ray(Vec3(0.5, 0.0, 5.0), Vec3(0.0, 0.0, -1.0)),
+                0.0,
+                Float::MAX,
+            )
+            .unwrap();
+        assert_relative_eq!(h.t, 4.0);
+        assert_eq!(h.normal, Vec3(0.0, 0.0, 1.0));
+        assert_relative_eq!(h.u, 0.75);
+        assert_relative_eq!(h.v, 0.5);
+        assert_eq!(h.primitive_id, 5);
+        assert_eq!((h.dpdu, h.dpdv), (Vec3(2.0, 0.0, 0.0), Vec3(0.0, 2.0, 0.0)));
+        assert!(h.front_face);
+        // from the inside we hit the far face, with an outward normal
+        let h = c
+            .hit(
+                &ray(Vec3(0.0, 0.0, 0.0), Vec3(-1.0, 0.0, 0.0)),
+                0.0,
+                Float::MAX,
+            )
+            .unwrap();
+        assert_relative_eq!(h.t, 1.0);
+        assert_eq!(h.normal, Vec3(-1.0, 0.0, 0.0));
+        assert_eq!(h.primitive_id, 0);
+        assert!(!h.front_face);
+        let misses = ray(Vec3(0.0, 2.0, 5.0), Vec3(0.0, 0.0, -1.0));
+        assert!(c.hit(&misses, 0.0, Float::MAX).is_none());
+        // a ray lying on the plane of a face hits the box, as its bounds do
+        let grazing = ray(Vec3(0.0, 1.0, 5.0), Vec3(0.0, 0.0, -1.0));
+        assert!(c.bounding_box().unwrap().hit(&grazing, 0.0, Float::MAX));
+        let h = c.hit(&grazing, 0.0, Float::MAX).unwrap();
+        assert_relative_eq!(h.t, 4.0);
+        assert_eq!(h.normal, Vec3(0.0, 0.0, 1.0));
+        assert_eq!(
+            c.bounding_box().unwrap(),
+            Aabb::new(Vec3(-1.0, -1.0, -1.0), Vec3(1.0, 1.0, 1.0))
+        );
+    }
+
+    fn random_dir(rng: &mut StdRng) -> Vec3 {
+        Vec3(
+            rng.gen::<Float>() - 0.5,
+            rng.gen::<Float>() - 0.5,
+            rng.gen::<Float>() - 0.5,
+        )
+        .normalized()
+    }
+
+    #[test]
+    fn spawned_rays_clear_flat_surfaces() {
+        // tilted, and far from the origin, where the rounding errors are large
+        let center = Vec3(1000.0, -2000.0, 500.0);
+        let (u, v) = (Vec3(2.0, 0.5, -1.0), Vec3(0.3, 1.0, 2.0));
+        let shapes: Vec<Box<dyn Hittable>> = vec![
+            Box::new(Triangle {
+                a: center - u - v,
+                b: center + 2.0 * u,
+                c: center + 2.0 * v,
+                material: Box::new(TestMaterial {}),
+            }),
+            Box::new(Quad {
+                q: center - u - v,
+                u: 2.0 * u,
+                v: 2.0 * v,
+                material: Box::new(TestMaterial {}),
+            }),
+            Box::new(Plane {
+                point: center,
+                normal: u.cross(v).normalized(),
+                material: Box::new(TestMaterial {}),
+            }),
+        ];
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut hits = 0;
+        for shape in &shapes {
+            for _ in 0..2000 {
+                let pos = center + 10.0 * random_dir(&mut rng);
+                let target = center + rng.gen::<Float>() * u + rng.gen::<Float>() * v;
+                let hit = match shape.hit(&ray(pos, target - pos), 0.0, Float::MAX) {
+                    Some(hit) => hit,
+                    None => continue,
+                };
+                hits += 1;
+                let dir = random_dir(&mut rng);
+                let spawned = ray(hit.spawn_point(dir), dir);
+                assert!(shape.hit(&spawned, 0.0, Float::MAX).is_none());
+            }
+        }
+        assert!(hits > 5000);
+    }
+}
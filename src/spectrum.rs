@@ -0,0 +1,423 @@
+use crate::float::Float;
+use crate::mat4::Mat4;
+use crate::vec3::*;
+use std::ops;
+use std::sync::OnceLock;
+
+/// The visible range that spectral rendering samples wavelengths from, in
+/// nanometers.
+pub const LAMBDA_MIN: Float = 380.0;
+pub const LAMBDA_MAX: Float = 720.0;
+/// wavelengths carried by each path
+pub const N_WAVELENGTHS: usize = 4;
+
+/// The wavelengths of a path, chosen with hero wavelength sampling
+/// (Wilkie et al. 2014): the first one is uniform over the visible range
+/// and the others are evenly spaced from it, wrapping around at the end of
+/// the range. Each is uniformly distributed on its own.
+#[derive(Debug, Clone, Copy)]
+pub struct SampledWavelengths {
+    pub lambda: [Float; N_WAVELENGTHS],
+}
+
+impl SampledWavelengths {
+    /// `u` is uniform in [0, 1)
+    pub fn sample(u: Float) -> Self {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let hero = LAMBDA_MIN + u * range;
+        let mut lambda = [hero; N_WAVELENGTHS];
+        for (i, l) in lambda.iter_mut().enumerate().skip(1) {
+            let shifted = hero + i as Float * range / N_WAVELENGTHS as Float;
+            *l = if shifted > LAMBDA_MAX {
+                shifted - range
+            } else {
+                shifted
+            };
+        }
+        SampledWavelengths { lambda }
+    }
+
+    pub fn pdf(&self) -> Float {
+        1.0 / (LAMBDA_MAX - LAMBDA_MIN)
+    }
+}
+
+/// Spectral values at the wavelengths of a `SampledWavelengths`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampledSpectrum(pub [Float; N_WAVELENGTHS]);
+
+impl SampledSpectrum {
+    pub fn constant(value: Float) -> Self {
+        SampledSpectrum([value; N_WAVELENGTHS])
+    }
+
+    pub fn is_black(&self) -> bool {
+        self.0.iter().all(|v| *v == 0.0)
+    }
+
+    /// Keeps only the hero wavelength, for paths that could only follow one
+    /// wavelength; it stands in for all of them.
+    pub fn hero_only(&self) -> Self {
+        let mut result = SampledSpectrum::constant(0.0);
+        result.0[0] = self.0[0] * N_WAVELENGTHS as Float;
+        result
+    }
+}
+
+impl ops::Add<SampledSpectrum> for SampledSpectrum {
+    type Output = SampledSpectrum;
+    fn add(self, rhs: SampledSpectrum) -> SampledSpectrum {
+        let mut result = self.0;
+        for (r, v) in result.iter_mut().zip(rhs.0.iter()) {
+            *r += v;
+        }
+        SampledSpectrum(result)
+    }
+}
+
+impl ops::Mul<SampledSpectrum> for SampledSpectrum {
+    type Output = SampledSpectrum;
+    fn mul(self, rhs: SampledSpectrum) -> SampledSpectrum {
+        let mut result = self.0;
+        for (r, v) in result.iter_mut().zip(rhs.0.iter()) {
+            *r *= v;
+        }
+        SampledSpectrum(result)
+    }
+}
+
+impl ops::Mul<Float> for SampledSpectrum {
+    type Output = SampledSpectrum;
+    fn mul(self, rhs: Float) -> SampledSpectrum {
+        let mut result = self.0;
+        for r in result.iter_mut() {
+            *r *= rhs;
+        }
+        SampledSpectrum(result)
+    }
+}
+
+// Basis spectra of Smits, "An RGB-to-Spectrum Conversion for
+// Reflectances" (1999), in ten bins evenly spanning the visible range.
+const SMITS_WHITE: [Float; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [Float; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [Float; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [Float; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [Float; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [Float; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [Float; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+// a Smits basis spectrum at `lambda`, interpolated between bin centers
+fn smits_basis(basis: &[Float; 10], lambda: Float) -> Float {
+    let bin_width = (LAMBDA_MAX - LAMBDA_MIN) / 10.0;
+    let x = ((lambda - LAMBDA_MIN) / bin_width - 0.5).clamp(0.0, 9.0);
+    let i = (x.floor() as usize).min(8);
+    let f = x - i as Float;
+    basis[i] * (1.0 - f) + basis[i + 1] * f
+}
+
+fn smits(rgb: Vec3, lambda: Float) -> Float {
+    let (r, g, b) = (rgb.r(), rgb.g(), rgb.b());
+    let s = |basis: &[Float; 10]| smits_basis(basis, lambda);
+    if r <= g && r <= b {
+        if g <= b {
+            r * s(&SMITS_WHITE) + (g - r) * s(&SMITS_CYAN) + (b - g) * s(&SMITS_BLUE)
+        } else {
+            r * s(&SMITS_WHITE) + (b - r) * s(&SMITS_CYAN) + (g - b) * s(&SMITS_GREEN)
+        }
+    } else if g <= r && g <= b {
+        if r <= b {
+            g * s(&SMITS_WHITE) + (r - g) * s(&SMITS_MAGENTA) + (b - r) * s(&SMITS_BLUE)
+        } else {
+            g * s(&SMITS_WHITE) + (b - g) * s(&SMITS_MAGENTA) + (r - b) * s(&SMITS_RED)
+        }
+    } else if r <= g {
+        b * s(&SMITS_WHITE) + (r - b) * s(&SMITS_YELLOW) + (g - r) * s(&SMITS_GREEN)
+    } else {
+        b * s(&SMITS_WHITE) + (g - b) * s(&SMITS_YELLOW) + (r - g) * s(&SMITS_RED)
+    }
+}
+
+/// A continuous spectrum, used for reflectances as well as emission.
+#[derive(Debug, Clone)]
+pub enum Spectrum {
+    Constant(Float),
+    /// an RGB reflectance upsampled with Smits' method, which keeps colors
+    /// up to 1 within [0, 1] at every wavelength
+    Rgb(Vec3),
+    /// An RGB light upsampled with the illuminant basis of the converter.
+    /// It is linear in the color, so it works for any brightness, and
+    /// converts back to exactly the same color.
+    Illuminant(Vec3),
+    /// (wavelength in nm, value) pairs sorted by wavelength, interpolated
+    /// linearly and held constant beyond the first and last samples
+    Tabulated(Vec<(Float, Float)>),
+}
+
+impl Spectrum {
+    /// None without samples, or with a wavelength that isn't finite
+    pub fn tabulated(mut samples: Vec<(Float, Float)>) -> Option<Self> {
+        if samples.is_empty() || samples.iter().any(|s| !s.0.is_finite()) {
+            return None;
+        }
+        samples.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Some(Spectrum::Tabulated(samples))
+    }
+
+    pub fn evaluate(&self, lambda: Float) -> Float {
+        match self {
+            Spectrum::Constant(value) => *value,
+            Spectrum::Rgb(rgb) => smits(*rgb, lambda),
+            Spectrum::Illuminant(rgb) => converter().illuminant(*rgb, lambda),
+            Spectrum::Tabulated(samples) => match samples.iter().position(|(l, _)| *l > lambda) {
+                Some(0) => samples[0].1,
+                Some(i) => {
+                    let (l0, v0) = samples[i - 1];
+                    let (l1, v1) = samples[i];
+                    v0 + (v1 - v0) * (lambda - l0) / (l1 - l0)
+                }
+                None => samples[samples.len() - 1].1,
+            },
+        }
+    }
+
+    pub fn sample(&self, wavelengths: &SampledWavelengths) -> SampledSpectrum {
+        let mut values = [0.0; N_WAVELENGTHS];
+        for (v, l) in values.iter_mut().zip(wavelengths.lambda.iter()) {
+            *v = self.evaluate(*l);
+        }
+        SampledSpectrum(values)
+    }
+}
+
+// asymmetric gaussian lobe of the color matching function fit
+fn lobe(lambda: Float, mu: Float, sigma_below: Float, sigma_above: Float) -> Float {
+    let sigma = if lambda < mu {
+        sigma_below
+    } else {
+        sigma_above
+    };
+    let x = (lambda - mu) / sigma;
+    (-0.5 * x * x).exp()
+}
+
+/// The CIE 1931 color matching functions, as fitted by Wyman, Sloan and
+/// Shirley, "Simple Analytic Approximations to the CIE XYZ Color Matching
+/// Functions" (2013).
+pub fn color_matching(lambda: Float) -> Vec3 {
+    Vec3(
+        1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+            - 0.065 * lobe(lambda, 501.1, 20.4, 26.2),
+        0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1),
+        1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8),
+    )
+}
+
+/// linear sRGB (D65 white) from CIE XYZ
+#[allow(clippy::excessive_precision)]
+pub fn xyz_to_srgb(xyz: Vec3) -> Vec3 {
+    Vec3(
+        3.2404542 * xyz.x() - 1.5371385 * xyz.y() - 0.4985314 * xyz.z(),
+        -0.9692660 * xyz.x() + 1.8760108 * xyz.y() + 0.0415560 * xyz.z(),
+        0.0556434 * xyz.x() - 0.2040259 * xyz.y() + 1.0572252 * xyz.z(),
+    )
+}
+
+/// Turns spectral path samples into colors for the film. XYZ is normalized
+/// so that a constant spectrum of 1 has Y = 1, and the output is white
+/// balanced so that it maps to RGB (1, 1, 1), matching the RGB renderer.
+pub struct SpectrumConverter {
+    y_integral: Float,
+    white: Vec3,
+    // the weights of the Smits red, green and blue spectra for the lights
+    // of pure red, green and blue, in the columns of the 3x3 part
+    illuminant_weights: Mat4,
+}
+
+// the integral of `f` against the color matching functions, in 1 nm steps,
+// which are plenty for the smooth matching functions
+fn integrate_xyz(f: impl Fn(Float) -> Float) -> Vec3 {
+    let steps = (LAMBDA_MAX - LAMBDA_MIN) as usize;
+    let mut xyz = Vec3(0.0, 0.0, 0.0);
+    for i in 0..steps {
+        let lambda = LAMBDA_MIN + i as Float + 0.5;
+        xyz += f(lambda) * color_matching(lambda);
+    }
+    xyz
+}
+
+impl SpectrumConverter {
+    pub fn new() -> Self {
+        let xyz = integrate_xyz(|_| 1.0);
+        let (y_integral, white) = (xyz.y(), xyz_to_srgb(xyz / xyz.y()));
+        // The illuminant basis mixes the Smits primaries so that each light
+        // primary converts back to exactly itself. The primaries' colors
+        // go in the columns of a matrix, and its inverse gives the mix.
+        let mut colors = Mat4::identity();
+        for (j, basis) in [&SMITS_RED, &SMITS_GREEN, &SMITS_BLUE].iter().enumerate() {
+            let xyz = integrate_xyz(|lambda| smits_basis(basis, lambda));
+            let rgb = xyz_to_srgb(xyz / y_integral) / white;
+            for i in 0..3 {
+                colors.0[i][j] = rgb[i];
+            }
+        }
+        SpectrumConverter {
+            y_integral,
+            white,
+            // the primaries are independent, so the inverse exists
+            illuminant_weights: colors.affine_inverse().unwrap_or_else(Mat4::identity),
+        }
+    }
+
+    /// the illuminant spectrum of the light `rgb` at `lambda`
+    pub fn illuminant(&self, rgb: Vec3, lambda: Float) -> Float {
+        let weights = self.illuminant_weights.transform_vector(rgb);
+        weights.r() * smits_basis(&SMITS_RED, lambda)
+            + weights.g() * smits_basis(&SMITS_GREEN, lambda)
+            + weights.b() * smits_basis(&SMITS_BLUE, lambda)
+    }
+
+    /// Monte Carlo estimate of XYZ from one path's samples
+    pub fn to_xyz(&self, spectrum: &SampledSpectrum, wavelengths: &SampledWavelengths) -> Vec3 {
+        let mut xyz = Vec3(0.0, 0.0, 0.0);
+        for (value, lambda) in spectrum.0.iter().zip(wavelengths.lambda.iter()) {
+            xyz += *value * color_matching(*lambda);
+        }
+        xyz / (N_WAVELENGTHS as Float * wavelengths.pdf() * self.y_integral)
+    }
+
+    pub fn to_rgb(&self, spectrum: &SampledSpectrum, wavelengths: &SampledWavelengths) -> Vec3 {
+        xyz_to_srgb(self.to_xyz(spectrum, wavelengths)) / self.white
+    }
+
+    /// The color weight of a path carrying the single wavelength `lambda`,
+    /// picked uniformly over the visible range. Averaged over all
+    /// wavelengths it is white.
+    pub fn wavelength_to_rgb(&self, lambda: Float) -> Vec3 {
+        xyz_to_srgb(color_matching(lambda)) * (LAMBDA_MAX - LAMBDA_MIN)
+            / self.y_integral
+            / self.white
+    }
+
+    /// the color of a whole spectrum, for using spectral data in RGB mode
+    pub fn spectrum_to_rgb(&self, spectrum: &Spectrum) -> Vec3 {
+        let xyz = integrate_xyz(|lambda| spectrum.evaluate(lambda));
+        xyz_to_srgb(xyz / self.y_integral) / self.white
+    }
+}
+
+impl Default for SpectrumConverter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// a shared converter, for code that has no place to keep its own
+pub fn converter() -> &'static SpectrumConverter {
+    static CONVERTER: OnceLock<SpectrumConverter> = OnceLock::new();
+    CONVERTER.get_or_init(SpectrumConverter::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn hero_wavelengths_are_spread_over_range() {
+        let wl = SampledWavelengths::sample(0.9);
+        assert_relative_eq!(wl.lambda[0], 686.0, epsilon = 1e-9);
+        assert_relative_eq!(wl.lambda[1], 431.0, epsilon = 1e-9);
+        assert_relative_eq!(wl.lambda[2], 516.0, epsilon = 1e-9);
+        assert_relative_eq!(wl.lambda[3], 601.0, epsilon = 1e-9);
+        for l in &wl.lambda {
+            assert!((LAMBDA_MIN..=LAMBDA_MAX).contains(l));
+        }
+    }
+
+    #[test]
+    fn tabulated_spectrum_interpolates() {
+        let s = Spectrum::tabulated(vec![(500.0, 1.0), (400.0, 0.0)]).unwrap();
+        assert_relative_eq!(s.evaluate(350.0), 0.0);
+        assert_relative_eq!(s.evaluate(425.0), 0.25);
+        assert_relative_eq!(s.evaluate(600.0), 1.0);
+        assert!(Spectrum::tabulated(vec![]).is_none());
+        assert!(Spectrum::tabulated(vec![(Float::NAN, 1.0)]).is_none());
+    }
+
+    #[test]
+    fn white_converts_to_white() {
+        let converter = SpectrumConverter::new();
+        let rgb = converter.spectrum_to_rgb(&Spectrum::Constant(1.0));
+        assert_relative_eq!(rgb.r(), 1.0, epsilon = 1e-9);
+        assert_relative_eq!(rgb.g(), 1.0, epsilon = 1e-9);
+        assert_relative_eq!(rgb.b(), 1.0, epsilon = 1e-9);
+        // a single path estimate of a flat spectrum is exact on average
+        let mut sum = Vec3(0.0, 0.0, 0.0);
+        let n = 1000;
+        for i in 0..n {
+            let wl = SampledWavelengths::sample((i as Float + 0.5) / n as Float);
+            sum += converter.to_rgb(&SampledSpectrum::constant(1.0), &wl);
+        }
+        assert_relative_eq!(sum.g() / n as Float, 1.0, epsilon = 1e-3);
+    }
+
+    #[test]
+    fn single_wavelengths_average_to_white() {
+        let n = 1000;
+        let mut sum = Vec3(0.0, 0.0, 0.0);
+        for i in 0..n {
+            let lambda = LAMBDA_MIN + (i as Float + 0.5) / n as Float * (LAMBDA_MAX - LAMBDA_MIN);
+            sum += converter().wavelength_to_rgb(lambda);
+        }
+        let mean = sum / n as Float;
+        assert_relative_eq!(mean.r(), 1.0, epsilon = 1e-3);
+        assert_relative_eq!(mean.g(), 1.0, epsilon = 1e-3);
+        assert_relative_eq!(mean.b(), 1.0, epsilon = 1e-3);
+        // blue light contributes to blue
+        let blue = converter().wavelength_to_rgb(450.0);
+        assert!(blue.b() > blue.g() && blue.b() > blue.r());
+    }
+
+    #[test]
+    fn rgb_round_trips_through_smits() {
+        let converter = SpectrumConverter::new();
+        for rgb in &[
+            Vec3(0.8, 0.2, 0.1),
+            Vec3(0.1, 0.6, 0.3),
+            Vec3(0.2, 0.3, 0.9),
+            Vec3(2.0, 2.0, 2.0),
+        ] {
+            let back = converter.spectrum_to_rgb(&Spectrum::Rgb(*rgb));
+            assert!((back - *rgb).length() < 0.1 * rgb.length(), "{:?}", back);
+        }
+    }
+
+    #[test]
+    fn illuminants_round_trip_exactly() {
+        for rgb in &[
+            Vec3(1.0, 1.0, 1.0),
+            Vec3(4.0, 0.5, 0.1),
+            Vec3(0.0, 0.0, 10.0),
+        ] {
+            let back = converter().spectrum_to_rgb(&Spectrum::Illuminant(*rgb));
+            assert_relative_eq!(back.r(), rgb.r(), epsilon = 1e-4, max_relative = 1e-4);
+            assert_relative_eq!(back.g(), rgb.g(), epsilon = 1e-4, max_relative = 1e-4);
+            assert_relative_eq!(back.b(), rgb.b(), epsilon = 1e-4, max_relative = 1e-4);
+        }
+    }
+}
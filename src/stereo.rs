@@ -0,0 +1,303 @@
+use crate::camera::*;
+use crate::error::Result;
+use crate::film::{clamp_to_u8, Film};
+use crate::float::Float;
+use crate::hittable::*;
+use crate::ray::*;
+use rand::rngs::StdRng;
+
+/// How the two eyes of a stereo image share the frame: the left eye on top
+/// or on the left.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StereoLayout {
+    OverUnder,
+    SideBySide,
+}
+
+impl StereoLayout {
+    /// the aspect ratio of each eye's image in a frame of `aspect`
+    pub fn eye_aspect(&self, aspect: Float) -> Float {
+        match self {
+            StereoLayout::OverUnder => 2.0 * aspect,
+            StereoLayout::SideBySide => 0.5 * aspect,
+        }
+    }
+
+    /// The pixels (x0, y0, width, height) of eye 0 (left) or 1 (right) in a
+    /// `width` x `height` frame, with y = 0 at the top.
+    pub fn eye_bounds(
+        &self,
+        eye: usize,
+        width: usize,
+        height: usize,
+    ) -> (usize, usize, usize, usize) {
+        match self {
+            StereoLayout::OverUnder => (0, eye * (height / 2), width, height / 2),
+            StereoLayout::SideBySide => (eye * (width / 2), 0, width / 2, height),
+        }
+    }
+
+    /// A red/cyan anaglyph of a stereo `film` in this layout, taking red
+    /// from the left eye and green and blue from the right. Returns the
+    /// image with its width and height.
+    pub fn anaglyph_rgba8(&self, film: &Film) -> (Vec<u8>, usize, usize) {
+        let (lx, ly, width, height) = self.eye_bounds(0, film.width, film.height);
+        let (rx, ry, _, _) = self.eye_bounds(1, film.width, film.height);
+        let mut data = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            for x in 0..width {
+                let left = film.pixel(lx + x, ly + y);
+                let right = film.pixel(rx + x, ry + y);
+                data.push(clamp_to_u8(left.r()));
+                data.push(clamp_to_u8(right.g()));
+                data.push(clamp_to_u8(right.b()));
+                data.push(255_u8);
+            }
+        }
+        (data, width, height)
+    }
+
+    // the eye seeing frame position (s, t), and the position in its image
+    fn split(&self, s: Float, t: Float) -> (usize, Float, Float) {
+        match self {
+            StereoLayout::OverUnder if t >= 0.5 => (0, s, 2.0 * t - 1.0),
+            StereoLayout::OverUnder => (1, s, 2.0 * t),
+            StereoLayout::SideBySide if s < 0.5 => (0, 2.0 * s, t),
+            StereoLayout::SideBySide => (1, 2.0 * s - 1.0, t),
+        }
+    }
+}
+
+/// Renders a left and a right camera into one frame. Its rays are weighted
+/// by the camera of the eye they come from.
+pub struct StereoCamera {
+    pub left: Box<dyn Camera + Send + Sync>,
+    pub right: Box<dyn Camera + Send + Sync>,
+    pub layout: StereoLayout,
+}
+
+impl StereoCamera {
+    /// Omni-directional stereo: a pair of equirectangular panoramas, each
+    /// ray leaving from where an eye would be when turned to look along it.
+    pub fn ods(panorama: EquirectangularCamera, interocular: Float, layout: StereoLayout) -> Self {
+        let eye = |offset: Float| -> Box<dyn Camera + Send + Sync> {
+            Box::new(OdsCamera {
+                panorama: panorama.clone(),
+                offset,
+            })
+        };
+        StereoCamera {
+            left: eye(-0.5 * interocular),
+            right: eye(0.5 * interocular),
+            layout,
+        }
+    }
+}
+
+impl StereoCamera {
+    // the eye's camera for frame position (s, t), and the position in its
+    // image
+    fn eye(&self, s: Float, t: Float) -> (&(dyn Camera + Send + Sync), Float, Float) {
+        let (eye, s, t) = self.layout.split(s, t);
+        let camera = if eye == 0 { &self.left } else { &self.right };
+        (&**camera, s, t)
+    }
+}
+
+impl Camera for StereoCamera {
+    fn get_ray(&self, s: Float, t: Float, rng: &mut StdRng) -> Option<Ray> {
+        let (camera, s, t) = self.eye(s, t);
+        camera.get_ray(s, t, rng)
+    }
+
+    fn weighted_ray(&self, s: Float, t: Float, rng: &mut StdRng) -> Option<(Ray, Float)> {
+        let (camera, s, t) = self.eye(s, t);
+        camera.weighted_ray(s, t, rng)
+    }
+}
+
+/// One eye of an omni-directional stereo pair, `offset` to the right of
+/// the panorama's center. The offset shrinks towards the poles, where the
+/// eyes could not agree on which way is right.
+pub struct OdsCamera {
+    pub panorama: EquirectangularCamera,
+    pub offset: Float,
+}
+
+impl Camera for OdsCamera {
+    fn get_ray(&self, s: Float, t: Float, rng: &mut StdRng) -> Option<Ray> {
+        let mut ray = self.panorama.get_ray(s, t, rng)?;
+        ray.pos += self.offset * ray.dir.cross(self.panorama.v);
+        Some(ray)
+    }
+}
+
+/// How the eyes of a stereo pair point.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StereoMode {
+    /// Both look straight ahead, with their images shifted so that things
+    /// at the convergence distance line up.
+    Parallel,
+    /// Both turn towards the point at the convergence distance. Simpler,
+    /// but adds vertical parallax towards the corners.
+    ToeIn,
+}
+
+/// A pair of thin lens cameras `interocular` apart, set up from `camera` as
+/// the point between the eyes.
+#[derive(Debug, Clone)]
+pub struct StereoRig {
+    pub camera: CameraBuilder,
+    pub interocular: Float,
+    pub convergence: Float,
+    pub mode: StereoMode,
+}
+
+impl StereoRig {
+    pub fn eyes(&self, world: &dyn Hittable) -> Result<(ThinLensCamera, ThinLensCamera)> {
+        let forward = (self.camera.look_at - self.camera.origin).normalized();
+        let right = forward.cross(self.camera.up).normalized();
+        let eye = |side: Float| {
+            let offset = side * 0.5 * self.interocular * right;
+            let mut builder = self.camera.clone();
+            builder.origin = self.camera.origin + offset;
+            match self.mode {
+                StereoMode::ToeIn => {
+                    builder.look_at = self.camera.origin + self.convergence * forward;
+                    builder.build(world)
+                }
+                StereoMode::Parallel => {
+                    builder.look_at = self.camera.look_at + offset;
+                    let mut camera = builder.build(world)?;
+                    // move the image window towards the middle, by the
+                    // eye's offset scaled from the convergence distance to
+                    // the window's
+                    let window = -(camera.lower_left - camera.origin).dot(camera.w);
+                    camera.lower_left -= window / self.convergence * offset;
+                    Ok(camera)
+                }
+            }
+        };
+        Ok((eye(-1.0)?, eye(1.0)?))
+    }
+
+    pub fn build(&self, world: &dyn Hittable, layout: StereoLayout) -> Result<StereoCamera> {
+        let (left, right) = self.eyes(world)?;
+        Ok(StereoCamera {
+            left: Box::new(left),
+            right: Box::new(right),
+            layout,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::BoxFilter;
+    use crate::float::tolerance;
+    use crate::vec3::*;
+    use approx::assert_relative_eq;
+    use rand::SeedableRng;
+    use std::sync::Arc;
+
+    fn rig(mode: StereoMode) -> StereoRig {
+        StereoRig {
+            camera: CameraBuilder::new(Vec3(0.0, 0.0, 0.0), Vec3(0.0, 0.0, -1.0), 1.0),
+            interocular: 0.064,
+            convergence: 2.0,
+            mode,
+        }
+    }
+
+    #[test]
+    fn eyes_converge() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let world = HittableList::new();
+        for mode in &[StereoMode::Parallel, StereoMode::ToeIn] {
+            let (left, right) = rig(*mode).eyes(&world).unwrap();
+            assert_relative_eq!(left.origin.x(), -0.032);
+            assert_relative_eq!(right.origin.x(), 0.032);
+            // the centers of both images look at the convergence point
+            for eye in &[left, right] {
+                let r = eye.get_ray(0.5, 0.5, &mut rng).unwrap();
+                let p = r.point_at_t(-2.0 / r.dir.z());
+                assert_relative_eq!(p.x(), 0.0, epsilon = tolerance(1e-12));
+                assert_relative_eq!(p.y(), 0.0, epsilon = tolerance(1e-12));
+            }
+        }
+        // parallel eyes keep looking straight ahead
+        let (left, _) = rig(StereoMode::Parallel).eyes(&world).unwrap();
+        assert_eq!(left.w, Vec3(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn layouts_split_the_frame() {
+        assert_eq!(StereoLayout::OverUnder.split(0.3, 0.75), (0, 0.3, 0.5));
+        assert_eq!(StereoLayout::OverUnder.split(0.3, 0.25), (1, 0.3, 0.5));
+        assert_eq!(StereoLayout::SideBySide.split(0.25, 0.4), (0, 0.5, 0.4));
+        assert_eq!(StereoLayout::SideBySide.split(0.75, 0.4), (1, 0.5, 0.4));
+        assert_eq!(
+            StereoLayout::OverUnder.eye_bounds(1, 100, 80),
+            (0, 40, 100, 40)
+        );
+        assert_eq!(StereoLayout::SideBySide.eye_aspect(2.0), 1.0);
+    }
+
+    #[test]
+    fn rays_are_weighted_by_their_eye() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let world = HittableList::new();
+        let mut stereo = rig(StereoMode::Parallel);
+        stereo.camera = stereo.camera.aperture(0.01).exposure(0.5, 100.0);
+        let (left, mut right) = stereo.eyes(&world).unwrap();
+        right.exposure *= 2.0;
+        let camera = StereoCamera {
+            left: Box::new(left),
+            right: Box::new(right),
+            layout: StereoLayout::SideBySide,
+        };
+        let (_, l) = camera.weighted_ray(0.25, 0.5, &mut rng).unwrap();
+        let (_, r) = camera.weighted_ray(0.75, 0.5, &mut rng).unwrap();
+        assert_relative_eq!(r, 2.0 * l);
+    }
+
+    #[test]
+    fn anaglyph_combines_eyes() {
+        let mut film = Film::new(4, 2, Arc::new(BoxFilter { radius: 0.5 }));
+        let mut tile = film.tile(0, 0, 4, 2);
+        for y in 0..2 {
+            tile.add_sample(0.5, y as Float + 0.5, Vec3(1.0, 0.0, 0.0));
+            tile.add_sample(1.5, y as Float + 0.5, Vec3(1.0, 0.0, 0.0));
+            tile.add_sample(2.5, y as Float + 0.5, Vec3(0.0, 1.0, 0.25));
+            tile.add_sample(3.5, y as Float + 0.5, Vec3(0.0, 1.0, 0.25));
+        }
+        film.merge_tile(&tile);
+        let (data, width, height) = StereoLayout::SideBySide.anaglyph_rgba8(&film);
+        assert_eq!((width, height), (2, 2));
+        assert_eq!(&data[..4], &[255, 255, 127, 255]);
+    }
+
+    #[test]
+    fn ods_eyes_circle_the_center() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let panorama = EquirectangularCamera::new(
+            Vec3(0.0, 0.0, 0.0),
+            Vec3(0.0, 0.0, -1.0),
+            Vec3(0.0, 1.0, 0.0),
+        );
+        let camera = StereoCamera::ods(panorama, 0.064, StereoLayout::OverUnder);
+        // looking forward the left eye is on the left, looking right it is
+        // in front
+        let r = camera.get_ray(0.5, 0.75, &mut rng).unwrap();
+        assert_relative_eq!(r.pos.x(), -0.032, epsilon = tolerance(1e-12));
+        let r = camera.get_ray(0.75, 0.75, &mut rng).unwrap();
+        assert_relative_eq!(r.pos.z(), -0.032, epsilon = tolerance(1e-12));
+        assert_relative_eq!(r.dir.x(), 1.0, epsilon = tolerance(1e-12));
+        let r = camera.get_ray(0.75, 0.25, &mut rng).unwrap();
+        assert_relative_eq!(r.pos.z(), 0.032, epsilon = tolerance(1e-12));
+        // at the poles both eyes merge
+        let r = camera.get_ray(0.5, 1.0, &mut rng).unwrap();
+        assert_relative_eq!(r.pos.length(), 0.0, epsilon = tolerance(1e-12));
+    }
+}
@@ -0,0 +1,607 @@
+use crate::aabb::Aabb;
+use crate::float::{gamma, Float};
+use crate::hittable::*;
+use crate::materials::Material;
+use crate::poly::*;
+use crate::ray::*;
+use crate::vec3::*;
+
+use crate::float::consts::PI;
+
+// The shapes in here are modeled around the y axis; use an instance
+// transform to orient them differently.
+
+// angle around the y axis, scaled to [0, 1]
+fn azimuth(p: Vec3) -> Float {
+    let phi = p.z().atan2(p.x());
+    if phi < 0.0 {
+        (phi + 2.0 * PI) / (2.0 * PI)
+    } else {
+        phi / (2.0 * PI)
+    }
+}
+
+// the derivative of a point at `p` with respect to `azimuth(p)`
+fn azimuth_tangent(p: Vec3) -> Vec3 {
+    2.0 * PI * Vec3(-p.z(), 0.0, p.x())
+}
+
+// Keeps track of the closest of several candidate intersections of `ray`.
+struct Closest<'r, 'a> {
+    ray: &'r Ray,
+    t_min: Float,
+    t_max: Float,
+    material: HitMaterial<'a>,
+    best: Option<HitRecord<'a>>,
+}
+
+impl<'r, 'a> Closest<'r, 'a> {
+    fn new(ray: &'r Ray, t_min: Float, t_max: Float, material: HitMaterial<'a>) -> Self {
+        Closest {
+            ray,
+            t_min,
+            t_max,
+            material,
+            best: None,
+        }
+    }
+
+    fn consider(
+        &mut self,
+        t: Float,
+        normal: Vec3,
+        (u, v): (Float, Float),
+        (dpdu, dpdv): (Vec3, Vec3),
+    ) {
+        if t >= self.t_min && t <= self.t_max && self.best.as_ref().is_none_or(|b| t < b.t) {
+            let hit = HitRecord::new(self.ray, t, normal, self.material)
+                .uv(u, v)
+                .tangents(dpdu, dpdv);
+            self.best = Some(hit);
+        }
+    }
+}
+
+// Adds the hit with the horizontal cap disk at local height `y`.
+fn consider_cap(closest: &mut Closest, o: Vec3, d: Vec3, y: Float, radius: Float, normal_y: Float) {
+    if d.y() == 0.0 {
+        return;
+    }
+    let t = (y - o.y()) / d.y();
+    let p = o + t * d;
+    let dist = (p.x() * p.x() + p.z() * p.z()).sqrt();
+    if dist <= radius {
+        let radial = Vec3(p.x(), 0.0, p.z()) * (radius / dist.max(1e-12));
+        closest.consider(
+            t,
+            Vec3(0.0, normal_y, 0.0),
+            (azimuth(p), dist / radius),
+            (azimuth_tangent(p), radial),
+        );
+    }
+}
+
+/// A cylinder standing on `base` and reaching `height` up the y axis.
+/// Side UVs are the azimuth and the height fraction, cap UVs the azimuth and
+/// the distance from the axis.
+pub struct Cylinder {
+    pub base: Vec3,
+    pub radius: Float,
+    pub height: Float,
+    pub capped: bool,
+    pub material: Box<dyn Material + Send + Sync>,
+}
+
+impl Hittable for Cylinder {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        let o = ray.pos - self.base;
+        let d = ray.dir;
+        let mut closest = Closest::new(ray, t_min, t_max, HitMaterial::Dyn(&*self.material));
+        if let Some((t0, t1)) = solve_quadratic(
+            o.x() * o.x() + o.z() * o.z() - self.radius * self.radius,
+            2.0 * (o.x() * d.x() + o.z() * d.z()),
+            d.x() * d.x() + d.z() * d.z(),
+        ) {
+            for t in [t0, t1] {
+                let p = o + t * d;
+                if p.y() >= 0.0 && p.y() <= self.height {
+                    let normal = Vec3(p.x(), 0.0, p.z()) / self.radius;
+                    closest.consider(
+                        t,
+                        normal,
+                        (azimuth(p), p.y() / self.height),
+                        (azimuth_tangent(p), Vec3(0.0, self.height, 0.0)),
+                    );
+                }
+            }
+        }
+        if self.capped {
+            consider_cap(&mut closest, o, d, 0.0, self.radius, -1.0);
+            consider_cap(&mut closest, o, d, self.height, self.radius, 1.0);
+        }
+        closest.best
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(
+            self.base - Vec3(self.radius, 0.0, self.radius),
+            self.base + Vec3(self.radius, self.height, self.radius),
+        ))
+    }
+}
+
+/// A cone with its base disk of `radius` on `base`, and its apex `height`
+/// further up the y axis.
+pub struct Cone {
+    pub base: Vec3,
+    pub radius: Float,
+    pub height: Float,
+    pub capped: bool,
+    pub material: Box<dyn Material + Send + Sync>,
+}
+
+impl Hittable for Cone {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        let o = ray.pos - self.base;
+        let d = ray.dir;
+        // x^2 + z^2 = k^2 (height - y)^2
+        let k2 = (self.radius / self.height) * (self.radius / self.height);
+        let below_apex = self.height - o.y();
+        let mut closest = Closest::new(ray, t_min, t_max, HitMaterial::Dyn(&*self.material));
+        if let Some((t0, t1)) = solve_quadratic(
+            o.x() * o.x() + o.z() * o.z() - k2 * below_apex * below_apex,
+            2.0 * (o.x() * d.x() + o.z() * d.z() + k2 * below_apex * d.y()),
+            d.x() * d.x() + d.z() * d.z() - k2 * d.y() * d.y(),
+        ) {
+            for t in [t0, t1] {
+                let p = o + t * d;
+                if p.y() >= 0.0 && p.y() <= self.height {
+                    let normal = Vec3(p.x(), k2 * (self.height - p.y()), p.z()).normalized();
+                    // from the base circle up to the apex
+                    let angle = 2.0 * PI * azimuth(p);
+                    let slant = Vec3(
+                        -self.radius * angle.cos(),
+                        self.height,
+                        -self.radius * angle.sin(),
+                    );
+                    closest.consider(
+                        t,
+                        normal,
+                        (azimuth(p), p.y() / self.height),
+                        (azimuth_tangent(p), slant),
+                    );
+                }
+            }
+        }
+        if self.capped {
+            consider_cap(&mut closest, o, d, 0.0, self.radius, -1.0);
+        }
+        closest.best
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(
+            self.base - Vec3(self.radius, 0.0, self.radius),
+            self.base + Vec3(self.radius, self.height, self.radius),
+        ))
+    }
+}
+
+/// A torus around the y axis: a tube of `minor_radius` swept along a circle
+/// of `major_radius` in the xz plane. u is the angle around the y axis and v
+/// the angle around the tube.
+pub struct Torus {
+    pub center: Vec3,
+    pub major_radius: Float,
+    pub minor_radius: Float,
+    pub material: Box<dyn Material + Send + Sync>,
+}
+
+impl Hittable for Torus {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        let (big_r, small_r) = (self.major_radius, self.minor_radius);
+        // work with a unit direction, starting from where the ray enters
+        // the bounding sphere or from its origin inside, so the quartic is
+        // well conditioned even for far away ray origins
+        let len = ray.dir.length();
+        let d = ray.dir / len;
+        let o = ray.pos - self.center;
+        let bound = big_r + small_r;
+        let (enter, exit) = solve_quadratic(o.dot(o) - bound * bound, 2.0 * o.dot(d), 1.0)?;
+        let lo = (t_min * len).max(enter);
+        let hi = (t_max * len).min(exit);
+        if lo > hi {
+            return None;
+        }
+        let o = o + lo * d;
+
+        let f = o.dot(d);
+        let k = o.dot(o) + big_r * big_r - small_r * small_r;
+        let r2 = 4.0 * big_r * big_r;
+        let coeffs = [
+            k * k - r2 * (o.x() * o.x() + o.z() * o.z()),
+            4.0 * f * k - 2.0 * r2 * (o.x() * d.x() + o.z() * d.z()),
+            4.0 * f * f + 2.0 * k - r2 * (d.x() * d.x() + d.z() * d.z()),
+            4.0 * f,
+            1.0,
+        ];
+        let roots = real_roots_in(&coeffs, 0.0, hi - lo);
+        let s = *roots.first()?;
+
+        let p = o + s * d;
+        let ring = Vec3(p.x(), 0.0, p.z());
+        let ring_dist = ring.length();
+        let on_ring = if ring_dist > 0.0 {
+            ring * (big_r / ring_dist)
+        } else {
+            Vec3(big_r, 0.0, 0.0)
+        };
+        let normal = (p - on_ring).normalized();
+        let theta = p.y().atan2(ring_dist - big_r);
+        let v = if theta < 0.0 {
+            (theta + 2.0 * PI) / (2.0 * PI)
+        } else {
+            theta / (2.0 * PI)
+        };
+        // around the tube, from its outer equator up over the top
+        let ring_dir = on_ring / big_r;
+        let around = normal.dot(ring_dir) * Vec3(0.0, 1.0, 0.0) - normal.y() * ring_dir;
+        // Projected onto the tube. The solver takes points within its
+        // tolerance of the surface for roots, so they count as error too.
+        let tube = small_r * normal;
+        let gradient = 4.0 * (p.dot(p) + big_r * big_r - small_r * small_r) * p
+            - 8.0 * big_r * big_r * Vec3(p.x(), 0.0, p.z());
+        let slack = root_tolerance(&coeffs) / gradient.length();
+        let error =
+            gamma(7) * (self.center.abs() + on_ring.abs() + tube.abs()) + slack * normal.abs();
+        Some(
+            HitRecord::new(
+                ray,
+                (lo + s) / len,
+                normal,
+                HitMaterial::Dyn(&*self.material),
+            )
+            .point_with_error(self.center + on_ring + tube, error)
+            .uv(azimuth(p), v)
+            .tangents(azimuth_tangent(p), 2.0 * PI * small_r * around),
+        )
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        let extent = Vec3(
+            self.major_radius + self.minor_radius,
+            self.minor_radius,
+            self.major_radius + self.minor_radius,
+        );
+        Some(Aabb::new(self.center - extent, self.center + extent))
+    }
+}
+
+/// The general quadric surface
+///   a x^2 + b y^2 + c z^2 + d xy + e xz + f yz + g x + h y + i z + j = 0
+/// with `coeffs` holding [a, b, c, d, e, f, g, h, i, j]. The outside is where
+/// the left hand side is positive, and the normal is its gradient. Most
+/// quadrics are unbounded, so they can be clipped to `bounds`. UVs are the
+/// spherical coordinates of the normal.
+pub struct Quadric {
+    pub coeffs: [Float; 10],
+    pub bounds: Option<Aabb>,
+    pub material: Box<dyn Material + Send + Sync>,
+}
+
+impl Quadric {
+    fn value(&self, p: Vec3) -> Float {
+        let [a, b, c, d, e, f, g, h, i, j] = self.coeffs;
+        let (x, y, z) = (p.x(), p.y(), p.z());
+        a * x * x
+            + b * y * y
+            + c * z * z
+            + d * x * y
+            + e * x * z
+            + f * y * z
+            + g * x
+            + h * y
+            + i * z
+            + j
+    }
+
+    fn gradient(&self, p: Vec3) -> Vec3 {
+        let [a, b, c, d, e, f, g, h, i, _] = self.coeffs;
+        let (x, y, z) = (p.x(), p.y(), p.z());
+        Vec3(
+            2.0 * a * x + d * y + e * z + g,
+            2.0 * b * y + d * x + f * z + h,
+            2.0 * c * z + e * x + f * y + i,
+        )
+    }
+}
+
+impl Hittable for Quadric {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        let [a, b, c, d, e, f, _, _, _, _] = self.coeffs;
+        let (o, dir) = (ray.pos, ray.dir);
+        let (dx, dy, dz) = (dir.x(), dir.y(), dir.z());
+        let quadratic =
+            a * dx * dx + b * dy * dy + c * dz * dz + d * dx * dy + e * dx * dz + f * dy * dz;
+        // the gradient at the origin contains all of the linear terms
+        let linear = self.gradient(o).dot(dir);
+        let mut closest = Closest::new(ray, t_min, t_max, HitMaterial::Dyn(&*self.material));
+        if let Some((t0, t1)) = solve_quadratic(self.value(o), linear, quadratic) {
+            for t in [t0, t1] {
+                let p = ray.point_at_t(t);
+                if self.bounds.is_none_or(|b| b.contains(p)) {
+                    let normal = self.gradient(p).normalized();
+                    let (u, v) = sphere_uv(&normal);
+                    closest.consider(t, normal, (u, v), normal.orthonormal_basis());
+                }
+            }
+        }
+        closest.best
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bounds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    struct TestMaterial {}
+    impl Material for TestMaterial {}
+
+    fn ray(pos: Vec3, dir: Vec3) -> Ray {
+        Ray::new(pos, dir)
+    }
+
+    #[test]
+    fn open_and_capped_cylinders() {
+        let mut c = Cylinder {
+            base: Vec3(0.0, 0.0, 0.0),
+            radius: 1.0,
+            height: 2.0,
+            capped: false,
+            material: Box::new(TestMaterial {}),
+        };
+        let side = c
+            .hit(
+                &ray(Vec3(5.0, 1.0, 0.0), Vec3(-1.0, 0.0, 0.0)),
+                0.0,
+                Float::MAX,
+            )
+            .unwrap();
+        assert_relative_eq!(side.t, 4.0);
+        assert_eq!(side.normal, Vec3(1.0, 0.0, 0.0));
+        assert_relative_eq!(side.v, 0.5);
+        // looking straight down through an open cylinder hits nothing...
+        let down = ray(Vec3(0.0, 5.0, 0.0), Vec3(0.0, -1.0, 0.0));
+        assert!(c.hit(&down, 0.0, Float::MAX).is_none());
+        // ...but hits the top cap when capped
+        c.capped = true;
+        let top = c.hit(&down, 0.0, Float::MAX).unwrap();
+        assert_relative_eq!(top.t, 3.0);
+        assert_eq!(top.normal, Vec3(0.0, 1.0, 0.0));
+        let b = c.bounding_box().unwrap();
+        assert_eq!(b.max, Vec3(1.0, 2.0, 1.0));
+    }
+
+    #[test]
+    fn cone_side_and_cap() {
+        let c = Cone {
+            base: Vec3(0.0, 0.0, 0.0),
+            radius: 1.0,
+            height: 1.0,
+            capped: true,
+            material: Box::new(TestMaterial {}),
+        };
+        // half way up, the cone has radius 0.5
+        let side = c
+            .hit(
+                &ray(Vec3(5.0, 0.5, 0.0), Vec3(-1.0, 0.0, 0.0)),
+                0.0,
+                Float::MAX,
+            )
+            .unwrap();
+        assert_relative_eq!(side.t, 4.5);
+        assert_relative_eq!(side.normal.x(), side.normal.y());
+        assert!(side.normal.x() > 0.0);
+        let up = c
+            .hit(
+                &ray(Vec3(0.2, -1.0, 0.0), Vec3(0.0, 1.0, 0.0)),
+                0.0,
+                Float::MAX,
+            )
+            .unwrap();
+        assert_relative_eq!(up.t, 1.0);
+        assert_eq!(up.normal, Vec3(0.0, -1.0, 0.0));
+        // the other nappe of the double cone above the apex is not part of it
+        let above = ray(Vec3(5.0, 1.5, 0.0), Vec3(-1.0, 0.0, 0.0));
+        assert!(c.hit(&above, 0.0, Float::MAX).is_none());
+    }
+
+    #[test]
+    fn torus_hits() {
+        let t = Torus {
+            center: Vec3(0.0, 0.0, 0.0),
+            major_radius: 2.0,
+            minor_radius: 0.5,
+            material: Box::new(TestMaterial {}),
+        };
+        let h = t
+            .hit(
+                &ray(Vec3(10.0, 0.0, 0.0), Vec3(-2.0, 0.0, 0.0)),
+                0.0,
+                Float::MAX,
+            )
+            .unwrap();
+        assert_relative_eq!(h.t, 3.75, epsilon = 1e-9);
+        assert_relative_eq!(h.normal.x(), 1.0, epsilon = 1e-9);
+        // through the hole
+        let hole = ray(Vec3(0.0, 10.0, 0.0), Vec3(0.0, -1.0, 0.0));
+        assert!(t.hit(&hole, 0.0, Float::MAX).is_none());
+        // straight down onto the tube
+        let h = t
+            .hit(
+                &ray(Vec3(0.0, 10.0, 2.0), Vec3(0.0, -1.0, 0.0)),
+                0.0,
+                Float::MAX,
+            )
+            .unwrap();
+        assert_relative_eq!(h.t, 9.5, epsilon = 1e-9);
+        assert_relative_eq!(h.normal.y(), 1.0, epsilon = 1e-9);
+        assert_relative_eq!(h.v, 0.25, epsilon = 1e-9);
+        // the inner wall of the tube, once the first hit is excluded
+        let h = t
+            .hit(
+                &ray(Vec3(10.0, 0.0, 0.0), Vec3(-1.0, 0.0, 0.0)),
+                8.0,
+                Float::MAX,
+            )
+            .unwrap();
+        assert_relative_eq!(h.t, 8.5, epsilon = 1e-9);
+        assert_relative_eq!(h.normal.x(), -1.0, epsilon = 1e-9);
+    }
+
+    #[test]
+    #[cfg_attr(feature = "f32", ignore = "finite differences need f64")]
+    fn tangents_lie_in_the_surface() {
+        let material = || -> Box<dyn Material + Send + Sync> { Box::new(TestMaterial {}) };
+        let shapes: Vec<Box<dyn Hittable>> = vec![
+            Box::new(Cylinder {
+                base: Vec3(0.0, 0.0, 0.0),
+                radius: 1.0,
+                height: 2.0,
+                capped: true,
+                material: material(),
+            }),
+            Box::new(Cone {
+                base: Vec3(0.0, 0.0, 0.0),
+                radius: 1.0,
+                height: 2.0,
+                capped: true,
+                material: material(),
+            }),
+            Box::new(Torus {
+                center: Vec3(0.0, 0.0, 0.0),
+                major_radius: 1.5,
+                minor_radius: 0.5,
+                material: material(),
+            }),
+        ];
+        let rays = [
+            ray(Vec3(0.3, 5.0, 0.2), Vec3(0.0, -1.0, 0.0)),
+            ray(Vec3(5.0, 0.3, 0.1), Vec3(-1.0, 0.0, 0.1)),
+            ray(Vec3(5.0, 5.0, 0.2), Vec3(-1.0, -1.2, 0.0)),
+        ];
+        let hits: Vec<_> = shapes
+            .iter()
+            .flat_map(|shape| {
+                rays.iter()
+                    .filter_map(move |r| shape.hit(r, 0.0, Float::MAX))
+            })
+            .collect();
+        assert!(hits.len() >= 7);
+        for hit in &hits {
+            {
+                for tangent in &[hit.dpdu, hit.dpdv] {
+                    assert!(tangent.length() > 0.1);
+                    assert_relative_eq!(tangent.dot(hit.normal), 0.0, epsilon = 1e-9);
+                }
+                // a step along dpdu advances u by as much
+                let step = hit.point + 1e-6 * hit.dpdu;
+                assert_relative_eq!(azimuth(step) - hit.u, 1e-6, epsilon = 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn quadric_sphere_matches_sphere() {
+        // x^2 + y^2 + z^2 - 1 = 0
+        let q = Quadric {
+            coeffs: [1.0, 1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, -1.0],
+            bounds: None,
+            material: Box::new(TestMaterial {}),
+        };
+        let h = q
+            .hit(
+                &ray(Vec3(0.0, 0.0, 5.0), Vec3(0.0, 0.0, -1.0)),
+                0.0,
+                Float::MAX,
+            )
+            .unwrap();
+        assert_relative_eq!(h.t, 4.0);
+        assert_eq!(h.normal, Vec3(0.0, 0.0, 1.0));
+        assert!(q.bounding_box().is_none());
+    }
+
+    #[test]
+    fn quadric_is_clipped_by_bounds() {
+        // an infinite cylinder x^2 + z^2 - 1 = 0, cut to |y| <= 1
+        let q = Quadric {
+            coeffs: [1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, -1.0],
+            bounds: Some(Aabb::new(Vec3(-1.0, -1.0, -1.0), Vec3(1.0, 1.0, 1.0))),
+            material: Box::new(TestMaterial {}),
+        };
+        let inside = q
+            .hit(
+                &ray(Vec3(5.0, 0.5, 0.0), Vec3(-1.0, 0.0, 0.0)),
+                0.0,
+                Float::MAX,
+            )
+            .unwrap();
+        assert_relative_eq!(inside.t, 4.0);
+        let outside = ray(Vec3(5.0, 1.5, 0.0), Vec3(-1.0, 0.0, 0.0));
+        assert!(q.hit(&outside, 0.0, Float::MAX).is_none());
+    }
+
+    fn random_dir(rng: &mut StdRng) -> Vec3 {
+        Vec3(
+            rng.gen::<Float>() - 0.5,
+            rng.gen::<Float>() - 0.5,
+            rng.gen::<Float>() - 0.5,
+        )
+        .normalized()
+    }
+
+    #[test]
+    fn spawned_rays_clear_the_surface() {
+        // far from the origin, where the rounding errors are large
+        let center = Vec3(1000.0, -2000.0, 500.0);
+        let shapes: Vec<Box<dyn Hittable>> = vec![
+            Box::new(Torus {
+                center,
+                major_radius: 2.0,
+                minor_radius: 0.5,
+                material: Box::new(TestMaterial {}),
+            }),
+            Box::new(Cylinder {
+                base: center,
+                radius: 1.0,
+                height: 2.0,
+                capped: true,
+                material: Box::new(TestMaterial {}),
+            }),
+        ];
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut hits = 0;
+        for shape in &shapes {
+            for _ in 0..2000 {
+                let pos = center + 10.0 * random_dir(&mut rng);
+                let target = center + 2.0 * random_dir(&mut rng);
+                let hit = match shape.hit(&ray(pos, target - pos), 0.0, Float::MAX) {
+                    Some(hit) => hit,
+                    None => continue,
+                };
+                hits += 1;
+                let dir = random_dir(&mut rng);
+                let leaving = dir.dot(hit.geometric_normal) > 0.0;
+                let spawned = ray(hit.spawn_point(dir), dir);
+                // a ray that set off outside can only hit the outside
+                if let Some(next) = shape.hit(&spawned, 0.0, Float::MAX) {
+                    assert_eq!(next.front_face, leaving);
+                }
+            }
+        }
+        assert!(hits > 1000);
+    }
+}
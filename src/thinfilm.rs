@@ -0,0 +1,251 @@
+use crate::float::consts::PI;
+use crate::float::Float;
+use crate::noise::Perlin;
+use crate::spectrum::*;
+use crate::vec3::*;
+use std::ops;
+
+// Just enough complex arithmetic for the Fresnel equations of absorbing
+// and totally reflecting interfaces.
+#[derive(Debug, Clone, Copy)]
+struct Complex {
+    re: Float,
+    im: Float,
+}
+
+impl Complex {
+    fn new(re: Float, im: Float) -> Self {
+        Complex { re, im }
+    }
+
+    fn norm_sqr(self) -> Float {
+        self.re * self.re + self.im * self.im
+    }
+
+    // principal square root
+    fn sqrt(self) -> Complex {
+        let r = self.norm_sqr().sqrt();
+        let re = ((r + self.re) / 2.0).max(0.0).sqrt();
+        let im = ((r - self.re) / 2.0).max(0.0).sqrt();
+        Complex::new(re, if self.im < 0.0 { -im } else { im })
+    }
+
+    // e^(i z)
+    fn exp_i(self) -> Complex {
+        let scale = (-self.im).exp();
+        Complex::new(scale * self.re.cos(), scale * self.re.sin())
+    }
+}
+
+impl ops::Add<Complex> for Complex {
+    type Output = Complex;
+    fn add(self, rhs: Complex) -> Complex {
+        Complex::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl ops::Sub<Complex> for Complex {
+    type Output = Complex;
+    fn sub(self, rhs: Complex) -> Complex {
+        Complex::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl ops::Mul<Complex> for Complex {
+    type Output = Complex;
+    fn mul(self, rhs: Complex) -> Complex {
+        Complex::new(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+}
+
+impl ops::Div<Complex> for Complex {
+    type Output = Complex;
+    fn div(self, rhs: Complex) -> Complex {
+        let d = rhs.norm_sqr();
+        Complex::new(
+            (self.re * rhs.re + self.im * rhs.im) / d,
+            (self.im * rhs.re - self.re * rhs.im) / d,
+        )
+    }
+}
+
+// cosine of the angle to the normal after refracting from index `n1` at
+// sin^2 = `sin2` into index `n2`; imaginary past the critical angle
+fn refracted_cos(n1: Complex, n2: Complex, sin2: Float) -> Complex {
+    let ratio = n1 / n2;
+    (Complex::new(1.0, 0.0) - ratio * ratio * Complex::new(sin2, 0.0)).sqrt()
+}
+
+// s and p polarized amplitude reflection coefficients
+fn fresnel(n1: Complex, cos1: Complex, n2: Complex, cos2: Complex) -> (Complex, Complex) {
+    let s = (n1 * cos1 - n2 * cos2) / (n1 * cos1 + n2 * cos2);
+    let p = (n2 * cos1 - n1 * cos2) / (n2 * cos1 + n1 * cos2);
+    (s, p)
+}
+
+/// The film thickness in nanometers across a surface, at texture
+/// coordinates (u, v) and point `p`.
+pub trait ThicknessTexture {
+    fn thickness(&self, u: Float, v: Float, p: Vec3) -> Float;
+}
+
+/// a uniform thickness
+impl ThicknessTexture for Float {
+    fn thickness(&self, _u: Float, _v: Float, _p: Vec3) -> Float {
+        *self
+    }
+}
+
+/// Thickness varying smoothly between `min` and `max`, like the swirls on a
+/// soap bubble.
+pub struct NoiseThickness {
+    pub noise: Perlin,
+    pub frequency: Float,
+    pub min: Float,
+    pub max: Float,
+}
+
+impl ThicknessTexture for NoiseThickness {
+    fn thickness(&self, _u: Float, _v: Float, p: Vec3) -> Float {
+        let n = (0.5 + 0.5 * self.noise.noise(self.frequency * p)).clamp(0.0, 1.0);
+        self.min + (self.max - self.min) * n
+    }
+}
+
+// wavelengths the RGB reflectance of a film is averaged over
+const RGB_SAMPLES: usize = 16;
+
+/// A thin transparent coating, e.g. soap or an oxide layer, whose
+/// interference colors the reflection. Reflectance follows the Airy
+/// formula for a single film on a substrate and is averaged over both
+/// polarizations.
+pub struct ThinFilm {
+    pub ior: Float,
+    pub thickness: Box<dyn ThicknessTexture + Send + Sync>,
+}
+
+impl ThinFilm {
+    /// Reflectance at `lambda` nm for light arriving with `cos_incident`
+    /// from a medium of index `outside`, onto a film `thickness` nm thick
+    /// over a substrate of complex index `substrate` (n, k).
+    pub fn reflectance(
+        &self,
+        thickness: Float,
+        lambda: Float,
+        cos_incident: Float,
+        outside: Float,
+        substrate: (Float, Float),
+    ) -> Float {
+        let n1 = Complex::new(outside, 0.0);
+        let n2 = Complex::new(self.ior, 0.0);
+        let n3 = Complex::new(substrate.0, substrate.1);
+        let cos1 = Complex::new(cos_incident.abs().min(1.0), 0.0);
+        let sin2 = 1.0 - cos1.re * cos1.re;
+        let cos2 = refracted_cos(n1, n2, sin2);
+        let cos3 = refracted_cos(n1, n3, sin2);
+        let (r12_s, r12_p) = fresnel(n1, cos1, n2, cos2);
+        let (r23_s, r23_p) = fresnel(n2, cos2, n3, cos3);
+        // phase difference between the rays reflected at the two interfaces
+        let delta = Complex::new(4.0 * PI * thickness / lambda, 0.0) * n2 * cos2;
+        let phase = delta.exp_i();
+        let one = Complex::new(1.0, 0.0);
+        let airy = |r12: Complex, r23: Complex| {
+            ((r12 + r23 * phase) / (one + r12 * r23 * phase)).norm_sqr()
+        };
+        (0.5 * (airy(r12_s, r23_s) + airy(r12_p, r23_p))).min(1.0)
+    }
+
+    /// the reflectance seen by the RGB renderer, averaged over the visible
+    /// range with the color of each wavelength
+    pub fn reflectance_rgb<F: Fn(Float) -> (Float, Float)>(
+        &self,
+        thickness: Float,
+        cos_incident: Float,
+        outside: Float,
+        substrate: F,
+    ) -> Vec3 {
+        let mut sum = Vec3(0.0, 0.0, 0.0);
+        let mut weights = Vec3(0.0, 0.0, 0.0);
+        for i in 0..RGB_SAMPLES {
+            let lambda =
+                LAMBDA_MIN + (i as Float + 0.5) / RGB_SAMPLES as Float * (LAMBDA_MAX - LAMBDA_MIN);
+            let weight = converter().wavelength_to_rgb(lambda);
+            let r = self.reflectance(thickness, lambda, cos_incident, outside, substrate(lambda));
+            sum += r * weight;
+            weights += weight;
+        }
+        (sum / weights).max(Vec3(0.0, 0.0, 0.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::float::tolerance;
+    use approx::assert_relative_eq;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn film(ior: Float) -> ThinFilm {
+        ThinFilm {
+            ior,
+            thickness: Box::new(0.0),
+        }
+    }
+
+    #[test]
+    fn no_film_is_plain_fresnel() {
+        let f = film(1.33);
+        assert_relative_eq!(
+            f.reflectance(0.0, 550.0, 1.0, 1.0, (1.5, 0.0)),
+            0.04,
+            epsilon = tolerance(1e-12)
+        );
+        // past the critical angle, leaving glass
+        assert_relative_eq!(
+            f.reflectance(0.0, 550.0, 0.3, 1.5, (1.0, 0.0)),
+            1.0,
+            epsilon = tolerance(1e-9)
+        );
+    }
+
+    #[test]
+    fn quarter_wave_coating_cancels_reflection() {
+        let ior = (1.5 as Float).sqrt();
+        let f = film(ior);
+        let thickness = 550.0 / (4.0 * ior);
+        assert!(f.reflectance(thickness, 550.0, 1.0, 1.0, (1.5, 0.0)) < 1e-12);
+        assert!(f.reflectance(thickness, 400.0, 1.0, 1.0, (1.5, 0.0)) > 1e-3);
+    }
+
+    #[test]
+    fn soap_film_is_iridescent() {
+        let f = film(1.33);
+        let rgb = f.reflectance_rgb(300.0, 1.0, 1.0, |_| (1.0, 0.0));
+        let spread = (rgb.r() - rgb.g()).abs() + (rgb.g() - rgb.b()).abs();
+        assert!(spread > 0.01, "{:?}", rgb);
+        // and the color changes with the viewing angle
+        let grazing = f.reflectance_rgb(300.0, 0.3, 1.0, |_| (1.0, 0.0));
+        assert!((grazing - rgb).length() > 0.01);
+    }
+
+    #[test]
+    fn textured_thickness() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let t = NoiseThickness {
+            noise: Perlin::new(&mut rng),
+            frequency: 2.0,
+            min: 200.0,
+            max: 600.0,
+        };
+        let d = t.thickness(0.0, 0.0, Vec3(0.3, 0.7, 0.1));
+        assert!((200.0..=600.0).contains(&d));
+        assert_relative_eq!(
+            (400.0 as Float).thickness(0.5, 0.5, Vec3(0.0, 0.0, 0.0)),
+            400.0
+        );
+    }
+}
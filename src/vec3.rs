@@ -1,39 +1,52 @@
+use crate::float::Float;
 use std::ops;
 
 #[derive(Debug, PartialEq, Copy)]
-pub struct Vec3(pub f64, pub f64, pub f64);
+pub struct Vec3(pub Float, pub Float, pub Float);
 
 impl Vec3 {
-    pub fn x(&self) -> f64 {
+    pub fn x(&self) -> Float {
         self.0
     }
-    pub fn y(&self) -> f64 {
+    pub fn y(&self) -> Float {
         self.1
     }
-    pub fn z(&self) -> f64 {
+    pub fn z(&self) -> Float {
         self.2
     }
-    pub fn r(&self) -> f64 {
+    pub fn r(&self) -> Float {
         self.0
     }
-    pub fn g(&self) -> f64 {
+    pub fn g(&self) -> Float {
         self.1
     }
-    pub fn b(&self) -> f64 {
+    pub fn b(&self) -> Float {
         self.2
     }
-    pub fn length(&self) -> f64 {
+    pub fn length(&self) -> Float {
         (self.0 * self.0 + self.1 * self.1 + self.2 * self.2).sqrt()
     }
-    pub fn squared_length(&self) -> f64 {
+    pub fn squared_length(&self) -> Float {
         self.0 * self.0 + self.1 * self.1 + self.2 * self.2
     }
-    pub fn normalized(&self) -> (Vec3) {
+    pub fn normalized(&self) -> Vec3 {
         *self / self.length()
     }
-    pub fn dot(&self, rhs: Vec3) -> f64 {
+    pub fn dot(&self, rhs: Vec3) -> Float {
         self.0 * rhs.0 + self.1 * rhs.1 + self.2 * rhs.2
     }
+    pub fn min(&self, rhs: Vec3) -> Vec3 {
+        Vec3(self.0.min(rhs.0), self.1.min(rhs.1), self.2.min(rhs.2))
+    }
+    pub fn max(&self, rhs: Vec3) -> Vec3 {
+        Vec3(self.0.max(rhs.0), self.1.max(rhs.1), self.2.max(rhs.2))
+    }
+    pub fn abs(&self) -> Vec3 {
+        Vec3(self.0.abs(), self.1.abs(), self.2.abs())
+    }
+    pub fn exp(&self) -> Vec3 {
+        Vec3(self.0.exp(), self.1.exp(), self.2.exp())
+    }
     pub fn cross(&self, rhs: Vec3) -> Vec3 {
         Vec3(
             self.1 * rhs.2 - self.2 * rhs.1,
@@ -41,6 +54,18 @@ impl Vec3 {
             self.0 * rhs.1 - self.1 * rhs.0,
         )
     }
+    /// two unit vectors that together with this (unit) vector form a
+    /// right-handed orthonormal basis (Duff et al., "Building an Orthonormal
+    /// Basis, Revisited")
+    pub fn orthonormal_basis(&self) -> (Vec3, Vec3) {
+        let sign = (1.0 as Float).copysign(self.2);
+        let a = -1.0 / (sign + self.2);
+        let b = self.0 * self.1 * a;
+        (
+            Vec3(1.0 + sign * self.0 * self.0 * a, sign * b, -sign * self.0),
+            Vec3(b, sign + self.1 * self.1 * a, -self.1),
+        )
+    }
 }
 
 impl Clone for Vec3 {
@@ -49,6 +74,18 @@ impl Clone for Vec3 {
     }
 }
 
+impl ops::Index<usize> for Vec3 {
+    type Output = Float;
+    fn index(&self, axis: usize) -> &Float {
+        match axis {
+            0 => &self.0,
+            1 => &self.1,
+            2 => &self.2,
+            _ => panic!("Vec3 index out of range: {}", axis),
+        }
+    }
+}
+
 impl ops::Add<Vec3> for Vec3 {
     type Output = Vec3;
     fn add(self, rhs: Vec3) -> Vec3 {
@@ -93,14 +130,14 @@ impl ops::Mul<Vec3> for Vec3 {
     }
 }
 
-impl ops::Mul<f64> for Vec3 {
+impl ops::Mul<Float> for Vec3 {
     type Output = Vec3;
-    fn mul(self, rhs: f64) -> Vec3 {
+    fn mul(self, rhs: Float) -> Vec3 {
         Vec3(self.0 * rhs, self.1 * rhs, self.2 * rhs)
     }
 }
 
-impl ops::Mul<Vec3> for f64 {
+impl ops::Mul<Vec3> for Float {
     type Output = Vec3;
     fn mul(self, rhs: Vec3) -> Vec3 {
         Vec3(self * rhs.0, self * rhs.1, self * rhs.2)
@@ -114,9 +151,9 @@ impl ops::Div<Vec3> for Vec3 {
     }
 }
 
-impl ops::Div<f64> for Vec3 {
+impl ops::Div<Float> for Vec3 {
     type Output = Vec3;
-    fn div(self, rhs: f64) -> Vec3 {
+    fn div(self, rhs: Float) -> Vec3 {
         Vec3(self.0 / rhs, self.1 / rhs, self.2 / rhs)
     }
 }
@@ -129,8 +166,8 @@ impl ops::MulAssign<Vec3> for Vec3 {
     }
 }
 
-impl ops::MulAssign<f64> for Vec3 {
-    fn mul_assign(&mut self, rhs: f64) -> () {
+impl ops::MulAssign<Float> for Vec3 {
+    fn mul_assign(&mut self, rhs: Float) -> () {
         self.0 *= rhs;
         self.1 *= rhs;
         self.2 *= rhs;
@@ -145,8 +182,8 @@ impl ops::DivAssign<Vec3> for Vec3 {
     }
 }
 
-impl ops::DivAssign<f64> for Vec3 {
-    fn div_assign(&mut self, rhs: f64) -> () {
+impl ops::DivAssign<Float> for Vec3 {
+    fn div_assign(&mut self, rhs: Float) -> () {
         self.0 /= rhs;
         self.1 /= rhs;
         self.2 /= rhs;
@@ -155,6 +192,7 @@ impl ops::DivAssign<f64> for Vec3 {
 
 #[cfg(test)]
 mod tests {
+    use crate::float::tolerance;
     // Note this useful idiom: importing names from outer (for mod tests) scope.
     use super::*;
 
@@ -169,6 +207,22 @@ mod tests {
         assert_eq!(v.b(), v.2);
     }
 
+    #[test]
+    fn index_matches_accessors() {
+        let v = Vec3(1.0, 2.0, 3.0);
+        assert_eq!(v[0], v.x());
+        assert_eq!(v[1], v.y());
+        assert_eq!(v[2], v.z());
+    }
+
+    #[test]
+    fn component_min_max() {
+        let v = Vec3(1.0, 5.0, -3.0);
+        let v2 = Vec3(2.0, 4.0, -6.0);
+        assert_eq!(v.min(v2), Vec3(1.0, 4.0, -6.0));
+        assert_eq!(v.max(v2), Vec3(2.0, 5.0, -3.0));
+    }
+
     #[test]
     fn adds_two_vectors() {
         let v = Vec3(1.0, 2.0, 3.0);
@@ -319,6 +373,22 @@ mod tests {
         assert_eq!(v.cross(v2), Vec3(-49.0, -7.0, 28.0));
     }
 
+    #[test]
+    fn orthonormal_basis() {
+        for n in &[
+            Vec3(0.0, 0.0, 1.0),
+            Vec3(0.0, 0.0, -1.0),
+            Vec3(1.0, 2.0, 3.0).normalized(),
+        ] {
+            let (t, b) = n.orthonormal_basis();
+            assert!((t.length() - 1.0).abs() < tolerance(1e-12));
+            assert!((b.length() - 1.0).abs() < tolerance(1e-12));
+            assert!(t.dot(*n).abs() < tolerance(1e-12));
+            assert!(b.dot(*n).abs() < tolerance(1e-12));
+            assert!((t.cross(b) - *n).length() < tolerance(1e-12));
+        }
+    }
+
     #[test]
     fn cross_is_right_handed() {
         let x = Vec3(1.0, 0.0, 0.0);
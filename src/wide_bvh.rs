@@ -0,0 +1,566 @@
+use crate::aabb::Aabb;
+use crate::float::Float;
+use crate::hittable::*;
+use crate::lanes::{simd_available, LANES};
+use crate::ray::*;
+use crate::vec3::*;
+
+/// Children per node, the lanes of an AVX register: four in double
+/// precision and eight in single.
+pub const WIDTH: usize = LANES;
+
+// Child slots hold a node index, an object index tagged with `LEAF`, or
+// `EMPTY`.
+const LEAF: u32 = 1 << 31;
+const EMPTY: u32 = u32::MAX;
+
+// Deep enough for any tree built by median splits: each level pushes at
+// most WIDTH - 1 more nodes than it pops.
+const STACK_SIZE: usize = 128;
+
+// SAH costs of visiting a node, which tests all its children at once, and
+// of intersecting an object.
+const TRAVERSAL_COST: Float = 1.0;
+const INTERSECTION_COST: Float = 1.0;
+
+// A node's children, with their bounding boxes stored as 8 bit offsets
+// from the node's origin in steps of 2^exponent along each axis. The
+// offsets are rounded outwards, so the boxes they decode to contain the
+// children.
+struct WideNode {
+    origin: [Float; 3],
+    exponent: [i8; 3],
+    lo: [[u8; WIDTH]; 3],
+    hi: [[u8; WIDTH]; 3],
+    children: [u32; WIDTH],
+}
+
+fn step(exponent: i8) -> Float {
+    (2.0 as Float).powi(exponent as i32)
+}
+
+impl WideNode {
+    // Quantizes `boxes` against their union. Slots past the end of
+    // `children` are left empty.
+    fn new(boxes: &[Aabb], children: &[u32]) -> Self {
+        let bounds = boxes[1..].iter().fold(boxes[0], |acc, b| acc.union(b));
+        let mut node = WideNode {
+            origin: [bounds.min.x(), bounds.min.y(), bounds.min.z()],
+            exponent: [0; 3],
+            lo: [[u8::MAX; WIDTH]; 3],
+            hi: [[0; WIDTH]; 3],
+            children: [EMPTY; WIDTH],
+        };
+        for axis in 0..3 {
+            let origin = node.origin[axis];
+            let extent = bounds.max[axis] - origin;
+            let mut exponent = (extent / 255.0).log2().ceil().clamp(-127.0, 127.0) as i8;
+            while exponent < 127 && origin + 255.0 * step(exponent) < bounds.max[axis] {
+                exponent += 1;
+            }
+            node.exponent[axis] = exponent;
+            let scale = step(exponent);
+            let decode = |q: u8| origin + q as Float * scale;
+            for (i, b) in boxes.iter().enumerate() {
+                let mut lo = ((b.min[axis] - origin) / scale).floor().clamp(0.0, 255.0) as u8;
+                while lo > 0 && decode(lo) > b.min[axis] {
+                    lo -= 1;
+                }
+                let mut hi = ((b.max[axis] - origin) / scale).ceil().clamp(0.0, 255.0) as u8;
+                while hi < u8::MAX && decode(hi) < b.max[axis] {
+                    hi += 1;
+                }
+                node.lo[axis][i] = lo;
+                node.hi[axis][i] = hi;
+            }
+        }
+        node.children[..children.len()].copy_from_slice(children);
+        node
+    }
+
+    // the box of child `i` as the slab tests see it
+    fn child_box(&self, i: usize) -> Aabb {
+        let decode = |q: &[[u8; WIDTH]; 3], axis: usize| {
+            self.origin[axis] + q[axis][i] as Float * step(self.exponent[axis])
+        };
+        Aabb {
+            min: Vec3(
+                decode(&self.lo, 0),
+                decode(&self.lo, 1),
+                decode(&self.lo, 2),
+            ),
+            max: Vec3(
+                decode(&self.hi, 0),
+                decode(&self.hi, 1),
+                decode(&self.hi, 2),
+            ),
+        }
+    }
+
+    // Slab tests all children as `Aabb::hit` does, writing where the ray
+    // enters each box to `t_near` and returning a bit mask of those hit.
+    fn hit_children(
+        &self,
+        ray: &Ray,
+        t_min: Float,
+        t_max: Float,
+        t_near: &mut [Float; WIDTH],
+    ) -> u32 {
+        let mut mask = 0;
+        for (i, near) in t_near.iter_mut().enumerate() {
+            let b = self.child_box(i);
+            let (mut lo, mut hi) = (t_min, t_max);
+            for axis in 0..3 {
+                let inv_d = 1.0 / ray.dir[axis];
+                let mut t0 = (b.min[axis] - ray.pos[axis]) * inv_d;
+                let mut t1 = (b.max[axis] - ray.pos[axis]) * inv_d;
+                if inv_d < 0.0 {
+                    std::mem::swap(&mut t0, &mut t1);
+                }
+                lo = if t0 > lo { t0 } else { lo };
+                hi = if t1 < hi { t1 } else { hi };
+            }
+            *near = lo;
+            if hi >= lo {
+                mask |= 1 << i;
+            }
+        }
+        mask
+    }
+}
+
+/// Statistics of a wide BVH. The SAH cost is the expected cost of a ray
+/// that hits the root, from the surface areas of the quantized boxes.
+/// Memory counts the nodes and the pointers to the objects, including the
+/// unbounded ones outside the tree, but not the objects themselves.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BvhStats {
+    pub nodes: usize,
+    pub objects: usize,
+    pub depth: usize,
+    pub sah_cost: Float,
+    pub memory_bytes: usize,
+}
+
+/// A bounding volume hierarchy with up to WIDTH children per node, in a
+/// flat array with the root first. All children of a node are slab tested
+/// together, with AVX where the CPU has it. The tree is built by median
+/// splits as `Bvh` is, two levels at a time. Objects without a bounding box
+/// are tested one by one.
+pub struct WideBvh {
+    nodes: Vec<WideNode>,
+    objects: Vec<Box<dyn Hittable + Send + Sync>>,
+    unbounded: HittableList,
+    simd: bool,
+}
+
+impl WideBvh {
+    pub fn new(objects: Vec<Box<dyn Hittable + Send + Sync>>) -> Self {
+        let mut bounded = Vec::new();
+        let mut unbounded = HittableList::new();
+        for object in objects {
+            match object.bounding_box() {
+                Some(bbox) => bounded.push((bbox, object)),
+                None => unbounded.push_boxed(object),
+            }
+        }
+        let mut boxes: Vec<(Aabb, u32)> = bounded
+            .iter()
+            .enumerate()
+            .map(|(i, (bbox, _))| (*bbox, i as u32))
+            .collect();
+        let mut nodes = Vec::new();
+        if !boxes.is_empty() {
+            build_node(&mut nodes, &mut boxes, 0);
+        }
+        // store the objects in the order the leaves refer to them
+        let mut bounded: Vec<_> = bounded.into_iter().map(|(_, o)| Some(o)).collect();
+        let objects = boxes
+            .iter()
+            .map(|(_, i)| bounded[*i as usize].take().unwrap())
+            .collect();
+        WideBvh {
+            nodes,
+            objects,
+            unbounded,
+            simd: simd_available(),
+        }
+    }
+
+    /// slab tests one child at a time even where SIMD is available
+    pub fn without_simd(mut self) -> Self {
+        self.simd = false;
+        self
+    }
+
+    pub fn stats(&self) -> BvhStats {
+        let pointers = self.objects.len() + self.unbounded.len();
+        let mut stats = BvhStats {
+            nodes: self.nodes.len(),
+            objects: self.objects.len(),
+            depth: 0,
+            sah_cost: 0.0,
+            memory_bytes: self.nodes.len() * std::mem::size_of::<WideNode>()
+                + pointers * std::mem::size_of::<Box<dyn Hittable + Send + Sync>>(),
+        };
+        if let Some(root) = self.bounds() {
+            let area = root.surface_area();
+            stats.sah_cost = TRAVERSAL_COST + self.cost(0, area);
+            stats.depth = self.depth(0);
+        }
+        stats
+    }
+
+    // the SAH cost below node `index`, relative to the root's `area`
+    fn cost(&self, index: usize, area: Float) -> Float {
+        let node = &self.nodes[index];
+        let mut cost = 0.0;
+        for (i, &child) in node.children.iter().enumerate() {
+            let p = node.child_box(i).surface_area() / area;
+            if child == EMPTY {
+                continue;
+            } else if child & LEAF != 0 {
+                cost += p * INTERSECTION_COST;
+            } else {
+                cost += p * TRAVERSAL_COST + self.cost(child as usize, area);
+            }
+        }
+        cost
+    }
+
+    fn depth(&self, index: usize) -> usize {
+        let inner = self.nodes[index]
+            .children
+            .iter()
+            .filter(|&&c| c != EMPTY && c & LEAF == 0);
+        1 + inner.map(|&c| self.depth(c as usize)).max().unwrap_or(0)
+    }
+
+    // the union of the root's quantized child boxes
+    fn bounds(&self) -> Option<Aabb> {
+        let root = self.nodes.first()?;
+        (0..WIDTH)
+            .filter(|&i| root.children[i] != EMPTY)
+            .map(|i| root.child_box(i))
+            .reduce(|a, b| a.union(&b))
+    }
+
+    fn hit_children(
+        &self,
+        node: &WideNode,
+        ray: &Ray,
+        t_min: Float,
+        t_max: Float,
+        t_near: &mut [Float; WIDTH],
+    ) -> u32 {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if self.simd {
+                // only set when the CPU has AVX
+                return unsafe { avx::hit_children(node, ray, t_min, t_max, t_near) };
+            }
+        }
+        node.hit_children(ray, t_min, t_max, t_near)
+    }
+}
+
+// Adds the node over `boxes`, which start at `start` in the final object
+// order, and returns its index. The boxes are split at the median centroid
+// along the axis where they spread most, and the larger part is split
+// again until there are WIDTH parts or every part is a single object.
+fn build_node(nodes: &mut Vec<WideNode>, boxes: &mut [(Aabb, u32)], start: usize) -> u32 {
+    let index = nodes.len();
+    // reserve the slot, so that the root is first
+    nodes.push(WideNode::new(&[boxes[0].0], &[]));
+    // one level above the leaves, parts that fit in a node stay whole
+    let last_level = boxes.len() > WIDTH && boxes.len() <= WIDTH * WIDTH;
+    let mut parts = vec![(0, boxes.len())];
+    while parts.len() < WIDTH {
+        let (i, &(from, to)) = parts
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, (from, to))| to - from)
+            .unwrap();
+        if to - from < 2 || (last_level && to - from <= WIDTH) {
+            break;
+        }
+        let mid = split(&mut boxes[from..to]) + from;
+        parts[i] = (from, mid);
+        parts.insert(i + 1, (mid, to));
+    }
+    let mut child_boxes = Vec::new();
+    let mut children = Vec::new();
+    for (from, to) in parts {
+        let part = &mut boxes[from..to];
+        child_boxes.push(part[1..].iter().fold(part[0].0, |acc, (b, _)| acc.union(b)));
+        children.push(if part.len() == 1 {
+            (start + from) as u32 | LEAF
+        } else {
+            build_node(nodes, part, start + from)
+        });
+    }
+    nodes[index] = WideNode::new(&child_boxes, &children);
+    index as u32
+}
+
+// sorts `boxes` along the axis where their centroids spread most and
+// returns the middle
+fn split(boxes: &mut [(Aabb, u32)]) -> usize {
+    let centroids = boxes[1..].iter().fold(
+        Aabb::new(boxes[0].0.centroid(), boxes[0].0.centroid()),
+        |acc, (b, _)| acc.union(&Aabb::new(b.centroid(), b.centroid())),
+    );
+    let extent = centroids.max - centroids.min;
+    let axis = if extent.x() > extent.y() && extent.x() > extent.z() {
+        0
+    } else if extent.y() > extent.z() {
+        1
+    } else {
+        2
+    };
+    boxes.sort_by(|(a, _), (b, _)| {
+        a.centroid()[axis]
+            .partial_cmp(&b.centroid()[axis])
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    boxes.len() / 2
+}
+
+impl Hittable for WideBvh {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitRecord<'_>> {
+        let mut closest = self.unbounded.hit(ray, t_min, t_max);
+        let mut t_closest = closest.as_ref().map_or(t_max, |h| h.t);
+        if self.nodes.is_empty() {
+            return closest;
+        }
+        let mut stack = [(0u32, 0.0); STACK_SIZE];
+        stack[0] = (0, t_min);
+        let mut top = 1;
+        let mut t_near = [0.0; WIDTH];
+        while top > 0 {
+            top -= 1;
+            let (index, t_enter) = stack[top];
+            if t_enter > t_closest {
+                continue;
+            }
+            let node = &self.nodes[index as usize];
+            let mask = self.hit_children(node, ray, t_min, t_closest, &mut t_near);
+            // the children hit, nearest first
+            let mut hits = [(0.0, EMPTY); WIDTH];
+            let mut n = 0;
+            for (i, &child) in node.children.iter().enumerate() {
+                if child != EMPTY && mask & (1 << i) != 0 {
+                    let mut j = n;
+                    while j > 0 && hits[j - 1].0 > t_near[i] {
+                        hits[j] = hits[j - 1];
+                        j -= 1;
+                    }
+                    hits[j] = (t_near[i], child);
+                    n += 1;
+                }
+            }
+            // objects are tested right away, nodes are pushed so that the
+            // nearest is popped first
+            for &(t, child) in hits[..n].iter().rev() {
+                if child & LEAF != 0 {
+                    continue;
+                }
+                stack[top] = (child, t);
+                top += 1;
+            }
+            for &(t, child) in &hits[..n] {
+                if child & LEAF == 0 || t > t_closest {
+                    continue;
+                }
+                let object = &self.objects[(child & !LEAF) as usize];
+                if let Some(hit_record) = object.hit(ray, t_min, t_closest) {
+                    t_closest = hit_record.t;
+                    closest = Some(hit_record);
+                }
+            }
+        }
+        closest
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        if self.unbounded.is_empty() {
+            self.bounds()
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx {
+    use super::{step, WideNode, WIDTH};
+    use crate::float::Float;
+    use crate::lanes::*;
+    use crate::ray::Ray;
+
+    /// `WideNode::hit_children` for all children at once. Max and min
+    /// return their second operand when the first is NaN, as the scalar
+    /// comparisons keep the running bounds.
+    #[target_feature(enable = "avx")]
+    pub unsafe fn hit_children(
+        node: &WideNode,
+        ray: &Ray,
+        t_min: Float,
+        t_max: Float,
+        t_near: &mut [Float; WIDTH],
+    ) -> u32 {
+        let mut lo = splat(t_min);
+        let mut hi = splat(t_max);
+        for axis in 0..3 {
+            let origin = splat(node.origin[axis]);
+            let scale = splat(step(node.exponent[axis]));
+            let min = add(origin, mul(widen(node.lo[axis]), scale));
+            let max = add(origin, mul(widen(node.hi[axis]), scale));
+            let inv_d = 1.0 / ray.dir[axis];
+            let pos = splat(ray.pos[axis]);
+            let inv = splat(inv_d);
+            let mut t0 = mul(sub(min, pos), inv);
+            let mut t1 = mul(sub(max, pos), inv);
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            lo = crate::lanes::max(t0, lo);
+            hi = crate::lanes::min(t1, hi);
+        }
+        store(lo, t_near);
+        bits(ge(hi, lo))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::Bvh;
+    use crate::float::tolerance;
+    use crate::materials::Material;
+    use crate::shapes::Plane;
+    use approx::assert_relative_eq;
+    use rand::rngs::StdRng;
+    use rand::Rng;
+    use rand::SeedableRng;
+
+    struct TestMaterial {}
+    impl Material for TestMaterial {}
+
+    fn random_spheres(n: usize) -> Vec<Box<dyn Hittable + Send + Sync>> {
+        let mut rng = StdRng::seed_from_u64(1);
+        (0..n)
+            .map(|_| {
+                let sphere = Sphere {
+                    center: Vec3(
+                        rng.gen::<Float>() * 10.0 - 5.0,
+                        rng.gen::<Float>() * 10.0 - 5.0,
+                        rng.gen::<Float>() * 10.0 - 5.0,
+                    ),
+                    radius: 0.05 + rng.gen::<Float>() * 0.3,
+                    material: Box::new(TestMaterial {}),
+                };
+                Box::new(sphere) as Box<dyn Hittable + Send + Sync>
+            })
+            .collect()
+    }
+
+    #[test]
+    fn matches_binary_bvh() {
+        let bvh = Bvh::new(random_spheres(500));
+        let wide = WideBvh::new(random_spheres(500));
+        let scalar = WideBvh::new(random_spheres(500)).without_simd();
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let ray = Ray::new(
+                Vec3(0.0, 0.0, -20.0),
+                Vec3(rng.gen::<Float>() - 0.5, rng.gen::<Float>() - 0.5, 1.0),
+            );
+            let expected = bvh.hit(&ray, 0.001, Float::MAX).map(|h| h.t);
+            assert_eq!(wide.hit(&ray, 0.001, Float::MAX).map(|h| h.t), expected);
+            assert_eq!(scalar.hit(&ray, 0.001, Float::MAX).map(|h| h.t), expected);
+            hits += expected.is_some() as usize;
+        }
+        assert!(hits > 200);
+    }
+
+    #[test]
+    fn quantized_boxes_contain_children() {
+        let objects = random_spheres(300);
+        let boxes: Vec<Aabb> = objects.iter().map(|o| o.bounding_box().unwrap()).collect();
+        let wide = WideBvh::new(objects);
+        let exact = boxes[1..].iter().fold(boxes[0], |acc, b| acc.union(b));
+        let quantized = wide.bounding_box().unwrap();
+        for axis in 0..3 {
+            assert!(quantized.min[axis] <= exact.min[axis]);
+            assert!(quantized.max[axis] >= exact.max[axis]);
+            // at most one step of 1/255 of the extent out on each side
+            let slack = 2.0 * (exact.max[axis] - exact.min[axis]) / 255.0;
+            assert!(quantized.max[axis] - exact.max[axis] < slack);
+        }
+        // every object's box is inside the quantized box of its leaf slot
+        for node in &wide.nodes {
+            for (i, &child) in node.children.iter().enumerate() {
+                if child != EMPTY && child & LEAF != 0 {
+                    let b = wide.objects[(child & !LEAF) as usize]
+                        .bounding_box()
+                        .unwrap();
+                    let q = node.child_box(i);
+                    assert!(q.contains(b.min) && q.contains(b.max));
+                }
+            }
+        }
+    }
+
+    const POINTER_SIZE: usize = std::mem::size_of::<Box<dyn Hittable + Send + Sync>>();
+
+    #[test]
+    fn reports_stats() {
+        let wide = WideBvh::new(random_spheres(1000));
+        let stats = wide.stats();
+        assert_eq!(stats.objects, 1000);
+        // a full tree over 1000 objects, 333 nodes when 4-ary
+        let full = 999 / (WIDTH - 1);
+        assert!(stats.nodes >= full && stats.nodes < full * 3 / 2);
+        let levels = (1000 as Float).log(WIDTH as Float).ceil() as usize;
+        assert!(stats.depth >= levels && stats.depth <= 2 * levels);
+        assert_eq!(
+            stats.memory_bytes,
+            stats.nodes * std::mem::size_of::<WideNode>() + 1000 * POINTER_SIZE
+        );
+        // at least the root visit and one intersection
+        assert!(stats.sah_cost > TRAVERSAL_COST + INTERSECTION_COST);
+        assert!(stats.sah_cost < 100.0);
+    }
+
+    #[test]
+    fn unbounded_and_single_objects() {
+        let mut objects = random_spheres(1);
+        let sphere = objects[0].bounding_box().unwrap();
+        objects.push(Box::new(Plane {
+            point: Vec3(0.0, -10.0, 0.0),
+            normal: Vec3(0.0, 1.0, 0.0),
+            material: Box::new(TestMaterial {}),
+        }));
+        let wide = WideBvh::new(objects);
+        assert!(wide.bounding_box().is_none());
+        let stats = wide.stats();
+        assert_eq!(stats.nodes, 1);
+        // the plane's pointer counts too
+        assert_eq!(
+            stats.memory_bytes,
+            std::mem::size_of::<WideNode>() + 2 * POINTER_SIZE
+        );
+        let center = sphere.centroid();
+        let down = |x: Float| Ray::new(Vec3(x, 20.0, center.z()), Vec3(0.0, -1.0, 0.0));
+        let hit = wide.hit(&down(center.x()), 0.001, Float::MAX).unwrap();
+        assert_relative_eq!(hit.t, 20.0 - sphere.max.y(), epsilon = tolerance(1e-9));
+        let hit = wide
+            .hit(&down(center.x() + 1.0), 0.001, Float::MAX)
+            .unwrap();
+        assert_relative_eq!(hit.t, 30.0);
+        let empty = WideBvh::new(Vec::new());
+        assert!(empty.hit(&down(0.0), 0.001, Float::MAX).is_none());
+        assert_eq!(empty.stats().sah_cost, 0.0);
+    }
+}